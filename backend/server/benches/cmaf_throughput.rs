@@ -0,0 +1,120 @@
+//! Throughput of the CMAF segmenter on 30 s of synthetic 15 fps H.264.
+//!
+//! The server is a binary crate, so the segmenter and the modules it uses are
+//! compiled into the bench directly.
+
+// Under `--all-targets` the modules' unit tests are compiled but not run, so
+// their imports go unused.
+#[allow(dead_code, unused_imports)]
+#[path = "../src/rtsp"]
+mod rtsp {
+    pub mod cmaf;
+    pub mod depacketizer;
+    pub mod rtp;
+    pub mod stream;
+}
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rtsp::cmaf::{CmafSegmenter, WriterMode};
+use rtsp::depacketizer::AccessUnit;
+use std::path::{Path, PathBuf};
+
+const FPS: u64 = 15;
+const SECONDS: u64 = 30;
+const GOP_FRAMES: u64 = 2 * FPS;
+const IDR_BYTES: usize = 40 * 1024;
+const P_FRAME_BYTES: usize = 8 * 1024;
+const SPS: &[u8] = &[
+    0x67, 0x64, 0x00, 0x1f, 0xac, 0xd9, 0x40, 0x50, 0x05, 0xbb, 0x01, 0x10, 0x00, 0x00, 0x03, 0x00,
+    0x10, 0x00, 0x00, 0x03, 0x03, 0xc0, 0xf1, 0x83, 0x19, 0x60,
+];
+const PPS: &[u8] = &[0x68, 0xeb, 0xe3, 0xcb, 0x22, 0xc0];
+
+/// Frames with pseudo-random payloads so nothing downstream can shortcut on
+/// repeated bytes.
+fn synthetic_stream() -> Vec<(AccessUnit, u64)> {
+    let mut seed = 0x2545_f491_u32;
+    (0..FPS * SECONDS)
+        .map(|frame| {
+            let is_idr = frame % GOP_FRAMES == 0;
+            let len = if is_idr { IDR_BYTES } else { P_FRAME_BYTES };
+            let mut nal = Vec::with_capacity(len);
+            nal.push(if is_idr { 0x65 } else { 0x41 });
+            while nal.len() < len {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                nal.extend_from_slice(&seed.to_le_bytes());
+            }
+            nal.truncate(len);
+            let access_unit = AccessUnit {
+                nals: vec![nal],
+                rtp_timestamp: (frame * 90_000 / FPS) as u32,
+                is_idr,
+            };
+            (access_unit, frame * 90_000 / FPS)
+        })
+        .collect()
+}
+
+/// Segments `frames` into `dir` and returns the number of bytes written.
+async fn segment(frames: &[(AccessUnit, u64)], dir: &Path) -> u64 {
+    let mut segmenter = CmafSegmenter::new(
+        WriterMode::File(dir.to_path_buf()),
+        2.0,
+        6,
+        0.333,
+        None,
+        15.0,
+    )
+    .await
+    .expect("segmenter");
+    segmenter.set_parameter_sets(SPS.to_vec(), PPS.to_vec());
+    for (access_unit, pts) in frames {
+        segmenter
+            .push_access_unit(access_unit.clone(), *pts)
+            .await
+            .expect("push");
+    }
+    segmenter.finalize_segment().await.expect("finalize");
+    segmenter.bytes_produced()
+}
+
+fn scratch_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("cmaf-bench-{}", std::process::id()))
+}
+
+fn cmaf_throughput(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("runtime");
+    let frames = synthetic_stream();
+    let dir = scratch_dir();
+
+    // One untimed pass to report the output size.
+    let written = runtime.block_on(segment(&frames, &dir));
+    let input: u64 = frames
+        .iter()
+        .map(|(access_unit, _)| access_unit.nals[0].len() as u64)
+        .sum();
+    println!("cmaf_throughput: {input} bytes in, {written} bytes written");
+
+    let mut group = c.benchmark_group("cmaf");
+    group.throughput(Throughput::Bytes(input));
+    group.sample_size(20);
+    group.bench_function("segment_30s_15fps", |b| {
+        b.iter_batched(
+            || {
+                let _ = std::fs::remove_dir_all(&dir);
+            },
+            |()| runtime.block_on(segment(&frames, &dir)),
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+criterion_group!(benches, cmaf_throughput);
+criterion_main!(benches);
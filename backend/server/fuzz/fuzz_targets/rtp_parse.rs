@@ -0,0 +1,14 @@
+#![no_main]
+
+// The server is a binary crate, so the parsers are compiled in directly.
+#[allow(dead_code)]
+#[path = "../../src/rtsp/rtp.rs"]
+mod rtp;
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(packet) = rtp::RtpPacket::parse(data) {
+        assert!(packet.payload.len() <= data.len().saturating_sub(12));
+    }
+});
@@ -0,0 +1,15 @@
+#![no_main]
+
+#[allow(dead_code)]
+#[path = "../../src/rtsp/parser.rs"]
+mod parser;
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Split the input so partial messages buffered across reads are covered too.
+    let split = data.first().map_or(0, |&b| b as usize % (data.len() + 1));
+    let mut stream = parser::RtspStreamParser::new();
+    let _ = stream.append(&data[..split]);
+    let _ = stream.append(&data[split..]);
+});
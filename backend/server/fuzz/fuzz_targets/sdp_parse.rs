@@ -0,0 +1,11 @@
+#![no_main]
+
+#[allow(dead_code)]
+#[path = "../../src/rtsp/sdp.rs"]
+mod sdp;
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = sdp::parse_sdp(data);
+});
@@ -1,36 +1,227 @@
 use axum::http::HeaderMap;
+use axum::http::{Method, StatusCode};
 use axum::response::{IntoResponse, Response};
-use axum::http::StatusCode;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+pub const SIGNATURE_HEADER: &str = "x-signature";
+pub const TIMESTAMP_HEADER: &str = "x-timestamp";
+/// Signed requests older (or further in the future) than this are rejected.
+const MAX_SIGNATURE_AGE_SECS: u64 = 5 * 60;
+/// Identity of callers that signed with `API_HMAC_SECRET`.
+const SIGNED_CLIENT: &str = "signed-client";
+/// Identity of callers that signed with `API_ADMIN_HMAC_SECRET`.
+const ADMIN_CLIENT: &str = "admin-client";
 
 #[derive(Clone, Debug)]
 pub struct AuthContext {
     pub email: String,
+    /// May delete, restore and purge printers.
+    pub is_admin: bool,
 }
 
 #[derive(Clone)]
-pub struct AuthManager;
+pub struct AuthManager {
+    hmac_secret: Option<Arc<[u8]>>,
+    admin_hmac_secret: Option<Arc<[u8]>>,
+}
 
 impl AuthManager {
-    pub fn new() -> Self {
-        tracing::debug!("authentication disabled (no auth required)");
-        Self
+    pub fn new(hmac_secret: Option<String>, admin_hmac_secret: Option<String>) -> Self {
+        if hmac_secret.is_some() || admin_hmac_secret.is_some() {
+            tracing::info!("HMAC request signing enabled");
+        } else {
+            tracing::debug!("authentication disabled (no auth required)");
+        }
+        Self {
+            hmac_secret: hmac_secret.map(|secret| Arc::from(secret.into_bytes())),
+            admin_hmac_secret: admin_hmac_secret.map(|secret| Arc::from(secret.into_bytes())),
+        }
+    }
+
+    /// Whether the request claims a signature, so its body has to be read.
+    pub fn is_signed(headers: &HeaderMap) -> bool {
+        headers.contains_key(SIGNATURE_HEADER)
     }
 
-    pub async fn authenticate(&self, _headers: &HeaderMap) -> Result<AuthContext, AuthError> {
+    /// With a secret set, every request must carry a valid `X-Signature`;
+    /// without one, requests are anonymous and unrestricted. Requests signed
+    /// with `API_ADMIN_HMAC_SECRET` are admins, as are those signed with
+    /// `API_HMAC_SECRET` when no admin secret is configured. `path` includes
+    /// any query string.
+    pub async fn authenticate(
+        &self,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<AuthContext, AuthError> {
+        if self.hmac_secret.is_none() && self.admin_hmac_secret.is_none() {
+            if Self::is_signed(headers) {
+                return Err(AuthError::unauthorized("request signing is not configured"));
+            }
+            return Ok(AuthContext {
+                email: "anonymous".to_string(),
+                is_admin: true,
+            });
+        }
+        if !Self::is_signed(headers) {
+            return Err(AuthError::unauthorized("request signature is required"));
+        }
+        let now = Utc::now().timestamp();
+        let verify = |secret: &[u8]| verify_signature(secret, method, path, headers, body, now);
+        if let Some(admin_secret) = &self.admin_hmac_secret {
+            match verify(admin_secret) {
+                Ok(()) => {
+                    return Ok(AuthContext {
+                        email: ADMIN_CLIENT.to_string(),
+                        is_admin: true,
+                    })
+                }
+                Err(error) if self.hmac_secret.is_none() => return Err(error),
+                Err(_) => {}
+            }
+        }
+        if let Some(secret) = &self.hmac_secret {
+            verify(secret)?;
+        }
         Ok(AuthContext {
-            email: "anonymous".to_string(),
+            email: SIGNED_CLIENT.to_string(),
+            is_admin: self.admin_hmac_secret.is_none(),
         })
     }
 }
 
+/// Checks `X-Signature: sha256=<hex>` against
+/// `HMAC-SHA256(secret, "method\npath\nbody_sha256\ntimestamp")`.
+fn verify_signature(
+    secret: &[u8],
+    method: &Method,
+    path: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+    now: i64,
+) -> Result<(), AuthError> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let timestamp = header(TIMESTAMP_HEADER)
+        .ok_or_else(|| AuthError::unauthorized("X-Timestamp header is required"))?;
+    let sent_at: i64 = timestamp
+        .trim()
+        .parse()
+        .map_err(|_| AuthError::unauthorized("X-Timestamp must be unix seconds"))?;
+    if now.abs_diff(sent_at) > MAX_SIGNATURE_AGE_SECS {
+        return Err(AuthError::unauthorized("request timestamp is too old"));
+    }
+    let signature = header(SIGNATURE_HEADER)
+        .and_then(|value| value.trim().strip_prefix("sha256="))
+        .and_then(decode_hex)
+        .ok_or_else(|| AuthError::unauthorized("X-Signature must be sha256=<hex>"))?;
+    signing_mac(secret, method, path, body, timestamp.trim())
+        .verify_slice(&signature)
+        .map_err(|_| AuthError::unauthorized("invalid request signature"))
+}
+
+fn signing_mac(
+    secret: &[u8],
+    method: &Method,
+    path: &str,
+    body: &[u8],
+    timestamp: &str,
+) -> Hmac<Sha256> {
+    let body_sha256 = encode_hex(&Sha256::digest(body));
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(format!("{method}\n{path}\n{body_sha256}\n{timestamp}").as_bytes());
+    mac
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(value.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
 #[derive(Debug)]
 pub struct AuthError {
     status: StatusCode,
     message: String,
 }
 
+impl AuthError {
+    fn unauthorized(message: &str) -> Self {
+        Self {
+            status: StatusCode::UNAUTHORIZED,
+            message: message.to_string(),
+        }
+    }
+}
+
 impl IntoResponse for AuthError {
     fn into_response(self) -> Response {
         (self.status, self.message).into_response()
     }
 }
+
+/// `X-Signature` value for a request, as a client would compute it.
+#[cfg(test)]
+pub fn sign(secret: &str, method: &Method, path: &str, body: &[u8], timestamp: i64) -> String {
+    let mac = signing_mac(
+        secret.as_bytes(),
+        method,
+        path,
+        body,
+        &timestamp.to_string(),
+    );
+    format!("sha256={}", encode_hex(&mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_cover_method_path_body_and_time() {
+        let now = 1_700_000_000;
+        let body = br#"{"text":"hi"}"#;
+        let headers = |signature: &str, timestamp: i64| {
+            let mut headers = HeaderMap::new();
+            headers.insert(SIGNATURE_HEADER, signature.parse().expect("header"));
+            headers.insert(TIMESTAMP_HEADER, timestamp.into());
+            headers
+        };
+        let verify = |headers: &HeaderMap, path: &str, body: &[u8]| {
+            verify_signature(b"secret", &Method::POST, path, headers, body, now).is_ok()
+        };
+        let signed = headers(
+            &sign("secret", &Method::POST, "/api/x?y=1", body, now - 60),
+            now - 60,
+        );
+
+        assert!(verify(&signed, "/api/x?y=1", body));
+        assert!(!verify(&signed, "/api/x?y=2", body));
+        assert!(!verify(&signed, "/api/x?y=1", b"{}"));
+        let stale = now - MAX_SIGNATURE_AGE_SECS as i64 - 1;
+        let old = headers(
+            &sign("secret", &Method::POST, "/api/x?y=1", body, stale),
+            stale,
+        );
+        assert!(!verify(&old, "/api/x?y=1", body));
+        let other_key = headers(&sign("other", &Method::POST, "/api/x?y=1", body, now), now);
+        assert!(!verify(&other_key, "/api/x?y=1", body));
+        assert!(!verify(&headers("sha256=zz", now), "/api/x?y=1", body));
+        let extreme = headers(
+            &sign("secret", &Method::POST, "/api/x?y=1", body, 0),
+            i64::MIN,
+        );
+        assert!(!verify(&extreme, "/api/x?y=1", body));
+    }
+}
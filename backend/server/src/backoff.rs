@@ -0,0 +1,75 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// Exponential reconnect delay with jitter, so a printer that is switched off
+/// is not hammered every couple of seconds while short blips still recover fast.
+#[derive(Clone, Debug)]
+pub struct RetryBackoff {
+    initial: Duration,
+    max: Duration,
+    multiplier: f64,
+    jitter: f64,
+    current: Duration,
+}
+
+impl RetryBackoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        let initial = initial.max(Duration::from_millis(100));
+        Self {
+            initial,
+            max: max.max(initial),
+            multiplier: 2.0,
+            jitter: 0.2,
+            current: initial,
+        }
+    }
+
+    pub fn from_secs_f64(initial_secs: f64, max_secs: f64) -> Self {
+        Self::new(secs(initial_secs), secs(max_secs))
+    }
+
+    /// Returns the delay to wait before the next attempt and grows the
+    /// following one.
+    pub fn next_delay(&mut self) -> Duration {
+        let base = self.current;
+        self.current = base.mul_f64(self.multiplier).min(self.max);
+        let spread = rand::thread_rng().gen_range(-self.jitter..=self.jitter);
+        base.mul_f64(1.0 + spread)
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+fn secs(value: f64) -> Duration {
+    if value.is_finite() && value > 0.0 {
+        Duration::from_secs_f64(value)
+    } else {
+        Duration::ZERO
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn within_jitter(delay: Duration, expected_secs: f64) -> bool {
+        let secs = delay.as_secs_f64();
+        secs >= expected_secs * 0.8 - 1e-9 && secs <= expected_secs * 1.2 + 1e-9
+    }
+
+    #[test]
+    fn delay_doubles_up_to_max_and_resets() {
+        let mut backoff = RetryBackoff::from_secs_f64(1.0, 5.0);
+
+        assert!(within_jitter(backoff.next_delay(), 1.0));
+        assert!(within_jitter(backoff.next_delay(), 2.0));
+        assert!(within_jitter(backoff.next_delay(), 4.0));
+        assert!(within_jitter(backoff.next_delay(), 5.0));
+        assert!(within_jitter(backoff.next_delay(), 5.0));
+
+        backoff.reset();
+        assert!(within_jitter(backoff.next_delay(), 1.0));
+    }
+}
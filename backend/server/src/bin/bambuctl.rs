@@ -1,8 +1,10 @@
 use base64::{engine::general_purpose::STANDARD, Engine as _};
 use clap::{Args, Parser, Subcommand, ValueEnum};
-use reqwest::Client;
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Method, RequestBuilder, Url};
 use serde::Serialize;
 use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 
 #[derive(Parser, Debug)]
 #[command(name = "bambuctl", about = "Generic CLI for the Bambu LAN Viewer HTTP API")]
@@ -19,6 +21,11 @@ struct Cli {
     #[arg(long, env = "BAMBUCTL_PASSWORD")]
     password: Option<String>,
 
+    /// Secret to sign requests with, matching the server's `API_HMAC_SECRET`
+    /// (or `API_ADMIN_HMAC_SECRET`).
+    #[arg(long, env = "BAMBUCTL_HMAC_SECRET", hide_env_values = true)]
+    hmac_secret: Option<String>,
+
     /// Emit compact JSON instead of human-readable output.
     #[arg(long, global = true)]
     json: bool,
@@ -64,11 +71,19 @@ struct LightArgs {
     #[arg(long)]
     id: i64,
 
-    #[arg(long, conflicts_with = "off")]
+    #[arg(long, conflicts_with_all = ["off", "flashing"])]
     on: bool,
 
-    #[arg(long, conflicts_with = "on")]
+    #[arg(long, conflicts_with_all = ["on", "flashing"])]
     off: bool,
+
+    /// Flash the chamber light to draw attention.
+    #[arg(long, conflicts_with_all = ["on", "off"])]
+    flashing: bool,
+
+    /// Number of flashes when using --flashing.
+    #[arg(long, requires = "flashing")]
+    loops: Option<u32>,
 }
 
 #[derive(Copy, Clone, Debug, ValueEnum)]
@@ -114,6 +129,12 @@ struct ExtrudeArgs {
     feed_rate: Option<u32>,
 }
 
+/// HTTP client that signs every request when a secret is configured.
+struct Api {
+    client: Client,
+    hmac_secret: Option<String>,
+}
+
 #[derive(Serialize)]
 struct CommandResponse {
     ok: bool,
@@ -131,7 +152,10 @@ async fn main() {
 }
 
 async fn run(cli: Cli) -> anyhow::Result<()> {
-    let client = build_client(&cli)?;
+    let client = Api {
+        client: build_client(&cli)?,
+        hmac_secret: cli.hmac_secret.clone(),
+    };
     let base = cli.base_url.trim_end_matches('/');
 
     match cli.command {
@@ -145,12 +169,16 @@ async fn run(cli: Cli) -> anyhow::Result<()> {
                 print_output(&value, cli.json)?;
             }
             PrintersSubcommand::Light(args) => {
-                let on = match (args.on, args.off) {
-                    (true, false) => true,
-                    (false, true) => false,
-                    _ => anyhow::bail!("specify exactly one of --on or --off"),
+                let mode = match (args.on, args.off, args.flashing) {
+                    (true, false, false) => "on",
+                    (false, true, false) => "off",
+                    (false, false, true) => "flashing",
+                    _ => anyhow::bail!("specify exactly one of --on, --off, or --flashing"),
                 };
-                let payload = json!({ "type": "light", "on": on });
+                let mut payload = json!({ "type": "light", "mode": mode });
+                if let Some(loops) = args.loops {
+                    payload["loop_times"] = json!(loops);
+                }
                 let value = post_json(&client, &format!("{base}/api/printers/{}/command", args.id), &payload).await?;
                 print_output(&value, cli.json)?;
             }
@@ -208,17 +236,52 @@ fn build_client(cli: &Cli) -> anyhow::Result<Client> {
     Ok(builder.build()?)
 }
 
-async fn get_json(client: &Client, url: &str) -> anyhow::Result<Value> {
-    let response = client.get(url).send().await?;
+async fn get_json(client: &Api, url: &str) -> anyhow::Result<Value> {
+    let response = client.request(Method::GET, url, Vec::new())?.send().await?;
     parse_response(response).await
 }
 
-async fn post_json(client: &Client, url: &str, payload: &Value) -> anyhow::Result<Value> {
-    let response = client.post(url).json(payload).send().await?;
+async fn post_json(client: &Api, url: &str, payload: &Value) -> anyhow::Result<Value> {
+    let body = serde_json::to_vec(payload)?;
+    let response = client
+        .request(Method::POST, url, body)?
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .send()
+        .await?;
     parse_response(response).await
 }
 
-async fn post_simple_command(client: &Client, base: &str, id: i64, kind: &str, json_output: bool) -> anyhow::Result<()> {
+impl Api {
+    /// Adds `X-Timestamp` and `X-Signature` as the server's `API_HMAC_SECRET`
+    /// check expects: HMAC-SHA256 over "METHOD\npath?query\nsha256(body)\ntimestamp".
+    fn request(&self, method: Method, url: &str, body: Vec<u8>) -> anyhow::Result<RequestBuilder> {
+        let url = Url::parse(url)?;
+        let Some(secret) = &self.hmac_secret else {
+            return Ok(self.client.request(method, url).body(body));
+        };
+        let path = match url.query() {
+            Some(query) => format!("{}?{query}", url.path()),
+            None => url.path().to_string(),
+        };
+        let timestamp = chrono::Utc::now().timestamp().to_string();
+        let body_sha256 = encode_hex(&Sha256::digest(&body));
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+        mac.update(format!("{method}\n{path}\n{body_sha256}\n{timestamp}").as_bytes());
+        let signature = encode_hex(&mac.finalize().into_bytes());
+        Ok(self
+            .client
+            .request(method, url)
+            .header("X-Timestamp", timestamp)
+            .header("X-Signature", format!("sha256={signature}"))
+            .body(body))
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+async fn post_simple_command(client: &Api, base: &str, id: i64, kind: &str, json_output: bool) -> anyhow::Result<()> {
     let payload = json!({ "type": kind });
     let value = post_json(client, &format!("{base}/api/printers/{id}/command"), &payload).await?;
     print_output(&value, json_output)
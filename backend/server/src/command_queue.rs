@@ -0,0 +1,183 @@
+use crate::commands::CommandRequest;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, Notify};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+const QUEUE_CAPACITY: usize = 32;
+
+#[derive(Debug)]
+pub struct QueueFull;
+
+/// Serializes commands for one printer. Pending setpoint commands are replaced
+/// by newer ones of the same kind, and the worker waits `min_spacing` between
+/// publishes so a jittery UI cannot flood the printer.
+#[derive(Clone, Debug)]
+pub struct CommandQueue {
+    pending: Arc<Mutex<VecDeque<CommandRequest>>>,
+    notify: Arc<Notify>,
+}
+
+impl CommandQueue {
+    pub fn new() -> Self {
+        Self {
+            pending: Arc::new(Mutex::new(VecDeque::new())),
+            notify: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn enqueue(&self, command: CommandRequest) -> Result<(), QueueFull> {
+        {
+            let Ok(mut pending) = self.pending.lock() else {
+                return Err(QueueFull);
+            };
+            // The replacement goes to the back, so it still runs after
+            // anything submitted before it.
+            if let Some(index) = pending
+                .iter()
+                .position(|existing| command.supersedes(existing))
+            {
+                pending.remove(index);
+            } else if pending.len() >= QUEUE_CAPACITY {
+                return Err(QueueFull);
+            }
+            pending.push_back(command);
+        }
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    pub fn depth(&self) -> usize {
+        self.pending
+            .lock()
+            .map(|pending| pending.len())
+            .unwrap_or(0)
+    }
+
+    fn pop(&self) -> Option<CommandRequest> {
+        self.pending.lock().ok()?.pop_front()
+    }
+
+    /// Forwards commands until `shutdown` fires, then hands whatever is still
+    /// pending to the MQTT task without spacing and drops the channel so the
+    /// MQTT task can drain it and disconnect.
+    pub async fn run(
+        self,
+        command_tx: mpsc::Sender<CommandRequest>,
+        min_spacing: Duration,
+        shutdown: CancellationToken,
+    ) {
+        loop {
+            let Some(command) = self.pop() else {
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = self.notify.notified() => continue,
+                }
+            };
+            if command_tx.send(command).await.is_err() {
+                warn!("command channel closed; stopping command queue");
+                return;
+            }
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep(min_spacing) => {}
+            }
+        }
+        while let Some(command) = self.pop() {
+            if command_tx.send(command).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newer_setpoints_replace_pending_ones_at_the_back() {
+        let queue = CommandQueue::new();
+        queue.enqueue(CommandRequest::Home).unwrap();
+        queue
+            .enqueue(CommandRequest::SetNozzleTemp { target_c: 200.0 })
+            .unwrap();
+        queue.enqueue(CommandRequest::Pause).unwrap();
+        queue
+            .enqueue(CommandRequest::SetNozzleTemp { target_c: 215.0 })
+            .unwrap();
+
+        assert_eq!(queue.depth(), 3);
+        assert!(matches!(queue.pop(), Some(CommandRequest::Home)));
+        assert!(matches!(queue.pop(), Some(CommandRequest::Pause)));
+        assert!(matches!(
+            queue.pop(),
+            Some(CommandRequest::SetNozzleTemp { target_c }) if target_c == 215.0
+        ));
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn ordered_commands_are_never_coalesced() {
+        let queue = CommandQueue::new();
+        queue.enqueue(CommandRequest::Home).unwrap();
+        queue.enqueue(CommandRequest::Home).unwrap();
+
+        assert_eq!(queue.depth(), 2);
+    }
+
+    #[test]
+    fn rejects_commands_when_full() {
+        let queue = CommandQueue::new();
+        for _ in 0..QUEUE_CAPACITY {
+            queue.enqueue(CommandRequest::Home).unwrap();
+        }
+
+        assert!(queue.enqueue(CommandRequest::Home).is_err());
+        assert!(queue
+            .enqueue(CommandRequest::SetBedTemp { target_c: 60.0 })
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn worker_forwards_commands_in_order() {
+        let queue = CommandQueue::new();
+        let (tx, mut rx) = mpsc::channel(4);
+        tokio::spawn(
+            queue
+                .clone()
+                .run(tx, Duration::from_millis(1), CancellationToken::new()),
+        );
+
+        queue.enqueue(CommandRequest::Home).unwrap();
+        queue.enqueue(CommandRequest::Resume).unwrap();
+
+        assert!(matches!(rx.recv().await, Some(CommandRequest::Home)));
+        assert!(matches!(rx.recv().await, Some(CommandRequest::Resume)));
+    }
+
+    #[tokio::test]
+    async fn shutdown_flushes_pending_commands_and_closes_channel() {
+        let queue = CommandQueue::new();
+        let (tx, mut rx) = mpsc::channel(4);
+        let shutdown = CancellationToken::new();
+        queue.enqueue(CommandRequest::Home).unwrap();
+        queue.enqueue(CommandRequest::Pause).unwrap();
+        queue.enqueue(CommandRequest::Resume).unwrap();
+        let worker = tokio::spawn(
+            queue
+                .clone()
+                .run(tx, Duration::from_secs(60), shutdown.clone()),
+        );
+
+        assert!(matches!(rx.recv().await, Some(CommandRequest::Home)));
+        shutdown.cancel();
+        worker.await.unwrap();
+
+        assert!(matches!(rx.recv().await, Some(CommandRequest::Pause)));
+        assert!(matches!(rx.recv().await, Some(CommandRequest::Resume)));
+        assert!(rx.recv().await.is_none());
+    }
+}
@@ -9,6 +9,37 @@ const NOZZLE_TEMP_MIN_C: f64 = 0.0;
 const NOZZLE_TEMP_MAX_C: f64 = 320.0;
 const BED_TEMP_MIN_C: f64 = 0.0;
 const BED_TEMP_MAX_C: f64 = 120.0;
+const CHAMBER_TEMP_MIN_C: f64 = 0.0;
+const CHAMBER_TEMP_MAX_C: f64 = 65.0;
+const LIGHT_DEFAULT_PERIOD_MS: u32 = 500;
+const LIGHT_MAX_PERIOD_MS: u32 = 10_000;
+const LIGHT_MAX_LOOPS: u32 = 1_000;
+const RAW_GCODE_MAX_LINES: usize = 32;
+const RAW_GCODE_MAX_LINE_LEN: usize = 96;
+
+/// G/M codes accepted by `raw_gcode` when `GCODE_ALLOWLIST` is unset: motion,
+/// homing, positioning modes, temperatures, and the part fan.
+pub const DEFAULT_GCODE_ALLOWLIST: &[&str] = &[
+    "G0", "G1", "G28", "G90", "G91", "M82", "M83", "M104", "M106", "M107", "M140", "M400",
+];
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LightMode {
+    On,
+    Off,
+    Flashing,
+}
+
+impl LightMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            LightMode::On => "on",
+            LightMode::Off => "off",
+            LightMode::Flashing => "flashing",
+        }
+    }
+}
 
 #[derive(Clone, Copy, Debug, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -41,7 +72,10 @@ pub enum CommandRequest {
     Resume,
     Stop,
     Light {
-        on: bool,
+        mode: LightMode,
+        on_time_ms: u32,
+        off_time_ms: u32,
+        loop_times: u32,
     },
     Home,
     Move {
@@ -55,10 +89,16 @@ pub enum CommandRequest {
     SetBedTemp {
         target_c: f64,
     },
+    SetChamberTemp {
+        target_c: f64,
+    },
     Extrude {
         amount_mm: f64,
         feed_rate: Option<u32>,
     },
+    RawGcode {
+        gcode: String,
+    },
 }
 
 #[derive(Debug, Deserialize)]
@@ -67,8 +107,14 @@ pub enum CommandPayload {
     Pause,
     Resume,
     Stop,
+    /// Accepts the legacy `{ "on": bool }` form or an explicit `mode`, which
+    /// wins when both are present.
     Light {
-        on: bool,
+        on: Option<bool>,
+        mode: Option<LightMode>,
+        on_time_ms: Option<u32>,
+        off_time_ms: Option<u32>,
+        loop_times: Option<u32>,
     },
     Home,
     Move {
@@ -82,10 +128,63 @@ pub enum CommandPayload {
     SetBedTemp {
         target_c: f64,
     },
+    SetChamberTemp {
+        target_c: f64,
+    },
     Extrude {
         amount_mm: f64,
         feed_rate: Option<u32>,
     },
+    /// Must pass `validate` first; lines are sent verbatim.
+    RawGcode {
+        lines: Vec<String>,
+    },
+}
+
+impl CommandPayload {
+    /// Rejects payloads that cannot be sanitized by clamping, currently only
+    /// raw gcode outside the allowlist or size limits.
+    pub fn validate(&self, gcode_allowlist: &[String]) -> Result<(), String> {
+        let CommandPayload::RawGcode { lines } = self else {
+            return Ok(());
+        };
+        if lines.is_empty() {
+            return Err("at least one gcode line is required".to_string());
+        }
+        if lines.len() > RAW_GCODE_MAX_LINES {
+            return Err(format!(
+                "at most {RAW_GCODE_MAX_LINES} gcode lines are allowed"
+            ));
+        }
+        for line in lines {
+            if line.len() > RAW_GCODE_MAX_LINE_LEN {
+                return Err(format!(
+                    "gcode lines must be at most {RAW_GCODE_MAX_LINE_LEN} characters"
+                ));
+            }
+            if line.chars().any(|c| c.is_control()) {
+                return Err("gcode lines must not contain control characters".to_string());
+            }
+            let code = gcode_word(line)
+                .ok_or_else(|| format!("`{}` does not start with a G or M code", line.trim()))?;
+            if !gcode_allowlist.contains(&code) {
+                return Err(format!("{code} is not in the gcode allowlist"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Normalizes the leading G/M word of a line (`g01 X5` -> `G1`).
+pub fn gcode_word(line: &str) -> Option<String> {
+    let word = line.split_whitespace().next()?;
+    let mut chars = word.chars();
+    let letter = chars.next()?.to_ascii_uppercase();
+    if letter != 'G' && letter != 'M' {
+        return None;
+    }
+    let number: u32 = chars.as_str().parse().ok()?;
+    Some(format!("{letter}{number}"))
 }
 
 impl From<CommandPayload> for CommandRequest {
@@ -94,7 +193,26 @@ impl From<CommandPayload> for CommandRequest {
             CommandPayload::Pause => CommandRequest::Pause,
             CommandPayload::Resume => CommandRequest::Resume,
             CommandPayload::Stop => CommandRequest::Stop,
-            CommandPayload::Light { on } => CommandRequest::Light { on },
+            CommandPayload::Light {
+                on,
+                mode,
+                on_time_ms,
+                off_time_ms,
+                loop_times,
+            } => CommandRequest::Light {
+                mode: mode.unwrap_or(if on.unwrap_or(false) {
+                    LightMode::On
+                } else {
+                    LightMode::Off
+                }),
+                on_time_ms: on_time_ms
+                    .unwrap_or(LIGHT_DEFAULT_PERIOD_MS)
+                    .min(LIGHT_MAX_PERIOD_MS),
+                off_time_ms: off_time_ms
+                    .unwrap_or(LIGHT_DEFAULT_PERIOD_MS)
+                    .min(LIGHT_MAX_PERIOD_MS),
+                loop_times: loop_times.unwrap_or(0).min(LIGHT_MAX_LOOPS),
+            },
             CommandPayload::Home => CommandRequest::Home,
             CommandPayload::Move {
                 axis,
@@ -109,6 +227,9 @@ impl From<CommandPayload> for CommandRequest {
                 CommandRequest::SetNozzleTemp { target_c }
             }
             CommandPayload::SetBedTemp { target_c } => CommandRequest::SetBedTemp { target_c },
+            CommandPayload::SetChamberTemp { target_c } => {
+                CommandRequest::SetChamberTemp { target_c }
+            }
             CommandPayload::Extrude {
                 amount_mm,
                 feed_rate,
@@ -116,11 +237,49 @@ impl From<CommandPayload> for CommandRequest {
                 amount_mm,
                 feed_rate,
             },
+            CommandPayload::RawGcode { lines } => {
+                let mut gcode = String::new();
+                for line in lines {
+                    gcode.push_str(line.trim());
+                    gcode.push('\n');
+                }
+                CommandRequest::RawGcode { gcode }
+            }
         }
     }
 }
 
 impl CommandRequest {
+    /// Tokens this command takes from the per-printer rate limiter. Pause and
+    /// stop are free so they can always get through; motion costs extra.
+    pub fn rate_cost(&self) -> u32 {
+        match self {
+            CommandRequest::Pause | CommandRequest::Stop => 0,
+            CommandRequest::Move { .. }
+            | CommandRequest::Extrude { .. }
+            | CommandRequest::RawGcode { .. } => 2,
+            _ => 1,
+        }
+    }
+
+    /// Setpoint-style commands only matter in their latest form, so a newer
+    /// one can replace a still-queued command of the same kind.
+    pub fn supersedes(&self, other: &CommandRequest) -> bool {
+        matches!(
+            (self, other),
+            (
+                CommandRequest::SetNozzleTemp { .. },
+                CommandRequest::SetNozzleTemp { .. }
+            ) | (
+                CommandRequest::SetBedTemp { .. },
+                CommandRequest::SetBedTemp { .. }
+            ) | (
+                CommandRequest::SetChamberTemp { .. },
+                CommandRequest::SetChamberTemp { .. }
+            ) | (CommandRequest::Light { .. }, CommandRequest::Light { .. })
+        )
+    }
+
     pub fn to_payload(&self, user_id: &str, sequence_id: u64) -> Value {
         let sequence_id = sequence_id.to_string();
         match self {
@@ -145,16 +304,21 @@ impl CommandRequest {
                     "command": "stop"
                 }
             }),
-            CommandRequest::Light { on } => json!({
+            CommandRequest::Light {
+                mode,
+                on_time_ms,
+                off_time_ms,
+                loop_times,
+            } => json!({
                 "user_id": user_id,
                 "system": {
                     "sequence_id": sequence_id,
                     "command": "ledctrl",
                     "led_node": "chamber_light",
-                    "led_mode": if *on { "on" } else { "off" },
-                    "led_on_time": 500,
-                    "led_off_time": 500,
-                    "loop_times": 0,
+                    "led_mode": mode.as_str(),
+                    "led_on_time": on_time_ms,
+                    "led_off_time": off_time_ms,
+                    "loop_times": loop_times,
                     "interval_time": 0
                 }
             }),
@@ -208,6 +372,19 @@ impl CommandRequest {
                     }
                 })
             }
+            CommandRequest::SetChamberTemp { target_c } => {
+                let sanitized =
+                    sanitize_temperature(*target_c, CHAMBER_TEMP_MIN_C, CHAMBER_TEMP_MAX_C);
+                let gcode = format!("M141 S{}\n", format_gcode_number(sanitized));
+                json!({
+                    "user_id": user_id,
+                    "print": {
+                        "sequence_id": sequence_id,
+                        "command": "gcode_line",
+                        "param": gcode
+                    }
+                })
+            }
             CommandRequest::Extrude {
                 amount_mm,
                 feed_rate,
@@ -224,6 +401,14 @@ impl CommandRequest {
                     }
                 })
             }
+            CommandRequest::RawGcode { gcode } => json!({
+                "user_id": user_id,
+                "print": {
+                    "sequence_id": sequence_id,
+                    "command": "gcode_line",
+                    "param": gcode
+                }
+            }),
         }
     }
 }
@@ -298,6 +483,48 @@ mod tests {
         assert_eq!(payload["print"]["param"], "G28 \n");
     }
 
+    fn light_request(payload: Value) -> CommandRequest {
+        CommandRequest::from(serde_json::from_value::<CommandPayload>(payload).unwrap())
+    }
+
+    #[test]
+    fn light_boolean_form_maps_to_on_and_off() {
+        let on = light_request(json!({ "type": "light", "on": true })).to_payload("1", 1);
+        assert_eq!(on["system"]["command"], "ledctrl");
+        assert_eq!(on["system"]["led_mode"], "on");
+        assert_eq!(on["system"]["led_on_time"], 500);
+        assert_eq!(on["system"]["led_off_time"], 500);
+        assert_eq!(on["system"]["loop_times"], 0);
+
+        let off = light_request(json!({ "type": "light", "on": false })).to_payload("1", 2);
+        assert_eq!(off["system"]["led_mode"], "off");
+    }
+
+    #[test]
+    fn light_explicit_mode_overrides_boolean() {
+        let payload =
+            light_request(json!({ "type": "light", "on": true, "mode": "off" })).to_payload("1", 3);
+        assert_eq!(payload["system"]["led_mode"], "off");
+    }
+
+    #[test]
+    fn light_flashing_mode_passes_timings_through() {
+        let payload = light_request(json!({
+            "type": "light",
+            "mode": "flashing",
+            "on_time_ms": 250,
+            "off_time_ms": 750,
+            "loop_times": 5
+        }))
+        .to_payload("1", 4);
+
+        assert_eq!(payload["system"]["led_node"], "chamber_light");
+        assert_eq!(payload["system"]["led_mode"], "flashing");
+        assert_eq!(payload["system"]["led_on_time"], 250);
+        assert_eq!(payload["system"]["led_off_time"], 750);
+        assert_eq!(payload["system"]["loop_times"], 5);
+    }
+
     #[test]
     fn move_payload_wraps_relative_axis_move() {
         let payload = CommandRequest::Move {
@@ -346,6 +573,16 @@ mod tests {
         assert_eq!(gcode, "M140 S0\n");
     }
 
+    #[test]
+    fn set_chamber_temp_uses_m141_with_clamping() {
+        let payload = CommandRequest::SetChamberTemp { target_c: 90.0 }.to_payload("1", 14);
+        assert_eq!(payload["print"]["command"], "gcode_line");
+        assert_eq!(payload["print"]["param"], "M141 S65\n");
+
+        let payload = CommandRequest::SetChamberTemp { target_c: 42.4 }.to_payload("1", 15);
+        assert_eq!(payload["print"]["param"], "M141 S42\n");
+    }
+
     #[test]
     fn extrude_uses_relative_extrusion_gcode() {
         let payload = CommandRequest::Extrude {
@@ -358,4 +595,50 @@ mod tests {
         assert_eq!(payload["print"]["command"], "gcode_line");
         assert_eq!(gcode, "M83\nG1 E5 F240\n");
     }
+
+    fn default_allowlist() -> Vec<String> {
+        DEFAULT_GCODE_ALLOWLIST
+            .iter()
+            .map(|code| code.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn raw_gcode_allowed_sequence_is_sent_as_one_gcode_line() {
+        let payload: CommandPayload = serde_json::from_value(json!({
+            "type": "raw_gcode",
+            "lines": ["G28", "g91", "G1 X10 F3000 ; nudge", "M106 S255"]
+        }))
+        .unwrap();
+        assert_eq!(payload.validate(&default_allowlist()), Ok(()));
+
+        let sent = CommandRequest::from(payload).to_payload("1", 13);
+        assert_eq!(sent["print"]["command"], "gcode_line");
+        assert_eq!(
+            sent["print"]["param"],
+            "G28\ng91\nG1 X10 F3000 ; nudge\nM106 S255\n"
+        );
+    }
+
+    #[test]
+    fn raw_gcode_rejects_codes_outside_allowlist_and_limits() {
+        let firmware = CommandPayload::RawGcode {
+            lines: vec!["G28".to_string(), "M997".to_string()],
+        };
+        let error = firmware.validate(&default_allowlist()).unwrap_err();
+        assert!(error.contains("M997"));
+
+        let smuggled = CommandPayload::RawGcode {
+            lines: vec!["G1 X1\nM997".to_string()],
+        };
+        assert!(smuggled.validate(&default_allowlist()).is_err());
+
+        let too_many = CommandPayload::RawGcode {
+            lines: vec!["G1 X1".to_string(); RAW_GCODE_MAX_LINES + 1],
+        };
+        assert!(too_many.validate(&default_allowlist()).is_err());
+        assert!(CommandPayload::RawGcode { lines: vec![] }
+            .validate(&default_allowlist())
+            .is_err());
+    }
 }
@@ -1,9 +1,17 @@
+use crate::commands::{gcode_word, DEFAULT_GCODE_ALLOWLIST};
+use crate::rtsp::cmaf::DEFAULT_SEGMENT_PATTERN;
 use serde::{Deserialize, Serialize};
 use std::env;
 
+/// Username Bambu printers expect on their camera stream.
+const DEFAULT_RTSP_USERNAME: &str = "bblp";
+
 #[derive(Clone, Debug)]
 pub struct AppConfig {
     pub database_url: String,
+    pub secret_key: Option<String>,
+    pub api_hmac_secret: Option<String>,
+    pub api_admin_hmac_secret: Option<String>,
     pub mqtt_port: u16,
     pub mqtt_tls: bool,
     pub mqtt_tls_insecure: bool,
@@ -14,7 +22,13 @@ pub struct AppConfig {
     pub mqtt_keep_alive_secs: u64,
     pub mqtt_user_id: String,
     pub rtsp_tls_insecure: bool,
+    pub rtsp_user_agent: String,
+    pub ftps_tls_insecure: bool,
     pub rtsp_packet_timeout_secs: u64,
+    pub rtsp_reconnect_initial_secs: f64,
+    pub rtsp_reconnect_max_secs: f64,
+    pub rtsp_push_listen: Option<String>,
+    pub rtsp_mdns_discovery: bool,
     pub cmaf_output_dir: String,
     pub cmaf_target_duration_secs: f64,
     pub cmaf_window_segments: usize,
@@ -22,7 +36,25 @@ pub struct AppConfig {
     pub cmaf_ws_backlog_secs: f64,
     pub cmaf_write_files: bool,
     pub cmaf_fallback_fps: f64,
+    pub cmaf_emit_sidx: bool,
+    pub cmaf_encryption_key: Option<[u8; 16]>,
+    pub cmaf_max_segment_bytes: Option<u64>,
+    pub cmaf_max_segment_secs: Option<f64>,
+    pub cmaf_segment_pattern: String,
+    pub cmaf_segments_per_dir: Option<u64>,
+    pub max_output_disk_mb: Option<u64>,
+    pub metrics_retention_days: u64,
     pub http_bind: String,
+    pub static_dir: Option<String>,
+    pub cors_allowed_origins: Vec<String>,
+    pub cors_allow_credentials: bool,
+    pub request_timeout_secs: u64,
+    pub security_headers_enabled: bool,
+    pub command_rate_per_sec: f64,
+    pub command_burst: u32,
+    pub command_min_spacing_ms: u64,
+    pub webhook_url: Option<String>,
+    pub gcode_allowlist: Vec<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -34,6 +66,72 @@ pub struct PrinterConfig {
     pub serial: String,
     pub access_code: String,
     pub rtsp_url: Option<String>,
+    pub overrides: Option<PrinterOverrides>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Stamped on commands instead of `MQTT_USER_ID` when set.
+    #[serde(default)]
+    pub mqtt_user_id: Option<String>,
+    /// RTSP login for non-Bambu sources; the password is always the access code.
+    #[serde(default)]
+    pub rtsp_username: Option<String>,
+}
+
+/// Per-printer settings that take precedence over the global `AppConfig`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PrinterOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mqtt_port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mqtt_tls: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mqtt_tls_insecure: Option<bool>,
+}
+
+impl PrinterConfig {
+    /// Host without IPv6 brackets, as socket and TLS APIs expect it.
+    pub fn connect_host(&self) -> &str {
+        strip_ipv6_brackets(&self.host)
+    }
+
+    /// The user id commands to this printer are attributed to.
+    pub fn resolved_mqtt_user_id<'a>(&'a self, settings: &'a AppConfig) -> &'a str {
+        self.mqtt_user_id
+            .as_deref()
+            .unwrap_or(&settings.mqtt_user_id)
+    }
+
+    pub fn resolved_rtsp_username(&self) -> &str {
+        self.rtsp_username
+            .as_deref()
+            .unwrap_or(DEFAULT_RTSP_USERNAME)
+    }
+
+    /// Whether both configs reach the printer the same way over MQTT and
+    /// RTSP; names, tags and the MQTT user id can change under a running task.
+    pub fn same_connection(&self, other: &PrinterConfig) -> bool {
+        self.id == other.id
+            && self.host == other.host
+            && self.serial == other.serial
+            && self.access_code == other.access_code
+            && self.rtsp_url == other.rtsp_url
+            && self.rtsp_username == other.rtsp_username
+            && self.overrides == other.overrides
+    }
+}
+
+/// `[fd00::1]` -> `fd00::1`; anything else is returned unchanged.
+pub fn strip_ipv6_brackets(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|inner| inner.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+impl PrinterOverrides {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
 }
 
 impl AppConfig {
@@ -42,6 +140,15 @@ impl AppConfig {
             .or_else(|_| env::var("DB_PATH"))
             .unwrap_or_else(|_| "data/printers.db".to_string());
         let database_url = normalize_db_url(&database_url);
+        let secret_key = env::var("SECRET_KEY")
+            .ok()
+            .filter(|value| !value.is_empty());
+        let api_hmac_secret = env::var("API_HMAC_SECRET")
+            .ok()
+            .filter(|value| !value.is_empty());
+        let api_admin_hmac_secret = env::var("API_ADMIN_HMAC_SECRET")
+            .ok()
+            .filter(|value| !value.is_empty());
         let mqtt_tls = env_bool("MQTT_TLS", true);
         let mqtt_port = env_u16("MQTT_PORT").unwrap_or(if mqtt_tls { 8883 } else { 1883 });
         let mqtt_ca_cert = env::var("MQTT_CA_CERT").ok();
@@ -55,7 +162,21 @@ impl AppConfig {
         let mqtt_keep_alive_secs = env_u64("MQTT_KEEP_ALIVE_SECS").unwrap_or(30);
         let mqtt_user_id = env::var("MQTT_USER_ID").unwrap_or_else(|_| "1".to_string());
         let rtsp_tls_insecure = env_bool("RTSP_TLS_INSECURE", true);
+        let rtsp_user_agent = env::var("RTSP_USER_AGENT")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .unwrap_or_else(|| "BambuLANViewer/1.0".to_string());
+        if rtsp_user_agent.contains(['\r', '\n']) {
+            anyhow::bail!("RTSP_USER_AGENT must not contain line breaks");
+        }
+        let ftps_tls_insecure = env_bool("FTPS_TLS_INSECURE", true);
         let rtsp_packet_timeout_secs = env_u64("RTSP_PACKET_TIMEOUT_SECS").unwrap_or(10);
+        let rtsp_reconnect_initial_secs = env_f64("RTSP_RECONNECT_INITIAL_SECS").unwrap_or(1.0);
+        let rtsp_reconnect_max_secs = env_f64("RTSP_RECONNECT_MAX_SECS").unwrap_or(30.0);
+        let rtsp_push_listen = env::var("RTSP_PUSH_LISTEN")
+            .ok()
+            .filter(|value| !value.trim().is_empty());
+        let rtsp_mdns_discovery = env_bool("RTSP_MDNS_DISCOVERY", false);
         let cmaf_output_dir = env::var("CMAF_OUTPUT_DIR").unwrap_or_else(|_| "cmaf".to_string());
         let cmaf_target_duration_secs = env_f64("CMAF_TARGET_DURATION_SECS").unwrap_or(2.0);
         let cmaf_window_segments = env_usize("CMAF_WINDOW_SEGMENTS").unwrap_or(6);
@@ -63,10 +184,69 @@ impl AppConfig {
         let cmaf_ws_backlog_secs = env_f64("CMAF_WS_BACKLOG_SECS").unwrap_or(3.0);
         let cmaf_write_files = env_bool("CMAF_WRITE_FILES", false);
         let cmaf_fallback_fps = env_f64("CMAF_FALLBACK_FPS").unwrap_or(15.0);
+        let cmaf_emit_sidx = env_bool("CMAF_EMIT_SIDX", true);
+        let cmaf_encryption_key = match env::var("CMAF_ENCRYPTION_KEY") {
+            Ok(value) if !value.trim().is_empty() => Some(
+                parse_aes_key(value.trim())
+                    .ok_or_else(|| anyhow::anyhow!("CMAF_ENCRYPTION_KEY must be 32 hex digits"))?,
+            ),
+            _ => None,
+        };
+        let cmaf_max_segment_bytes = env_u64("CMAF_MAX_SEGMENT_BYTES");
+        let cmaf_max_segment_secs = env_f64("CMAF_MAX_SEGMENT_SECS");
+        let cmaf_segment_pattern = env::var("CMAF_SEGMENT_PATTERN")
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_SEGMENT_PATTERN.to_string());
+        if !cmaf_segment_pattern.contains("{seq}") || cmaf_segment_pattern.contains('/') {
+            anyhow::bail!("CMAF_SEGMENT_PATTERN must contain {{seq}} and no `/`");
+        }
+        let cmaf_segments_per_dir = env_u64("CMAF_SEGMENTS_PER_DIR").filter(|count| *count > 0);
+        let max_output_disk_mb = env_u64("MAX_OUTPUT_DISK_MB").filter(|mb| *mb > 0);
+        let metrics_retention_days = env_u64("METRICS_RETENTION_DAYS").unwrap_or(7);
         let http_bind = env::var("HTTP_BIND").unwrap_or_else(|_| "0.0.0.0:8080".to_string());
+        let static_dir = env::var("STATIC_DIR")
+            .ok()
+            .filter(|value| !value.trim().is_empty());
+        let cors_allowed_origins = env::var("CORS_ALLOWED_ORIGINS")
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|origin| origin.trim().to_string())
+                    .filter(|origin| !origin.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let cors_allow_credentials = env_bool("CORS_ALLOW_CREDENTIALS", false);
+        let request_timeout_secs = env_u64("REQUEST_TIMEOUT_SECS").unwrap_or(30);
+        let security_headers_enabled = env_bool("SECURITY_HEADERS_ENABLED", true);
+        let command_rate_per_sec = env_f64("COMMAND_RATE_PER_SEC").unwrap_or(5.0);
+        let command_burst = env_u32("COMMAND_BURST").unwrap_or(10);
+        let command_min_spacing_ms = env_u64("COMMAND_MIN_SPACING_MS").unwrap_or(250);
+        let webhook_url = env::var("WEBHOOK_URL")
+            .ok()
+            .filter(|value| !value.trim().is_empty());
+        let gcode_allowlist = match env::var("GCODE_ALLOWLIST") {
+            Ok(value) => value
+                .split(',')
+                .filter(|code| !code.trim().is_empty())
+                .map(|code| {
+                    gcode_word(code).ok_or_else(|| {
+                        anyhow::anyhow!("GCODE_ALLOWLIST entry `{}` is not a G/M code", code.trim())
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?,
+            Err(_) => DEFAULT_GCODE_ALLOWLIST
+                .iter()
+                .map(|code| code.to_string())
+                .collect(),
+        };
 
         Ok(Self {
             database_url,
+            secret_key,
+            api_hmac_secret,
+            api_admin_hmac_secret,
             mqtt_port,
             mqtt_tls,
             mqtt_tls_insecure,
@@ -77,7 +257,13 @@ impl AppConfig {
             mqtt_keep_alive_secs,
             mqtt_user_id,
             rtsp_tls_insecure,
+            rtsp_user_agent,
+            ftps_tls_insecure,
             rtsp_packet_timeout_secs,
+            rtsp_reconnect_initial_secs,
+            rtsp_reconnect_max_secs,
+            rtsp_push_listen,
+            rtsp_mdns_discovery,
             cmaf_output_dir,
             cmaf_target_duration_secs,
             cmaf_window_segments,
@@ -85,9 +271,86 @@ impl AppConfig {
             cmaf_ws_backlog_secs,
             cmaf_write_files,
             cmaf_fallback_fps,
+            cmaf_emit_sidx,
+            cmaf_encryption_key,
+            cmaf_max_segment_bytes,
+            cmaf_max_segment_secs,
+            cmaf_segment_pattern,
+            cmaf_segments_per_dir,
+            max_output_disk_mb,
+            metrics_retention_days,
             http_bind,
+            static_dir,
+            cors_allowed_origins,
+            cors_allow_credentials,
+            request_timeout_secs,
+            security_headers_enabled,
+            command_rate_per_sec,
+            command_burst,
+            command_min_spacing_ms,
+            webhook_url,
+            gcode_allowlist,
         })
     }
+
+    /// Fixed settings for tests, whatever the environment holds: the
+    /// defaults, except that video scratch space is the temp dir, nothing is
+    /// written to disk and no mDNS discovery runs.
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        Self {
+            database_url: "sqlite::memory:".to_string(),
+            secret_key: None,
+            api_hmac_secret: None,
+            api_admin_hmac_secret: None,
+            mqtt_port: 8883,
+            mqtt_tls: true,
+            mqtt_tls_insecure: true,
+            mqtt_ca_cert: None,
+            mqtt_max_incoming_packet_size: 256 * 1024,
+            mqtt_max_outgoing_packet_size: 64 * 1024,
+            mqtt_client_id: "bambu-lan-viewer".to_string(),
+            mqtt_keep_alive_secs: 30,
+            mqtt_user_id: "1".to_string(),
+            rtsp_tls_insecure: true,
+            rtsp_user_agent: "BambuLANViewer/1.0".to_string(),
+            ftps_tls_insecure: true,
+            rtsp_packet_timeout_secs: 10,
+            rtsp_reconnect_initial_secs: 1.0,
+            rtsp_reconnect_max_secs: 30.0,
+            rtsp_push_listen: None,
+            rtsp_mdns_discovery: false,
+            cmaf_output_dir: env::temp_dir().display().to_string(),
+            cmaf_target_duration_secs: 2.0,
+            cmaf_window_segments: 6,
+            cmaf_part_duration_secs: 0.333,
+            cmaf_ws_backlog_secs: 3.0,
+            cmaf_write_files: false,
+            cmaf_fallback_fps: 15.0,
+            cmaf_emit_sidx: true,
+            cmaf_encryption_key: None,
+            cmaf_max_segment_bytes: None,
+            cmaf_max_segment_secs: None,
+            cmaf_segment_pattern: DEFAULT_SEGMENT_PATTERN.to_string(),
+            cmaf_segments_per_dir: None,
+            max_output_disk_mb: None,
+            metrics_retention_days: 7,
+            http_bind: "127.0.0.1:0".to_string(),
+            static_dir: None,
+            cors_allowed_origins: Vec::new(),
+            cors_allow_credentials: false,
+            request_timeout_secs: 30,
+            security_headers_enabled: true,
+            command_rate_per_sec: 5.0,
+            command_burst: 10,
+            command_min_spacing_ms: 250,
+            webhook_url: None,
+            gcode_allowlist: DEFAULT_GCODE_ALLOWLIST
+                .iter()
+                .map(|code| code.to_string())
+                .collect(),
+        }
+    }
 }
 
 fn normalize_db_url(value: &str) -> String {
@@ -98,6 +361,18 @@ fn normalize_db_url(value: &str) -> String {
     }
 }
 
+fn parse_aes_key(value: &str) -> Option<[u8; 16]> {
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    if value.len() != 32 || !value.is_ascii() {
+        return None;
+    }
+    let mut key = [0u8; 16];
+    for (index, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&value[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(key)
+}
+
 fn env_bool(name: &str, default: bool) -> bool {
     match env::var(name) {
         Ok(value) => matches!(value.as_str(), "1" | "true" | "TRUE" | "yes" | "YES"),
@@ -109,6 +384,10 @@ fn env_u16(name: &str) -> Option<u16> {
     env::var(name).ok().and_then(|value| value.parse().ok())
 }
 
+fn env_u32(name: &str) -> Option<u32> {
+    env::var(name).ok().and_then(|value| value.parse().ok())
+}
+
 fn env_u64(name: &str) -> Option<u64> {
     env::var(name).ok().and_then(|value| value.parse().ok())
 }
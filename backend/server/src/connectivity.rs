@@ -0,0 +1,41 @@
+use chrono::{DateTime, TimeZone, Utc};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+
+/// Last time a link event happened, shared without locking so hot paths can
+/// update it freely.
+#[derive(Clone, Debug, Default)]
+pub struct LinkTimestamp(Arc<AtomicI64>);
+
+impl LinkTimestamp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn touch(&self) {
+        self.0
+            .store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> Option<DateTime<Utc>> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            millis => Utc.timestamp_millis_opt(millis).single(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_is_unset_until_touched() {
+        let timestamp = LinkTimestamp::new();
+        assert_eq!(timestamp.get(), None);
+
+        let before = Utc::now() - chrono::Duration::milliseconds(1);
+        timestamp.clone().touch();
+        assert!(timestamp.get().is_some_and(|at| at >= before));
+    }
+}
@@ -1,10 +1,34 @@
-use crate::config::PrinterConfig;
+use crate::config::{strip_ipv6_brackets, PrinterConfig, PrinterOverrides};
+use crate::secrets::SecretCipher;
+use crate::state::{PrinterState, VideoStatus};
 use anyhow::Context;
-use serde::Deserialize;
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
 use sqlx::sqlite::SqliteRow;
 use sqlx::{Row, SqlitePool};
+use std::net::Ipv6Addr;
 use std::path::{Path, PathBuf};
 
+/// Columns read by `row_to_printer`; tags come back as a JSON array.
+const PRINTER_COLUMNS: &str = r#"
+    id, name, host, serial, access_code, rtsp_url, printer_config, mqtt_user_id,
+    rtsp_username,
+    (SELECT json_group_array(tags.name) FROM printer_tags
+     JOIN tags ON tags.id = printer_tags.tag_id
+     WHERE printer_tags.printer_id = printers.id) AS tags
+"#;
+/// Columns read by `row_to_tag`.
+const TAG_COLUMNS: &str = r#"
+    tags.id, tags.name,
+    (SELECT COUNT(*) FROM printer_tags
+     JOIN printers ON printers.id = printer_tags.printer_id
+     WHERE printer_tags.tag_id = tags.id AND printers.deleted_at IS NULL) AS printer_count
+"#;
+/// At most this many tags per printer, each up to `MAX_TAG_LEN` characters.
+const MAX_TAGS: usize = 16;
+const MAX_TAG_LEN: usize = 32;
+const MAX_NOTE_LEN: usize = 4000;
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PrinterCreateRequest {
@@ -13,9 +37,13 @@ pub struct PrinterCreateRequest {
     pub serial: String,
     pub access_code: String,
     pub rtsp_url: Option<String>,
+    pub overrides: Option<PrinterOverrides>,
+    pub tags: Option<Vec<String>>,
+    pub mqtt_user_id: Option<String>,
+    pub rtsp_username: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PrinterUpdateRequest {
     pub name: Option<String>,
@@ -23,8 +51,105 @@ pub struct PrinterUpdateRequest {
     pub serial: Option<String>,
     pub access_code: Option<String>,
     pub rtsp_url: Option<String>,
+    pub overrides: Option<PrinterOverrides>,
+    pub tags: Option<Vec<String>>,
+    pub mqtt_user_id: Option<String>,
+    pub rtsp_username: Option<String>,
+}
+
+/// Full replacement for `PUT`: every field is required, and omitting
+/// `rtspUrl`, `overrides`, `tags`, `mqttUserId` or `rtspUsername` clears them.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrinterReplaceRequest {
+    pub name: String,
+    pub host: String,
+    pub serial: String,
+    pub access_code: String,
+    pub rtsp_url: Option<String>,
+    pub overrides: Option<PrinterOverrides>,
+    pub tags: Option<Vec<String>>,
+    pub mqtt_user_id: Option<String>,
+    pub rtsp_username: Option<String>,
+}
+
+impl From<PrinterReplaceRequest> for PrinterUpdateRequest {
+    fn from(payload: PrinterReplaceRequest) -> Self {
+        Self {
+            name: Some(payload.name),
+            host: Some(payload.host),
+            serial: Some(payload.serial),
+            access_code: Some(payload.access_code),
+            rtsp_url: Some(payload.rtsp_url.unwrap_or_default()),
+            overrides: Some(payload.overrides.unwrap_or_default()),
+            tags: Some(payload.tags.unwrap_or_default()),
+            mqtt_user_id: Some(payload.mqtt_user_id.unwrap_or_default()),
+            rtsp_username: Some(payload.rtsp_username.unwrap_or_default()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tag {
+    pub id: i64,
+    pub name: String,
+    /// Printers carrying the tag, not counting deleted ones.
+    pub printer_count: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrinterNote {
+    pub id: i64,
+    pub printer_id: i64,
+    pub author: String,
+    pub text: String,
+    pub created_at: DateTime<Utc>,
 }
 
+/// Temperatures and progress at one point in time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MetricsSample {
+    pub sampled_at: DateTime<Utc>,
+    pub nozzle_c: Option<f64>,
+    pub bed_c: Option<f64>,
+    pub chamber_c: Option<f64>,
+    pub percent: Option<u8>,
+}
+
+/// Samples averaged over one interval; `percent` is the highest reported.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsBucket {
+    pub start: DateTime<Utc>,
+    pub nozzle_c: Option<f64>,
+    pub bed_c: Option<f64>,
+    pub chamber_c: Option<f64>,
+    pub percent: Option<u8>,
+    pub samples: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeletedPrinter {
+    #[serde(flatten)]
+    pub printer: PrinterConfig,
+    pub deleted_at: DateTime<Utc>,
+}
+
+/// A rejected printer field; the HTTP layer maps it to 400.
+#[derive(Debug)]
+pub struct ValidationError(String);
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
 pub async fn init(database_url: &str) -> anyhow::Result<SqlitePool> {
     ensure_parent_dir(database_url)?;
     let pool = SqlitePool::connect(database_url).await?;
@@ -48,38 +173,206 @@ pub async fn init(database_url: &str) -> anyhow::Result<SqlitePool> {
     )
     .execute(&pool)
     .await?;
+    ensure_column(&pool, "printers", "printer_config", "TEXT").await?;
+    ensure_column(&pool, "printers", "deleted_at", "DATETIME").await?;
+    ensure_column(&pool, "printers", "mqtt_user_id", "TEXT").await?;
+    ensure_column(&pool, "printers", "rtsp_username", "TEXT").await?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS printer_state_cache (
+            printer_id INTEGER PRIMARY KEY REFERENCES printers(id) ON DELETE CASCADE,
+            state_json TEXT NOT NULL
+        )
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS tags (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE COLLATE NOCASE
+        )
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS printer_tags (
+            printer_id INTEGER NOT NULL REFERENCES printers(id) ON DELETE CASCADE,
+            tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+            PRIMARY KEY (printer_id, tag_id)
+        )
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS printer_notes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            printer_id INTEGER NOT NULL REFERENCES printers(id) ON DELETE CASCADE,
+            author TEXT NOT NULL,
+            body TEXT NOT NULL,
+            created_at DATETIME NOT NULL
+        )
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS printer_metrics (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            printer_id INTEGER NOT NULL REFERENCES printers(id) ON DELETE CASCADE,
+            sampled_at DATETIME NOT NULL,
+            nozzle_c REAL,
+            bed_c REAL,
+            chamber_c REAL,
+            percent INTEGER
+        )
+        "#,
+    )
+    .execute(&pool)
+    .await?;
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS printer_metrics_by_time ON printer_metrics (printer_id, sampled_at)",
+    )
+    .execute(&pool)
+    .await?;
     Ok(pool)
 }
 
-pub async fn list_printers(pool: &SqlitePool) -> anyhow::Result<Vec<PrinterConfig>> {
-    let rows = sqlx::query(
+async fn ensure_column(
+    pool: &SqlitePool,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> anyhow::Result<()> {
+    let columns = sqlx::query(&format!("PRAGMA table_info({table})"))
+        .fetch_all(pool)
+        .await?;
+    let exists = columns
+        .iter()
+        .any(|row| row.get::<String, _>("name") == column);
+    if !exists {
+        sqlx::query(&format!(
+            "ALTER TABLE {table} ADD COLUMN {column} {definition}"
+        ))
+        .execute(pool)
+        .await
+        .with_context(|| format!("add column {table}.{column}"))?;
+    }
+    Ok(())
+}
+
+pub async fn list_printers(
+    pool: &SqlitePool,
+    cipher: &SecretCipher,
+) -> anyhow::Result<Vec<PrinterConfig>> {
+    let rows = sqlx::query(&format!(
         r#"
-        SELECT id, name, host, serial, access_code, rtsp_url
+        SELECT {PRINTER_COLUMNS}
         FROM printers
+        WHERE deleted_at IS NULL
         ORDER BY name COLLATE NOCASE, id
-        "#,
-    )
+        "#
+    ))
     .fetch_all(pool)
     .await?;
-    Ok(rows.into_iter().map(row_to_printer).collect())
+    rows.into_iter()
+        .map(|row| row_to_printer(row, cipher))
+        .collect()
 }
 
-pub async fn get_printer(pool: &SqlitePool, id: i64) -> anyhow::Result<Option<PrinterConfig>> {
-    let row = sqlx::query(
+/// One page of printers whose name, serial or host contains `search`
+/// (case-insensitive) and that carry `tag`, plus the number of matches across
+/// all pages.
+pub async fn search_printers(
+    pool: &SqlitePool,
+    cipher: &SecretCipher,
+    search: Option<&str>,
+    tag: Option<&str>,
+    limit: Option<i64>,
+    offset: i64,
+) -> anyhow::Result<(Vec<PrinterConfig>, i64)> {
+    let pattern = search
+        .map(str::trim)
+        .filter(|search| !search.is_empty())
+        .map(|search| format!("%{}%", escape_like(search)));
+    let filter = r#"
+        WHERE deleted_at IS NULL
+          AND (?1 IS NULL
+               OR name LIKE ?1 ESCAPE '\'
+               OR serial LIKE ?1 ESCAPE '\'
+               OR host LIKE ?1 ESCAPE '\')
+          AND (?2 IS NULL OR EXISTS (
+               SELECT 1 FROM printer_tags
+               JOIN tags ON tags.id = printer_tags.tag_id
+               WHERE printer_tags.printer_id = printers.id AND tags.name = ?2))
+    "#;
+    let tag = tag.map(str::trim).filter(|tag| !tag.is_empty());
+    let total: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM printers {filter}"))
+        .bind(&pattern)
+        .bind(tag)
+        .fetch_one(pool)
+        .await?;
+    let rows = sqlx::query(&format!(
         r#"
-        SELECT id, name, host, serial, access_code, rtsp_url
+        SELECT {PRINTER_COLUMNS}
         FROM printers
-        WHERE id = ?
-        "#,
-    )
+        {filter}
+        ORDER BY name COLLATE NOCASE, id
+        LIMIT ?3 OFFSET ?4
+        "#
+    ))
+    .bind(&pattern)
+    .bind(tag)
+    // SQLite treats a negative limit as no limit.
+    .bind(limit.unwrap_or(-1))
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+    let printers = rows
+        .into_iter()
+        .map(|row| row_to_printer(row, cipher))
+        .collect::<anyhow::Result<_>>()?;
+    Ok((printers, total))
+}
+
+fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        if matches!(ch, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+pub async fn get_printer(
+    pool: &SqlitePool,
+    cipher: &SecretCipher,
+    id: i64,
+) -> anyhow::Result<Option<PrinterConfig>> {
+    let row = sqlx::query(&format!(
+        r#"
+        SELECT {PRINTER_COLUMNS}
+        FROM printers
+        WHERE id = ? AND deleted_at IS NULL
+        "#
+    ))
     .bind(id)
     .fetch_optional(pool)
     .await?;
-    Ok(row.map(row_to_printer))
+    row.map(|row| row_to_printer(row, cipher)).transpose()
 }
 
 pub async fn create_printer(
     pool: &SqlitePool,
+    cipher: &SecretCipher,
     payload: PrinterCreateRequest,
 ) -> anyhow::Result<PrinterConfig> {
     let name = payload.name.trim().to_string();
@@ -87,34 +380,47 @@ pub async fn create_printer(
     let serial = payload.serial.trim().to_string();
     let access_code = payload.access_code.trim().to_string();
     let rtsp_url = normalize_optional(payload.rtsp_url);
+    let printer_config = encode_overrides(payload.overrides.as_ref())?;
+    let tags = normalize_tags(payload.tags.unwrap_or_default())?;
+    let mqtt_user_id = normalize_optional(payload.mqtt_user_id);
+    let rtsp_username = normalize_optional(payload.rtsp_username);
 
     validate_printer_fields(&name, &host, &serial, &access_code)?;
+    validate_rtsp_username(rtsp_username.as_deref())?;
+    let stored_access_code = cipher.encrypt(&access_code)?;
     let result = sqlx::query(
         r#"
-        INSERT INTO printers (name, host, serial, access_code, rtsp_url)
-        VALUES (?, ?, ?, ?, ?)
+        INSERT INTO printers
+            (name, host, serial, access_code, rtsp_url, printer_config, mqtt_user_id,
+             rtsp_username)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
         "#,
     )
     .bind(name)
     .bind(host)
     .bind(serial)
-    .bind(access_code)
+    .bind(stored_access_code)
     .bind(rtsp_url)
+    .bind(printer_config)
+    .bind(mqtt_user_id)
+    .bind(rtsp_username)
     .execute(pool)
     .await
     .context("insert printer")?;
     let id = result.last_insert_rowid();
-    get_printer(pool, id)
+    set_printer_tags(pool, id, &tags).await?;
+    get_printer(pool, cipher, id)
         .await?
         .ok_or_else(|| anyhow::anyhow!("printer insert failed"))
 }
 
 pub async fn update_printer(
     pool: &SqlitePool,
+    cipher: &SecretCipher,
     id: i64,
     payload: PrinterUpdateRequest,
 ) -> anyhow::Result<Option<PrinterConfig>> {
-    let existing = get_printer(pool, id).await?;
+    let existing = get_printer(pool, cipher, id).await?;
     let Some(existing) = existing else {
         return Ok(None);
     };
@@ -131,24 +437,47 @@ pub async fn update_printer(
         Some(value) => normalize_optional(Some(value)),
         None => existing.rtsp_url,
     };
+    let overrides = match payload.overrides {
+        Some(overrides) => Some(overrides).filter(|value| !value.is_empty()),
+        None => existing.overrides,
+    };
+    let printer_config = encode_overrides(overrides.as_ref())?;
+    let tags = payload.tags.map(normalize_tags).transpose()?;
+    let mqtt_user_id = match payload.mqtt_user_id {
+        Some(value) => normalize_optional(Some(value)),
+        None => existing.mqtt_user_id,
+    };
+    let rtsp_username = match payload.rtsp_username {
+        Some(value) => normalize_optional(Some(value)),
+        None => existing.rtsp_username,
+    };
 
     validate_printer_fields(&name, &host, &serial, &access_code)?;
+    validate_rtsp_username(rtsp_username.as_deref())?;
+    let stored_access_code = cipher.encrypt(&access_code)?;
 
     sqlx::query(
         r#"
         UPDATE printers
-        SET name = ?, host = ?, serial = ?, access_code = ?, rtsp_url = ?
+        SET name = ?, host = ?, serial = ?, access_code = ?, rtsp_url = ?,
+            printer_config = ?, mqtt_user_id = ?, rtsp_username = ?
         WHERE id = ?
         "#,
     )
     .bind(&name)
     .bind(&host)
     .bind(&serial)
-    .bind(&access_code)
+    .bind(&stored_access_code)
     .bind(&rtsp_url)
+    .bind(&printer_config)
+    .bind(&mqtt_user_id)
+    .bind(&rtsp_username)
     .bind(id)
     .execute(pool)
     .await?;
+    if let Some(tags) = &tags {
+        set_printer_tags(pool, id, tags).await?;
+    }
 
     Ok(Some(PrinterConfig {
         id,
@@ -157,17 +486,470 @@ pub async fn update_printer(
         serial,
         access_code,
         rtsp_url,
+        overrides,
+        tags: tags.unwrap_or(existing.tags),
+        mqtt_user_id,
+        rtsp_username,
     }))
 }
 
+/// Replaces the printer's tags, creating tag names not seen before.
+async fn set_printer_tags(
+    pool: &SqlitePool,
+    printer_id: i64,
+    tags: &[String],
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM printer_tags WHERE printer_id = ?")
+        .bind(printer_id)
+        .execute(&mut *tx)
+        .await?;
+    for tag in tags {
+        sqlx::query("INSERT INTO tags (name) VALUES (?) ON CONFLICT(name) DO NOTHING")
+            .bind(tag)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO printer_tags (printer_id, tag_id) SELECT ?, id FROM tags WHERE name = ?",
+        )
+        .bind(printer_id)
+        .bind(tag)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await.context("store printer tags")
+}
+
+/// Every tag, sorted case-insensitively, including ones no printer carries.
+pub async fn list_tags(pool: &SqlitePool) -> anyhow::Result<Vec<Tag>> {
+    let rows = sqlx::query(&format!(
+        "SELECT {TAG_COLUMNS} FROM tags ORDER BY tags.name"
+    ))
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(row_to_tag).collect())
+}
+
+pub async fn get_tag(pool: &SqlitePool, id: i64) -> anyhow::Result<Option<Tag>> {
+    let row = sqlx::query(&format!("SELECT {TAG_COLUMNS} FROM tags WHERE tags.id = ?"))
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.map(row_to_tag))
+}
+
+/// Returns `None` when a tag with the same name (in any case) exists.
+pub async fn create_tag(pool: &SqlitePool, name: &str) -> anyhow::Result<Option<Tag>> {
+    let name = normalize_tag(name)?;
+    let result = sqlx::query("INSERT INTO tags (name) VALUES (?) ON CONFLICT(name) DO NOTHING")
+        .bind(name)
+        .execute(pool)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Ok(None);
+    }
+    get_tag(pool, result.last_insert_rowid()).await
+}
+
+/// Deletes the tag and detaches it from every printer.
+pub async fn delete_tag(pool: &SqlitePool, id: i64) -> anyhow::Result<bool> {
+    let result = sqlx::query("DELETE FROM tags WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn list_printer_tags(pool: &SqlitePool, printer_id: i64) -> anyhow::Result<Vec<Tag>> {
+    let rows = sqlx::query(&format!(
+        r#"
+        SELECT {TAG_COLUMNS}
+        FROM tags
+        JOIN printer_tags ON printer_tags.tag_id = tags.id
+        WHERE printer_tags.printer_id = ?
+        ORDER BY tags.name
+        "#
+    ))
+    .bind(printer_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(row_to_tag).collect())
+}
+
+/// Attaches an existing tag; attaching it twice is a no-op.
+pub async fn add_printer_tag(
+    pool: &SqlitePool,
+    printer_id: i64,
+    tag_id: i64,
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    let (attached, count): (bool, i64) = sqlx::query_as(
+        r#"
+        SELECT COALESCE(MAX(tag_id = ?), 0), COUNT(*)
+        FROM printer_tags
+        WHERE printer_id = ?
+        "#,
+    )
+    .bind(tag_id)
+    .bind(printer_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    if attached {
+        return Ok(());
+    }
+    if count as usize >= MAX_TAGS {
+        return Err(validation_error(&format!(
+            "a printer can have at most {MAX_TAGS} tags"
+        )));
+    }
+    sqlx::query("INSERT INTO printer_tags (printer_id, tag_id) VALUES (?, ?)")
+        .bind(printer_id)
+        .bind(tag_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await.context("attach printer tag")
+}
+
+/// Returns `false` when the printer did not carry the tag.
+pub async fn remove_printer_tag(
+    pool: &SqlitePool,
+    printer_id: i64,
+    tag_id: i64,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query("DELETE FROM printer_tags WHERE printer_id = ? AND tag_id = ?")
+        .bind(printer_id)
+        .bind(tag_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Soft delete: the row, its cached state and its notes stay until
+/// `purge_printer`.
 pub async fn delete_printer(pool: &SqlitePool, id: i64) -> anyhow::Result<bool> {
-    let result = sqlx::query("DELETE FROM printers WHERE id = ?")
+    let result =
+        sqlx::query("UPDATE printers SET deleted_at = ? WHERE id = ? AND deleted_at IS NULL")
+            .bind(Utc::now().to_rfc3339())
+            .bind(id)
+            .execute(pool)
+            .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn list_deleted_printers(
+    pool: &SqlitePool,
+    cipher: &SecretCipher,
+) -> anyhow::Result<Vec<DeletedPrinter>> {
+    let rows = sqlx::query(&format!(
+        r#"
+        SELECT {PRINTER_COLUMNS}, deleted_at
+        FROM printers
+        WHERE deleted_at IS NOT NULL
+        ORDER BY deleted_at DESC, id
+        "#
+    ))
+    .fetch_all(pool)
+    .await?;
+    rows.into_iter()
+        .map(|row| {
+            let deleted_at: String = row.try_get("deleted_at")?;
+            let deleted_at = DateTime::parse_from_rfc3339(&deleted_at)
+                .context("parse deleted_at")?
+                .with_timezone(&Utc);
+            Ok(DeletedPrinter {
+                printer: row_to_printer(row, cipher)?,
+                deleted_at,
+            })
+        })
+        .collect()
+}
+
+/// Clears `deleted_at`; returns `None` if the printer is not soft-deleted.
+pub async fn restore_printer(
+    pool: &SqlitePool,
+    cipher: &SecretCipher,
+    id: i64,
+) -> anyhow::Result<Option<PrinterConfig>> {
+    let result = sqlx::query(
+        "UPDATE printers SET deleted_at = NULL WHERE id = ? AND deleted_at IS NOT NULL",
+    )
+    .bind(id)
+    .execute(pool)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Ok(None);
+    }
+    get_printer(pool, cipher, id).await
+}
+
+/// Removes a soft-deleted printer for good, freeing its serial. Returns
+/// `false` if the printer does not exist or was not deleted first.
+pub async fn purge_printer(pool: &SqlitePool, id: i64) -> anyhow::Result<bool> {
+    let result = sqlx::query("DELETE FROM printers WHERE id = ? AND deleted_at IS NOT NULL")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+    sqlx::query("DELETE FROM printer_state_cache WHERE printer_id = ?")
         .bind(id)
         .execute(pool)
         .await?;
+    Ok(true)
+}
+
+pub async fn save_printer_state(
+    pool: &SqlitePool,
+    printer_id: i64,
+    state: &PrinterState,
+) -> anyhow::Result<()> {
+    let state_json = serde_json::to_string(state)?;
+    sqlx::query(
+        r#"
+        INSERT INTO printer_state_cache (printer_id, state_json)
+        VALUES (?, ?)
+        ON CONFLICT(printer_id) DO UPDATE SET state_json = excluded.state_json
+        "#,
+    )
+    .bind(printer_id)
+    .bind(state_json)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Loads the last persisted snapshot. Restored state is never live, so it is
+/// always returned as disconnected until the printer reports again.
+pub async fn load_printer_state(
+    pool: &SqlitePool,
+    printer_id: i64,
+) -> anyhow::Result<Option<PrinterState>> {
+    let row = sqlx::query("SELECT state_json FROM printer_state_cache WHERE printer_id = ?")
+        .bind(printer_id)
+        .fetch_optional(pool)
+        .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let state_json: String = row.get("state_json");
+    let mut state: PrinterState =
+        serde_json::from_str(&state_json).context("parse cached printer state")?;
+    state.connected = false;
+    state.command_queue_depth = 0;
+    state.video_status = VideoStatus::default();
+    state.fps = None;
+    Ok(Some(state))
+}
+
+/// Notes on a printer, newest first.
+pub async fn list_printer_notes(
+    pool: &SqlitePool,
+    printer_id: i64,
+) -> anyhow::Result<Vec<PrinterNote>> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, printer_id, author, body, created_at
+        FROM printer_notes
+        WHERE printer_id = ?
+        ORDER BY created_at DESC, id DESC
+        "#,
+    )
+    .bind(printer_id)
+    .fetch_all(pool)
+    .await?;
+    rows.into_iter().map(row_to_note).collect()
+}
+
+pub async fn create_printer_note(
+    pool: &SqlitePool,
+    printer_id: i64,
+    author: &str,
+    text: &str,
+) -> anyhow::Result<PrinterNote> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(validation_error("note text is required"));
+    }
+    if text.chars().count() > MAX_NOTE_LEN {
+        return Err(validation_error(&format!(
+            "notes must be at most {MAX_NOTE_LEN} characters"
+        )));
+    }
+    let created_at = Utc::now();
+    let result = sqlx::query(
+        "INSERT INTO printer_notes (printer_id, author, body, created_at) VALUES (?, ?, ?, ?)",
+    )
+    .bind(printer_id)
+    .bind(author)
+    .bind(text)
+    .bind(created_at.to_rfc3339())
+    .execute(pool)
+    .await
+    .context("insert printer note")?;
+    Ok(PrinterNote {
+        id: result.last_insert_rowid(),
+        printer_id,
+        author: author.to_string(),
+        text: text.to_string(),
+        created_at,
+    })
+}
+
+/// Returns `false` when the note does not exist on this printer.
+pub async fn delete_printer_note(
+    pool: &SqlitePool,
+    printer_id: i64,
+    note_id: i64,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query("DELETE FROM printer_notes WHERE id = ? AND printer_id = ?")
+        .bind(note_id)
+        .bind(printer_id)
+        .execute(pool)
+        .await?;
     Ok(result.rows_affected() > 0)
 }
 
+fn row_to_tag(row: SqliteRow) -> Tag {
+    Tag {
+        id: row.get("id"),
+        name: row.get("name"),
+        printer_count: row.get("printer_count"),
+    }
+}
+
+fn row_to_note(row: SqliteRow) -> anyhow::Result<PrinterNote> {
+    let created_at: String = row.try_get("created_at")?;
+    Ok(PrinterNote {
+        id: row.try_get("id")?,
+        printer_id: row.try_get("printer_id")?,
+        author: row.try_get("author")?,
+        text: row.try_get("body")?,
+        created_at: DateTime::parse_from_rfc3339(&created_at)
+            .context("parse note created_at")?
+            .with_timezone(&Utc),
+    })
+}
+
+pub async fn record_metrics(
+    pool: &SqlitePool,
+    printer_id: i64,
+    sample: &MetricsSample,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO printer_metrics (printer_id, sampled_at, nozzle_c, bed_c, chamber_c, percent)
+        VALUES (?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(printer_id)
+    .bind(metrics_timestamp(sample.sampled_at))
+    .bind(sample.nozzle_c)
+    .bind(sample.bed_c)
+    .bind(sample.chamber_c)
+    .bind(sample.percent)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Samples in `[from, to)` grouped into `interval_secs` buckets aligned to
+/// the Unix epoch. Empty buckets are omitted.
+pub async fn aggregate_metrics(
+    pool: &SqlitePool,
+    printer_id: i64,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    interval_secs: i64,
+) -> anyhow::Result<Vec<MetricsBucket>> {
+    let rows = sqlx::query(
+        r#"
+        SELECT CAST(strftime('%s', sampled_at) AS INTEGER) / ?1 * ?1 AS bucket,
+               AVG(nozzle_c) AS nozzle_c, AVG(bed_c) AS bed_c, AVG(chamber_c) AS chamber_c,
+               MAX(percent) AS percent, COUNT(*) AS samples
+        FROM printer_metrics
+        WHERE printer_id = ?2 AND sampled_at >= ?3 AND sampled_at < ?4
+        GROUP BY bucket
+        ORDER BY bucket
+        "#,
+    )
+    .bind(interval_secs)
+    .bind(printer_id)
+    .bind(metrics_timestamp(from))
+    .bind(metrics_timestamp(to))
+    .fetch_all(pool)
+    .await?;
+    rows.into_iter()
+        .map(|row| {
+            let bucket: i64 = row.try_get("bucket")?;
+            let start = Utc
+                .timestamp_opt(bucket, 0)
+                .single()
+                .context("metrics bucket out of range")?;
+            let percent: Option<i64> = row.try_get("percent")?;
+            Ok(MetricsBucket {
+                start,
+                nozzle_c: row.try_get("nozzle_c")?,
+                bed_c: row.try_get("bed_c")?,
+                chamber_c: row.try_get("chamber_c")?,
+                percent: percent.and_then(|percent| u8::try_from(percent).ok()),
+                samples: row.try_get("samples")?,
+            })
+        })
+        .collect()
+}
+
+/// Deletes samples older than `cutoff`; returns how many were removed.
+pub async fn delete_metrics_before(
+    pool: &SqlitePool,
+    cutoff: DateTime<Utc>,
+) -> anyhow::Result<u64> {
+    let result = sqlx::query("DELETE FROM printer_metrics WHERE sampled_at < ?")
+        .bind(metrics_timestamp(cutoff))
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Fixed-width UTC timestamps, so range filters can compare them as text.
+fn metrics_timestamp(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Re-writes any plaintext access codes with the configured key. Returns the
+/// number of rows migrated; a no-op when encryption is disabled.
+pub async fn encrypt_plaintext_secrets(
+    pool: &SqlitePool,
+    cipher: &SecretCipher,
+) -> anyhow::Result<usize> {
+    if !cipher.is_enabled() {
+        return Ok(0);
+    }
+    let rows = sqlx::query("SELECT id, access_code FROM printers")
+        .fetch_all(pool)
+        .await?;
+    let mut migrated = 0;
+    for row in rows {
+        let id: i64 = row.get("id");
+        let access_code: String = row.get("access_code");
+        if SecretCipher::is_encrypted(&access_code) {
+            continue;
+        }
+        sqlx::query("UPDATE printers SET access_code = ? WHERE id = ?")
+            .bind(cipher.encrypt(&access_code)?)
+            .bind(id)
+            .execute(pool)
+            .await
+            .context("encrypt access code")?;
+        migrated += 1;
+    }
+    Ok(migrated)
+}
+
+fn validation_error(message: &str) -> anyhow::Error {
+    anyhow::Error::new(ValidationError(message.to_string()))
+}
+
 fn validate_printer_fields(
     name: &str,
     host: &str,
@@ -175,28 +957,117 @@ fn validate_printer_fields(
     access_code: &str,
 ) -> anyhow::Result<()> {
     if name.trim().is_empty() {
-        return Err(anyhow::anyhow!("printer name is required"));
+        return Err(validation_error("printer name is required"));
     }
     if host.trim().is_empty() {
-        return Err(anyhow::anyhow!("printer host is required"));
+        return Err(validation_error("printer host is required"));
     }
+    validate_host(host.trim())?;
     if serial.trim().is_empty() {
-        return Err(anyhow::anyhow!("printer serial is required"));
+        return Err(validation_error("printer serial is required"));
     }
     if access_code.trim().is_empty() {
-        return Err(anyhow::anyhow!("printer access code is required"));
+        return Err(validation_error("printer access code is required"));
     }
     Ok(())
 }
 
-fn row_to_printer(row: SqliteRow) -> PrinterConfig {
-    PrinterConfig {
-        id: row.get("id"),
+/// The username ends up in RTSP `Authorization` headers and Basic
+/// credentials, so quotes, colons and line breaks are refused.
+fn validate_rtsp_username(username: Option<&str>) -> anyhow::Result<()> {
+    if username.is_some_and(|name| name.chars().any(|c| c == '"' || c == ':' || c.is_control())) {
+        return Err(validation_error(
+            "rtsp username must not contain quotes, colons or control characters",
+        ));
+    }
+    Ok(())
+}
+
+/// Accepts hostnames, IPv4, and IPv6 literals with or without brackets.
+fn validate_host(host: &str) -> anyhow::Result<()> {
+    if host.starts_with('[') || host.ends_with(']') {
+        let inner = strip_ipv6_brackets(host);
+        if inner == host || inner.parse::<Ipv6Addr>().is_err() {
+            return Err(validation_error("printer host is not a valid IPv6 address"));
+        }
+        return Ok(());
+    }
+    if host.contains(':') && host.parse::<Ipv6Addr>().is_err() {
+        return Err(validation_error("printer host must not include a port"));
+    }
+    Ok(())
+}
+
+fn row_to_printer(row: SqliteRow, cipher: &SecretCipher) -> anyhow::Result<PrinterConfig> {
+    let id: i64 = row.get("id");
+    let stored_access_code: String = row.get("access_code");
+    let access_code = cipher
+        .decrypt(&stored_access_code)
+        .with_context(|| format!("decrypt access code for printer {id}"))?;
+    let printer_config: Option<String> = row.get("printer_config");
+    let overrides = printer_config
+        .map(|raw| serde_json::from_str::<PrinterOverrides>(&raw))
+        .transpose()
+        .with_context(|| format!("parse printer_config for printer {id}"))?
+        .filter(|overrides| !overrides.is_empty());
+    let tags: String = row.get("tags");
+    let mut tags: Vec<String> =
+        serde_json::from_str(&tags).with_context(|| format!("parse tags for printer {id}"))?;
+    tags.sort_by_key(|tag| tag.to_lowercase());
+    Ok(PrinterConfig {
+        id,
         name: row.get("name"),
         host: row.get("host"),
         serial: row.get("serial"),
-        access_code: row.get("access_code"),
+        access_code,
         rtsp_url: row.get("rtsp_url"),
+        overrides,
+        tags,
+        mqtt_user_id: row.get("mqtt_user_id"),
+        rtsp_username: row.get("rtsp_username"),
+    })
+}
+
+/// Trims tags and drops empty or case-insensitively repeated ones.
+fn normalize_tags(tags: Vec<String>) -> anyhow::Result<Vec<String>> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        if tag.trim().is_empty() {
+            continue;
+        }
+        let tag = normalize_tag(&tag)?;
+        if !normalized
+            .iter()
+            .any(|existing| existing.eq_ignore_ascii_case(&tag))
+        {
+            normalized.push(tag);
+        }
+    }
+    if normalized.len() > MAX_TAGS {
+        return Err(validation_error(&format!(
+            "a printer can have at most {MAX_TAGS} tags"
+        )));
+    }
+    Ok(normalized)
+}
+
+fn normalize_tag(tag: &str) -> anyhow::Result<String> {
+    let tag = tag.trim();
+    if tag.is_empty() {
+        return Err(validation_error("tag name is required"));
+    }
+    if tag.chars().count() > MAX_TAG_LEN {
+        return Err(validation_error(&format!(
+            "tags must be at most {MAX_TAG_LEN} characters"
+        )));
+    }
+    Ok(tag.to_string())
+}
+
+fn encode_overrides(overrides: Option<&PrinterOverrides>) -> anyhow::Result<Option<String>> {
+    match overrides.filter(|value| !value.is_empty()) {
+        Some(overrides) => Ok(Some(serde_json::to_string(overrides)?)),
+        None => Ok(None),
     }
 }
 
@@ -243,3 +1114,401 @@ fn sqlite_path_from_url(database_url: &str) -> Option<PathBuf> {
 fn strip_query(value: &str) -> &str {
     value.split('?').next().unwrap_or(value)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn temp_pool() -> (SqlitePool, PathBuf) {
+        let path = std::env::temp_dir().join(format!(
+            "bambu-lan-viewer-test-{}.db",
+            rand::random::<u64>()
+        ));
+        let pool = init(&format!("sqlite://{}", path.display()))
+            .await
+            .expect("init db");
+        (pool, path)
+    }
+
+    #[test]
+    fn host_validation_accepts_ipv6_literals() {
+        assert!(validate_host("[fd00::1]").is_ok());
+        assert!(validate_host("fd00::1").is_ok());
+        assert!(validate_host("192.168.1.20").is_ok());
+        assert!(validate_host("printer.lan").is_ok());
+        assert!(validate_host("[fd00::1").is_err());
+        assert!(validate_host("[printer.lan]").is_err());
+        assert!(validate_host("192.168.1.20:8883").is_err());
+    }
+
+    #[tokio::test]
+    async fn tags_are_normalized_and_filterable() {
+        let (pool, path) = temp_pool().await;
+        let cipher = SecretCipher::new(None);
+        let create = |name: &str, serial: &str, tags: &[&str]| PrinterCreateRequest {
+            name: name.to_string(),
+            host: "192.168.1.20".to_string(),
+            serial: serial.to_string(),
+            access_code: "12345678".to_string(),
+            rtsp_url: None,
+            overrides: None,
+            tags: Some(tags.iter().map(|tag| tag.to_string()).collect()),
+            mqtt_user_id: None,
+            rtsp_username: None,
+        };
+        let first = create_printer(
+            &pool,
+            &cipher,
+            create(
+                "X1C",
+                "01S00A000000000",
+                &["room-a", " Room-A ", "", "owner:sam"],
+            ),
+        )
+        .await
+        .expect("create printer");
+        assert_eq!(first.tags, ["owner:sam", "room-a"]);
+        create_printer(
+            &pool,
+            &cipher,
+            create("P1S", "01P00A000000000", &["ROOM-A"]),
+        )
+        .await
+        .expect("create printer");
+        let too_long = "x".repeat(MAX_TAG_LEN + 1);
+        assert!(create_printer(
+            &pool,
+            &cipher,
+            create("A1", "03900A000000000", &[too_long.as_str()])
+        )
+        .await
+        .is_err());
+
+        // Tags match case-insensitively and reuse the first spelling.
+        let (printers, total) = search_printers(&pool, &cipher, None, Some("Room-A"), None, 0)
+            .await
+            .expect("search");
+        assert_eq!(total, 2);
+        assert_eq!(printers[0].tags, ["room-a"]);
+        let tag_counts = |tags: Vec<Tag>| {
+            tags.into_iter()
+                .map(|tag| (tag.name, tag.printer_count))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            tag_counts(list_tags(&pool).await.expect("tags")),
+            [("owner:sam".to_string(), 1), ("room-a".to_string(), 2)]
+        );
+
+        let updated = update_printer(
+            &pool,
+            &cipher,
+            first.id,
+            PrinterUpdateRequest {
+                name: None,
+                host: None,
+                serial: None,
+                access_code: None,
+                rtsp_url: None,
+                overrides: None,
+                tags: Some(vec!["room-b".to_string()]),
+                mqtt_user_id: None,
+                rtsp_username: None,
+            },
+        )
+        .await
+        .expect("update")
+        .expect("updated printer");
+        assert_eq!(updated.tags, ["room-b"]);
+        let (printers, _) = search_printers(&pool, &cipher, None, Some("room-b"), None, 0)
+            .await
+            .expect("search");
+        assert_eq!(printers.len(), 1);
+        assert_eq!(printers[0].id, first.id);
+
+        // Deleted printers no longer count towards their tags.
+        delete_printer(&pool, first.id).await.expect("delete");
+        assert_eq!(
+            tag_counts(list_tags(&pool).await.expect("tags")),
+            [
+                ("owner:sam".to_string(), 0),
+                ("room-a".to_string(), 1),
+                ("room-b".to_string(), 0)
+            ]
+        );
+
+        pool.close().await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[tokio::test]
+    async fn metrics_are_bucketed_and_expire() {
+        let (pool, path) = temp_pool().await;
+        let cipher = SecretCipher::new(None);
+        let printer = create_printer(
+            &pool,
+            &cipher,
+            PrinterCreateRequest {
+                name: "X1C".to_string(),
+                host: "192.168.1.20".to_string(),
+                serial: "01S00A000000000".to_string(),
+                access_code: "12345678".to_string(),
+                rtsp_url: None,
+                overrides: None,
+                tags: None,
+                mqtt_user_id: None,
+                rtsp_username: None,
+            },
+        )
+        .await
+        .expect("create printer");
+        let start = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        for (offset_secs, nozzle_c, percent) in [(0, 200.0, 10), (30, 210.0, 12), (61, 220.0, 15)] {
+            let sample = MetricsSample {
+                sampled_at: start + chrono::Duration::seconds(offset_secs),
+                nozzle_c: Some(nozzle_c),
+                bed_c: Some(60.0),
+                chamber_c: None,
+                percent: Some(percent),
+            };
+            record_metrics(&pool, printer.id, &sample)
+                .await
+                .expect("record");
+        }
+
+        let buckets = aggregate_metrics(
+            &pool,
+            printer.id,
+            start,
+            start + chrono::Duration::hours(1),
+            60,
+        )
+        .await
+        .expect("aggregate");
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[0].start, start);
+        assert_eq!(buckets[0].nozzle_c, Some(205.0));
+        assert_eq!(buckets[0].bed_c, Some(60.0));
+        assert_eq!(buckets[0].chamber_c, None);
+        assert_eq!(buckets[0].percent, Some(12));
+        assert_eq!(buckets[0].samples, 2);
+        assert_eq!(buckets[1].start, start + chrono::Duration::seconds(60));
+
+        let window = aggregate_metrics(
+            &pool,
+            printer.id,
+            start + chrono::Duration::seconds(30),
+            start + chrono::Duration::seconds(61),
+            3600,
+        )
+        .await
+        .expect("aggregate");
+        assert_eq!(window.len(), 1);
+        assert_eq!(window[0].samples, 1);
+
+        let removed = delete_metrics_before(&pool, start + chrono::Duration::seconds(60))
+            .await
+            .expect("expire");
+        assert_eq!(removed, 2);
+
+        pool.close().await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[tokio::test]
+    async fn replace_clears_omitted_optional_fields() {
+        let (pool, path) = temp_pool().await;
+        let cipher = SecretCipher::new(None);
+        let printer = create_printer(
+            &pool,
+            &cipher,
+            PrinterCreateRequest {
+                name: "X1C".to_string(),
+                host: "192.168.1.20".to_string(),
+                serial: "01S00A000000000".to_string(),
+                access_code: "12345678".to_string(),
+                rtsp_url: Some("rtsps://192.168.1.20:322/streaming/live/1".to_string()),
+                overrides: None,
+                tags: None,
+                mqtt_user_id: Some(" 4242 ".to_string()),
+                rtsp_username: Some("viewer".to_string()),
+            },
+        )
+        .await
+        .expect("create printer");
+        assert_eq!(printer.mqtt_user_id.as_deref(), Some("4242"));
+
+        let patched = update_printer(
+            &pool,
+            &cipher,
+            printer.id,
+            PrinterUpdateRequest {
+                name: Some("Workshop".to_string()),
+                host: None,
+                serial: None,
+                access_code: None,
+                rtsp_url: None,
+                overrides: None,
+                tags: None,
+                mqtt_user_id: None,
+                rtsp_username: None,
+            },
+        )
+        .await
+        .expect("patch")
+        .expect("patched printer");
+        assert_eq!(patched.name, "Workshop");
+        assert_eq!(patched.rtsp_url, printer.rtsp_url);
+        assert_eq!(patched.mqtt_user_id, printer.mqtt_user_id);
+        assert_eq!(patched.resolved_rtsp_username(), "viewer");
+        for bad in ["a:b", "a\"b", "a\r\nCSeq: 9"] {
+            let error = update_printer(
+                &pool,
+                &cipher,
+                printer.id,
+                PrinterUpdateRequest {
+                    rtsp_username: Some(bad.to_string()),
+                    ..PrinterUpdateRequest::default()
+                },
+            )
+            .await
+            .expect_err("invalid rtsp username");
+            assert!(error.downcast_ref::<ValidationError>().is_some());
+        }
+
+        let replaced = update_printer(
+            &pool,
+            &cipher,
+            printer.id,
+            PrinterReplaceRequest {
+                name: "P1S".to_string(),
+                host: "192.168.1.21".to_string(),
+                serial: "01P00A000000000".to_string(),
+                access_code: "87654321".to_string(),
+                rtsp_url: None,
+                overrides: None,
+                tags: None,
+                mqtt_user_id: None,
+                rtsp_username: None,
+            }
+            .into(),
+        )
+        .await
+        .expect("replace")
+        .expect("replaced printer");
+        assert_eq!(replaced.name, "P1S");
+        assert_eq!(replaced.rtsp_url, None);
+        assert_eq!(replaced.mqtt_user_id, None);
+        assert_eq!(replaced.resolved_rtsp_username(), "bblp");
+        assert_eq!(
+            get_printer(&pool, &cipher, printer.id)
+                .await
+                .expect("get printer")
+                .expect("printer")
+                .rtsp_url,
+            None
+        );
+
+        pool.close().await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[tokio::test]
+    async fn persisted_state_is_restored_as_disconnected() {
+        let (pool, path) = temp_pool().await;
+        let cipher = SecretCipher::new(None);
+        let printer = create_printer(
+            &pool,
+            &cipher,
+            PrinterCreateRequest {
+                name: "X1C".to_string(),
+                host: "192.168.1.20".to_string(),
+                serial: "01S00A000000000".to_string(),
+                access_code: "12345678".to_string(),
+                rtsp_url: None,
+                overrides: None,
+                tags: None,
+                mqtt_user_id: None,
+                rtsp_username: None,
+            },
+        )
+        .await
+        .expect("create printer");
+
+        let state = PrinterState {
+            connected: true,
+            job_state: Some("RUNNING".to_string()),
+            percent: Some(42),
+            nozzle_c: Some(219.5),
+            rtsp_url: Some("rtsps://192.168.1.20:322/streaming/live/1".to_string()),
+            print_started_at: Some(chrono::Utc::now() - chrono::Duration::minutes(5)),
+            last_update: Some(chrono::Utc::now()),
+            ..PrinterState::default()
+        };
+        save_printer_state(&pool, printer.id, &state)
+            .await
+            .expect("save state");
+
+        let restored = load_printer_state(&pool, printer.id)
+            .await
+            .expect("load state")
+            .expect("cached state");
+        assert!(!restored.connected);
+        assert_eq!(restored.job_state.as_deref(), Some("RUNNING"));
+        assert_eq!(restored.percent, Some(42));
+        assert_eq!(restored.nozzle_c, Some(219.5));
+        assert_eq!(restored.rtsp_url, state.rtsp_url);
+        assert_eq!(restored.print_started_at, state.print_started_at);
+        assert_eq!(restored.last_update, state.last_update);
+
+        assert!(delete_printer(&pool, printer.id).await.expect("delete"));
+        assert!(load_printer_state(&pool, printer.id)
+            .await
+            .expect("load state")
+            .is_some());
+        assert!(get_printer(&pool, &cipher, printer.id)
+            .await
+            .expect("get printer")
+            .is_none());
+        assert!(!delete_printer(&pool, printer.id).await.expect("delete"));
+
+        let deleted = list_deleted_printers(&pool, &cipher)
+            .await
+            .expect("list deleted");
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].printer.serial, printer.serial);
+        let restored = restore_printer(&pool, &cipher, printer.id)
+            .await
+            .expect("restore")
+            .expect("restored printer");
+        assert_eq!(restored.access_code, printer.access_code);
+        assert_eq!(list_printers(&pool, &cipher).await.expect("list").len(), 1);
+        assert!(restore_printer(&pool, &cipher, printer.id)
+            .await
+            .expect("restore")
+            .is_none());
+
+        assert!(!purge_printer(&pool, printer.id).await.expect("purge"));
+        assert!(delete_printer(&pool, printer.id).await.expect("delete"));
+        assert!(purge_printer(&pool, printer.id).await.expect("purge"));
+        assert!(load_printer_state(&pool, printer.id)
+            .await
+            .expect("load state")
+            .is_none());
+        assert!(list_deleted_printers(&pool, &cipher)
+            .await
+            .expect("list deleted")
+            .is_empty());
+
+        pool.close().await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Eviction frees space down to this fraction of the cap, so one new segment
+/// does not immediately trigger the next pass.
+const EVICT_TARGET: f64 = 0.9;
+const MEDIA_EXTENSIONS: [&str; 3] = ["m4s", "mp4", "ts"];
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Eviction {
+    pub total_bytes: u64,
+    pub evicted_files: usize,
+    pub evicted_bytes: u64,
+}
+
+/// Keeps everything under `dir` below `max_bytes` until shutdown.
+pub async fn run(dir: PathBuf, max_bytes: u64, shutdown: CancellationToken) {
+    loop {
+        let check_dir = dir.clone();
+        match tokio::task::spawn_blocking(move || enforce(&check_dir, max_bytes)).await {
+            Ok(Ok(eviction)) if eviction.evicted_files > 0 => warn!(
+                total_bytes = eviction.total_bytes,
+                max_bytes,
+                evicted_files = eviction.evicted_files,
+                evicted_bytes = eviction.evicted_bytes,
+                "output directory over its disk cap; evicted oldest media"
+            ),
+            Ok(Ok(_)) => {}
+            Ok(Err(error)) => warn!(?error, "failed to check output directory usage"),
+            Err(error) => warn!(?error, "output directory check panicked"),
+        }
+        tokio::select! {
+            _ = shutdown.cancelled() => return,
+            _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+        }
+    }
+}
+
+/// One pass: when usage exceeds `max_bytes`, deletes the oldest media files
+/// that are not part of a live window. A live window is everything a
+/// directory's playlist references plus its init segment and the newest
+/// media file, which is still being written.
+pub fn enforce(dir: &Path, max_bytes: u64) -> io::Result<Eviction> {
+    let mut files = Vec::new();
+    let mut protected = HashSet::new();
+    walk(dir, &mut files, &mut protected)?;
+    let total_bytes: u64 = files.iter().map(|(_, len, _)| len).sum();
+    let mut eviction = Eviction {
+        total_bytes,
+        ..Eviction::default()
+    };
+    if total_bytes <= max_bytes {
+        return Ok(eviction);
+    }
+
+    let target = (max_bytes as f64 * EVICT_TARGET) as u64;
+    let mut candidates: Vec<_> = files
+        .into_iter()
+        .filter(|(path, _, _)| is_media(path) && !protected.contains(path))
+        .collect();
+    candidates.sort_by_key(|(_, _, modified)| *modified);
+    let mut remaining = total_bytes;
+    for (path, len, _) in candidates {
+        if remaining <= target {
+            break;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                remaining -= len;
+                eviction.evicted_files += 1;
+                eviction.evicted_bytes += len;
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => remaining -= len,
+            Err(error) => return Err(error),
+        }
+    }
+    if remaining > max_bytes {
+        warn!(
+            remaining,
+            max_bytes, "live windows alone exceed the output disk cap"
+        );
+    }
+    Ok(eviction)
+}
+
+fn walk(
+    dir: &Path,
+    files: &mut Vec<(PathBuf, u64, SystemTime)>,
+    protected: &mut HashSet<PathBuf>,
+) -> io::Result<()> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error),
+    };
+    let mut newest_media: Option<(SystemTime, PathBuf)> = None;
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            walk(&path, files, protected)?;
+            continue;
+        }
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        if path.extension().is_some_and(|ext| ext == "m3u8") {
+            if let Ok(playlist) = std::fs::read_to_string(&path) {
+                protected.extend(playlist_uris(&playlist).map(|uri| dir.join(uri)));
+            }
+        }
+        if path.file_name().is_some_and(|name| name == "init.mp4") {
+            protected.insert(path.clone());
+        } else if is_media(&path)
+            && newest_media
+                .as_ref()
+                .is_none_or(|(newest, _)| modified > *newest)
+        {
+            newest_media = Some((modified, path.clone()));
+        }
+        files.push((path, metadata.len(), modified));
+    }
+    if let Some((_, path)) = newest_media {
+        protected.insert(path);
+    }
+    Ok(())
+}
+
+/// Segment lines and `URI="..."` attributes (parts, preload hints, keys).
+fn playlist_uris(playlist: &str) -> impl Iterator<Item = &str> {
+    playlist.lines().flat_map(|line| {
+        let line = line.trim();
+        let uri = if line.is_empty() {
+            None
+        } else if line.starts_with('#') {
+            line.split_once("URI=\"")
+                .and_then(|(_, rest)| rest.split_once('"'))
+                .map(|(uri, _)| uri)
+        } else {
+            Some(line)
+        };
+        uri.filter(|uri| !uri.contains("://"))
+    })
+}
+
+fn is_media(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| MEDIA_EXTENSIONS.contains(&ext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_aged(path: &Path, len: usize, age_secs: u64) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, vec![0u8; len]).unwrap();
+        let modified = SystemTime::now() - Duration::from_secs(age_secs);
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    #[test]
+    fn over_cap_usage_evicts_oldest_segments_outside_the_live_window() {
+        let root = std::env::temp_dir().join(format!("disk-limit-{}", rand::random::<u64>()));
+        let live = root.join("1");
+        write_aged(&live.join("init.mp4"), 100, 600);
+        for (index, age) in [(0, 500), (1, 400), (2, 300), (3, 200), (4, 10)] {
+            write_aged(&live.join(format!("seg{index}.m4s")), 1_000, age);
+        }
+        std::fs::write(
+            live.join("stream.m3u8"),
+            "#EXTM3U\n#EXT-X-PART:DURATION=0.333,URI=\"seg2.m4s\"\n#EXTINF:2.000,\nseg2.m4s\n#EXTINF:2.000,\nseg3.m4s\n",
+        )
+        .unwrap();
+        // A stale recording from a printer directory without a playlist.
+        write_aged(&root.join("2/old.mp4"), 1_000, 900);
+        write_aged(&root.join("2/new.mp4"), 1_000, 5);
+
+        let eviction = enforce(&root, 5_000).expect("enforce");
+
+        assert!(eviction.total_bytes > 7_000);
+        assert_eq!(eviction.evicted_files, 3);
+        assert!(!root.join("2/old.mp4").exists());
+        assert!(!live.join("seg0.m4s").exists());
+        assert!(!live.join("seg1.m4s").exists());
+        for kept in [
+            "init.mp4",
+            "seg2.m4s",
+            "seg3.m4s",
+            "seg4.m4s",
+            "stream.m3u8",
+        ] {
+            assert!(live.join(kept).exists(), "{kept} evicted");
+        }
+        assert!(root.join("2/new.mp4").exists());
+
+        // Under the cap nothing is touched.
+        let eviction = enforce(&root, 1_000_000).expect("enforce");
+        assert_eq!(eviction.evicted_files, 0);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
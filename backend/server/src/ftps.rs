@@ -0,0 +1,475 @@
+use crate::tls;
+use anyhow::Context;
+use async_stream::stream;
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use futures_core::Stream;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+/// Bambu printers serve their SD card over implicit FTPS only.
+const FTPS_PORT: u16 = 990;
+const FTPS_USER: &str = "bblp";
+const FTPS_TIMEOUT: Duration = Duration::from_secs(15);
+pub const STORAGE_DIRS: &[&str] = &["/model", "/timelapse", "/cache"];
+const DOWNLOAD_CHUNK_BYTES: usize = 64 * 1024;
+
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteFile {
+    pub directory: String,
+    pub name: String,
+    pub path: String,
+    pub size: u64,
+    pub modified: Option<DateTime<Utc>>,
+}
+
+/// A logged-in control connection. Each data transfer opens its own passive
+/// connection, protected with the same TLS config so the session is resumed
+/// (the printer refuses data connections that do not resume it).
+pub struct FtpsClient {
+    host: String,
+    connector: TlsConnector,
+    control: BufReader<TlsStream<TcpStream>>,
+}
+
+impl FtpsClient {
+    pub async fn connect(
+        host: &str,
+        access_code: &str,
+        tls_insecure: bool,
+    ) -> anyhow::Result<Self> {
+        let tls_config = if tls_insecure {
+            tls::insecure_client_config()
+        } else {
+            rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(rustls::RootCertStore::empty())
+                .with_no_client_auth()
+        };
+        let connector = TlsConnector::from(Arc::new(tls_config));
+        let stream = tokio::time::timeout(FTPS_TIMEOUT, TcpStream::connect((host, FTPS_PORT)))
+            .await
+            .context("ftps connect timed out")?
+            .context("ftps connect")?;
+        let stream = connector
+            .connect(tls::server_name(host)?, stream)
+            .await
+            .context("ftps tls handshake")?;
+
+        let mut client = Self {
+            host: host.to_string(),
+            connector,
+            control: BufReader::new(stream),
+        };
+        client.expect_reply(&[220]).await?;
+        client.command(&format!("USER {FTPS_USER}"), &[331]).await?;
+        client
+            .command(&format!("PASS {access_code}"), &[230])
+            .await?;
+        client.command("PBSZ 0", &[200]).await?;
+        client.command("PROT P", &[200]).await?;
+        client.command("TYPE I", &[200]).await?;
+        Ok(client)
+    }
+
+    /// Files (not subdirectories) in `dir`.
+    pub async fn list(&mut self, dir: &str) -> anyhow::Result<Vec<RemoteFile>> {
+        let listing = self.transfer(&format!("LIST {dir}")).await?;
+        let now = Utc::now();
+        Ok(String::from_utf8_lossy(&listing)
+            .lines()
+            .filter_map(|line| parse_list_line(line, now))
+            .filter(|entry| !entry.is_dir)
+            .map(|entry| RemoteFile {
+                directory: dir.to_string(),
+                path: format!("{}/{}", dir.trim_end_matches('/'), entry.name),
+                name: entry.name,
+                size: entry.size,
+                modified: entry.modified,
+            })
+            .collect())
+    }
+
+    pub async fn quit(mut self) {
+        let _ = self.send("QUIT").await;
+    }
+
+    /// Size of `path` in bytes.
+    pub async fn size(&mut self, path: &str) -> anyhow::Result<u64> {
+        let reply = self.command(&format!("SIZE {path}"), &[213]).await?;
+        reply
+            .get(4..)
+            .and_then(|size| size.trim().parse().ok())
+            .ok_or_else(|| anyhow::anyhow!("unexpected SIZE reply: {}", reply.trim()))
+    }
+
+    /// Streams `len` bytes of `path` starting at `offset`. The control
+    /// connection is owned by the stream and closed when it ends.
+    pub async fn retrieve(
+        mut self,
+        path: &str,
+        offset: u64,
+        len: u64,
+    ) -> anyhow::Result<impl Stream<Item = std::io::Result<Bytes>>> {
+        if offset > 0 {
+            self.command(&format!("REST {offset}"), &[350]).await?;
+        }
+        let data = self.open_data(&format!("RETR {path}")).await?;
+        Ok(stream! {
+            let mut data = data.take(len);
+            loop {
+                let mut chunk = BytesMut::with_capacity(DOWNLOAD_CHUNK_BYTES);
+                // A stalled printer would otherwise hold the download open forever.
+                let Ok(read) = tokio::time::timeout(FTPS_TIMEOUT, data.read_buf(&mut chunk)).await
+                else {
+                    yield Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "ftps data transfer timed out",
+                    ));
+                    break;
+                };
+                match read {
+                    Ok(0) => break,
+                    Ok(_) => yield Ok(chunk.freeze()),
+                    Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                    Err(error) => {
+                        yield Err(error);
+                        break;
+                    }
+                }
+            }
+            // Partial reads leave the transfer unfinished; closing both
+            // connections aborts it on the printer.
+            drop(data);
+            self.quit().await;
+        })
+    }
+
+    async fn transfer(&mut self, command: &str) -> anyhow::Result<Vec<u8>> {
+        let mut data = self.open_data(command).await?;
+        let mut body = Vec::new();
+        let read = tokio::time::timeout(FTPS_TIMEOUT, data.read_to_end(&mut body))
+            .await
+            .context("ftps data transfer timed out")?;
+        // Some servers close the data channel without a TLS close_notify.
+        if let Err(error) = read {
+            if error.kind() != std::io::ErrorKind::UnexpectedEof {
+                return Err(error).context("ftps data read");
+            }
+        }
+        drop(data);
+        self.expect_reply(&[226, 250]).await?;
+        Ok(body)
+    }
+
+    async fn open_data(&mut self, command: &str) -> anyhow::Result<TlsStream<TcpStream>> {
+        let reply = self.command("PASV", &[227]).await?;
+        // Use the control host rather than the advertised address, which may
+        // be unreachable behind NAT or container networking.
+        let port = parse_pasv_port(&reply)
+            .ok_or_else(|| anyhow::anyhow!("unexpected PASV reply: {reply}"))?;
+        let data = TcpStream::connect((self.host.as_str(), port))
+            .await
+            .context("ftps data connect")?;
+        // The reply comes before the data handshake, so a 550 for a missing
+        // path is seen without waiting on a data channel that never opens.
+        self.command(command, &[125, 150]).await?;
+        let server_name = tls::server_name(&self.host)?;
+        tokio::time::timeout(FTPS_TIMEOUT, self.connector.connect(server_name, data))
+            .await
+            .context("ftps data tls handshake timed out")?
+            .context("ftps data tls handshake")
+    }
+
+    async fn command(&mut self, command: &str, expected: &[u16]) -> anyhow::Result<String> {
+        self.send(command).await?;
+        self.expect_reply(expected).await.with_context(|| {
+            let verb = command.split_whitespace().next().unwrap_or(command);
+            format!("ftps {verb}")
+        })
+    }
+
+    async fn send(&mut self, command: &str) -> anyhow::Result<()> {
+        let stream = self.control.get_mut();
+        stream.write_all(command.as_bytes()).await?;
+        stream.write_all(b"\r\n").await?;
+        stream.flush().await?;
+        Ok(())
+    }
+
+    async fn expect_reply(&mut self, expected: &[u16]) -> anyhow::Result<String> {
+        let (code, text) = tokio::time::timeout(FTPS_TIMEOUT, read_reply(&mut self.control))
+            .await
+            .context("ftps reply timed out")??;
+        if expected.contains(&code) {
+            Ok(text)
+        } else {
+            Err(FtpsReplyError { code, text }.into())
+        }
+    }
+}
+
+/// A reply with an unexpected status code, e.g. 550 for a missing directory.
+#[derive(Debug)]
+pub struct FtpsReplyError {
+    pub code: u16,
+    pub text: String,
+}
+
+impl std::fmt::Display for FtpsReplyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ftps server replied {} {}", self.code, self.text.trim())
+    }
+}
+
+impl std::error::Error for FtpsReplyError {}
+
+/// Lists every storage directory, skipping ones the model does not have.
+pub async fn list_storage(
+    host: &str,
+    access_code: &str,
+    tls_insecure: bool,
+) -> anyhow::Result<Vec<RemoteFile>> {
+    let mut client = FtpsClient::connect(host, access_code, tls_insecure).await?;
+    let mut files = Vec::new();
+    for dir in STORAGE_DIRS {
+        match client.list(dir).await {
+            Ok(mut listed) => files.append(&mut listed),
+            Err(error)
+                if error
+                    .downcast_ref::<FtpsReplyError>()
+                    .is_some_and(|reply| reply.code == 550) =>
+            {
+                tracing::debug!(dir, "printer storage directory missing");
+            }
+            Err(error) => return Err(error),
+        }
+    }
+    client.quit().await;
+    Ok(files)
+}
+
+/// Maps a URL path like `timelapse/video.mp4` to an absolute path inside one
+/// of the storage directories, rejecting traversal and odd characters.
+pub fn storage_path(relative: &str) -> Option<String> {
+    let segments: Vec<&str> = relative.trim_start_matches('/').split('/').collect();
+    let valid = segments.len() >= 2 && segments.iter().all(|segment| valid_segment(segment));
+    let path = format!("/{}", segments.join("/"));
+    let allowed = STORAGE_DIRS
+        .iter()
+        .any(|dir| path.starts_with(dir) && path.as_bytes().get(dir.len()) == Some(&b'/'));
+    (valid && allowed).then_some(path)
+}
+
+/// One path component that is safe to put on an FTP command line: no
+/// traversal, separators or control characters (which would end the command).
+pub fn valid_segment(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment != "."
+        && segment != ".."
+        && !segment
+            .chars()
+            .any(|c| c.is_control() || c == '\\' || c == '/')
+}
+
+/// Reads one (possibly multi-line, `123-...` to `123 ...`) reply.
+async fn read_reply<R: tokio::io::AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> anyhow::Result<(u16, String)> {
+    let mut text = String::new();
+    let mut line = String::new();
+    let mut code: Option<u16> = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            anyhow::bail!("ftps control connection closed");
+        }
+        text.push_str(&line);
+        let status = line.get(..3).and_then(|digits| digits.parse::<u16>().ok());
+        let first = *code.get_or_insert(
+            status.ok_or_else(|| anyhow::anyhow!("malformed ftps reply: {}", line.trim()))?,
+        );
+        if status == Some(first) && line.as_bytes().get(3) != Some(&b'-') {
+            return Ok((first, text));
+        }
+    }
+}
+
+/// Port from `227 Entering Passive Mode (h1,h2,h3,h4,p1,p2)`.
+fn parse_pasv_port(reply: &str) -> Option<u16> {
+    let start = reply.find('(')?;
+    let end = reply[start..].find(')')? + start;
+    let fields: Vec<u16> = reply[start + 1..end]
+        .split(',')
+        .map(|field| field.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    match fields.as_slice() {
+        [_, _, _, _, high, low] if *high < 256 && *low < 256 => Some(high * 256 + low),
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct ListEntry {
+    name: String,
+    size: u64,
+    modified: Option<DateTime<Utc>>,
+    is_dir: bool,
+}
+
+/// Parses a Unix `ls -l` style LIST line:
+/// `-rw-r--r-- 1 root root 1234 Jan 02 12:34 name with spaces.3mf`.
+fn parse_list_line(line: &str, now: DateTime<Utc>) -> Option<ListEntry> {
+    let mut rest = line.trim_end();
+    let mut fields = Vec::with_capacity(8);
+    for _ in 0..8 {
+        rest = rest.trim_start();
+        let end = rest.find(char::is_whitespace)?;
+        fields.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    let name = rest.trim_start();
+    if name.is_empty() || name == "." || name == ".." {
+        return None;
+    }
+    let is_dir = fields[0].starts_with('d');
+    let size = fields[4].parse().ok()?;
+    let modified = parse_list_time(fields[5], fields[6], fields[7], now);
+    Some(ListEntry {
+        name: name.to_string(),
+        size,
+        modified,
+        is_dir,
+    })
+}
+
+/// `Jan 02 12:34` (within the last year) or `Jan 02 2024`.
+fn parse_list_time(
+    month: &str,
+    day: &str,
+    time_or_year: &str,
+    now: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    let month = match month.to_ascii_lowercase().as_str() {
+        "jan" => 1,
+        "feb" => 2,
+        "mar" => 3,
+        "apr" => 4,
+        "may" => 5,
+        "jun" => 6,
+        "jul" => 7,
+        "aug" => 8,
+        "sep" => 9,
+        "oct" => 10,
+        "nov" => 11,
+        "dec" => 12,
+        _ => return None,
+    };
+    let day: u32 = day.parse().ok()?;
+    if let Some((hour, minute)) = time_or_year.split_once(':') {
+        let (hour, minute) = (hour.parse().ok()?, minute.parse().ok()?);
+        let at = |year| {
+            NaiveDate::from_ymd_opt(year, month, day)
+                .and_then(|date| date.and_hms_opt(hour, minute, 0))
+                .map(|naive: NaiveDateTime| Utc.from_utc_datetime(&naive))
+        };
+        let this_year = at(now.year())?;
+        if this_year > now + chrono::Duration::days(1) {
+            at(now.year() - 1)
+        } else {
+            Some(this_year)
+        }
+    } else {
+        let year = time_or_year.parse().ok()?;
+        NaiveDate::from_ymd_opt(year, month, day)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|naive| Utc.from_utc_datetime(&naive))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pasv_reply_yields_data_port() {
+        assert_eq!(
+            parse_pasv_port("227 Entering Passive Mode (192,168,1,20,195,80)."),
+            Some(195 * 256 + 80)
+        );
+        assert_eq!(parse_pasv_port("227 Entering Passive Mode"), None);
+        assert_eq!(parse_pasv_port("227 (1,2,3,4,300,1)"), None);
+    }
+
+    #[test]
+    fn list_lines_parse_files_and_directories() {
+        let now = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        let file = parse_list_line(
+            "-rw-rw-rw-   1 root  root   2345678 Feb 27 09:15 Benchy plate 1.gcode.3mf",
+            now,
+        )
+        .unwrap();
+        assert_eq!(file.name, "Benchy plate 1.gcode.3mf");
+        assert_eq!(file.size, 2_345_678);
+        assert!(!file.is_dir);
+        assert_eq!(
+            file.modified,
+            Some(Utc.with_ymd_and_hms(2026, 2, 27, 9, 15, 0).unwrap())
+        );
+
+        // A time later than now belongs to last year.
+        let old = parse_list_line("-rw-r--r-- 1 root root 10 Dec 30 23:59 old.mp4", now).unwrap();
+        assert_eq!(
+            old.modified,
+            Some(Utc.with_ymd_and_hms(2025, 12, 30, 23, 59, 0).unwrap())
+        );
+
+        let dated = parse_list_line("-rw-r--r-- 1 root root 10 Jun 01 2024 a.3mf", now).unwrap();
+        assert_eq!(
+            dated.modified,
+            Some(Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap())
+        );
+
+        let dir = parse_list_line("drwxr-xr-x 2 root root 4096 Jan 01 00:00 plates", now).unwrap();
+        assert!(dir.is_dir);
+        assert!(parse_list_line("total 12", now).is_none());
+    }
+
+    #[test]
+    fn storage_paths_stay_inside_storage_dirs() {
+        assert_eq!(
+            storage_path("timelapse/video_2026-03-01.mp4").as_deref(),
+            Some("/timelapse/video_2026-03-01.mp4")
+        );
+        assert_eq!(
+            storage_path("/cache/plate 1.3mf").as_deref(),
+            Some("/cache/plate 1.3mf")
+        );
+        assert_eq!(
+            storage_path("model/sub/a.png").as_deref(),
+            Some("/model/sub/a.png")
+        );
+        assert_eq!(storage_path("timelapse/../etc/passwd"), None);
+        assert_eq!(storage_path("timelapse/./a.mp4"), None);
+        assert_eq!(storage_path("timelapse//a.mp4"), None);
+        assert_eq!(storage_path("timelapse"), None);
+        assert_eq!(storage_path("timelapses/a.mp4"), None);
+        assert_eq!(storage_path("etc/passwd"), None);
+        assert_eq!(storage_path("cache/a\r\nDELE x"), None);
+    }
+
+    #[tokio::test]
+    async fn multi_line_replies_are_read_to_the_final_line() {
+        let mut input: &[u8] = b"220-Welcome\r\n220-still welcome\r\n220 Ready\r\n230 ok\r\n";
+        let (code, text) = read_reply(&mut input).await.unwrap();
+        assert_eq!(code, 220);
+        assert!(text.ends_with("220 Ready\r\n"));
+        assert_eq!(read_reply(&mut input).await.unwrap().0, 230);
+    }
+}
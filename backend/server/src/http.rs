@@ -1,86 +1,512 @@
+use crate::auth::{AuthContext, AuthManager};
 use crate::commands::{CommandPayload, CommandRequest};
-use crate::config::AppConfig;
-use crate::db::{self, PrinterCreateRequest, PrinterUpdateRequest};
-use crate::printers::PrinterRuntime;
-use crate::state::PrinterState;
+use crate::config::{AppConfig, PrinterConfig};
+use crate::db::{self, PrinterCreateRequest, PrinterReplaceRequest, PrinterUpdateRequest};
+use crate::ftps::{self, RemoteFile};
+use crate::metrics_history;
+use crate::printers::{PrinterLocks, PrinterRuntime};
+use crate::ratelimit::CommandRateLimiter;
+use crate::secrets::SecretCipher;
+use crate::state::{HmsSeverity, PrinterState};
+use anyhow::Context;
 use async_stream::stream;
+use axum::body::{Body, Bytes, StreamBody};
 use axum::extract::{
     ws::{Message, WebSocket, WebSocketUpgrade},
-    Path, State,
+    Extension, FromRequest, Path, Query, State,
 };
-use axum::http::{header, StatusCode};
+use axum::http::{header, HeaderMap, Method, Request, StatusCode};
+use axum::middleware::{self, Next};
 use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
 use axum::{Json, Router};
-use serde::Serialize;
+use chrono::{DateTime, Utc};
+use metrics_exporter_prometheus::PrometheusHandle;
+use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 use std::collections::HashMap;
 use std::convert::Infallible;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::broadcast;
 use tokio::sync::RwLock;
-use tower_http::cors::{Any, CorsLayer};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::task::{AbortHandle, JoinSet};
+use tokio_util::sync::CancellationToken;
+use tower_http::cors::{AllowHeaders, AllowOrigin, Any, CorsLayer};
+use tower_http::timeout::TimeoutLayer;
+use tracing::Instrument;
+
+const TOTAL_COUNT_HEADER: &str = "x-total-count";
+const RESET_SETTLE_TIMEOUT: Duration = Duration::from_secs(3);
+/// How often the global status stream picks up added, removed or reset printers.
+const STATUS_RESCAN_INTERVAL: Duration = Duration::from_secs(2);
+/// How long a range request past the end of a segment waits for the segmenter
+/// to append the part it names, as LL-HLS preload hints expect.
+const PRELOAD_HINT_WAIT: Duration = Duration::from_secs(5);
+const PRELOAD_HINT_POLL: Duration = Duration::from_millis(50);
 
 #[derive(Clone)]
 pub struct AppState {
     pub config: AppConfig,
     pub db: SqlitePool,
+    pub cipher: SecretCipher,
     pub printers: Arc<RwLock<HashMap<i64, Arc<PrinterRuntime>>>>,
+    pub printer_locks: Arc<PrinterLocks>,
+    pub command_limiter: Arc<CommandRateLimiter>,
+    pub auth: AuthManager,
+    /// Renders `/metrics`; `None` when no recorder is installed.
+    pub metrics: Option<PrometheusHandle>,
+    pub shutdown: CancellationToken,
 }
 
-pub fn router(state: Arc<AppState>) -> Router {
-    let protected = Router::new()
+pub fn router(state: Arc<AppState>) -> anyhow::Result<Router> {
+    // Long-lived SSE and WebSocket routes are kept out of the timeout layer.
+    let streaming = Router::new()
+        .route("/api/printers/:id/status/stream", get(get_status_stream))
+        .route("/api/printers/:id/video/cmaf", get(get_cmaf_stream_ws))
+        .route("/api/status/stream", get(get_all_status_stream))
+        .route("/api/overview/stream", get(get_overview_stream))
+        .route("/api/printers/:id/files/*path", get(download_printer_file));
+    let timed = Router::new()
         .route("/api/printers", get(list_printers).post(create_printer))
+        .route("/api/printers/deleted", get(list_deleted_printers))
+        .route(
+            "/api/printers/deleted/:id",
+            axum::routing::delete(purge_printer),
+        )
+        .route("/api/tags", get(list_tags).post(create_tag))
+        .route("/api/tags/:tag_id", axum::routing::delete(delete_tag))
+        .route("/api/overview", get(get_overview))
         .route(
             "/api/printers/:id",
-            get(get_printer).put(update_printer).delete(delete_printer),
+            get(get_printer)
+                .put(replace_printer)
+                .patch(update_printer)
+                .delete(delete_printer),
         )
+        .route("/api/printers/:id/restore", post(restore_printer))
+        .route("/api/printers/:id/reset", post(reset_printer))
         .route("/api/printers/:id/status", get(get_status))
-        .route("/api/printers/:id/status/stream", get(get_status_stream))
+        .route("/api/printers/:id/connectivity", get(get_connectivity))
+        .route("/api/printers/:id/stream/stats", get(get_stream_stats))
+        .route("/api/printers/:id/rtsp/stats", get(get_rtsp_stats))
+        .route("/api/printers/:id/metrics", get(get_printer_metrics))
+        .route(
+            "/api/printers/:id/notes",
+            get(list_printer_notes).post(create_printer_note),
+        )
+        .route(
+            "/api/printers/:id/notes/:note_id",
+            axum::routing::delete(delete_printer_note),
+        )
+        .route("/api/printers/:id/tags", get(list_printer_tags))
+        .route(
+            "/api/printers/:id/tags/:tag_id",
+            post(add_printer_tag).delete(remove_printer_tag),
+        )
         .route("/api/printers/:id/command", post(post_command))
-        .route("/api/printers/:id/video/cmaf", get(get_cmaf_stream_ws));
+        .route("/api/printers/bulk/command", post(post_bulk_command))
+        .route("/api/printers/:id/files", get(list_printer_files))
+        .route("/api/printers/:id/thumbnail.png", get(get_thumbnail))
+        .route("/api/printers/:id/video/init.mp4", get(get_cmaf_init))
+        .route("/api/printers/:id/video/key", get(get_cmaf_key))
+        .route(
+            "/api/printers/:id/video/stream.json",
+            get(get_playlist_json),
+        )
+        .route("/api/printers/:id/video/*segment", get(get_cmaf_segment))
+        .layer(TimeoutLayer::new(Duration::from_secs(
+            state.config.request_timeout_secs,
+        )))
+        .layer(middleware::map_response(timeout_error_body));
+    let protected = Router::new()
+        .merge(timed)
+        .merge(streaming)
+        .route_layer(middleware::from_fn(require_json_body))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&state),
+            authenticate_request,
+        ));
 
-    Router::new()
+    let router = Router::new()
         .merge(protected)
+        .route("/api/version", get(get_version))
+        .route("/metrics", get(get_metrics))
         .route("/healthz", get(healthz))
-        .route("/readyz", get(readyz))
-        .with_state(state)
-        .layer(
-            CorsLayer::new()
-                .allow_origin(Any)
-                .allow_methods([
-                    axum::http::Method::GET,
-                    axum::http::Method::POST,
-                    axum::http::Method::PUT,
-                    axum::http::Method::DELETE,
-                ])
-.allow_headers(Any),
+        .route("/readyz", get(readyz));
+    let router =
+        with_static_files(router, &state.config).layer(middleware::from_fn(api_error_bodies));
+    let router = if state.config.security_headers_enabled {
+        router.layer(middleware::from_fn(security_headers))
+    } else {
+        router
+    };
+
+    let cors = cors_layer(
+        &state.config.cors_allowed_origins,
+        state.config.cors_allow_credentials,
+    )?;
+
+    Ok(router.with_state(state).layer(cors))
+}
+
+/// Allows any origin unless `origins` is set. Credentials require an explicit
+/// origin list, since browsers reject `*` with credentials.
+fn cors_layer(origins: &[String], allow_credentials: bool) -> anyhow::Result<CorsLayer> {
+    let layer = CorsLayer::new()
+        .allow_methods([
+            axum::http::Method::GET,
+            axum::http::Method::POST,
+            axum::http::Method::PUT,
+            axum::http::Method::PATCH,
+            axum::http::Method::DELETE,
+        ])
+        .expose_headers([header::HeaderName::from_static(TOTAL_COUNT_HEADER)]);
+    if origins.is_empty() {
+        if allow_credentials {
+            anyhow::bail!("CORS_ALLOW_CREDENTIALS requires CORS_ALLOWED_ORIGINS");
+        }
+        return Ok(layer.allow_origin(Any).allow_headers(Any));
+    }
+
+    let origins = origins
+        .iter()
+        .map(|origin| {
+            header::HeaderValue::from_str(origin)
+                .with_context(|| format!("invalid CORS origin: {origin}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let layer = layer.allow_origin(AllowOrigin::list(origins));
+    Ok(if allow_credentials {
+        // `*` is not allowed for headers either once credentials are on.
+        layer
+            .allow_credentials(true)
+            .allow_headers(AllowHeaders::mirror_request())
+    } else {
+        layer.allow_headers(Any)
+    })
+}
+
+/// Replaces the empty 408 from `TimeoutLayer` with the usual JSON error body.
+async fn timeout_error_body(response: Response) -> Response {
+    if response.status() != StatusCode::REQUEST_TIMEOUT {
+        return response;
+    }
+    (
+        StatusCode::REQUEST_TIMEOUT,
+        Json(ErrorResponse::new(REQUEST_TIMEOUT, "request timed out")),
     )
+        .into_response()
 }
 
-async fn list_printers(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    match db::list_printers(&state.db).await {
-        Ok(printers) => (StatusCode::OK, Json(printers)).into_response(),
+/// Gives axum's empty 404/405 responses under `/api` the usual JSON error
+/// body; axum fills in the route's `Allow` list on the 405 afterwards.
+/// `OPTIONS` never gets here, since the CORS layer answers every one.
+async fn api_error_bodies<B>(request: Request<B>, next: Next<B>) -> Response {
+    let is_api = request.uri().path().starts_with("/api/");
+    let response = next.run(request).await;
+    // Handlers' own errors already carry a JSON body.
+    if !is_api || response.headers().contains_key(header::CONTENT_TYPE) {
+        return response;
+    }
+    match response.status() {
+        StatusCode::NOT_FOUND => (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(ROUTE_NOT_FOUND, "no such API route")),
+        )
+            .into_response(),
+        StatusCode::METHOD_NOT_ALLOWED => (
+            StatusCode::METHOD_NOT_ALLOWED,
+            Json(ErrorResponse::new(
+                METHOD_NOT_ALLOWED,
+                "method not allowed for this route",
+            )),
+        )
+            .into_response(),
+        _ => response,
+    }
+}
+
+/// Adds hardening headers to every response. The CSP only goes on API
+/// responses that are not media: the frontend needs its scripts and styles.
+async fn security_headers<B>(request: Request<B>, next: Next<B>) -> Response {
+    let is_api = request.uri().path().starts_with("/api/");
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert(
+        header::X_FRAME_OPTIONS,
+        header::HeaderValue::from_static("DENY"),
+    );
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        header::HeaderValue::from_static("nosniff"),
+    );
+    headers.insert(
+        header::REFERRER_POLICY,
+        header::HeaderValue::from_static("no-referrer"),
+    );
+    let is_media = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|mime| {
+            ["video/", "image/", "audio/"]
+                .iter()
+                .any(|kind| mime.starts_with(kind))
+        });
+    if is_api && !is_media {
+        headers.insert(
+            header::CONTENT_SECURITY_POLICY,
+            header::HeaderValue::from_static("default-src 'none'"),
+        );
+    }
+    response
+}
+
+/// Runs `AuthManager::authenticate`, hands the identity to handlers as an
+/// `AuthContext` extension and records it on the request span. Only signed
+/// requests have their body buffered.
+async fn authenticate_request(
+    State(state): State<Arc<AppState>>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let (parts, body) = request.into_parts();
+    let (body, bytes) = if AuthManager::is_signed(&parts.headers) {
+        match Bytes::from_request(Request::new(body), &()).await {
+            Ok(bytes) => (Body::from(bytes.clone()), bytes),
+            Err(rejection) => return rejection.into_response(),
+        }
+    } else {
+        (body, Bytes::new())
+    };
+    let path = parts
+        .uri
+        .path_and_query()
+        .map_or(parts.uri.path(), |path| path.as_str());
+    let context = match state
+        .auth
+        .authenticate(&parts.method, path, &parts.headers, &bytes)
+        .await
+    {
+        Ok(context) => context,
+        Err(error) => return error.into_response(),
+    };
+    // Every log line emitted while handling the request carries the caller.
+    let span = tracing::info_span!(
+        "request",
+        method = %parts.method,
+        path = %parts.uri.path(),
+        user.email = tracing::field::Empty,
+    );
+    span.record("user.email", context.email.as_str());
+    let mut request = Request::from_parts(parts, body);
+    request.extensions_mut().insert(context);
+    next.run(request).instrument(span).await
+}
+
+/// Rejects POST/PUT/PATCH bodies that are not JSON with a 415 instead of axum's
+/// extractor error. Bodyless requests such as `/reset` pass through.
+async fn require_json_body<B>(request: Request<B>, next: Next<B>) -> Response {
+    let method = request.method();
+    if method != Method::POST && method != Method::PUT && method != Method::PATCH {
+        return next.run(request).await;
+    }
+    let headers = request.headers();
+    let has_body = headers.contains_key(header::TRANSFER_ENCODING)
+        || headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .is_some_and(|length| length > 0);
+    if !has_body {
+        return next.run(request).await;
+    }
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"));
+    if is_json {
+        return next.run(request).await;
+    }
+    (
+        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        Json(ErrorResponse::new(
+            UNSUPPORTED_MEDIA_TYPE,
+            "request body must be application/json",
+        )),
+    )
+        .into_response()
+}
+
+#[cfg(feature = "static-files")]
+fn with_static_files(router: Router<Arc<AppState>>, config: &AppConfig) -> Router<Arc<AppState>> {
+    match config.static_dir.as_deref() {
+        Some(dir) => crate::static_files::mount(router, std::path::Path::new(dir)),
+        None => router,
+    }
+}
+
+#[cfg(not(feature = "static-files"))]
+fn with_static_files(router: Router<Arc<AppState>>, config: &AppConfig) -> Router<Arc<AppState>> {
+    if config.static_dir.is_some() {
+        tracing::warn!("STATIC_DIR is set but the static-files feature is disabled");
+    }
+    router
+}
+
+/// `GET /api/printers` filters; the total match count goes in `X-Total-Count`.
+#[derive(Debug, Deserialize)]
+struct ListPrintersQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+    search: Option<String>,
+    tag: Option<String>,
+    connected: Option<bool>,
+}
+
+async fn list_printers(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListPrintersQuery>,
+) -> impl IntoResponse {
+    if query.limit.is_some_and(|limit| limit < 1) || query.offset.is_some_and(|offset| offset < 0) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(
+                VALIDATION_ERROR,
+                "limit must be positive and offset non-negative",
+            )),
+        )
+            .into_response();
+    }
+    let offset = query.offset.unwrap_or(0);
+    let search = query.search.as_deref();
+    let tag = query.tag.as_deref();
+    let result = match query.connected {
+        None => {
+            db::search_printers(&state.db, &state.cipher, search, tag, query.limit, offset).await
+        }
+        // Connection state lives in the runtimes, so page after filtering.
+        Some(connected) => {
+            match db::search_printers(&state.db, &state.cipher, search, tag, None, 0).await {
+                Ok((printers, _)) => {
+                    let runtimes = state.printers.read().await;
+                    let mut matching = Vec::new();
+                    for printer in printers {
+                        let is_connected = match runtimes.get(&printer.id) {
+                            Some(runtime) => runtime.state.read().await.connected,
+                            None => false,
+                        };
+                        if is_connected == connected {
+                            matching.push(printer);
+                        }
+                    }
+                    let total = matching.len() as i64;
+                    let page = matching
+                        .into_iter()
+                        .skip(offset as usize)
+                        .take(query.limit.map_or(usize::MAX, |limit| limit as usize))
+                        .collect();
+                    Ok((page, total))
+                }
+                Err(error) => Err(error),
+            }
+        }
+    };
+    let (printers, total) = match result {
+        Ok(page) => page,
         Err(error) => {
             tracing::error!(?error, "failed to list printers");
-            (
+            return (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse::new("database error")),
+                Json(ErrorResponse::new(DATABASE_ERROR, "database error")),
             )
-                .into_response()
+                .into_response();
+        }
+    };
+    ([(TOTAL_COUNT_HEADER, total.to_string())], Json(printers)).into_response()
+}
+
+async fn list_tags(State(state): State<Arc<AppState>>) -> Response {
+    match db::list_tags(&state.db).await {
+        Ok(tags) => Json(tags).into_response(),
+        Err(error) => db_error_response(error),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TagRequest {
+    name: String,
+}
+
+async fn create_tag(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<TagRequest>,
+) -> Response {
+    match db::create_tag(&state.db, &payload.name).await {
+        Ok(Some(tag)) => (StatusCode::CREATED, Json(tag)).into_response(),
+        Ok(None) => (
+            StatusCode::CONFLICT,
+            Json(ErrorResponse::new(
+                DUPLICATE_TAG,
+                "a tag with this name already exists",
+            )),
+        )
+            .into_response(),
+        Err(error) => db_error_response(error),
+    }
+}
+
+/// Deletes the tag and drops it from every running printer's config.
+async fn delete_tag(State(state): State<Arc<AppState>>, Path(tag_id): Path<i64>) -> Response {
+    let tag = match db::get_tag(&state.db, tag_id).await {
+        Ok(Some(tag)) => tag,
+        Ok(None) => return tag_not_found(),
+        Err(error) => return db_error_response(error),
+    };
+    match db::delete_tag(&state.db, tag_id).await {
+        Ok(true) => {}
+        Ok(false) => return tag_not_found(),
+        Err(error) => return db_error_response(error),
+    }
+    for runtime in state.printers.read().await.values() {
+        let mut config = runtime.config();
+        if config
+            .tags
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(&tag.name))
+        {
+            config
+                .tags
+                .retain(|name| !name.eq_ignore_ascii_case(&tag.name));
+            runtime.update_config(config);
         }
     }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+fn tag_not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse::new(TAG_NOT_FOUND, "tag not found")),
+    )
+        .into_response()
 }
 
 async fn create_printer(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<PrinterCreateRequest>,
 ) -> impl IntoResponse {
-    match db::create_printer(&state.db, payload).await {
+    match db::create_printer(&state.db, &state.cipher, payload).await {
         Ok(printer) => {
-            let runtime = PrinterRuntime::spawn(printer.clone(), &state.config);
+            let runtime = PrinterRuntime::spawn(
+                printer.clone(),
+                &state.config,
+                state.db.clone(),
+                state.shutdown.child_token(),
+            )
+            .await;
             let mut printers = state.printers.write().await;
             printers.insert(printer.id, runtime);
             (StatusCode::CREATED, Json(printer)).into_response()
@@ -90,32 +516,310 @@ async fn create_printer(
 }
 
 async fn get_printer(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> impl IntoResponse {
-    match db::get_printer(&state.db, id).await {
+    match db::get_printer(&state.db, &state.cipher, id).await {
         Ok(Some(printer)) => (StatusCode::OK, Json(printer)).into_response(),
         Ok(None) => (
             StatusCode::NOT_FOUND,
-            Json(ErrorResponse::new("printer not found")),
+            Json(ErrorResponse::new(PRINTER_NOT_FOUND, "printer not found")),
         )
             .into_response(),
         Err(error) => {
             tracing::error!(?error, "failed to load printer");
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse::new("database error")),
+                Json(ErrorResponse::new(DATABASE_ERROR, "database error")),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct FileListResponse {
+    files: Vec<RemoteFile>,
+}
+
+/// Lists the printer's SD card over FTPS; connects on every request.
+async fn list_printer_files(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    let printer = match printer_config(&state, id).await {
+        Ok(printer) => printer,
+        Err(response) => return response,
+    };
+    match ftps::list_storage(
+        printer.connect_host(),
+        &printer.access_code,
+        state.config.ftps_tls_insecure,
+    )
+    .await
+    {
+        Ok(files) => Json(FileListResponse { files }).into_response(),
+        Err(error) => {
+            tracing::warn!(?error, printer_id = id, "failed to list printer files");
+            (
+                StatusCode::BAD_GATEWAY,
+                Json(ErrorResponse::new(
+                    PRINTER_STORAGE_UNAVAILABLE,
+                    &format!("{error:#}"),
+                )),
             )
                 .into_response()
         }
     }
 }
 
+/// Streams a file off printer storage, honouring a single `Range`.
+async fn download_printer_file(
+    State(state): State<Arc<AppState>>,
+    Path((id, path)): Path<(i64, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(path) = ftps::storage_path(&path) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(
+                VALIDATION_ERROR,
+                "path must be inside model, timelapse or cache",
+            )),
+        )
+            .into_response();
+    };
+    let printer = match printer_config(&state, id).await {
+        Ok(printer) => printer,
+        Err(response) => return response,
+    };
+    let storage_error = |error: anyhow::Error| {
+        let missing = error
+            .downcast_ref::<ftps::FtpsReplyError>()
+            .is_some_and(|reply| reply.code == 550);
+        if missing {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse::new(FILE_NOT_FOUND, "file not found")),
+            )
+                .into_response();
+        }
+        tracing::warn!(?error, printer_id = id, "failed to download printer file");
+        (
+            StatusCode::BAD_GATEWAY,
+            Json(ErrorResponse::new(
+                PRINTER_STORAGE_UNAVAILABLE,
+                &format!("{error:#}"),
+            )),
+        )
+            .into_response()
+    };
+
+    let mut client = match ftps::FtpsClient::connect(
+        printer.connect_host(),
+        &printer.access_code,
+        state.config.ftps_tls_insecure,
+    )
+    .await
+    {
+        Ok(client) => client,
+        Err(error) => return storage_error(error),
+    };
+    let size = match client.size(&path).await {
+        Ok(size) => size,
+        Err(error) => return storage_error(error),
+    };
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_byte_range(value, size));
+    let (start, end) = match range {
+        Some(Ok(range)) => range,
+        Some(Err(())) => {
+            client.quit().await;
+            return (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{size}"))],
+                Json(ErrorResponse::new(
+                    RANGE_NOT_SATISFIABLE,
+                    "range not satisfiable",
+                )),
+            )
+                .into_response();
+        }
+        None => (0, size.saturating_sub(1)),
+    };
+    let len = if size == 0 { 0 } else { end - start + 1 };
+    let body = match client.retrieve(&path, start, len).await {
+        Ok(body) => body,
+        Err(error) => return storage_error(error),
+    };
+
+    let name = path.rsplit('/').next().unwrap_or_default();
+    let mut response = StreamBody::new(body).into_response();
+    *response.status_mut() = if range.is_some() {
+        StatusCode::PARTIAL_CONTENT
+    } else {
+        StatusCode::OK
+    };
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static(content_type_for(name)),
+    );
+    headers.insert(header::CONTENT_LENGTH, header::HeaderValue::from(len));
+    headers.insert(
+        header::ACCEPT_RANGES,
+        header::HeaderValue::from_static("bytes"),
+    );
+    if range.is_some() {
+        if let Ok(value) = header::HeaderValue::from_str(&format!("bytes {start}-{end}/{size}")) {
+            headers.insert(header::CONTENT_RANGE, value);
+        }
+    }
+    response
+}
+
+/// Plate preview from the current job's 3MF, cached per job.
+async fn get_thumbnail(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    let runtime = match runtime_for(&state, id).await {
+        Ok(runtime) => runtime,
+        Err(response) => return response.into_response(),
+    };
+    let printer = match printer_config(&state, id).await {
+        Ok(printer) => printer,
+        Err(response) => return response,
+    };
+    let snapshot = runtime.state.read().await.clone();
+    match runtime
+        .thumbnail
+        .get(&printer, &snapshot, state.config.ftps_tls_insecure)
+        .await
+    {
+        Some(png) => (
+            [
+                (header::CONTENT_TYPE, "image/png"),
+                (header::CACHE_CONTROL, "no-cache"),
+            ],
+            png,
+        )
+            .into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(
+                THUMBNAIL_UNAVAILABLE,
+                "no thumbnail for the current job",
+            )),
+        )
+            .into_response(),
+    }
+}
+
+/// Inclusive byte range for a single-range `Range` header. `None` means the
+/// header should be ignored (unsupported unit); `Some(Err(()))` means it
+/// cannot be satisfied for a file of `size` bytes. Multi-range requests are
+/// unsatisfiable too, since `multipart/byteranges` is not supported.
+fn parse_byte_range(value: &str, size: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return Some(Err(()));
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    let range = if start.is_empty() {
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 || size == 0 {
+            return Some(Err(()));
+        }
+        (size.saturating_sub(suffix), size - 1)
+    } else {
+        let start: u64 = start.parse().ok()?;
+        let end = if end.is_empty() {
+            u64::MAX
+        } else {
+            end.parse().ok()?
+        };
+        if start > end || start >= size {
+            return Some(Err(()));
+        }
+        (start, end.min(size - 1))
+    };
+    Some(Ok(range))
+}
+
+/// First byte of a `bytes=N-` or `bytes=N-M` range.
+fn range_start(value: &str) -> Option<u64> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    let (start, _) = spec.split_once('-')?;
+    start.trim().parse().ok()
+}
+
+fn content_type_for(name: &str) -> &'static str {
+    let extension = name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("mp4") => "video/mp4",
+        Some("m4s") => "video/iso.segment",
+        Some("avi") => "video/x-msvideo",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("3mf") => "model/3mf",
+        Some("gcode") => "text/x-gcode",
+        _ => "application/octet-stream",
+    }
+}
+
+async fn printer_config(state: &Arc<AppState>, id: i64) -> Result<PrinterConfig, Response> {
+    match db::get_printer(&state.db, &state.cipher, id).await {
+        Ok(Some(printer)) => Ok(printer),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(PRINTER_NOT_FOUND, "printer not found")),
+        )
+            .into_response()),
+        Err(error) => {
+            tracing::error!(?error, "failed to load printer");
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(DATABASE_ERROR, "database error")),
+            )
+                .into_response())
+        }
+    }
+}
+
+async fn replace_printer(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Json(payload): Json<PrinterReplaceRequest>,
+) -> Response {
+    apply_printer_update(&state, id, payload.into()).await
+}
+
 async fn update_printer(
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
     Json(payload): Json<PrinterUpdateRequest>,
-) -> impl IntoResponse {
-    match db::update_printer(&state.db, id, payload).await {
+) -> Response {
+    apply_printer_update(&state, id, payload).await
+}
+
+/// Stores the update and restarts the printer's runtime when the edit changes
+/// how it connects. Cosmetic edits (name, tags) keep the live connections.
+async fn apply_printer_update(
+    state: &Arc<AppState>,
+    id: i64,
+    payload: PrinterUpdateRequest,
+) -> Response {
+    let _lock = state.printer_locks.lock(id).await;
+    match db::update_printer(&state.db, &state.cipher, id, payload).await {
         Ok(Some(printer)) => {
-            let runtime = PrinterRuntime::spawn(printer.clone(), &state.config);
+            let existing = state.printers.read().await.get(&id).cloned();
+            if existing.is_some_and(|runtime| runtime.update_config(printer.clone())) {
+                return (StatusCode::OK, Json(printer)).into_response();
+            }
+            let runtime = PrinterRuntime::spawn(
+                printer.clone(),
+                &state.config,
+                state.db.clone(),
+                state.shutdown.child_token(),
+            )
+            .await;
             let mut printers = state.printers.write().await;
             if let Some(existing) = printers.remove(&id) {
                 existing.shutdown();
@@ -125,7 +829,7 @@ async fn update_printer(
         }
         Ok(None) => (
             StatusCode::NOT_FOUND,
-            Json(ErrorResponse::new("printer not found")),
+            Json(ErrorResponse::new(PRINTER_NOT_FOUND, "printer not found")),
         )
             .into_response(),
         Err(error) => db_error_response(error),
@@ -135,291 +839,2479 @@ async fn update_printer(
 async fn delete_printer(
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
+    Extension(auth): Extension<AuthContext>,
 ) -> impl IntoResponse {
+    if let Err(response) = require_admin(&auth) {
+        return response.into_response();
+    }
+    let _lock = state.printer_locks.lock(id).await;
     match db::delete_printer(&state.db, id).await {
         Ok(true) => {
             let runtime = {
                 let mut printers = state.printers.write().await;
                 printers.remove(&id)
             };
+            state.command_limiter.remove(id);
             if let Some(runtime) = runtime {
                 runtime.shutdown();
-                let _ = tokio::fs::remove_dir_all(&runtime.cmaf_dir).await;
             }
             StatusCode::NO_CONTENT.into_response()
         }
         Ok(false) => (
             StatusCode::NOT_FOUND,
-            Json(ErrorResponse::new("printer not found")),
+            Json(ErrorResponse::new(PRINTER_NOT_FOUND, "printer not found")),
         )
             .into_response(),
         Err(error) => {
             tracing::error!(?error, "failed to delete printer");
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse::new("database error")),
+                Json(ErrorResponse::new(DATABASE_ERROR, "database error")),
             )
                 .into_response()
         }
     }
 }
 
-async fn get_status(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> impl IntoResponse {
-    match runtime_for(&state, id).await {
-        Ok(runtime) => {
-            let snapshot = runtime.state.read().await.clone();
-            Json(snapshot).into_response()
+/// Tears down and respawns a printer's connections without touching its
+/// configuration. The swap runs in its own task: a client hanging up midway
+/// must not leave the printer without a runtime.
+async fn reset_printer(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    match tokio::spawn(reset_runtime(state, id)).await {
+        Ok(response) => response,
+        Err(error) => {
+            tracing::error!(?error, printer_id = id, "printer reset task failed");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
         }
-        Err(response) => response.into_response(),
     }
 }
 
-async fn get_status_stream(
-    State(state): State<Arc<AppState>>,
-    Path(id): Path<i64>,
-) -> impl IntoResponse {
-    let runtime = match runtime_for(&state, id).await {
-        Ok(runtime) => runtime,
-        Err(response) => return response.into_response(),
-    };
-    let mut rx = runtime.status_tx.subscribe();
-    let initial = rx.borrow_and_update().clone();
-
-    let stream = stream! {
-        yield Ok::<Event, Infallible>(
-            Event::default()
-                .event("status")
-                .data(serialize_status(&initial)),
-        );
-
-        loop {
-            if rx.changed().await.is_err() {
-                break;
-            }
-            let snapshot = rx.borrow().clone();
-            yield Ok::<Event, Infallible>(
-                Event::default()
-                    .event("status")
-                    .data(serialize_status(&snapshot)),
-            );
+async fn reset_runtime(state: Arc<AppState>, id: i64) -> Response {
+    let _lock = state.printer_locks.lock(id).await;
+    let printer = match db::get_printer(&state.db, &state.cipher, id).await {
+        Ok(Some(printer)) => printer,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse::new(PRINTER_NOT_FOUND, "printer not found")),
+            )
+                .into_response()
+        }
+        Err(error) => {
+            tracing::error!(?error, "failed to load printer");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(DATABASE_ERROR, "database error")),
+            )
+                .into_response();
         }
     };
 
-    let mut response = Sse::new(stream)
-        .keep_alive(
-            KeepAlive::new()
-                .interval(Duration::from_secs(15))
-                .text("keep-alive"),
-        )
-        .into_response();
-    let headers = response.headers_mut();
-    headers.insert(
-        header::CACHE_CONTROL,
-        header::HeaderValue::from_static("no-store"),
-    );
-    response
+    let existing = state.printers.write().await.remove(&id);
+    if let Some(existing) = existing {
+        existing.shutdown_and_wait(RESET_SETTLE_TIMEOUT).await;
+    }
+    let runtime = PrinterRuntime::spawn(
+        printer.clone(),
+        &state.config,
+        state.db.clone(),
+        state.shutdown.child_token(),
+    )
+    .await;
+    // Deletes wait on the lock, but the row is the source of truth.
+    if !matches!(
+        db::get_printer(&state.db, &state.cipher, id).await,
+        Ok(Some(_))
+    ) {
+        runtime.shutdown();
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(PRINTER_NOT_FOUND, "printer not found")),
+        )
+            .into_response();
+    }
+    state.printers.write().await.insert(id, runtime);
+    tracing::info!(printer_id = id, "printer runtime reset");
+    (StatusCode::OK, Json(printer)).into_response()
 }
 
-async fn post_command(
+async fn list_deleted_printers(
+    State(state): State<Arc<AppState>>,
+    Extension(auth): Extension<AuthContext>,
+) -> impl IntoResponse {
+    if let Err(response) = require_admin(&auth) {
+        return response.into_response();
+    }
+    match db::list_deleted_printers(&state.db, &state.cipher).await {
+        Ok(printers) => (StatusCode::OK, Json(printers)).into_response(),
+        Err(error) => {
+            tracing::error!(?error, "failed to list deleted printers");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(DATABASE_ERROR, "database error")),
+            )
+                .into_response()
+        }
+    }
+}
+
+async fn restore_printer(
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
-    Json(payload): Json<CommandPayload>,
+    Extension(auth): Extension<AuthContext>,
 ) -> impl IntoResponse {
-    let runtime = match runtime_for(&state, id).await {
-        Ok(runtime) => runtime,
-        Err(response) => return response.into_response(),
-    };
-    let connected = runtime.state.read().await.connected;
-    if !connected {
-        return (
-            StatusCode::SERVICE_UNAVAILABLE,
-            Json(CommandResponse {
-                ok: false,
-                error: Some("printer not connected".to_string()),
-            }),
+    if let Err(response) = require_admin(&auth) {
+        return response.into_response();
+    }
+    let _lock = state.printer_locks.lock(id).await;
+    match db::restore_printer(&state.db, &state.cipher, id).await {
+        Ok(Some(printer)) => {
+            let runtime = PrinterRuntime::spawn(
+                printer.clone(),
+                &state.config,
+                state.db.clone(),
+                state.shutdown.child_token(),
+            )
+            .await;
+            let mut printers = state.printers.write().await;
+            printers.insert(printer.id, runtime);
+            (StatusCode::OK, Json(printer)).into_response()
+        }
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(
+                PRINTER_NOT_FOUND,
+                "deleted printer not found",
+            )),
         )
-            .into_response();
+            .into_response(),
+        Err(error) => {
+            tracing::error!(?error, "failed to restore printer");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(DATABASE_ERROR, "database error")),
+            )
+                .into_response()
+        }
     }
+}
 
-    let command = CommandRequest::from(payload);
-    if runtime.command_tx.send(command).await.is_err() {
-        return (
-            StatusCode::SERVICE_UNAVAILABLE,
-            Json(CommandResponse {
-                ok: false,
-                error: Some("command channel unavailable".to_string()),
-            }),
+/// Hard-deletes a soft-deleted printer along with its CMAF output, so its
+/// serial can be added again.
+async fn purge_printer(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Extension(auth): Extension<AuthContext>,
+) -> Response {
+    if let Err(response) = require_admin(&auth) {
+        return response.into_response();
+    }
+    match db::purge_printer(&state.db, id).await {
+        Ok(true) => {
+            let cmaf_dir =
+                std::path::PathBuf::from(&state.config.cmaf_output_dir).join(id.to_string());
+            let _ = tokio::fs::remove_dir_all(cmaf_dir).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(
+                PRINTER_NOT_FOUND,
+                "deleted printer not found",
+            )),
         )
-            .into_response();
+            .into_response(),
+        Err(error) => {
+            tracing::error!(?error, "failed to purge printer");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(DATABASE_ERROR, "database error")),
+            )
+                .into_response()
+        }
     }
-
-    (
-        StatusCode::OK,
-        Json(CommandResponse {
-            ok: true,
-            error: None,
-        }),
-    )
-        .into_response()
 }
 
-async fn healthz() -> impl IntoResponse {
-    (StatusCode::OK, "ok")
+fn require_admin(auth: &AuthContext) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    if auth.is_admin {
+        return Ok(());
+    }
+    Err((
+        StatusCode::FORBIDDEN,
+        Json(ErrorResponse::new(FORBIDDEN, "admin access required")),
+    ))
 }
 
-async fn readyz(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    if let Err(error) = sqlx::query("SELECT 1").execute(&state.db).await {
-        tracing::error!(?error, "readyz database check failed");
-        return (StatusCode::SERVICE_UNAVAILABLE, "db unavailable").into_response();
-    }
+/// At most this many `?extra=` pointers per status request.
+const MAX_EXTRA_POINTERS: usize = 32;
 
-    (StatusCode::OK, "ready").into_response()
+#[derive(Debug, Deserialize)]
+struct StatusQuery {
+    /// Comma-separated JSON pointers into the printer's raw report.
+    extra: Option<String>,
 }
 
-async fn get_cmaf_stream_ws(
-    ws: WebSocketUpgrade,
+#[derive(Serialize)]
+struct StatusResponse {
+    #[serde(flatten)]
+    state: PrinterState,
+    /// Requested raw values keyed by pointer; missing ones are left out.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extra: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+async fn get_status(
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
-) -> impl IntoResponse {
+    Query(query): Query<StatusQuery>,
+) -> Response {
     let runtime = match runtime_for(&state, id).await {
         Ok(runtime) => runtime,
         Err(response) => return response.into_response(),
     };
-
-    ws.on_upgrade(move |socket| async move {
-        handle_cmaf_ws(socket, runtime).await;
+    let pointers = match query.extra.as_deref().map(parse_extra_pointers).transpose() {
+        Ok(pointers) => pointers,
+        Err(message) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse::new(VALIDATION_ERROR, &message)),
+            )
+                .into_response()
+        }
+    };
+    let mut snapshot = runtime.state.read().await.clone();
+    snapshot.command_queue_depth = runtime.command_queue.depth();
+    snapshot.video_status = runtime.stream_stats.status();
+    snapshot.fps = runtime.stream_stats.fps();
+    let extra = pointers.map(|pointers| {
+        pointers
+            .into_iter()
+            .filter_map(|pointer| {
+                let value = snapshot.raw_report.as_ref()?.pointer(pointer)?.clone();
+                Some((pointer.to_string(), value))
+            })
+            .collect()
+    });
+    Json(StatusResponse {
+        state: snapshot,
+        extra,
     })
+    .into_response()
 }
 
-async fn handle_cmaf_ws(mut socket: WebSocket, runtime: Arc<PrinterRuntime>) {
-    let mut subscription = runtime.cmaf_stream.subscribe();
-    let init = match tokio::time::timeout(Duration::from_secs(5), async {
-        loop {
-            if let Some(init) = subscription.init_rx.borrow().clone() {
-                break Some(init);
-            }
-            if subscription.init_rx.changed().await.is_err() {
-                break None;
-            }
+/// Splits `?extra=` into RFC 6901 pointers, rejecting malformed ones.
+fn parse_extra_pointers(value: &str) -> Result<Vec<&str>, String> {
+    let pointers: Vec<&str> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|pointer| !pointer.is_empty())
+        .collect();
+    if pointers.len() > MAX_EXTRA_POINTERS {
+        return Err(format!(
+            "extra accepts at most {MAX_EXTRA_POINTERS} pointers"
+        ));
+    }
+    for pointer in &pointers {
+        let valid_escapes = pointer
+            .match_indices('~')
+            .all(|(index, _)| matches!(pointer.as_bytes().get(index + 1), Some(b'0' | b'1')));
+        if !pointer.starts_with('/') || !valid_escapes {
+            return Err(format!(
+                "`{pointer}` is not a JSON pointer like /print/spd_lvl"
+            ));
         }
-    })
-    .await
-    {
-        Ok(Some(init)) => init,
-        _ => return,
-    };
-
-    if socket
-        .send(Message::Text(format!("codec:{}", init.codec)))
-        .await
-        .is_err()
-    {
-        return;
     }
+    Ok(pointers)
+}
 
-    if socket
-        .send(Message::Binary(init.bytes.to_vec()))
-        .await
-        .is_err()
-    {
-        return;
-    }
+async fn get_connectivity(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    let runtime = match runtime_for(&state, id).await {
+        Ok(runtime) => runtime,
+        Err(response) => return response.into_response(),
+    };
+    let mqtt_connected = runtime.state.read().await.connected;
+    let stream = runtime.stream_stats.snapshot();
+    Json(ConnectivityResponse {
+        mqtt: MqttConnectivity {
+            connected: mqtt_connected,
+            last_connect_at: runtime.mqtt_connected_at.get(),
+        },
+        rtsp: RtspConnectivity {
+            active: stream.rtsp_connected,
+            last_packet_at: stream.last_packet_at,
+        },
+    })
+    .into_response()
+}
 
-    let backlog = runtime.cmaf_stream.backlog_snapshot();
-    let mut last_seq = backlog.last().map(|fragment| fragment.seq).unwrap_or(0);
-    for fragment in backlog {
-        if socket
-            .send(Message::Binary(fragment.bytes.to_vec()))
-            .await
-            .is_err()
-        {
-            return;
+async fn get_stream_stats(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    match runtime_for(&state, id).await {
+        Ok(runtime) => {
+            let mut stats = runtime.stream_stats.snapshot();
+            stats.cmaf_backlog_depth = runtime.cmaf_stream.backlog_len();
+            Json(stats).into_response()
         }
+        Err(response) => response.into_response(),
     }
+}
 
-    loop {
-        match subscription.fragment_rx.recv().await {
-            Ok(fragment) => {
-                if fragment.seq <= last_seq {
-                    continue;
-                }
-                last_seq = fragment.seq;
-                if socket
-                    .send(Message::Binary(fragment.bytes.to_vec()))
-                    .await
-                    .is_err()
-                {
-                    break;
-                }
-            }
-            Err(broadcast::error::RecvError::Lagged(skipped)) => {
-                tracing::warn!(
-                    skipped,
-                    "cmaf websocket lagged; replaying from in-memory backlog"
-                );
-                let backlog = runtime.cmaf_stream.backlog_snapshot();
-                for fragment in backlog {
-                    if fragment.seq <= last_seq {
-                        continue;
-                    }
-                    last_seq = fragment.seq;
-                    if socket
-                        .send(Message::Binary(fragment.bytes.to_vec()))
-                        .await
-                        .is_err()
-                    {
-                        return;
-                    }
-                }
-                continue;
-            }
-            Err(broadcast::error::RecvError::Closed) => break,
-        }
+async fn get_rtsp_stats(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    match runtime_for(&state, id).await {
+        Ok(runtime) => Json(runtime.stream_stats.rtsp().snapshot()).into_response(),
+        Err(response) => response.into_response(),
     }
 }
 
-#[derive(Serialize)]
-struct CommandResponse {
-    ok: bool,
-    error: Option<String>,
+#[derive(Debug, Deserialize)]
+struct PrinterNoteRequest {
+    text: String,
 }
 
-#[derive(Serialize)]
-struct ErrorResponse {
-    error: String,
+async fn list_printer_tags(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    if let Err(response) = runtime_for(&state, id).await {
+        return response.into_response();
+    }
+    match db::list_printer_tags(&state.db, id).await {
+        Ok(tags) => Json(tags).into_response(),
+        Err(error) => db_error_response(error),
+    }
 }
 
-impl ErrorResponse {
-    fn new(message: &str) -> Self {
-        Self {
-            error: message.to_string(),
+/// Attaches an existing tag and answers with the printer's tags.
+async fn add_printer_tag(
+    State(state): State<Arc<AppState>>,
+    Path((id, tag_id)): Path<(i64, i64)>,
+) -> Response {
+    let runtime = match runtime_for(&state, id).await {
+        Ok(runtime) => runtime,
+        Err(response) => return response.into_response(),
+    };
+    match db::get_tag(&state.db, tag_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return tag_not_found(),
+        Err(error) => return db_error_response(error),
+    }
+    if let Err(error) = db::add_printer_tag(&state.db, id, tag_id).await {
+        return db_error_response(error);
+    }
+    reload_runtime_config(&state, &runtime, id).await;
+    match db::list_printer_tags(&state.db, id).await {
+        Ok(tags) => Json(tags).into_response(),
+        Err(error) => db_error_response(error),
+    }
+}
+
+async fn remove_printer_tag(
+    State(state): State<Arc<AppState>>,
+    Path((id, tag_id)): Path<(i64, i64)>,
+) -> Response {
+    let runtime = match runtime_for(&state, id).await {
+        Ok(runtime) => runtime,
+        Err(response) => return response.into_response(),
+    };
+    match db::remove_printer_tag(&state.db, id, tag_id).await {
+        Ok(true) => {
+            reload_runtime_config(&state, &runtime, id).await;
+            StatusCode::NO_CONTENT.into_response()
         }
+        Ok(false) => tag_not_found(),
+        Err(error) => db_error_response(error),
     }
 }
 
-fn serialize_status(state: &PrinterState) -> String {
-    serde_json::to_string(state).unwrap_or_else(|_| "{}".to_string())
+/// Picks up tag edits made outside `apply_printer_update`.
+async fn reload_runtime_config(state: &Arc<AppState>, runtime: &PrinterRuntime, id: i64) {
+    match db::get_printer(&state.db, &state.cipher, id).await {
+        Ok(Some(printer)) => {
+            runtime.update_config(printer);
+        }
+        Ok(None) => {}
+        Err(error) => tracing::warn!(?error, id, "failed to reload printer config"),
+    }
 }
 
-async fn runtime_for(
-    state: &Arc<AppState>,
-    id: i64,
-) -> Result<Arc<PrinterRuntime>, (StatusCode, Json<ErrorResponse>)> {
-    let printers = state.printers.read().await;
-    printers.get(&id).cloned().ok_or((
-        StatusCode::NOT_FOUND,
-        Json(ErrorResponse::new("printer not found")),
-    ))
+async fn list_printer_notes(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    if let Err(response) = runtime_for(&state, id).await {
+        return response.into_response();
+    }
+    match db::list_printer_notes(&state.db, id).await {
+        Ok(notes) => Json(notes).into_response(),
+        Err(error) => db_error_response(error),
+    }
 }
 
-fn db_error_response(error: anyhow::Error) -> Response {
-    let message = error.to_string();
-    let status = if message.contains("UNIQUE constraint failed") {
-        StatusCode::CONFLICT
-    } else if message.contains("required") {
-        StatusCode::BAD_REQUEST
-    } else {
-        StatusCode::INTERNAL_SERVER_ERROR
-    };
-    (status, Json(ErrorResponse::new(&message))).into_response()
+/// Adds a note signed with the caller's identity.
+async fn create_printer_note(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Extension(auth): Extension<AuthContext>,
+    Json(payload): Json<PrinterNoteRequest>,
+) -> Response {
+    if let Err(response) = runtime_for(&state, id).await {
+        return response.into_response();
+    }
+    match db::create_printer_note(&state.db, id, &auth.email, &payload.text).await {
+        Ok(note) => (StatusCode::CREATED, Json(note)).into_response(),
+        Err(error) => db_error_response(error),
+    }
+}
+
+async fn delete_printer_note(
+    State(state): State<Arc<AppState>>,
+    Path((id, note_id)): Path<(i64, i64)>,
+) -> Response {
+    if let Err(response) = runtime_for(&state, id).await {
+        return response.into_response();
+    }
+    match db::delete_printer_note(&state.db, id, note_id).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(NOTE_NOT_FOUND, "note not found")),
+        )
+            .into_response(),
+        Err(error) => db_error_response(error),
+    }
+}
+
+const METRICS_DEFAULT_WINDOW_HOURS: i64 = 24;
+/// Most buckets one metrics request may return.
+const METRICS_MAX_BUCKETS: i64 = 10_000;
+
+#[derive(Debug, Deserialize)]
+struct MetricsQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    interval: Option<String>,
+}
+
+/// Temperature and progress history, averaged per `interval` (default `60s`)
+/// over `[from, to)` (default the last day).
+async fn get_printer_metrics(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(query): Query<MetricsQuery>,
+) -> Response {
+    if let Err(response) = runtime_for(&state, id).await {
+        return response.into_response();
+    }
+    let invalid = |message: &str| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(VALIDATION_ERROR, message)),
+        )
+            .into_response()
+    };
+    let Some(interval_secs) =
+        metrics_history::parse_interval(query.interval.as_deref().unwrap_or("60s"))
+    else {
+        return invalid("interval must look like 60s, 5m, 1h or 1d");
+    };
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query
+        .from
+        .unwrap_or(to - chrono::Duration::hours(METRICS_DEFAULT_WINDOW_HOURS));
+    if from >= to {
+        return invalid("from must be before to");
+    }
+    if (to - from).num_seconds() / interval_secs > METRICS_MAX_BUCKETS {
+        return invalid("range has too many intervals; use a larger interval");
+    }
+    match db::aggregate_metrics(&state.db, id, from, to, interval_secs).await {
+        Ok(buckets) => Json(buckets).into_response(),
+        Err(error) => {
+            tracing::error!(?error, "failed to read printer metrics");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse::new(DATABASE_ERROR, "database error")),
+            )
+                .into_response()
+        }
+    }
+}
+
+async fn get_status_stream(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    let runtime = match runtime_for(&state, id).await {
+        Ok(runtime) => runtime,
+        Err(response) => return response.into_response(),
+    };
+    let mut rx = runtime.status_tx.subscribe();
+    let mut video_rx = runtime.stream_stats.subscribe_status();
+    let stream_stats = runtime.stream_stats.clone();
+
+    let stream = stream! {
+        loop {
+            let mut snapshot = rx.borrow_and_update().clone();
+            snapshot.video_status = *video_rx.borrow_and_update();
+            snapshot.fps = stream_stats.fps();
+            yield Ok::<Event, Infallible>(
+                Event::default()
+                    .event("status")
+                    .data(serialize_status(&snapshot)),
+            );
+
+            let changed = tokio::select! {
+                changed = rx.changed() => changed,
+                changed = video_rx.changed() => changed,
+            };
+            if changed.is_err() {
+                break;
+            }
+        }
+    };
+
+    let mut response = Sse::new(stream)
+        .keep_alive(
+            KeepAlive::new()
+                .interval(Duration::from_secs(15))
+                .text("keep-alive"),
+        )
+        .into_response();
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CACHE_CONTROL,
+        header::HeaderValue::from_static("no-store"),
+    );
+    response
+}
+
+async fn post_command(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Json(payload): Json<CommandPayload>,
+) -> impl IntoResponse {
+    let runtime = match runtime_for(&state, id).await {
+        Ok(runtime) => runtime,
+        Err(response) => return response.into_response(),
+    };
+    if let Err(message) = payload.validate(&state.config.gcode_allowlist) {
+        return CommandRejection::new(StatusCode::BAD_REQUEST, VALIDATION_ERROR, message)
+            .into_response();
+    }
+
+    match queue_command(&state, id, &runtime, CommandRequest::from(payload)).await {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(CommandResponse {
+                ok: true,
+                error: None,
+                code: None,
+            }),
+        )
+            .into_response(),
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+/// Why a command was not queued for a printer.
+struct CommandRejection {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    retry_after: Option<Duration>,
+}
+
+impl CommandRejection {
+    fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+}
+
+impl IntoResponse for CommandRejection {
+    fn into_response(self) -> Response {
+        let mut response = (
+            self.status,
+            Json(CommandResponse {
+                ok: false,
+                error: Some(self.message),
+                code: Some(self.code),
+            }),
+        )
+            .into_response();
+        if let Some(retry_after) = self.retry_after {
+            let retry_secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_secs.into());
+        }
+        response
+    }
+}
+
+/// Checks the printer can take a validated command and queues it.
+async fn queue_command(
+    state: &AppState,
+    id: i64,
+    runtime: &PrinterRuntime,
+    command: CommandRequest,
+) -> Result<(), CommandRejection> {
+    let (connected, has_chamber_heater) = {
+        let printer = runtime.state.read().await;
+        (printer.connected, printer.chamber_target_c.is_some())
+    };
+    if !connected {
+        return Err(CommandRejection::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            PRINTER_NOT_CONNECTED,
+            "printer not connected",
+        ));
+    }
+    if matches!(command, CommandRequest::SetChamberTemp { .. }) && !has_chamber_heater {
+        return Err(CommandRejection::new(
+            StatusCode::BAD_REQUEST,
+            VALIDATION_ERROR,
+            "printer does not report a chamber heater",
+        ));
+    }
+    if let Err(retry_after) = state.command_limiter.check(id, command.rate_cost()) {
+        return Err(CommandRejection {
+            retry_after: Some(retry_after),
+            ..CommandRejection::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                RATE_LIMITED,
+                "command rate limit exceeded",
+            )
+        });
+    }
+    if runtime.command_queue.enqueue(command).is_err() {
+        return Err(CommandRejection::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            COMMAND_CHANNEL_UNAVAILABLE,
+            "command queue full",
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BulkCommandRequest {
+    printer_ids: Vec<i64>,
+    command: CommandPayload,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BulkCommandResult {
+    printer_id: i64,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+}
+
+impl BulkCommandResult {
+    fn new(printer_id: i64, outcome: Result<(), CommandRejection>) -> Self {
+        match outcome {
+            Ok(()) => Self {
+                printer_id,
+                ok: true,
+                error: None,
+                code: None,
+            },
+            Err(rejection) => Self {
+                printer_id,
+                ok: false,
+                error: Some(rejection.message),
+                code: Some(rejection.code),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BulkCommandResponse {
+    results: Vec<BulkCommandResult>,
+}
+
+/// Sends one command to several printers at once. Answers 200 with a result
+/// per printer, in request order, even when some of them reject it.
+async fn post_bulk_command(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<BulkCommandRequest>,
+) -> Response {
+    let invalid = |message: String| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse::new(VALIDATION_ERROR, &message)),
+        )
+            .into_response()
+    };
+    if payload.printer_ids.is_empty() {
+        return invalid("printerIds must list at least one printer".to_string());
+    }
+    if let Err(message) = payload.command.validate(&state.config.gcode_allowlist) {
+        return invalid(message);
+    }
+    let command = CommandRequest::from(payload.command);
+    let mut printer_ids = payload.printer_ids;
+    let mut seen = std::collections::HashSet::new();
+    printer_ids.retain(|id| seen.insert(*id));
+
+    let mut sends = JoinSet::new();
+    for (index, printer_id) in printer_ids.iter().copied().enumerate() {
+        let state = Arc::clone(&state);
+        let command = command.clone();
+        sends.spawn(async move {
+            let outcome = match runtime_for(&state, printer_id).await {
+                Ok(runtime) => queue_command(&state, printer_id, &runtime, command).await,
+                Err(_) => Err(CommandRejection::new(
+                    StatusCode::NOT_FOUND,
+                    PRINTER_NOT_FOUND,
+                    "printer not found",
+                )),
+            };
+            (index, BulkCommandResult::new(printer_id, outcome))
+        });
+    }
+    let mut results: Vec<BulkCommandResult> = printer_ids
+        .iter()
+        .map(|printer_id| {
+            BulkCommandResult::new(
+                *printer_id,
+                Err(CommandRejection::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    COMMAND_CHANNEL_UNAVAILABLE,
+                    "command dispatch failed",
+                )),
+            )
+        })
+        .collect();
+    while let Some(joined) = sends.join_next().await {
+        if let Ok((index, result)) = joined {
+            results[index] = result;
+        }
+    }
+    Json(BulkCommandResponse { results }).into_response()
+}
+
+#[derive(Debug, Serialize)]
+struct VersionResponse {
+    version: &'static str,
+    commit: Option<&'static str>,
+    build_time: Option<String>,
+    rustc_version: &'static str,
+}
+
+/// Build metadata stamped in by `build.rs`.
+async fn get_version() -> Json<VersionResponse> {
+    let build_time = env!("BUILD_TIMESTAMP")
+        .parse::<i64>()
+        .ok()
+        .and_then(|secs| DateTime::<Utc>::from_timestamp(secs, 0))
+        .map(|time| time.to_rfc3339());
+    Json(VersionResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        commit: option_env!("GIT_COMMIT_HASH"),
+        build_time,
+        rustc_version: env!("RUSTC_VERSION"),
+    })
+}
+
+async fn healthz() -> impl IntoResponse {
+    (StatusCode::OK, "ok")
+}
+
+/// Prometheus text exposition of everything recorded through `metrics`.
+async fn get_metrics(State(state): State<Arc<AppState>>) -> Response {
+    match state.metrics.as_ref() {
+        Some(handle) => (
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            handle.render(),
+        )
+            .into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(
+                ROUTE_NOT_FOUND,
+                "metrics recorder is not installed",
+            )),
+        )
+            .into_response(),
+    }
+}
+
+async fn readyz(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    if let Err(error) = sqlx::query("SELECT 1").execute(&state.db).await {
+        tracing::error!(?error, "readyz database check failed");
+        return (StatusCode::SERVICE_UNAVAILABLE, "db unavailable").into_response();
+    }
+
+    (StatusCode::OK, "ready").into_response()
+}
+
+async fn get_cmaf_stream_ws(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    let runtime = match runtime_for(&state, id).await {
+        Ok(runtime) => runtime,
+        Err(response) => return response.into_response(),
+    };
+
+    ws.on_upgrade(move |socket| async move {
+        handle_cmaf_ws(socket, runtime).await;
+    })
+}
+
+/// Serves the cached init segment, so it is available without
+/// `CMAF_WRITE_FILES` and without touching disk.
+async fn get_cmaf_init(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    let runtime = match runtime_for(&state, id).await {
+        Ok(runtime) => runtime,
+        Err(response) => return response.into_response(),
+    };
+    match runtime.cmaf_stream.latest_init() {
+        Some(init) => (
+            [
+                (header::CONTENT_TYPE, "video/mp4".to_string()),
+                (header::CACHE_CONTROL, "no-cache".to_string()),
+                (header::HeaderName::from_static("x-cmaf-codec"), init.codec),
+            ],
+            init.bytes,
+        )
+            .into_response(),
+        None => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorResponse::new(
+                VIDEO_NOT_READY,
+                "video init segment not available yet",
+            )),
+        )
+            .into_response(),
+    }
+}
+
+/// AES-128 key referenced by `EXT-X-KEY` in encrypted playlists. Served here
+/// rather than next to the segments so it sits behind request authentication.
+async fn get_cmaf_key(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    if let Err(response) = runtime_for(&state, id).await {
+        return response.into_response();
+    }
+    match state.config.cmaf_encryption_key {
+        Some(key) => (
+            [
+                (header::CONTENT_TYPE, "application/octet-stream"),
+                (header::CACHE_CONTROL, "no-store"),
+            ],
+            key.to_vec(),
+        )
+            .into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(
+                ENCRYPTION_DISABLED,
+                "video encryption is not enabled",
+            )),
+        )
+            .into_response(),
+    }
+}
+
+/// Serves the JSON form of the playlist written next to `stream.m3u8`. Only
+/// exists with `CMAF_WRITE_FILES`, since that is when playlists are rendered;
+/// its segment URIs resolve to `get_cmaf_segment`.
+async fn get_playlist_json(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Response {
+    let runtime = match runtime_for(&state, id).await {
+        Ok(runtime) => runtime,
+        Err(response) => return response.into_response(),
+    };
+    match tokio::fs::read(runtime.cmaf_dir.join("stream.json")).await {
+        Ok(bytes) => (
+            [
+                (header::CONTENT_TYPE, "application/json"),
+                (header::CACHE_CONTROL, "no-cache"),
+            ],
+            bytes,
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorResponse::new(
+                VIDEO_NOT_READY,
+                "playlist not available yet",
+            )),
+        )
+            .into_response(),
+    }
+}
+
+/// Serves a segment file named in the playlist, honouring a single `Range`
+/// so LL-HLS parts can be fetched by byte range. A range starting at the end
+/// of the file is the `EXT-X-PRELOAD-HINT` for the next part, so it is held
+/// until that part is written. Segments are small enough to read whole.
+async fn get_cmaf_segment(
+    State(state): State<Arc<AppState>>,
+    Path((id, segment)): Path<(i64, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let runtime = match runtime_for(&state, id).await {
+        Ok(runtime) => runtime,
+        Err(response) => return response.into_response(),
+    };
+    let segment = segment.trim_start_matches('/');
+    let valid = segment.ends_with(".m4s") && segment.split('/').all(ftps::valid_segment);
+    let path = runtime.cmaf_dir.join(segment);
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok());
+    if let Some(start) = range.and_then(range_start).filter(|_| valid) {
+        let deadline = tokio::time::Instant::now() + PRELOAD_HINT_WAIT;
+        while tokio::time::Instant::now() < deadline {
+            match tokio::fs::metadata(&path).await {
+                Ok(metadata) if metadata.len() <= start => {
+                    tokio::time::sleep(PRELOAD_HINT_POLL).await;
+                }
+                _ => break,
+            }
+        }
+    }
+    let bytes = if valid {
+        tokio::fs::read(&path).await.ok()
+    } else {
+        None
+    };
+    let Some(bytes) = bytes else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse::new(FILE_NOT_FOUND, "segment not found")),
+        )
+            .into_response();
+    };
+    let size = bytes.len() as u64;
+    let range = range.and_then(|value| parse_byte_range(value, size));
+    match range {
+        None => (
+            [
+                (header::CONTENT_TYPE, content_type_for(segment).to_string()),
+                (header::ACCEPT_RANGES, "bytes".to_string()),
+            ],
+            bytes,
+        )
+            .into_response(),
+        Some(Ok((start, end))) => (
+            StatusCode::PARTIAL_CONTENT,
+            [
+                (header::CONTENT_TYPE, content_type_for(segment).to_string()),
+                (header::ACCEPT_RANGES, "bytes".to_string()),
+                (header::CONTENT_RANGE, format!("bytes {start}-{end}/{size}")),
+            ],
+            bytes[start as usize..=end as usize].to_vec(),
+        )
+            .into_response(),
+        Some(Err(())) => (
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [(header::CONTENT_RANGE, format!("bytes */{size}"))],
+            Json(ErrorResponse::new(
+                RANGE_NOT_SATISFIABLE,
+                "range not satisfiable",
+            )),
+        )
+            .into_response(),
+    }
+}
+
+async fn handle_cmaf_ws(mut socket: WebSocket, runtime: Arc<PrinterRuntime>) {
+    let mut subscription = runtime.cmaf_stream.subscribe();
+    let init = match tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(init) = subscription.init_rx.borrow().clone() {
+                break Some(init);
+            }
+            if subscription.init_rx.changed().await.is_err() {
+                break None;
+            }
+        }
+    })
+    .await
+    {
+        Ok(Some(init)) => init,
+        _ => return,
+    };
+
+    if socket
+        .send(Message::Text(format!("codec:{}", init.codec)))
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    if socket
+        .send(Message::Binary(init.bytes.to_vec()))
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    let backlog = runtime.cmaf_stream.backlog_snapshot();
+    let mut last_seq = backlog.last().map(|fragment| fragment.seq).unwrap_or(0);
+    for fragment in backlog {
+        if socket
+            .send(Message::Binary(fragment.bytes.to_vec()))
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+
+    loop {
+        match subscription.fragment_rx.recv().await {
+            Ok(fragment) => {
+                if fragment.seq <= last_seq {
+                    continue;
+                }
+                last_seq = fragment.seq;
+                if socket
+                    .send(Message::Binary(fragment.bytes.to_vec()))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!(
+                    skipped,
+                    "cmaf websocket lagged; replaying from in-memory backlog"
+                );
+                let backlog = runtime.cmaf_stream.backlog_snapshot();
+                for fragment in backlog {
+                    if fragment.seq <= last_seq {
+                        continue;
+                    }
+                    last_seq = fragment.seq;
+                    if socket
+                        .send(Message::Binary(fragment.bytes.to_vec()))
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ConnectivityResponse {
+    mqtt: MqttConnectivity,
+    rtsp: RtspConnectivity,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MqttConnectivity {
+    connected: bool,
+    last_connect_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RtspConnectivity {
+    active: bool,
+    last_packet_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct CommandResponse {
+    ok: bool,
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+}
+
+const PRINTER_NOT_FOUND: &str = "PRINTER_NOT_FOUND";
+const PRINTER_NOT_CONNECTED: &str = "PRINTER_NOT_CONNECTED";
+const COMMAND_CHANNEL_UNAVAILABLE: &str = "COMMAND_CHANNEL_UNAVAILABLE";
+const RATE_LIMITED: &str = "RATE_LIMITED";
+const VALIDATION_ERROR: &str = "VALIDATION_ERROR";
+const DUPLICATE_SERIAL: &str = "DUPLICATE_SERIAL";
+const DATABASE_ERROR: &str = "DATABASE_ERROR";
+const VIDEO_NOT_READY: &str = "VIDEO_NOT_READY";
+const UNSUPPORTED_MEDIA_TYPE: &str = "UNSUPPORTED_MEDIA_TYPE";
+const REQUEST_TIMEOUT: &str = "REQUEST_TIMEOUT";
+const PRINTER_STORAGE_UNAVAILABLE: &str = "PRINTER_STORAGE_UNAVAILABLE";
+const FILE_NOT_FOUND: &str = "FILE_NOT_FOUND";
+const THUMBNAIL_UNAVAILABLE: &str = "THUMBNAIL_UNAVAILABLE";
+const RANGE_NOT_SATISFIABLE: &str = "RANGE_NOT_SATISFIABLE";
+const ROUTE_NOT_FOUND: &str = "ROUTE_NOT_FOUND";
+const METHOD_NOT_ALLOWED: &str = "METHOD_NOT_ALLOWED";
+const NOTE_NOT_FOUND: &str = "NOTE_NOT_FOUND";
+const TAG_NOT_FOUND: &str = "TAG_NOT_FOUND";
+const DUPLICATE_TAG: &str = "DUPLICATE_TAG";
+const ENCRYPTION_DISABLED: &str = "ENCRYPTION_DISABLED";
+const FORBIDDEN: &str = "FORBIDDEN";
+
+/// `error` is for humans; clients should branch on `code`.
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+}
+
+impl ErrorResponse {
+    fn new(code: &'static str, message: &str) -> Self {
+        Self {
+            error: message.to_string(),
+            code: Some(code),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PrinterStatusEvent<'a> {
+    printer_id: i64,
+    state: &'a PrinterState,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PrinterRemovedEvent {
+    printer_id: i64,
+}
+
+/// Fans every printer's status channel into one SSE stream. Each printer gets
+/// a forwarding task; the printer map is rescanned periodically so added,
+/// removed and reset printers are picked up without reconnecting.
+async fn get_all_status_stream(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let stream = stream! {
+        let (tx, mut rx) = mpsc::channel::<(i64, PrinterState)>(64);
+        // Dropping the set when the client disconnects aborts every forwarder.
+        let mut forwarders = JoinSet::new();
+        let mut watched: HashMap<i64, (Arc<PrinterRuntime>, AbortHandle)> = HashMap::new();
+        let mut rescan = tokio::time::interval(STATUS_RESCAN_INTERVAL);
+        rescan.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = rescan.tick() => {
+                    let current = state.printers.read().await.clone();
+                    let removed: Vec<i64> = watched
+                        .keys()
+                        .filter(|id| !current.contains_key(id))
+                        .copied()
+                        .collect();
+                    for id in removed {
+                        if let Some((_, handle)) = watched.remove(&id) {
+                            handle.abort();
+                        }
+                        let data = serde_json::to_string(&PrinterRemovedEvent { printer_id: id })
+                            .unwrap_or_else(|_| "{}".to_string());
+                        yield Ok::<Event, Infallible>(Event::default().event("removed").data(data));
+                    }
+                    for (id, runtime) in current {
+                        // Resets and updates replace the runtime, so compare by pointer.
+                        if let Some((existing, handle)) = watched.get(&id) {
+                            if Arc::ptr_eq(existing, &runtime) {
+                                continue;
+                            }
+                            handle.abort();
+                        }
+                        let handle = forwarders.spawn(forward_status(id, runtime.clone(), tx.clone()));
+                        watched.insert(id, (runtime, handle));
+                    }
+                }
+                Some((id, snapshot)) = rx.recv() => {
+                    let data = serde_json::to_string(&PrinterStatusEvent {
+                        printer_id: id,
+                        state: &snapshot,
+                    })
+                    .unwrap_or_else(|_| "{}".to_string());
+                    yield Ok::<Event, Infallible>(Event::default().event("status").data(data));
+                }
+                Some(_) = forwarders.join_next() => {}
+            }
+        }
+    };
+
+    let mut response = Sse::new(stream)
+        .keep_alive(
+            KeepAlive::new()
+                .interval(Duration::from_secs(15))
+                .text("keep-alive"),
+        )
+        .into_response();
+    response.headers_mut().insert(
+        header::CACHE_CONTROL,
+        header::HeaderValue::from_static("no-store"),
+    );
+    response
+}
+
+/// One tile of the farm overview.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PrinterOverview {
+    id: i64,
+    name: String,
+    connected: bool,
+    job_state: Option<String>,
+    percent: Option<u8>,
+    hms_severity: Option<HmsSeverity>,
+}
+
+/// Every printer's latest published status, sorted by name.
+async fn printer_overview(state: &AppState) -> Vec<PrinterOverview> {
+    let runtimes: Vec<_> = state.printers.read().await.values().cloned().collect();
+    let mut overview: Vec<PrinterOverview> = runtimes
+        .iter()
+        .map(|runtime| {
+            let config = runtime.config();
+            let status = runtime.status_tx.borrow();
+            PrinterOverview {
+                id: config.id,
+                name: config.name,
+                connected: status.connected,
+                job_state: status.job_state.clone(),
+                percent: status.percent,
+                hms_severity: status.hms_severity,
+            }
+        })
+        .collect();
+    overview.sort_by(|a, b| {
+        a.name
+            .to_lowercase()
+            .cmp(&b.name.to_lowercase())
+            .then(a.id.cmp(&b.id))
+    });
+    overview
+}
+
+async fn get_overview(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(printer_overview(&state).await)
+}
+
+/// Pushes the whole overview whenever it changes. Status updates that only
+/// touch other fields (temperatures, AMS) are not re-sent.
+async fn get_overview_stream(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let stream = stream! {
+        let (tx, mut rx) = mpsc::channel::<()>(1);
+        let mut forwarders = JoinSet::new();
+        let mut watched: HashMap<i64, (Arc<PrinterRuntime>, AbortHandle)> = HashMap::new();
+        let mut rescan = tokio::time::interval(STATUS_RESCAN_INTERVAL);
+        rescan.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut last_sent = None;
+
+        loop {
+            tokio::select! {
+                _ = rescan.tick() => {
+                    let current = state.printers.read().await.clone();
+                    watched.retain(|id, (_, handle)| {
+                        let keep = current.contains_key(id);
+                        if !keep {
+                            handle.abort();
+                        }
+                        keep
+                    });
+                    for (id, runtime) in current {
+                        if let Some((existing, handle)) = watched.get(&id) {
+                            if Arc::ptr_eq(existing, &runtime) {
+                                continue;
+                            }
+                            handle.abort();
+                        }
+                        let handle = forwarders.spawn(notify_status_changes(
+                            runtime.status_tx.subscribe(),
+                            tx.clone(),
+                        ));
+                        watched.insert(id, (runtime, handle));
+                    }
+                }
+                Some(()) = rx.recv() => {}
+                Some(_) = forwarders.join_next() => {}
+            }
+            let overview = printer_overview(&state).await;
+            if last_sent.as_ref() != Some(&overview) {
+                let data = serde_json::to_string(&overview).unwrap_or_else(|_| "[]".to_string());
+                yield Ok::<Event, Infallible>(Event::default().event("overview").data(data));
+                last_sent = Some(overview);
+            }
+        }
+    };
+
+    let mut response = Sse::new(stream)
+        .keep_alive(
+            KeepAlive::new()
+                .interval(Duration::from_secs(15))
+                .text("keep-alive"),
+        )
+        .into_response();
+    response.headers_mut().insert(
+        header::CACHE_CONTROL,
+        header::HeaderValue::from_static("no-store"),
+    );
+    response
+}
+
+/// Wakes `tx` on every status change until the runtime goes away. A full
+/// channel already has a wake-up pending, so extra changes are dropped.
+async fn notify_status_changes(mut rx: watch::Receiver<PrinterState>, tx: mpsc::Sender<()>) {
+    while rx.changed().await.is_ok() {
+        if let Err(mpsc::error::TrySendError::Closed(())) = tx.try_send(()) {
+            break;
+        }
+    }
+}
+
+/// Sends the current state, then every change, until the runtime goes away.
+async fn forward_status(
+    id: i64,
+    runtime: Arc<PrinterRuntime>,
+    tx: mpsc::Sender<(i64, PrinterState)>,
+) {
+    let mut rx = runtime.status_tx.subscribe();
+    let mut video_rx = runtime.stream_stats.subscribe_status();
+    let stream_stats = runtime.stream_stats.clone();
+    drop(runtime);
+    loop {
+        let mut snapshot = rx.borrow_and_update().clone();
+        snapshot.video_status = *video_rx.borrow_and_update();
+        snapshot.fps = stream_stats.fps();
+        if tx.send((id, snapshot)).await.is_err() {
+            break;
+        }
+        let changed = tokio::select! {
+            changed = rx.changed() => changed,
+            changed = video_rx.changed() => changed,
+        };
+        if changed.is_err() {
+            break;
+        }
+    }
+}
+
+fn serialize_status(state: &PrinterState) -> String {
+    serde_json::to_string(state).unwrap_or_else(|_| "{}".to_string())
+}
+
+async fn runtime_for(
+    state: &Arc<AppState>,
+    id: i64,
+) -> Result<Arc<PrinterRuntime>, (StatusCode, Json<ErrorResponse>)> {
+    let printers = state.printers.read().await;
+    printers.get(&id).cloned().ok_or((
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse::new(PRINTER_NOT_FOUND, "printer not found")),
+    ))
+}
+
+fn db_error_response(error: anyhow::Error) -> Response {
+    let (status, code) = if error.downcast_ref::<db::ValidationError>().is_some() {
+        (StatusCode::BAD_REQUEST, VALIDATION_ERROR)
+    } else if error
+        .chain()
+        .any(|cause| cause.to_string().contains("UNIQUE constraint failed"))
+    {
+        // `serial` is the only unique column.
+        (StatusCode::CONFLICT, DUPLICATE_SERIAL)
+    } else {
+        tracing::error!(?error, "printer write failed");
+        (StatusCode::INTERNAL_SERVER_ERROR, DATABASE_ERROR)
+    };
+    let message = match code {
+        DUPLICATE_SERIAL => {
+            "a printer with this serial already exists; restore or purge it if it was deleted"
+                .to_string()
+        }
+        _ => error.to_string(),
+    };
+    (status, Json(ErrorResponse::new(code, &message))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use tower::ServiceExt;
+
+    async fn preflight(layer: CorsLayer, origin: &str) -> Response {
+        Router::new()
+            .route("/api/printers", get(|| async { "printers" }))
+            .layer(layer)
+            .oneshot(
+                Request::builder()
+                    .method("OPTIONS")
+                    .uri("/api/printers")
+                    .header(header::ORIGIN, origin)
+                    .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+                    .body(Body::empty())
+                    .expect("request"),
+            )
+            .await
+            .expect("response")
+    }
+
+    #[tokio::test]
+    async fn cors_layer_uses_configured_origins() {
+        let origins = vec!["https://printers.example.com".to_string()];
+        let layer = cors_layer(&origins, true).expect("cors layer");
+
+        let allowed = preflight(layer.clone(), "https://printers.example.com").await;
+        let headers = allowed.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://printers.example.com"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+
+        let denied = preflight(layer, "https://evil.example.com").await;
+        assert!(!denied
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    async fn post_body(content_type: Option<&str>, body: &'static str) -> StatusCode {
+        let mut request = Request::builder().method("POST").uri("/api/printers");
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        Router::new()
+            .route("/api/printers", post(|| async { StatusCode::CREATED }))
+            .route_layer(middleware::from_fn(require_json_body))
+            .oneshot(
+                request
+                    .header(header::CONTENT_LENGTH, body.len())
+                    .body(Body::from(body))
+                    .expect("request"),
+            )
+            .await
+            .expect("response")
+            .status()
+    }
+
+    #[tokio::test]
+    async fn non_json_bodies_are_rejected() {
+        assert_eq!(
+            post_body(Some("application/json; charset=utf-8"), "{}").await,
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            post_body(Some("text/plain"), "{}").await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(
+            post_body(None, "{}").await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(post_body(None, "").await, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn slow_requests_time_out_with_json_body() {
+        let response = Router::new()
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    "done"
+                }),
+            )
+            .layer(TimeoutLayer::new(Duration::from_millis(10)))
+            .layer(middleware::map_response(timeout_error_body))
+            .oneshot(
+                Request::builder()
+                    .uri("/slow")
+                    .body(Body::empty())
+                    .expect("request"),
+            )
+            .await
+            .expect("response");
+
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .expect("body");
+        let body: serde_json::Value = serde_json::from_slice(&body).expect("json");
+        assert_eq!(body["code"], REQUEST_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn security_headers_are_added_unless_disabled() {
+        let fetch = |app: Router, uri: &'static str| async move {
+            app.oneshot(Request::get(uri).body(Body::empty()).expect("request"))
+                .await
+                .expect("response")
+        };
+        let (app, _) = test_app().await;
+
+        let health = fetch(app.clone(), "/healthz").await;
+        assert_eq!(health.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert!(!health
+            .headers()
+            .contains_key(header::CONTENT_SECURITY_POLICY));
+        let api = fetch(app, "/api/printers").await;
+        assert_eq!(api.headers()[header::X_FRAME_OPTIONS], "DENY");
+        assert_eq!(api.headers()[header::REFERRER_POLICY], "no-referrer");
+        assert_eq!(
+            api.headers()[header::CONTENT_SECURITY_POLICY],
+            "default-src 'none'"
+        );
+
+        // Media may be embedded, so it is exempt from the CSP.
+        let media = Router::new()
+            .route(
+                "/api/video.mp4",
+                get(|| async { ([(header::CONTENT_TYPE, "video/mp4")], "mp4") }),
+            )
+            .layer(middleware::from_fn(security_headers));
+        let video = fetch(media, "/api/video.mp4").await;
+        assert_eq!(video.headers()[header::X_FRAME_OPTIONS], "DENY");
+        assert!(!video
+            .headers()
+            .contains_key(header::CONTENT_SECURITY_POLICY));
+
+        let mut config = AppConfig::for_tests();
+        config.security_headers_enabled = false;
+        let (app, _) = test_app_with_config(AuthManager::new(None, None), config).await;
+        let api = fetch(app, "/api/printers").await;
+        assert!(!api.headers().contains_key(header::X_FRAME_OPTIONS));
+        assert!(!api.headers().contains_key(header::CONTENT_SECURITY_POLICY));
+    }
+
+    #[tokio::test]
+    async fn cors_layer_defaults_to_any_origin() {
+        let response = preflight(cors_layer(&[], false).expect("cors layer"), "http://x").await;
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(cors_layer(&[], true).is_err());
+    }
+
+    #[test]
+    fn byte_ranges_are_clamped_to_the_file() {
+        assert_eq!(parse_byte_range("bytes=0-99", 1000), Some(Ok((0, 99))));
+        assert_eq!(parse_byte_range("bytes=900-", 1000), Some(Ok((900, 999))));
+        assert_eq!(
+            parse_byte_range("bytes=900-5000", 1000),
+            Some(Ok((900, 999)))
+        );
+        assert_eq!(parse_byte_range("bytes=-100", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_byte_range("bytes=-5000", 1000), Some(Ok((0, 999))));
+        assert_eq!(parse_byte_range("bytes=1000-", 1000), Some(Err(())));
+        assert_eq!(parse_byte_range("bytes=50-10", 1000), Some(Err(())));
+        assert_eq!(parse_byte_range("bytes=0-1,5-6", 1000), Some(Err(())));
+        assert_eq!(
+            parse_byte_range("bytes=0-100, 200-300", 1000),
+            Some(Err(()))
+        );
+        assert_eq!(parse_byte_range("items=0-1", 1000), None);
+        assert_eq!(
+            content_type_for("video_2026-03-01_12-00-00.MP4"),
+            "video/mp4"
+        );
+        assert_eq!(content_type_for("plate_1.png"), "image/png");
+        assert_eq!(content_type_for("noext"), "application/octet-stream");
+    }
+
+    async fn test_app() -> (Router, Arc<AppState>) {
+        test_app_with_auth(AuthManager::new(None, None)).await
+    }
+
+    async fn test_app_with_auth(auth: AuthManager) -> (Router, Arc<AppState>) {
+        test_app_with_config(auth, AppConfig::for_tests()).await
+    }
+
+    async fn test_app_with_config(auth: AuthManager, config: AppConfig) -> (Router, Arc<AppState>) {
+        let db = db::init("sqlite::memory:").await.expect("init db");
+        let state = Arc::new(AppState {
+            command_limiter: Arc::new(CommandRateLimiter::new(
+                config.command_rate_per_sec,
+                config.command_burst,
+            )),
+            config,
+            db,
+            cipher: SecretCipher::new(None),
+            printers: Arc::new(RwLock::new(HashMap::new())),
+            printer_locks: Arc::default(),
+            auth,
+            metrics: None,
+            shutdown: CancellationToken::new(),
+        });
+        (router(Arc::clone(&state)).expect("router"), state)
+    }
+
+    async fn send(
+        app: &Router,
+        method: Method,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, Vec<u8>) {
+        let mut request = Request::builder().method(method).uri(uri);
+        let body = match body {
+            Some(body) => {
+                request = request.header(header::CONTENT_TYPE, "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+        let response = app
+            .clone()
+            .oneshot(request.body(body).expect("request"))
+            .await
+            .expect("response");
+        let status = response.status();
+        let bytes = hyper::body::to_bytes(response.into_body())
+            .await
+            .expect("body");
+        (status, bytes.to_vec())
+    }
+
+    /// `send`, signed with `secret` the way an API client would.
+    async fn send_signed(
+        app: &Router,
+        secret: &str,
+        method: Method,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, Vec<u8>) {
+        let body = body.map(|body| body.to_string()).unwrap_or_default();
+        let timestamp = Utc::now().timestamp();
+        let signature = crate::auth::sign(secret, &method, uri, body.as_bytes(), timestamp);
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(crate::auth::SIGNATURE_HEADER, signature)
+            .header(crate::auth::TIMESTAMP_HEADER, timestamp)
+            .body(Body::from(body))
+            .expect("request");
+        let response = app.clone().oneshot(request).await.expect("response");
+        let status = response.status();
+        let bytes = hyper::body::to_bytes(response.into_body())
+            .await
+            .expect("body");
+        (status, bytes.to_vec())
+    }
+
+    /// Creates a printer through the API and returns its id. `fields`
+    /// override the defaults; each call gets its own serial.
+    async fn create_printer_with(app: &Router, fields: serde_json::Value) -> i64 {
+        static NEXT_SERIAL: AtomicU64 = AtomicU64::new(1);
+        let mut printer = serde_json::json!({
+            "name": "X1C",
+            "host": "127.0.0.1",
+            "serial": format!("01S00A{:09}", NEXT_SERIAL.fetch_add(1, Ordering::Relaxed)),
+            "accessCode": "12345678"
+        });
+        if let (Some(printer), Some(fields)) = (printer.as_object_mut(), fields.as_object()) {
+            printer.extend(fields.clone());
+        }
+        let (status, body) = send(app, Method::POST, "/api/printers", Some(printer)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        serde_json::from_slice::<serde_json::Value>(&body).expect("json")["id"]
+            .as_i64()
+            .expect("id")
+    }
+
+    async fn create_printer(app: &Router) -> i64 {
+        create_printer_with(app, serde_json::json!({})).await
+    }
+
+    /// Stops the MQTT and video tasks of every printer the test started.
+    async fn shutdown_printers(state: &AppState) {
+        for runtime in state.printers.read().await.values() {
+            runtime.shutdown();
+        }
+    }
+
+    #[tokio::test]
+    async fn deleted_printers_are_admin_only_and_purged_to_free_the_serial() {
+        let (app, state) = test_app_with_auth(AuthManager::new(
+            Some("user".to_string()),
+            Some("admin".to_string()),
+        ))
+        .await;
+        let printer = serde_json::json!({
+            "name": "X1C",
+            "host": "127.0.0.1",
+            "serial": "01S00A000000001",
+            "accessCode": "12345678"
+        });
+        let (status, body) = send_signed(
+            &app,
+            "user",
+            Method::POST,
+            "/api/printers",
+            Some(printer.clone()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let id = serde_json::from_slice::<serde_json::Value>(&body).expect("json")["id"]
+            .as_i64()
+            .expect("id");
+        let uri = format!("/api/printers/{id}");
+
+        let (status, _) = send_signed(&app, "user", Method::DELETE, &uri, None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send_signed(&app, "admin", Method::DELETE, &uri, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) =
+            send_signed(&app, "user", Method::GET, "/api/printers/deleted", None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) =
+            send_signed(&app, "admin", Method::GET, "/api/printers/deleted", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let deleted: serde_json::Value = serde_json::from_slice(&body).expect("json");
+        assert_eq!(deleted.as_array().map(Vec::len), Some(1));
+
+        // The serial stays taken until the deleted printer is purged.
+        let (status, _) = send_signed(
+            &app,
+            "user",
+            Method::POST,
+            "/api/printers",
+            Some(printer.clone()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let purge = format!("/api/printers/deleted/{id}");
+        let (status, _) = send_signed(&app, "user", Method::DELETE, &purge, None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send_signed(&app, "admin", Method::DELETE, &purge, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send_signed(&app, "admin", Method::DELETE, &purge, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) =
+            send_signed(&app, "user", Method::POST, "/api/printers", Some(printer)).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        shutdown_printers(&state).await;
+    }
+
+    #[tokio::test]
+    async fn a_delete_racing_a_reset_leaves_no_runtime() {
+        let (app, state) = test_app().await;
+        let id = create_printer(&app).await;
+        let reset = format!("/api/printers/{id}/reset");
+        let uri = format!("/api/printers/{id}");
+
+        let (reset, delete) = tokio::join!(
+            send(&app, Method::POST, &reset, None),
+            send(&app, Method::DELETE, &uri, None)
+        );
+        assert!(
+            [StatusCode::OK, StatusCode::NOT_FOUND].contains(&reset.0),
+            "reset: {}",
+            reset.0
+        );
+        assert_eq!(delete.0, StatusCode::NO_CONTENT);
+        assert!(!state.printers.read().await.contains_key(&id));
+
+        shutdown_printers(&state).await;
+    }
+
+    #[tokio::test]
+    async fn metrics_are_served_in_prometheus_format() {
+        let (app, state) = test_app().await;
+        let (status, _) = send(&app, Method::GET, "/metrics", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let recorder = metrics_exporter_prometheus::PrometheusBuilder::new().build_recorder();
+        let state = Arc::new(AppState {
+            metrics: Some(recorder.handle()),
+            ..(*state).clone()
+        });
+        metrics::with_local_recorder(&recorder, || {
+            metrics::counter!("mqtt_lost_messages_total").increment(2);
+        });
+        let app = router(state).expect("router");
+        let (status, body) = send(&app, Method::GET, "/metrics", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let body = String::from_utf8(body).expect("utf8");
+        assert!(body.contains("mqtt_lost_messages_total 2"));
+    }
+
+    #[tokio::test]
+    async fn router_serves_printers_stored_before_boot() {
+        let config = AppConfig::for_tests();
+        let db = db::init("sqlite::memory:").await.expect("init db");
+        let cipher = SecretCipher::new(None);
+        let printer = db::create_printer(
+            &db,
+            &cipher,
+            serde_json::from_value(serde_json::json!({
+                "name": "X1C",
+                "host": "127.0.0.1",
+                "serial": "01S00A000000001",
+                "accessCode": "12345678"
+            }))
+            .expect("payload"),
+        )
+        .await
+        .expect("create printer");
+        // Mirrors main: runtimes come from the database, not from API calls.
+        let shutdown = CancellationToken::new();
+        let runtimes = crate::printers::spawn_all(&db, &cipher, &config, &shutdown)
+            .await
+            .expect("spawn runtimes");
+        let state = Arc::new(AppState {
+            command_limiter: Arc::new(CommandRateLimiter::new(
+                config.command_rate_per_sec,
+                config.command_burst,
+            )),
+            config,
+            db,
+            cipher,
+            printers: Arc::new(RwLock::new(runtimes)),
+            printer_locks: Arc::default(),
+            auth: AuthManager::new(None, None),
+            metrics: None,
+            shutdown,
+        });
+        let app = router(Arc::clone(&state)).expect("router");
+
+        let status_uri = format!("/api/printers/{}/status", printer.id);
+        assert_eq!(
+            send(&app, Method::GET, &status_uri, None).await.0,
+            StatusCode::OK
+        );
+        let (status, body) = send(&app, Method::GET, "/api/printers", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let listed: Vec<serde_json::Value> = serde_json::from_slice(&body).expect("json");
+        assert_eq!(listed.len(), 1);
+
+        shutdown_printers(&state).await;
+    }
+
+    #[tokio::test]
+    async fn playlist_segments_are_served_next_to_the_playlist() {
+        let (app, state) = test_app().await;
+        let id = create_printer(&app).await;
+        let cmaf_dir = state.printers.read().await[&id].cmaf_dir.clone();
+        let name = format!("seg-test-{}.m4s", rand::random::<u64>());
+        tokio::fs::create_dir_all(&cmaf_dir).await.expect("dir");
+        tokio::fs::write(cmaf_dir.join(&name), b"0123456789")
+            .await
+            .expect("segment");
+
+        let (status, body) = send(
+            &app,
+            Method::GET,
+            &format!("/api/printers/{id}/video/{name}"),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, b"0123456789");
+        let part = app
+            .clone()
+            .oneshot(
+                Request::get(format!("/api/printers/{id}/video/{name}"))
+                    .header(header::RANGE, "bytes=2-5")
+                    .body(Body::empty())
+                    .expect("request"),
+            )
+            .await
+            .expect("response");
+        assert_eq!(part.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(part.headers()[header::CONTENT_RANGE], "bytes 2-5/10");
+        let body = hyper::body::to_bytes(part.into_body()).await.expect("body");
+        assert_eq!(&body[..], b"2345");
+        let (status, _) = send(
+            &app,
+            Method::GET,
+            &format!("/api/printers/{id}/video/missing.txt"),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, body) = send(
+            &app,
+            Method::GET,
+            &format!("/api/printers/{id}/video/stream.json"),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let error: serde_json::Value = serde_json::from_slice(&body).expect("json");
+        assert_eq!(error["code"], VIDEO_NOT_READY);
+
+        let _ = tokio::fs::remove_file(cmaf_dir.join(&name)).await;
+        shutdown_printers(&state).await;
+    }
+
+    #[tokio::test]
+    async fn preload_hint_ranges_wait_for_the_next_part() {
+        let (app, state) = test_app().await;
+        let id = create_printer(&app).await;
+        let cmaf_dir = state.printers.read().await[&id].cmaf_dir.clone();
+        let name = format!("seg-test-{}.m4s", rand::random::<u64>());
+        let path = cmaf_dir.join(&name);
+        tokio::fs::create_dir_all(&cmaf_dir).await.expect("dir");
+        tokio::fs::write(&path, b"0123456789")
+            .await
+            .expect("segment");
+
+        let writer = tokio::spawn({
+            let path = path.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                tokio::fs::write(path, b"0123456789abcdef")
+                    .await
+                    .expect("part");
+            }
+        });
+        let part = app
+            .clone()
+            .oneshot(
+                Request::get(format!("/api/printers/{id}/video/{name}"))
+                    .header(header::RANGE, "bytes=10-")
+                    .body(Body::empty())
+                    .expect("request"),
+            )
+            .await
+            .expect("response");
+        writer.await.expect("writer");
+        assert_eq!(part.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(part.headers()[header::CONTENT_RANGE], "bytes 10-15/16");
+        let body = hyper::body::to_bytes(part.into_body()).await.expect("body");
+        assert_eq!(&body[..], b"abcdef");
+
+        let _ = tokio::fs::remove_file(path).await;
+        shutdown_printers(&state).await;
+    }
+
+    #[tokio::test]
+    async fn tags_are_managed_and_attached_to_printers() {
+        let (app, state) = test_app().await;
+        let id = create_printer(&app).await;
+
+        let (status, body) = send(
+            &app,
+            Method::POST,
+            "/api/tags",
+            Some(serde_json::json!({ "name": " Room-A " })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let tag: serde_json::Value = serde_json::from_slice(&body).expect("json");
+        assert_eq!(tag["name"], "Room-A");
+        assert_eq!(tag["printerCount"], 0);
+        let tag_id = tag["id"].as_i64().expect("tag id");
+        let (status, _) = send(
+            &app,
+            Method::POST,
+            "/api/tags",
+            Some(serde_json::json!({ "name": "room-a" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let uri = format!("/api/printers/{id}/tags/{tag_id}");
+        let (status, body) = send(&app, Method::POST, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let tags: serde_json::Value = serde_json::from_slice(&body).expect("json");
+        assert_eq!(tags[0]["printerCount"], 1);
+        let (status, _) = send(&app, Method::POST, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let runtime = state.printers.read().await[&id].clone();
+        assert_eq!(runtime.config().tags, ["Room-A"]);
+        let (_, body) = send(&app, Method::GET, "/api/printers?tag=room-a", None).await;
+        let printers: Vec<serde_json::Value> = serde_json::from_slice(&body).expect("json");
+        assert_eq!(printers.len(), 1);
+
+        let (status, _) = send(
+            &app,
+            Method::POST,
+            &format!("/api/printers/{id}/tags/{}", tag_id + 1),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = send(&app, Method::DELETE, &uri, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(&app, Method::DELETE, &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (_, body) = send(&app, Method::GET, &format!("/api/printers/{id}/tags"), None).await;
+        assert_eq!(body, b"[]");
+
+        send(&app, Method::POST, &uri, None).await;
+        let (status, _) = send(&app, Method::DELETE, &format!("/api/tags/{tag_id}"), None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(runtime.config().tags.is_empty());
+        let (_, body) = send(&app, Method::GET, "/api/tags", None).await;
+        assert_eq!(body, b"[]");
+
+        shutdown_printers(&state).await;
+    }
+
+    #[tokio::test]
+    async fn printer_list_supports_search_tags_and_paging() {
+        let (app, state) = test_app().await;
+        for (index, (name, host, tags)) in [
+            ("Alpha", "10.0.0.1", vec!["room-a"]),
+            ("Bravo", "garage.lan", vec![]),
+            ("Charlie", "10.0.0.3", vec!["room-a", "resin"]),
+            ("Delta_2", "10.0.0.4", vec!["room-b"]),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let printer = serde_json::json!({
+                "name": name,
+                "host": host,
+                "serial": format!("01S00A00000000{index}"),
+                "tags": tags
+            });
+            create_printer_with(&app, printer).await;
+        }
+        let list = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(Request::get(uri).body(Body::empty()).expect("request"))
+                    .await
+                    .expect("response");
+                assert_eq!(response.status(), StatusCode::OK);
+                let total = response.headers()[TOTAL_COUNT_HEADER]
+                    .to_str()
+                    .expect("total")
+                    .parse::<i64>()
+                    .expect("total");
+                let body = hyper::body::to_bytes(response.into_body())
+                    .await
+                    .expect("body");
+                let printers: Vec<serde_json::Value> = serde_json::from_slice(&body).expect("json");
+                let names: Vec<String> = printers
+                    .iter()
+                    .map(|printer| printer["name"].as_str().unwrap_or_default().to_string())
+                    .collect();
+                (names, total)
+            }
+        };
+
+        assert_eq!(list("/api/printers").await.1, 4);
+        assert_eq!(
+            list("/api/printers?limit=2&offset=1").await,
+            (vec!["Bravo".to_string(), "Charlie".to_string()], 4)
+        );
+        assert_eq!(
+            list("/api/printers?search=GARAGE").await,
+            (vec!["Bravo".to_string()], 1)
+        );
+        assert_eq!(list("/api/printers?search=10.0.0&offset=2").await.1, 3);
+        assert_eq!(
+            list("/api/printers?search=_").await,
+            (vec!["Delta_2".to_string()], 1)
+        );
+        assert_eq!(
+            list("/api/printers?search=000000002").await,
+            (vec!["Charlie".to_string()], 1)
+        );
+        assert_eq!(
+            list("/api/printers?tag=room-a&search=10.0.0").await,
+            (vec!["Alpha".to_string(), "Charlie".to_string()], 2)
+        );
+        let (status, body) = send(&app, Method::GET, "/api/tags", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let tags: Vec<serde_json::Value> = serde_json::from_slice(&body).expect("json");
+        let names: Vec<&str> = tags
+            .iter()
+            .map(|tag| tag["name"].as_str().unwrap_or_default())
+            .collect();
+        assert_eq!(names, ["resin", "room-a", "room-b"]);
+        // Nothing is connected to MQTT in tests.
+        assert_eq!(list("/api/printers?connected=true").await, (Vec::new(), 0));
+        assert_eq!(
+            list("/api/printers?connected=false&limit=1&offset=3").await,
+            (vec!["Delta_2".to_string()], 4)
+        );
+        assert_eq!(
+            send(&app, Method::GET, "/api/printers?limit=0", None)
+                .await
+                .0,
+            StatusCode::BAD_REQUEST
+        );
+
+        shutdown_printers(&state).await;
+    }
+
+    #[tokio::test]
+    async fn overview_summarizes_every_runtime() {
+        let (app, state) = test_app().await;
+        for name in ["Workshop", "Attic"] {
+            create_printer_with(&app, serde_json::json!({ "name": name })).await;
+        }
+        for runtime in state.printers.read().await.values() {
+            if runtime.config().name != "Workshop" {
+                continue;
+            }
+            // MQTT publishes from the shared state, so update it too.
+            let status = {
+                let mut status = runtime.state.write().await;
+                status.job_state = Some("RUNNING".to_string());
+                status.percent = Some(42);
+                status.hms_severity = Some(HmsSeverity::Common);
+                status.clone()
+            };
+            runtime.status_tx.send_replace(status);
+        }
+
+        let (status, body) = send(&app, Method::GET, "/api/overview", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let overview: serde_json::Value = serde_json::from_slice(&body).expect("json");
+        assert_eq!(overview[0]["name"], "Attic");
+        assert_eq!(overview[0]["connected"], false);
+        assert_eq!(overview[0]["jobState"], serde_json::Value::Null);
+        assert_eq!(overview[1]["name"], "Workshop");
+        assert_eq!(overview[1]["jobState"], "RUNNING");
+        assert_eq!(overview[1]["percent"], 42);
+        assert_eq!(overview[1]["hmsSeverity"], "common");
+        assert!(overview[1]["id"].is_i64());
+        assert_eq!(overview.as_array().map(Vec::len), Some(2));
+
+        shutdown_printers(&state).await;
+    }
+
+    #[tokio::test]
+    async fn bulk_command_reports_a_result_per_printer() {
+        let (app, state) = test_app().await;
+        let ids = [create_printer(&app).await, create_printer(&app).await];
+
+        let request = serde_json::json!({
+            "printerIds": [ids[0], 999, ids[0], ids[1]],
+            "command": { "type": "pause" }
+        });
+        let (status, body) = send(
+            &app,
+            Method::POST,
+            "/api/printers/bulk/command",
+            Some(request),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&body).expect("json");
+        let results = body["results"].as_array().expect("results");
+        let summary: Vec<(i64, bool, &str)> = results
+            .iter()
+            .map(|result| {
+                (
+                    result["printerId"].as_i64().unwrap_or_default(),
+                    result["ok"].as_bool().unwrap_or_default(),
+                    result["code"].as_str().unwrap_or_default(),
+                )
+            })
+            .collect();
+        // Nothing is connected to MQTT in tests.
+        assert_eq!(
+            summary,
+            [
+                (ids[0], false, PRINTER_NOT_CONNECTED),
+                (999, false, PRINTER_NOT_FOUND),
+                (ids[1], false, PRINTER_NOT_CONNECTED),
+            ]
+        );
+        assert_eq!(results[1]["error"], "printer not found");
+
+        let (status, _) = send(
+            &app,
+            Method::POST,
+            "/api/printers/bulk/command",
+            Some(serde_json::json!({ "printerIds": [], "command": { "type": "pause" } })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        shutdown_printers(&state).await;
+    }
+
+    #[tokio::test]
+    async fn signed_requests_are_verified() {
+        let (app, state) =
+            test_app_with_auth(AuthManager::new(Some("s3cret".to_string()), None)).await;
+        let signed = |method: Method, uri: &str, signed_body: &str, body: &str| {
+            let timestamp = Utc::now().timestamp();
+            let signature =
+                crate::auth::sign("s3cret", &method, uri, signed_body.as_bytes(), timestamp);
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(crate::auth::SIGNATURE_HEADER, signature)
+                .header(crate::auth::TIMESTAMP_HEADER, timestamp)
+                .body(Body::from(body.to_string()))
+                .expect("request")
+        };
+        let printer = serde_json::json!({
+            "name": "X1C",
+            "host": "127.0.0.1",
+            "serial": "01S00A000000001",
+            "accessCode": "12345678"
+        })
+        .to_string();
+
+        // Unsigned requests are refused once a secret is configured.
+        let (status, _) = send(
+            &app,
+            Method::POST,
+            "/api/printers",
+            Some(serde_json::from_str(&printer).expect("json")),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .clone()
+            .oneshot(signed(Method::POST, "/api/printers", &printer, &printer))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .expect("body");
+        let id = serde_json::from_slice::<serde_json::Value>(&body).expect("json")["id"]
+            .as_i64()
+            .expect("id");
+        let uri = format!("/api/printers/{id}/notes");
+        let body = serde_json::json!({ "text": "Signed" }).to_string();
+
+        let response = app
+            .clone()
+            .oneshot(signed(Method::POST, &uri, &body, &body))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let note = hyper::body::to_bytes(response.into_body())
+            .await
+            .expect("body");
+        let note: serde_json::Value = serde_json::from_slice(&note).expect("json");
+        assert_eq!(note["text"], "Signed");
+        assert_eq!(note["author"], "signed-client");
+
+        let tampered = serde_json::json!({ "text": "Tampered" }).to_string();
+        let response = app
+            .clone()
+            .oneshot(signed(Method::POST, &uri, &body, &tampered))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let (status, _) = send(&app, Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let response = app
+            .clone()
+            .oneshot(signed(Method::GET, &uri, "", ""))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::OK);
+
+        shutdown_printers(&state).await;
+    }
+
+    #[tokio::test]
+    async fn status_merges_requested_raw_report_values() {
+        let (app, state) = test_app().await;
+        let id = create_printer(&app).await;
+        {
+            let runtime = state.printers.read().await[&id].clone();
+            let mut status = runtime.state.write().await;
+            status.apply_report(&serde_json::json!({ "print": { "spd_lvl": 2, "fan_gear": 0 } }));
+            // Partial reports keep earlier values.
+            status.apply_report(&serde_json::json!({ "print": { "fan_gear": 15 } }));
+        }
+
+        let uri = format!(
+            "/api/printers/{id}/status?extra=/print/spd_lvl,/print/missing,/print/fan_gear"
+        );
+        let (status, body) = send(&app, Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&body).expect("json");
+        assert_eq!(
+            body["extra"],
+            serde_json::json!({ "/print/spd_lvl": 2, "/print/fan_gear": 15 })
+        );
+        assert!(body.get("rawReport").is_none());
+
+        let (_, body) = send(
+            &app,
+            Method::GET,
+            &format!("/api/printers/{id}/status"),
+            None,
+        )
+        .await;
+        let body: serde_json::Value = serde_json::from_slice(&body).expect("json");
+        assert!(body.get("extra").is_none());
+        let (status, _) = send(
+            &app,
+            Method::GET,
+            &format!("/api/printers/{id}/status?extra=print/spd_lvl"),
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        shutdown_printers(&state).await;
+    }
+
+    #[tokio::test]
+    async fn printer_notes_round_trip() {
+        let (app, state) = test_app().await;
+        let id = create_printer(&app).await;
+        let notes = format!("/api/printers/{id}/notes");
+
+        let (status, body) = send(
+            &app,
+            Method::POST,
+            &notes,
+            Some(serde_json::json!({ "text": "  Replaced the hotend.  " })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let note: serde_json::Value = serde_json::from_slice(&body).expect("json");
+        assert_eq!(note["text"], "Replaced the hotend.");
+        assert_eq!(note["author"], "anonymous");
+        assert_eq!(note["printerId"], id);
+        let (status, _) = send(
+            &app,
+            Method::POST,
+            &notes,
+            Some(serde_json::json!({ "text": " " })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = send(&app, Method::GET, &notes, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let listed: Vec<serde_json::Value> = serde_json::from_slice(&body).expect("json");
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0]["id"], note["id"]);
+
+        let note_uri = format!("{notes}/{}", note["id"]);
+        assert_eq!(
+            send(&app, Method::DELETE, &note_uri, None).await.0,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            send(&app, Method::DELETE, &note_uri, None).await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            send(&app, Method::GET, "/api/printers/999/notes", None)
+                .await
+                .0,
+            StatusCode::NOT_FOUND
+        );
+
+        shutdown_printers(&state).await;
+    }
+
+    #[tokio::test]
+    async fn cosmetic_updates_keep_the_runtime() {
+        let (app, state) = test_app().await;
+        let id = create_printer(&app).await;
+        let uri = format!("/api/printers/{id}");
+        let runtime = |state: Arc<AppState>| async move {
+            state
+                .printers
+                .read()
+                .await
+                .get(&id)
+                .cloned()
+                .expect("runtime")
+        };
+        let original = runtime(Arc::clone(&state)).await;
+
+        let (status, _) = send(
+            &app,
+            Method::PATCH,
+            &uri,
+            Some(serde_json::json!({ "name": "Workshop", "tags": ["room-a"] })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let current = runtime(Arc::clone(&state)).await;
+        assert!(Arc::ptr_eq(&original, &current));
+        assert_eq!(current.config().name, "Workshop");
+        assert_eq!(current.config().tags, ["room-a"]);
+
+        let (status, _) = send(
+            &app,
+            Method::PATCH,
+            &uri,
+            Some(serde_json::json!({ "host": "127.0.0.2" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let replaced = runtime(Arc::clone(&state)).await;
+        assert!(!Arc::ptr_eq(&original, &replaced));
+        assert_eq!(replaced.config().host, "127.0.0.2");
+
+        shutdown_printers(&state).await;
+    }
+
+    #[tokio::test]
+    async fn unsupported_methods_get_allow_and_a_json_body() {
+        let (app, _state) = test_app().await;
+        let request = |method: Method, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .expect("request")
+        };
+
+        let response = app
+            .clone()
+            .oneshot(request(Method::GET, "/api/printers/1/command"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[header::ALLOW], "POST");
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .expect("body");
+        let error: serde_json::Value = serde_json::from_slice(&body).expect("json");
+        assert_eq!(error["code"], METHOD_NOT_ALLOWED);
+
+        let response = app
+            .clone()
+            .oneshot(request(Method::POST, "/api/printers/1"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            response.headers()[header::ALLOW],
+            "GET,HEAD,PUT,PATCH,DELETE"
+        );
+
+        let response = app
+            .clone()
+            .oneshot(request(Method::DELETE, "/api/printers"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[header::ALLOW], "GET,HEAD,POST");
+
+        let (status, body) = send(&app, Method::GET, "/api/nope", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let error: serde_json::Value = serde_json::from_slice(&body).expect("json");
+        assert_eq!(error["code"], ROUTE_NOT_FOUND);
+    }
 }
@@ -1,23 +1,47 @@
+mod auth;
+mod backoff;
+mod command_queue;
 mod commands;
 mod config;
+mod connectivity;
 mod db;
+mod disk_limit;
+mod ftps;
 mod http;
+mod metrics_history;
 mod mqtt;
 mod printers;
+mod ratelimit;
 mod rtsp;
+mod secrets;
 mod state;
+#[cfg(feature = "static-files")]
+mod static_files;
+mod thumbnail;
 mod tls;
+mod webhooks;
 
+use crate::auth::AuthManager;
 use crate::config::AppConfig;
 use crate::http::AppState;
-use crate::printers::PrinterRuntime;
-use std::collections::HashMap;
+use crate::ratelimit::CommandRateLimiter;
+use crate::rtsp::server::RtspServer;
+use crate::secrets::SecretCipher;
+use anyhow::Context;
+use metrics_exporter_prometheus::PrometheusBuilder;
+use std::collections::HashSet;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
-use tracing::info;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
 use tracing_subscriber::EnvFilter;
 
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt()
@@ -28,27 +52,134 @@ async fn main() -> anyhow::Result<()> {
 
     let _ = dotenvy::dotenv();
     let config = AppConfig::from_env()?;
+    let metrics = PrometheusBuilder::new()
+        .install_recorder()
+        .context("install metrics recorder")?;
     let db = db::init(&config.database_url).await?;
-let printers = db::list_printers(&db).await?;
-    let mut runtime_map: HashMap<i64, Arc<PrinterRuntime>> = HashMap::new();
-    for printer in printers {
-        let runtime = PrinterRuntime::spawn(printer.clone(), &config);
-        runtime_map.insert(printer.id, runtime);
+    let cipher = SecretCipher::new(config.secret_key.as_deref());
+    if cipher.is_enabled() {
+        let migrated = db::encrypt_plaintext_secrets(&db, &cipher).await?;
+        if migrated > 0 {
+            info!(migrated, "encrypted plaintext printer access codes");
+        }
+    } else {
+        warn!("SECRET_KEY not set; printer access codes are stored in plaintext");
+    }
+    let shutdown = CancellationToken::new();
+    let runtime_map = printers::spawn_all(&db, &cipher, &config, &shutdown).await?;
+    let printer_ids: HashSet<i64> = runtime_map.keys().copied().collect();
+    match printers::remove_orphaned_output_dirs(Path::new(&config.cmaf_output_dir), &printer_ids)
+        .await
+    {
+        Ok(0) => {}
+        Ok(removed) => info!(removed, "removed cmaf directories of deleted printers"),
+        Err(error) => warn!(?error, "failed to clean up orphaned cmaf directories"),
+    }
+
+    if let Some(max_mb) = config.max_output_disk_mb {
+        tokio::spawn(disk_limit::run(
+            PathBuf::from(&config.cmaf_output_dir),
+            max_mb.saturating_mul(1024 * 1024),
+            shutdown.child_token(),
+        ));
+    }
+
+    if config.metrics_retention_days > 0 {
+        tokio::spawn(metrics_history::run_retention(
+            db.clone(),
+            config.metrics_retention_days,
+            shutdown.child_token(),
+        ));
     }
 
     let addr: SocketAddr = config.http_bind.parse()?;
     info!(%addr, "http server listening");
 
+    let command_limiter = Arc::new(CommandRateLimiter::new(
+        config.command_rate_per_sec,
+        config.command_burst,
+    ));
+    let printers = Arc::new(RwLock::new(runtime_map));
+    let push_server = match config.rtsp_push_listen.as_deref() {
+        Some(listen) => {
+            let server = RtspServer::new(listen.parse()?, config.clone(), Arc::clone(&printers));
+            let shutdown = shutdown.child_token();
+            Some(tokio::spawn(async move {
+                if let Err(error) = server.run(shutdown).await {
+                    warn!(?error, "rtsp push listener stopped");
+                }
+            }))
+        }
+        None => None,
+    };
+
+    let auth = AuthManager::new(
+        config.api_hmac_secret.clone(),
+        config.api_admin_hmac_secret.clone(),
+    );
     let app_state = Arc::new(AppState {
         config,
         db,
-        printers: Arc::new(RwLock::new(runtime_map)),
+        cipher,
+        printers,
+        printer_locks: Arc::default(),
+        command_limiter,
+        auth,
+        metrics: Some(metrics),
+        shutdown: shutdown.clone(),
     });
-    let app = http::router(app_state);
+    let app = http::router(Arc::clone(&app_state))?;
+
+    // SSE and websocket clients never finish on their own, so stop serving
+    // outright instead of waiting for open connections.
+    let server = axum::Server::bind(&addr).serve(app.into_make_service());
+    tokio::select! {
+        result = server => result?,
+        _ = shutdown_signal() => info!("shutdown signal received"),
+    }
 
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await?;
+    shutdown.cancel();
+    let runtimes: Vec<_> = app_state.printers.read().await.values().cloned().collect();
+    let mut draining = JoinSet::new();
+    for runtime in runtimes {
+        draining.spawn(async move { runtime.shutdown_gracefully(SHUTDOWN_GRACE).await });
+    }
+    if let Some(push_server) = push_server {
+        draining.spawn(async move {
+            let _ = tokio::time::timeout(SHUTDOWN_GRACE, push_server).await;
+        });
+    }
+    while draining.join_next().await.is_some() {}
+    info!("printer runtimes stopped");
 
     Ok(())
 }
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(error) = tokio::signal::ctrl_c().await {
+            warn!(?error, "failed to listen for ctrl-c");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(error) => {
+                warn!(?error, "failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
@@ -0,0 +1,100 @@
+use crate::db::{self, MetricsSample};
+use crate::state::PrinterState;
+use chrono::Utc;
+use sqlx::SqlitePool;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+const RETENTION_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Stores a sample each time a reported temperature changes. Reports the
+/// watch channel coalesces are never seen, which only drops in-between readings.
+pub async fn record(db: SqlitePool, printer_id: i64, mut rx: watch::Receiver<PrinterState>) {
+    let mut last_temperatures = None;
+    while rx.changed().await.is_ok() {
+        let sample = {
+            let state = rx.borrow_and_update();
+            // Disconnects republish the last temperatures; they are not new readings.
+            if !state.connected || state.last_update.is_none() {
+                continue;
+            }
+            sample_of(&state)
+        };
+        let temperatures = (sample.nozzle_c, sample.bed_c, sample.chamber_c);
+        if last_temperatures == Some(temperatures) {
+            continue;
+        }
+        last_temperatures = Some(temperatures);
+        if let Err(error) = db::record_metrics(&db, printer_id, &sample).await {
+            warn!(?error, printer_id, "failed to record printer metrics");
+        }
+    }
+}
+
+fn sample_of(state: &PrinterState) -> MetricsSample {
+    MetricsSample {
+        sampled_at: state.last_update.unwrap_or_else(Utc::now),
+        nozzle_c: state.nozzle_c,
+        bed_c: state.bed_c,
+        chamber_c: state.chamber_c,
+        percent: state.percent,
+    }
+}
+
+/// Deletes samples older than `retention_days` every hour until shutdown.
+pub async fn run_retention(db: SqlitePool, retention_days: u64, shutdown: CancellationToken) {
+    // Anything past a century is effectively forever.
+    let retention = chrono::Duration::days(retention_days.min(36_500) as i64);
+    loop {
+        match db::delete_metrics_before(&db, Utc::now() - retention).await {
+            Ok(0) => {}
+            Ok(removed) => info!(removed, retention_days, "expired printer metrics"),
+            Err(error) => warn!(?error, "failed to expire printer metrics"),
+        }
+        tokio::select! {
+            _ = shutdown.cancelled() => return,
+            _ = tokio::time::sleep(RETENTION_CHECK_INTERVAL) => {}
+        }
+    }
+}
+
+/// Parses `90`, `60s`, `5m`, `1h` or `1d` into whole seconds.
+pub fn parse_interval(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|ch: char| !ch.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    number
+        .parse::<i64>()
+        .ok()
+        .and_then(|number| number.checked_mul(scale))
+        .filter(|secs| *secs > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_accept_unit_suffixes() {
+        assert_eq!(parse_interval("60s"), Some(60));
+        assert_eq!(parse_interval("90"), Some(90));
+        assert_eq!(parse_interval("5m"), Some(300));
+        assert_eq!(parse_interval("1h"), Some(3600));
+        assert_eq!(parse_interval("1d"), Some(86_400));
+        assert_eq!(parse_interval("0s"), None);
+        assert_eq!(parse_interval("10w"), None);
+        assert_eq!(parse_interval("s"), None);
+        assert_eq!(parse_interval("-5m"), None);
+    }
+}
@@ -1,10 +1,15 @@
 use crate::commands::CommandRequest;
 use crate::config::{AppConfig, PrinterConfig};
+use crate::connectivity::LinkTimestamp;
 use crate::state::PrinterState;
 use crate::tls;
+use crate::webhooks::{TrackedState, WebhookNotifier};
 use rand::distributions::Alphanumeric;
 use rand::Rng;
-use rumqttc::{AsyncClient, Event, Incoming, MqttOptions, QoS, TlsConfiguration, Transport};
+use rumqttc::{
+    AsyncClient, Event, EventLoop, Incoming, MqttOptions, Outgoing, QoS, TlsConfiguration,
+    Transport,
+};
 use serde_json::Value;
 use std::sync::Arc;
 use std::time::Duration;
@@ -13,18 +18,23 @@ use tracing::{info, warn};
 
 pub async fn run(
     settings: AppConfig,
-    printer: PrinterConfig,
+    config_rx: watch::Receiver<PrinterConfig>,
     state: Arc<RwLock<PrinterState>>,
     mut command_rx: mpsc::Receiver<CommandRequest>,
     status_tx: watch::Sender<PrinterState>,
+    connected_at: LinkTimestamp,
+    webhooks: Option<WebhookNotifier>,
 ) {
-    let report_topic = format!("device/{}/report", printer.serial);
-    let request_topic = format!("device/{}/request", printer.serial);
+    // Connection fields never change under a running task; renames do.
+    let printer = config_rx.borrow().clone();
+    let report_topic = report_topic(&printer);
+    let request_topic = request_topic(&printer);
     let mut sequence_id: u64 = 1;
 
     loop {
         let mqtt_options = build_mqtt_options(&settings, &printer);
         let (client, mut eventloop) = AsyncClient::new(mqtt_options, 10);
+        let mut report_sequence = ReportSequence::default();
 
         if let Err(error) = client
             .subscribe(report_topic.clone(), QoS::AtMostOnce)
@@ -43,16 +53,34 @@ pub async fn run(
                 event = eventloop.poll() => {
                     match event {
                         Ok(Event::Incoming(Incoming::ConnAck(_))) => {
+                            connected_at.touch();
                             set_connected(&state, &status_tx, true).await;
                         }
                         Ok(Event::Incoming(Incoming::Publish(publish))) => {
                             if let Ok(report) = serde_json::from_slice::<Value>(&publish.payload) {
-                                let snapshot = {
+                                if let Some(report_id) = report_sequence_id(&report) {
+                                    let lost = report_sequence.observe(report_id);
+                                    if lost > 0 {
+                                        warn!(lost, sequence_id = report_id, "mqtt reports were lost");
+                                        metrics::counter!(
+                                            "mqtt_lost_messages_total",
+                                            "printer_id" => printer.id.to_string()
+                                        )
+                                        .increment(lost);
+                                    }
+                                }
+                                let (previous, snapshot) = {
                                     let mut guard = state.write().await;
+                                    let previous = TrackedState::of(&guard);
                                     guard.connected = true;
                                     guard.apply_report(&report);
-                                    guard.clone()
+                                    (previous, guard.clone())
                                 };
+                                if let Some(webhooks) = &webhooks {
+                                    for event in previous.events(&snapshot) {
+                                        webhooks.notify(&config_rx.borrow(), &previous, &snapshot, event);
+                                    }
+                                }
                                 let _ = status_tx.send(snapshot);
                             } else {
                                 warn!("failed to parse mqtt report payload");
@@ -69,9 +97,14 @@ pub async fn run(
                 command = command_rx.recv() => {
                     let Some(command) = command else {
                         info!("command channel closed; shutting down mqtt task");
+                        disconnect(&client, &mut eventloop).await;
                         return;
                     };
-                    let payload = command.to_payload(&settings.mqtt_user_id, sequence_id);
+                    // Read per command so a changed user id applies without a reconnect.
+                    let payload = {
+                        let printer = config_rx.borrow();
+                        command.to_payload(printer.resolved_mqtt_user_id(&settings), sequence_id)
+                    };
                     sequence_id = sequence_id.wrapping_add(1);
                     let payload_bytes = match serde_json::to_vec(&payload) {
                         Ok(bytes) => bytes,
@@ -95,7 +128,85 @@ pub async fn run(
     }
 }
 
+/// Spots gaps in the printer's status push sequence ids within one connection.
+#[derive(Default)]
+struct ReportSequence {
+    last: Option<u64>,
+}
+
+impl ReportSequence {
+    /// Reports skipped since the previous one. A lower id means the printer
+    /// restarted its count, which is not a loss.
+    fn observe(&mut self, sequence_id: u64) -> u64 {
+        let lost = match self.last {
+            Some(last) if sequence_id > last => sequence_id - last - 1,
+            _ => 0,
+        };
+        self.last = Some(sequence_id);
+        lost
+    }
+}
+
+/// `print.sequence_id` of a `push_status` report. Command replies echo the
+/// ids we sent, so they are not part of the printer's sequence.
+fn report_sequence_id(report: &Value) -> Option<u64> {
+    let print = report.get("print")?;
+    if print.get("command")?.as_str()? != "push_status" {
+        return None;
+    }
+    match print.get("sequence_id")? {
+        Value::String(value) => value.parse().ok(),
+        value => value.as_u64(),
+    }
+}
+
+/// Sends a clean DISCONNECT, polling the event loop so publishes that are
+/// still buffered in the client reach the printer first.
+async fn disconnect(client: &AsyncClient, eventloop: &mut EventLoop) {
+    if client.disconnect().await.is_err() {
+        return;
+    }
+    let flush = async {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Outgoing(Outgoing::Disconnect)) | Err(_) => return,
+                Ok(_) => {}
+            }
+        }
+    };
+    if tokio::time::timeout(Duration::from_secs(2), flush)
+        .await
+        .is_err()
+    {
+        warn!("timed out flushing mqtt client during shutdown");
+    }
+}
+
+fn report_topic(printer: &PrinterConfig) -> String {
+    format!("device/{}/report", printer.serial)
+}
+
+fn request_topic(printer: &PrinterConfig) -> String {
+    format!("device/{}/request", printer.serial)
+}
+
+/// Credentials and broker address come from the printer; transport settings
+/// from the app config unless the printer overrides them.
 fn build_mqtt_options(config: &AppConfig, printer: &PrinterConfig) -> MqttOptions {
+    let overrides = printer.overrides.clone().unwrap_or_default();
+    let mqtt_tls = overrides.mqtt_tls.unwrap_or(config.mqtt_tls);
+    let mqtt_tls_insecure = overrides
+        .mqtt_tls_insecure
+        .unwrap_or(config.mqtt_tls_insecure);
+    let mqtt_port = overrides
+        .mqtt_port
+        .unwrap_or(if mqtt_tls == config.mqtt_tls {
+            config.mqtt_port
+        } else if mqtt_tls {
+            8883
+        } else {
+            1883
+        });
     let random_suffix: String = rand::thread_rng()
         .sample_iter(&Alphanumeric)
         .take(6)
@@ -106,8 +217,8 @@ fn build_mqtt_options(config: &AppConfig, printer: &PrinterConfig) -> MqttOption
             "{}-{}-{}",
             config.mqtt_client_id, printer.serial, random_suffix
         ),
-        printer.host.clone(),
-        config.mqtt_port,
+        printer.connect_host().to_string(),
+        mqtt_port,
     );
     options.set_credentials("bblp", &printer.access_code);
     options.set_keep_alive(Duration::from_secs(config.mqtt_keep_alive_secs));
@@ -116,8 +227,8 @@ fn build_mqtt_options(config: &AppConfig, printer: &PrinterConfig) -> MqttOption
         config.mqtt_max_outgoing_packet_size,
     );
 
-    if config.mqtt_tls {
-        if config.mqtt_tls_insecure {
+    if mqtt_tls {
+        if mqtt_tls_insecure {
             warn!("mqtt tls verification disabled");
             let tls_config = tls::insecure_client_config();
             options.set_transport(Transport::Tls(TlsConfiguration::Rustls(Arc::new(
@@ -153,3 +264,66 @@ async fn set_connected(
     };
     let _ = status_tx.send(snapshot);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PrinterOverrides;
+
+    const SERIAL: &str = "01S00TEST";
+
+    #[test]
+    fn options_take_credentials_from_the_printer_and_transport_from_settings() {
+        let mut settings = AppConfig::for_tests();
+        settings.mqtt_tls = true;
+        settings.mqtt_port = 8883;
+        settings.mqtt_client_id = "viewer".to_string();
+        let mut printer = PrinterConfig {
+            id: 1,
+            name: "Test".to_string(),
+            host: "[fd00::5]".to_string(),
+            serial: SERIAL.to_string(),
+            access_code: "87654321".to_string(),
+            rtsp_url: None,
+            overrides: None,
+            tags: Vec::new(),
+            mqtt_user_id: None,
+            rtsp_username: None,
+        };
+
+        let options = build_mqtt_options(&settings, &printer);
+        assert_eq!(options.broker_address(), ("fd00::5".to_string(), 8883));
+        assert_eq!(
+            options.credentials(),
+            Some(("bblp".to_string(), "87654321".to_string()))
+        );
+        assert!(options
+            .client_id()
+            .starts_with(&format!("viewer-{SERIAL}-")));
+        assert_eq!(report_topic(&printer), format!("device/{SERIAL}/report"));
+        assert_eq!(request_topic(&printer), format!("device/{SERIAL}/request"));
+
+        printer.overrides = Some(PrinterOverrides {
+            mqtt_tls: Some(false),
+            ..PrinterOverrides::default()
+        });
+        let options = build_mqtt_options(&settings, &printer);
+        assert_eq!(options.broker_address().1, 1883);
+    }
+
+    #[test]
+    fn report_sequence_gaps_count_lost_messages() {
+        let push = |sequence_id: Value| serde_json::json!({ "print": { "command": "push_status", "sequence_id": sequence_id } });
+        assert_eq!(report_sequence_id(&push(Value::from("15"))), Some(15));
+        assert_eq!(report_sequence_id(&push(Value::from(16))), Some(16));
+        let reply = serde_json::json!({ "print": { "command": "pause", "sequence_id": "3" } });
+        assert_eq!(report_sequence_id(&reply), None);
+
+        let mut sequence = ReportSequence::default();
+        assert_eq!(sequence.observe(15), 0);
+        assert_eq!(sequence.observe(17), 1);
+        assert_eq!(sequence.observe(18), 0);
+        assert_eq!(sequence.observe(2), 0);
+        assert_eq!(sequence.observe(6), 3);
+    }
+}
@@ -1,29 +1,67 @@
-use crate::commands::CommandRequest;
+use crate::command_queue::CommandQueue;
 use crate::config::{AppConfig, PrinterConfig};
+use crate::connectivity::LinkTimestamp;
+use crate::db;
+use crate::metrics_history;
 use crate::mqtt;
 use crate::rtsp;
-use crate::rtsp::CmafStream;
+use crate::rtsp::{CmafStream, StreamStats};
+use crate::secrets::SecretCipher;
 use crate::state::PrinterState;
-use std::path::PathBuf;
+use crate::thumbnail::ThumbnailCache;
+use crate::webhooks::WebhookNotifier;
+use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::{mpsc, watch, RwLock};
-use tokio::task::AbortHandle;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch, Mutex, OwnedMutexGuard, RwLock};
+use tokio::task::{AbortHandle, JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+const STATE_PERSIST_INTERVAL: Duration = Duration::from_secs(15);
 
 #[derive(Debug)]
 pub struct PrinterRuntime {
     pub state: Arc<RwLock<PrinterState>>,
     pub status_tx: watch::Sender<PrinterState>,
-    pub command_tx: mpsc::Sender<CommandRequest>,
+    pub command_queue: CommandQueue,
     pub cmaf_dir: PathBuf,
     pub cmaf_stream: CmafStream,
+    pub stream_stats: StreamStats,
+    pub mqtt_connected_at: LinkTimestamp,
+    pub thumbnail: ThumbnailCache,
+    config_tx: watch::Sender<PrinterConfig>,
+    shutdown_token: CancellationToken,
+    drain_tasks: std::sync::Mutex<Vec<JoinHandle<()>>>,
     mqtt_abort: AbortHandle,
     rtsp_abort: AbortHandle,
+    queue_abort: AbortHandle,
+    persist_abort: AbortHandle,
+    metrics_abort: AbortHandle,
 }
 
 impl PrinterRuntime {
-    pub fn spawn(config: PrinterConfig, settings: &AppConfig) -> Arc<Self> {
-        let state = Arc::new(RwLock::new(PrinterState::default()));
-        let (status_tx, _status_rx) = watch::channel(PrinterState::default());
+    pub async fn spawn(
+        config: PrinterConfig,
+        settings: &AppConfig,
+        db: SqlitePool,
+        shutdown_token: CancellationToken,
+    ) -> Arc<Self> {
+        let initial = match db::load_printer_state(&db, config.id).await {
+            Ok(cached) => cached.unwrap_or_default(),
+            Err(error) => {
+                warn!(
+                    ?error,
+                    printer_id = config.id,
+                    "failed to restore cached printer state"
+                );
+                PrinterState::default()
+            }
+        };
+        let state = Arc::new(RwLock::new(initial.clone()));
+        let (status_tx, status_rx) = watch::channel(initial);
         let (command_tx, command_rx) = mpsc::channel(32);
         let cmaf_dir = PathBuf::from(&settings.cmaf_output_dir).join(config.id.to_string());
         let part_duration = if settings.cmaf_part_duration_secs > 0.0 {
@@ -36,11 +74,19 @@ impl PrinterRuntime {
         let backlog_capacity =
             ((settings.cmaf_ws_backlog_secs / part_duration).ceil() as usize).clamp(1, 240);
         let cmaf_stream = CmafStream::new(backlog_capacity);
+        let stream_stats = StreamStats::new();
 
         let mqtt_state = Arc::clone(&state);
         let mqtt_settings = settings.clone();
-        let mqtt_config = config.clone();
+        let (config_tx, mqtt_config) = watch::channel(config.clone());
         let mqtt_status_tx = status_tx.clone();
+        let mqtt_connected_at = LinkTimestamp::new();
+        let mqtt_link = mqtt_connected_at.clone();
+        let webhooks = settings.webhook_url.as_deref().and_then(|url| {
+            WebhookNotifier::new(url)
+                .map_err(|error| warn!(?error, "failed to build webhook client"))
+                .ok()
+        });
         let mqtt_handle = tokio::spawn(async move {
             mqtt::run(
                 mqtt_settings,
@@ -48,22 +94,42 @@ impl PrinterRuntime {
                 mqtt_state,
                 command_rx,
                 mqtt_status_tx,
+                mqtt_link,
+                webhooks,
             )
             .await;
         });
 
         let video_settings = settings.clone();
         let video_config = config.clone();
-        let video_state = Arc::clone(&state);
+        let video_status_rx = status_tx.subscribe();
         let video_cmaf_dir = cmaf_dir.clone();
         let video_stream = cmaf_stream.clone();
+        let video_stats = stream_stats.clone();
+        let video_shutdown = shutdown_token.clone();
+        let metrics_handle = tokio::spawn(metrics_history::record(
+            db.clone(),
+            config.id,
+            status_tx.subscribe(),
+        ));
+        let persist_handle = tokio::spawn(persist_state(db, config.id, status_rx));
+
+        let command_queue = CommandQueue::new();
+        let queue_handle = tokio::spawn(command_queue.clone().run(
+            command_tx,
+            Duration::from_millis(settings.command_min_spacing_ms),
+            shutdown_token.clone(),
+        ));
+
         let rtsp_handle = tokio::spawn(async move {
             rtsp::run_rtsp_hls(
                 video_settings,
                 video_config,
-                video_state,
+                video_status_rx,
                 video_cmaf_dir,
                 video_stream,
+                video_stats,
+                video_shutdown,
             )
             .await;
         });
@@ -71,16 +137,287 @@ impl PrinterRuntime {
         Arc::new(Self {
             state,
             status_tx,
-            command_tx,
+            command_queue,
             cmaf_dir,
             cmaf_stream,
+            stream_stats,
+            mqtt_connected_at,
+            thumbnail: ThumbnailCache::new(),
+            config_tx,
+            shutdown_token,
             mqtt_abort: mqtt_handle.abort_handle(),
             rtsp_abort: rtsp_handle.abort_handle(),
+            queue_abort: queue_handle.abort_handle(),
+            persist_abort: persist_handle.abort_handle(),
+            metrics_abort: metrics_handle.abort_handle(),
+            drain_tasks: std::sync::Mutex::new(vec![queue_handle, mqtt_handle, rtsp_handle]),
+        })
+    }
+
+    /// The printer's current config, including edits applied through
+    /// `update_config` since the runtime was spawned.
+    pub fn config(&self) -> PrinterConfig {
+        self.config_tx.borrow().clone()
+    }
+
+    /// Applies a config edit to the running printer. Returns `false` when the
+    /// edit changes how it connects, so the runtime has to be respawned.
+    pub fn update_config(&self, config: PrinterConfig) -> bool {
+        if !self.config_tx.borrow().same_connection(&config) {
+            return false;
+        }
+        self.config_tx.send_replace(config);
+        true
+    }
+
+    /// Stops the runtime cleanly: pending commands are flushed to the printer
+    /// and the open CMAF segment is finalized before tasks are aborted.
+    pub async fn shutdown_gracefully(&self, grace: Duration) {
+        self.shutdown_token.cancel();
+        let tasks = self
+            .drain_tasks
+            .lock()
+            .map(|mut tasks| std::mem::take(&mut *tasks))
+            .unwrap_or_default();
+        let drained = tokio::time::timeout(grace, async {
+            for task in tasks {
+                let _ = task.await;
+            }
         })
+        .await;
+        if drained.is_err() {
+            warn!("printer runtime did not stop within grace period; aborting");
+        }
+        self.shutdown();
+    }
+
+    /// Aborts all tasks and waits up to `settle` for them to finish, so the
+    /// printer's MQTT and RTSP sockets are closed before a new runtime connects.
+    pub async fn shutdown_and_wait(&self, settle: Duration) {
+        self.shutdown();
+        let tasks = self
+            .drain_tasks
+            .lock()
+            .map(|mut tasks| std::mem::take(&mut *tasks))
+            .unwrap_or_default();
+        let settled = tokio::time::timeout(settle, async {
+            for task in tasks {
+                let _ = task.await;
+            }
+        })
+        .await;
+        if settled.is_err() {
+            warn!("printer runtime tasks did not settle after abort");
+        }
     }
 
     pub fn shutdown(&self) {
         self.mqtt_abort.abort();
         self.rtsp_abort.abort();
+        self.queue_abort.abort();
+        self.persist_abort.abort();
+        self.metrics_abort.abort();
+    }
+}
+
+/// Dropping the last handle stops the printer's tasks even if `shutdown` was
+/// never called; aborting also wakes them out of any pending select.
+impl Drop for PrinterRuntime {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Per-printer locks held while a printer's runtime is replaced or removed, so
+/// a reset cannot interleave with a delete, restore or another reset.
+#[derive(Default)]
+pub struct PrinterLocks {
+    locks: std::sync::Mutex<HashMap<i64, Arc<Mutex<()>>>>,
+}
+
+impl PrinterLocks {
+    pub async fn lock(&self, printer_id: i64) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self
+                .locks
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            Arc::clone(locks.entry(printer_id).or_default())
+        };
+        lock.lock_owned().await
+    }
+}
+
+/// Starts a runtime for every printer in the database, so printers that
+/// existed before a restart are served without being touched through the API.
+pub async fn spawn_all(
+    db: &SqlitePool,
+    cipher: &SecretCipher,
+    settings: &AppConfig,
+    shutdown: &CancellationToken,
+) -> anyhow::Result<HashMap<i64, Arc<PrinterRuntime>>> {
+    let mut runtimes = HashMap::new();
+    for printer in db::list_printers(db, cipher).await? {
+        let id = printer.id;
+        let runtime =
+            PrinterRuntime::spawn(printer, settings, db.clone(), shutdown.child_token()).await;
+        runtimes.insert(id, runtime);
+    }
+    Ok(runtimes)
+}
+
+/// Removes per-printer CMAF directories left behind by printers that no longer
+/// exist, e.g. deleted while the server was down. Only directories named by a
+/// printer id are touched; returns how many were removed.
+pub async fn remove_orphaned_output_dirs(
+    output_dir: &Path,
+    printer_ids: &HashSet<i64>,
+) -> anyhow::Result<usize> {
+    let mut entries = match tokio::fs::read_dir(output_dir).await {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(error) => return Err(error.into()),
+    };
+    let mut removed = 0;
+    while let Some(entry) = entries.next_entry().await? {
+        let Some(id) = entry
+            .file_name()
+            .to_str()
+            .filter(|name| name.bytes().all(|byte| byte.is_ascii_digit()))
+            .and_then(|name| name.parse::<i64>().ok())
+        else {
+            continue;
+        };
+        if printer_ids.contains(&id) || !entry.file_type().await?.is_dir() {
+            continue;
+        }
+        tokio::fs::remove_dir_all(entry.path()).await?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+async fn persist_state(db: SqlitePool, printer_id: i64, mut rx: watch::Receiver<PrinterState>) {
+    loop {
+        if rx.changed().await.is_err() {
+            return;
+        }
+        let snapshot = rx.borrow_and_update().clone();
+        // Disconnect events clear `last_update`; keep the last live snapshot instead.
+        if snapshot.connected && snapshot.last_update.is_some() {
+            if let Err(error) = db::save_printer_state(&db, printer_id, &snapshot).await {
+                warn!(?error, printer_id, "failed to persist printer state");
+            }
+        }
+        tokio::time::sleep(STATE_PERSIST_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn orphaned_output_dirs_are_removed() {
+        let root = std::env::temp_dir().join(format!("cmaf-orphans-{}", rand::random::<u64>()));
+        for dir in ["1", "7", "notes"] {
+            std::fs::create_dir_all(root.join(dir)).expect("create dir");
+        }
+        std::fs::write(root.join("3"), b"not a directory").expect("write file");
+        std::fs::write(root.join("7/init.mp4"), b"stale").expect("write file");
+
+        let removed = remove_orphaned_output_dirs(&root, &HashSet::from([1]))
+            .await
+            .expect("cleanup");
+
+        assert_eq!(removed, 1);
+        assert!(root.join("1").is_dir());
+        assert!(!root.join("7").exists());
+        assert!(root.join("notes").is_dir());
+        assert!(root.join("3").is_file());
+        assert_eq!(
+            remove_orphaned_output_dirs(&root.join("missing"), &HashSet::new())
+                .await
+                .expect("missing root"),
+            0
+        );
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn every_stored_printer_gets_a_runtime_at_boot() {
+        let db = db::init("sqlite::memory:").await.expect("init db");
+        let cipher = SecretCipher::new(None);
+        let settings = AppConfig::for_tests();
+        let mut ids = HashSet::new();
+        for serial in ["01S00A000000001", "01S00A000000002", "01S00A000000003"] {
+            let printer = db::create_printer(
+                &db,
+                &cipher,
+                serde_json::from_value(serde_json::json!({
+                    "name": serial,
+                    "host": "127.0.0.1",
+                    "serial": serial,
+                    "accessCode": "12345678"
+                }))
+                .expect("payload"),
+            )
+            .await
+            .expect("create printer");
+            ids.insert(printer.id);
+        }
+        let deleted = ids.iter().copied().max().expect("printer");
+        db::delete_printer(&db, deleted).await.expect("delete");
+        ids.remove(&deleted);
+
+        let shutdown = CancellationToken::new();
+        let runtimes = spawn_all(&db, &cipher, &settings, &shutdown)
+            .await
+            .expect("spawn runtimes");
+
+        assert_eq!(runtimes.keys().copied().collect::<HashSet<_>>(), ids);
+        for (id, runtime) in &runtimes {
+            assert_eq!(runtime.config().id, *id);
+        }
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn dropping_runtime_aborts_its_tasks() {
+        let path = std::env::temp_dir().join(format!(
+            "bambu-lan-viewer-test-{}.db",
+            rand::random::<u64>()
+        ));
+        let db = db::init(&format!("sqlite://{}", path.display()))
+            .await
+            .expect("init db");
+        let settings = AppConfig::for_tests();
+        let printer = PrinterConfig {
+            id: 1,
+            name: "Test".to_string(),
+            host: "127.0.0.1".to_string(),
+            serial: "SERIAL".to_string(),
+            access_code: "12345678".to_string(),
+            rtsp_url: None,
+            overrides: None,
+            tags: Vec::new(),
+            mqtt_user_id: None,
+            rtsp_username: None,
+        };
+
+        let runtime = PrinterRuntime::spawn(printer, &settings, db, CancellationToken::new()).await;
+        // The MQTT and RTSP tasks each hold the shared state until they exit.
+        let state = Arc::downgrade(&runtime.state);
+        drop(runtime);
+
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while state.upgrade().is_some() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("printer tasks still running after drop");
+        let _ = std::fs::remove_file(path);
     }
 }
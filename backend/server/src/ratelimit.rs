@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+#[derive(Debug)]
+pub struct CommandRateLimiter {
+    rate_per_sec: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<i64, TokenBucket>>,
+}
+
+impl CommandRateLimiter {
+    pub fn new(rate_per_sec: f64, burst: u32) -> Self {
+        let rate_per_sec = if rate_per_sec.is_finite() && rate_per_sec > 0.0 {
+            rate_per_sec
+        } else {
+            5.0
+        };
+        Self {
+            rate_per_sec,
+            burst: f64::from(burst.max(1)),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes `cost` tokens from the printer's bucket, or returns how long the
+    /// caller should wait before retrying.
+    pub fn check(&self, printer_id: i64, cost: u32) -> Result<(), Duration> {
+        self.check_at(printer_id, cost, Instant::now())
+    }
+
+    fn check_at(&self, printer_id: i64, cost: u32, now: Instant) -> Result<(), Duration> {
+        if cost == 0 {
+            return Ok(());
+        }
+        let cost = f64::from(cost).min(self.burst);
+        let Ok(mut buckets) = self.buckets.lock() else {
+            return Ok(());
+        };
+        let bucket = buckets.entry(printer_id).or_insert(TokenBucket {
+            tokens: self.burst,
+            updated_at: now,
+        });
+        let elapsed = now
+            .saturating_duration_since(bucket.updated_at)
+            .as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate_per_sec).min(self.burst);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            return Ok(());
+        }
+        let missing = cost - bucket.tokens;
+        Err(Duration::from_secs_f64(missing / self.rate_per_sec))
+    }
+
+    pub fn remove(&self, printer_id: i64) {
+        if let Ok(mut buckets) = self.buckets.lock() {
+            buckets.remove(&printer_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_commands_beyond_burst_until_refilled() {
+        let limiter = CommandRateLimiter::new(5.0, 3);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check_at(1, 1, start).is_ok());
+        }
+        let retry_after = limiter.check_at(1, 1, start).expect_err("fourth rejected");
+        assert_eq!(retry_after, Duration::from_millis(200));
+
+        // Other printers have their own bucket.
+        assert!(limiter.check_at(2, 1, start).is_ok());
+
+        let later = start + Duration::from_millis(200);
+        assert!(limiter.check_at(1, 1, later).is_ok());
+        assert!(limiter.check_at(1, 1, later).is_err());
+    }
+
+    #[test]
+    fn zero_cost_commands_are_never_limited() {
+        let limiter = CommandRateLimiter::new(1.0, 1);
+        let now = Instant::now();
+
+        assert!(limiter.check_at(1, 1, now).is_ok());
+        assert!(limiter.check_at(1, 1, now).is_err());
+        assert!(limiter.check_at(1, 0, now).is_ok());
+    }
+}
@@ -1,5 +1,6 @@
 use base64::{engine::general_purpose, Engine as _};
 use rand::RngCore;
+use sha2::{Digest, Sha256};
 
 #[derive(Debug, Clone)]
 pub struct RtspCredentials {
@@ -7,6 +8,22 @@ pub struct RtspCredentials {
     pub password: String,
 }
 
+impl RtspCredentials {
+    /// Checks an `Authorization: Basic` header sent to our push listener.
+    pub fn matches_basic(&self, header: Option<&str>) -> bool {
+        let Some(encoded) = header.and_then(|value| value.trim().strip_prefix("Basic ")) else {
+            return false;
+        };
+        let Ok(decoded) = general_purpose::STANDARD.decode(encoded.trim()) else {
+            return false;
+        };
+        // Comparing digests keeps the time taken independent of how much of
+        // the password matched.
+        let expected = format!("{}:{}", self.username, self.password);
+        Sha256::digest(decoded) == Sha256::digest(expected.as_bytes())
+    }
+}
+
 pub struct RtspAuthenticator {
     credentials: RtspCredentials,
     digest: Option<DigestChallenge>,
@@ -9,10 +9,11 @@ use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
 use tokio::net::TcpStream;
 use tokio::sync::{mpsc, oneshot, Mutex};
-use tokio::time::sleep;
+use tokio::task::AbortHandle;
+use tokio::time::{sleep, timeout};
 use tokio_rustls::TlsConnector;
 use tracing::info;
-use url::Url;
+use url::{Host, Url};
 
 #[derive(Debug)]
 pub struct InterleavedPacket {
@@ -20,31 +21,84 @@ pub struct InterleavedPacket {
     pub payload: Vec<u8>,
 }
 
+const TEARDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
 pub struct RtspSession {
     pub sdp: SdpInfo,
     pub rtp_channel: u8,
     pub interleaved_rx: mpsc::Receiver<InterleavedPacket>,
-    _connection: Arc<RtspConnection>,
+    connection: Arc<RtspConnection>,
+    control_uri: String,
+}
+
+impl RtspSession {
+    /// Sends TEARDOWN so the printer frees the session, then closes the
+    /// connection. Errors are logged; the session is gone either way.
+    pub async fn teardown(self) {
+        let request = self
+            .connection
+            .send_request("TEARDOWN", &self.control_uri, HashMap::new());
+        match timeout(TEARDOWN_TIMEOUT, request).await {
+            Ok(Ok(response)) if response.status_code == 200 => {
+                tracing::debug!("rtsp session torn down");
+            }
+            Ok(Ok(response)) => {
+                tracing::debug!(status = response.status_code, "rtsp teardown rejected");
+            }
+            Ok(Err(error)) => tracing::debug!(?error, "rtsp teardown failed"),
+            Err(_) => tracing::debug!("rtsp teardown timed out"),
+        }
+        let _ = self.connection.writer.lock().await.shutdown().await;
+    }
+}
+
+impl Drop for RtspSession {
+    fn drop(&mut self) {
+        self.connection.abort_tasks();
+    }
 }
 
 pub struct RtspClient {
     url: Url,
     credentials: Option<RtspCredentials>,
     tls_insecure: bool,
+    user_agent: String,
 }
 
 impl RtspClient {
-    pub fn new(url: Url, credentials: Option<RtspCredentials>, tls_insecure: bool) -> Self {
+    pub fn new(
+        url: Url,
+        credentials: Option<RtspCredentials>,
+        tls_insecure: bool,
+        user_agent: String,
+    ) -> Self {
         Self {
             url,
             credentials,
             tls_insecure,
+            user_agent,
         }
     }
 
     pub async fn start(self) -> anyhow::Result<RtspSession> {
-        let (connection, interleaved_rx) =
-            RtspConnection::connect(&self.url, self.credentials, self.tls_insecure).await?;
+        let (connection, interleaved_rx) = RtspConnection::connect(
+            &self.url,
+            self.credentials,
+            self.tls_insecure,
+            self.user_agent,
+        )
+        .await?;
+
+        // Some servers only refresh the session timeout on GET_PARAMETER, so
+        // check what they support before picking the keepalive method.
+        let options = connection
+            .send_request_with_retry("OPTIONS", self.url.as_str(), HashMap::new())
+            .await?;
+        let mut public = if options.status_code == 200 {
+            options.header("public").map(str::to_string)
+        } else {
+            None
+        };
 
         let describe = connection
             .send_request_with_retry(
@@ -63,6 +117,9 @@ impl RtspClient {
             );
         }
 
+        if public.is_none() {
+            public = describe.header("public").map(str::to_string);
+        }
         let sdp = parse_sdp(&describe.body).ok_or_else(|| anyhow::anyhow!("invalid SDP"))?;
         let base_url = describe
             .header("content-base")
@@ -117,13 +174,16 @@ impl RtspClient {
             );
         }
 
-        connection.start_keepalive(play_uri).await;
+        connection
+            .start_keepalive(play_uri.clone(), keepalive_method(public.as_deref()))
+            .await;
 
         Ok(RtspSession {
             sdp,
             rtp_channel,
             interleaved_rx,
-            _connection: connection,
+            connection,
+            control_uri: play_uri,
         })
     }
 }
@@ -135,6 +195,10 @@ struct RtspConnection {
     session_id: Mutex<Option<String>>,
     session_timeout: Mutex<Option<Duration>>,
     cseq: Mutex<u32>,
+    user_agent: String,
+    // Reader and keepalive tasks both hold the connection, so they must be
+    // aborted explicitly for it to be dropped.
+    tasks: std::sync::Mutex<Vec<AbortHandle>>,
 }
 
 trait RtspStream: AsyncRead + AsyncWrite + Unpin + Send {}
@@ -142,17 +206,57 @@ impl<T> RtspStream for T where T: AsyncRead + AsyncWrite + Unpin + Send {}
 
 type BoxedStream = Box<dyn RtspStream>;
 
+const RTSP_DEFAULT_PORT: u16 = 554;
+/// Bambu printers serve RTSPS on 322; generic RTSPS endpoints use 443.
+const RTSPS_DEFAULT_PORTS: [u16; 2] = [322, 443];
+
+/// Ports to try in order: the explicit URL port, else the scheme defaults.
+fn candidate_ports(url: &Url) -> Vec<u16> {
+    if let Some(port) = url.port() {
+        return vec![port];
+    }
+    if url.scheme().eq_ignore_ascii_case("rtsps") {
+        RTSPS_DEFAULT_PORTS.to_vec()
+    } else {
+        vec![RTSP_DEFAULT_PORT]
+    }
+}
+
+/// URL host in connectable form; IPv6 literals lose their brackets.
+fn url_host(url: &Url) -> Option<String> {
+    match url.host()? {
+        Host::Domain(domain) => Some(domain.to_string()),
+        Host::Ipv4(addr) => Some(addr.to_string()),
+        Host::Ipv6(addr) => Some(addr.to_string()),
+    }
+}
+
+async fn connect_tcp(host: &str, ports: &[u16]) -> anyhow::Result<TcpStream> {
+    let mut last_error = None;
+    for &port in ports {
+        match TcpStream::connect((host, port)).await {
+            Ok(stream) => return Ok(stream),
+            Err(error) => {
+                tracing::debug!(?error, host, port, "rtsp connect attempt failed");
+                last_error = Some(error);
+            }
+        }
+    }
+    match last_error {
+        Some(error) => Err(error).context("rtsp connect"),
+        None => anyhow::bail!("rtsp connect: no port to try"),
+    }
+}
+
 impl RtspConnection {
     async fn connect(
         url: &Url,
         credentials: Option<RtspCredentials>,
         tls_insecure: bool,
+        user_agent: String,
     ) -> anyhow::Result<(Arc<Self>, mpsc::Receiver<InterleavedPacket>)> {
-        let host = url.host_str().unwrap_or("");
-        let port = url.port().unwrap_or(322);
-        let stream = TcpStream::connect((host, port))
-            .await
-            .context("rtsp connect")?;
+        let host = url_host(url).ok_or_else(|| anyhow::anyhow!("rtsp url has no host"))?;
+        let stream = connect_tcp(&host, &candidate_ports(url)).await?;
 
         let stream: BoxedStream = if url.scheme().eq_ignore_ascii_case("rtsps") {
             let tls_config = if tls_insecure {
@@ -164,8 +268,7 @@ impl RtspConnection {
                     .with_no_client_auth()
             };
             let connector = TlsConnector::from(Arc::new(tls_config));
-            let server_name = rustls::ServerName::try_from(host)
-                .map_err(|_| anyhow::anyhow!("invalid server name"))?;
+            let server_name = tls::server_name(&host)?;
             let tls_stream = connector.connect(server_name, stream).await?;
             Box::new(tls_stream)
         } else {
@@ -182,14 +285,17 @@ impl RtspConnection {
             session_id: Mutex::new(None),
             session_timeout: Mutex::new(None),
             cseq: Mutex::new(1),
+            user_agent,
+            tasks: std::sync::Mutex::new(Vec::new()),
         });
 
         let connection_clone = Arc::clone(&connection);
-        tokio::spawn(async move {
+        let reader_task = tokio::spawn(async move {
             if let Err(error) = reader_loop(reader, connection_clone, interleaved_tx).await {
                 tracing::warn!(?error, "rtsp reader loop ended");
             }
         });
+        connection.track_task(reader_task.abort_handle());
 
         Ok((connection, interleaved_rx))
     }
@@ -246,7 +352,14 @@ impl RtspConnection {
             .as_mut()
             .map(|auth| auth.authorization_header(method, uri));
 
-        let request = build_request(method, uri, cseq, &headers, auth_header.as_deref());
+        let request = build_request(
+            method,
+            uri,
+            cseq,
+            &self.user_agent,
+            &headers,
+            auth_header.as_deref(),
+        );
         let (tx, rx) = oneshot::channel();
         self.pending.lock().await.insert(cseq, tx);
 
@@ -260,7 +373,7 @@ impl RtspConnection {
             .map_err(|_| anyhow::anyhow!("rtsp response channel closed"))
     }
 
-    async fn start_keepalive(self: &Arc<Self>, uri: String) {
+    async fn start_keepalive(self: &Arc<Self>, uri: String, method: &'static str) {
         let timeout = *self.session_timeout.lock().await;
         let interval = if let Some(timeout) = timeout {
             let secs = timeout.as_secs_f64();
@@ -269,12 +382,15 @@ impl RtspConnection {
             Duration::from_secs(5)
         };
         let connection = Arc::clone(self);
-        tokio::spawn(async move {
+        let keepalive_task = tokio::spawn(async move {
             loop {
                 sleep(interval).await;
-                let headers = HashMap::new();
+                let mut headers = HashMap::new();
+                if let Some(session_id) = connection.session_id.lock().await.clone() {
+                    headers.insert("Session".to_string(), session_id);
+                }
                 let result = connection
-                    .send_request_with_retry("OPTIONS", &uri, headers)
+                    .send_request_with_retry(method, &uri, headers)
                     .await;
                 if let Err(error) = result {
                     tracing::warn!(?error, "rtsp keepalive failed");
@@ -282,6 +398,21 @@ impl RtspConnection {
                 }
             }
         });
+        self.track_task(keepalive_task.abort_handle());
+    }
+
+    fn track_task(&self, handle: AbortHandle) {
+        if let Ok(mut tasks) = self.tasks.lock() {
+            tasks.push(handle);
+        }
+    }
+
+    fn abort_tasks(&self) {
+        if let Ok(mut tasks) = self.tasks.lock() {
+            for task in tasks.drain(..) {
+                task.abort();
+            }
+        }
     }
 }
 
@@ -310,6 +441,9 @@ async fn reader_loop(
                         return Ok(());
                     }
                 }
+                RtspEvent::Request(request) => {
+                    tracing::debug!(method = %request.method, "ignoring rtsp request from server");
+                }
                 RtspEvent::Response(response) => {
                     if let Some(session_info) = parse_session_info(&response) {
                         *connection.session_id.lock().await = Some(session_info.0);
@@ -332,13 +466,14 @@ fn build_request(
     method: &str,
     uri: &str,
     cseq: u32,
+    user_agent: &str,
     headers: &HashMap<String, String>,
     auth_header: Option<&str>,
 ) -> String {
     let mut lines = Vec::new();
     lines.push(format!("{} {} RTSP/1.0", method, uri));
     lines.push(format!("CSeq: {}", cseq));
-    lines.push("User-Agent: BambuLANViewer/1.0".to_string());
+    lines.push(format!("User-Agent: {}", user_agent));
     for (key, value) in headers {
         lines.push(format!("{}: {}", key, value));
     }
@@ -350,8 +485,25 @@ fn build_request(
     lines.join("\r\n")
 }
 
+/// Prefers GET_PARAMETER when the server lists it in `Public`.
+fn keepalive_method(public: Option<&str>) -> &'static str {
+    let supports_get_parameter = public.is_some_and(|methods| {
+        methods
+            .split(',')
+            .any(|method| method.trim().eq_ignore_ascii_case("GET_PARAMETER"))
+    });
+    if supports_get_parameter {
+        "GET_PARAMETER"
+    } else {
+        "OPTIONS"
+    }
+}
+
 fn parse_interleaved_channels(response: &RtspResponse) -> Option<(u8, u8)> {
-    let transport = response.header("transport")?;
+    parse_interleaved(response.header("transport")?)
+}
+
+pub(crate) fn parse_interleaved(transport: &str) -> Option<(u8, u8)> {
     for part in transport.split(';') {
         let trimmed = part.trim();
         if let Some(value) = trimmed.strip_prefix("interleaved=") {
@@ -396,3 +548,80 @@ fn normalize_base_url(value: &str, fallback: &Url) -> Option<Url> {
     }
     Some(url)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_carry_the_configured_user_agent() {
+        let request = build_request(
+            "OPTIONS",
+            "rtsp://10.0.0.5/live",
+            3,
+            "RelayFriendly/2.0",
+            &HashMap::new(),
+            None,
+        );
+
+        assert!(request.starts_with("OPTIONS rtsp://10.0.0.5/live RTSP/1.0\r\nCSeq: 3\r\n"));
+        assert!(request.contains("\r\nUser-Agent: RelayFriendly/2.0\r\n"));
+        assert!(request.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn default_port_follows_scheme() {
+        let ports = |url: &str| candidate_ports(&Url::parse(url).expect("url"));
+
+        assert_eq!(ports("rtsp://10.0.0.5/streaming/live/1"), vec![554]);
+        assert_eq!(ports("rtsps://10.0.0.5/streaming/live/1"), vec![322, 443]);
+        assert_eq!(ports("RTSPS://10.0.0.5/streaming/live/1"), vec![322, 443]);
+        assert_eq!(ports("rtsp://10.0.0.5:8554/live"), vec![8554]);
+        assert_eq!(ports("rtsps://10.0.0.5:322/live"), vec![322]);
+    }
+
+    #[test]
+    fn ipv6_url_host_drops_brackets() {
+        let host = |url: &str| url_host(&Url::parse(url).expect("url"));
+
+        assert_eq!(
+            host("rtsps://[fd00::1]:322/streaming/live/1").as_deref(),
+            Some("fd00::1")
+        );
+        assert_eq!(
+            host("rtsp://192.168.1.20/live").as_deref(),
+            Some("192.168.1.20")
+        );
+        assert_eq!(
+            host("rtsp://printer.lan/live").as_deref(),
+            Some("printer.lan")
+        );
+        assert!(matches!(
+            tls::server_name("fd00::1"),
+            Ok(rustls::ServerName::IpAddress(_))
+        ));
+        assert!(matches!(
+            tls::server_name("printer.lan"),
+            Ok(rustls::ServerName::DnsName(_))
+        ));
+    }
+
+    #[test]
+    fn keepalive_prefers_get_parameter_when_public() {
+        assert_eq!(
+            keepalive_method(Some(
+                "OPTIONS, DESCRIBE, SETUP, PLAY, TEARDOWN, GET_PARAMETER"
+            )),
+            "GET_PARAMETER"
+        );
+        assert_eq!(
+            keepalive_method(Some("OPTIONS,DESCRIBE,get_parameter")),
+            "GET_PARAMETER"
+        );
+        assert_eq!(
+            keepalive_method(Some("OPTIONS, DESCRIBE, SET_PARAMETER")),
+            "OPTIONS"
+        );
+        assert_eq!(keepalive_method(None), "OPTIONS");
+    }
+}
@@ -1,31 +1,72 @@
 use crate::rtsp::depacketizer::AccessUnit;
 use crate::rtsp::stream::{CmafInit, CmafStream};
-use bytes::Bytes;
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::{BlockEncryptMut, KeyIvInit};
+use bytes::{BufMut, Bytes, BytesMut};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
 use std::collections::VecDeque;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 use tokio::fs;
 use tokio::io::AsyncWriteExt;
 use tracing::debug;
 
+/// Segment file name used unless `CMAF_SEGMENT_PATTERN` overrides it.
+/// `{session}` is new for every segmenter, so files a previous run left behind
+/// are never overwritten or mistaken for the current run's segments.
+pub const DEFAULT_SEGMENT_PATTERN: &str = "seg-{session}-{seq}.m4s";
+
+/// Where finished fragments go besides the in-memory `CmafStream`.
+#[derive(Debug, Clone)]
+pub enum WriterMode {
+    /// Also write segments, init and playlist into this directory.
+    File(PathBuf),
+    Memory,
+}
+
+impl WriterMode {
+    fn dir(&self) -> Option<&Path> {
+        match self {
+            Self::File(dir) => Some(dir),
+            Self::Memory => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct CmafSegmenter {
-    output_dir: PathBuf,
+    writer: WriterMode,
     target_duration: f64,
     window: usize,
     sequence: u64,
     segments: VecDeque<SegmentInfo>,
     current: Option<SegmentBuffer>,
+    vps: Option<Vec<u8>>,
     sps: Option<Vec<u8>>,
     pps: Option<Vec<u8>>,
+    last_init_vps: Option<Vec<u8>>,
     last_init_sps: Option<Vec<u8>>,
     last_init_pps: Option<Vec<u8>>,
     part_duration: f64,
     last_sample_duration: Option<u32>,
     fragment_sequence: u32,
+    bytes_produced: u64,
     stream: Option<CmafStream>,
-    write_files: bool,
     warned_non_monotonic_pts: bool,
     fallback_frame_duration_90k: u32,
+    emit_sidx: bool,
+    encryption_key: Option<[u8; 16]>,
+    encryption_key_uri: String,
+    max_segment_bytes: Option<u64>,
+    max_segment_secs: Option<f64>,
+    discontinuity_sequence: u64,
+    frame_rate: Option<f64>,
+    session: String,
+    pending_events: Vec<PlaylistEvent>,
+    event_sequence: u64,
+    segment_pattern: String,
+    segments_per_dir: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
@@ -34,6 +75,16 @@ struct SegmentInfo {
     duration: f64,
     filename: String,
     parts: Vec<PartInfo>,
+    discontinuity: bool,
+    started_at: DateTime<Utc>,
+    events: Vec<PlaylistEvent>,
+}
+
+/// A printer state change marked at the start of the following segment.
+#[derive(Debug, Clone)]
+struct PlaylistEvent {
+    id: String,
+    event_type: String,
 }
 
 #[derive(Debug, Clone)]
@@ -44,6 +95,63 @@ struct PartInfo {
     independent: bool,
 }
 
+/// `stream.json`: the playlist for consumers that would rather not parse M3U8.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PlaylistJson {
+    target_duration: u64,
+    part_target: f64,
+    media_sequence: u64,
+    discontinuity_sequence: u64,
+    init_uri: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encryption: Option<EncryptionJson>,
+    segments: Vec<SegmentJson>,
+}
+
+/// Segments are AES-128-CBC encrypted whole, with the segment's `seq` as the
+/// IV, as `EXT-X-KEY` describes them in the M3U8.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EncryptionJson {
+    method: &'static str,
+    key_uri: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SegmentJson {
+    seq: u64,
+    /// `None` while the segment is still being written.
+    duration: Option<f64>,
+    uri: String,
+    discontinuity: bool,
+    parts: Vec<PartJson>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PartJson {
+    duration: f64,
+    byte_start: u64,
+    byte_length: u64,
+    independent: bool,
+}
+
+impl PartJson {
+    fn list(parts: &[PartInfo]) -> Vec<Self> {
+        parts
+            .iter()
+            .map(|part| Self {
+                duration: part.duration,
+                byte_start: part.byte_start,
+                byte_length: part.byte_length,
+                independent: part.independent,
+            })
+            .collect()
+    }
+}
+
 #[derive(Debug)]
 struct SegmentBuffer {
     seq: u64,
@@ -52,6 +160,8 @@ struct SegmentBuffer {
     frames: u64,
     filename: String,
     file: Option<fs::File>,
+    /// Segment held back for encryption, written out once it is closed.
+    plaintext: Option<Vec<u8>>,
     bytes_written: u64,
     parts: Vec<PartInfo>,
     part_index: u32,
@@ -60,6 +170,9 @@ struct SegmentBuffer {
     part_samples: Vec<Sample>,
     part_bytes_estimate: usize,
     part_independent: bool,
+    discontinuity: bool,
+    started_at: DateTime<Utc>,
+    events: Vec<PlaylistEvent>,
 }
 
 #[derive(Debug, Clone)]
@@ -71,16 +184,15 @@ struct Sample {
 
 impl CmafSegmenter {
     pub async fn new(
-        output_dir: PathBuf,
+        writer: WriterMode,
         target_duration: f64,
         window: usize,
         part_duration: f64,
         stream: Option<CmafStream>,
-        write_files: bool,
         fallback_fps: f64,
     ) -> anyhow::Result<Self> {
-        if write_files {
-            fs::create_dir_all(&output_dir).await?;
+        if let Some(dir) = writer.dir() {
+            fs::create_dir_all(dir).await?;
         }
         let mut resolved_part_duration = part_duration;
         if !resolved_part_duration.is_finite() || resolved_part_duration <= 0.0 {
@@ -95,31 +207,148 @@ impl CmafSegmenter {
             6_000
         };
         Ok(Self {
-            output_dir,
+            writer,
             target_duration,
             window,
             sequence: 0,
             segments: VecDeque::new(),
             current: None,
+            vps: None,
             sps: None,
             pps: None,
+            last_init_vps: None,
             last_init_sps: None,
             last_init_pps: None,
             part_duration: resolved_part_duration,
             last_sample_duration: None,
             fragment_sequence: 1,
+            bytes_produced: 0,
             stream,
-            write_files,
             warned_non_monotonic_pts: false,
             fallback_frame_duration_90k,
+            emit_sidx: true,
+            encryption_key: None,
+            encryption_key_uri: String::new(),
+            max_segment_bytes: None,
+            max_segment_secs: None,
+            discontinuity_sequence: 0,
+            frame_rate: None,
+            session: format!("{:08x}", rand::random::<u32>()),
+            pending_events: Vec::new(),
+            event_sequence: 0,
+            segment_pattern: DEFAULT_SEGMENT_PATTERN.to_string(),
+            segments_per_dir: None,
         })
     }
 
     pub fn set_parameter_sets(&mut self, sps: Vec<u8>, pps: Vec<u8>) {
+        self.frame_rate = parse_sps_frame_rate(&sps);
+        self.vps = None;
         self.sps = Some(sps);
         self.pps = Some(pps);
     }
 
+    /// Switches the init segment to `hvc1` for H.265 streams.
+    pub fn set_hevc_parameter_sets(&mut self, vps: Vec<u8>, sps: Vec<u8>, pps: Vec<u8>) {
+        self.frame_rate = None;
+        self.vps = Some(vps);
+        self.sps = Some(sps);
+        self.pps = Some(pps);
+    }
+
+    pub fn set_emit_sidx(&mut self, enabled: bool) {
+        self.emit_sidx = enabled;
+    }
+
+    /// Encrypts segment files with AES-128-CBC (IV = media sequence number)
+    /// and points `EXT-X-KEY` at `key_uri`. Only whole segments can be
+    /// decrypted, so the playlist drops its parts; the MSE stream is never
+    /// encrypted.
+    pub fn set_encryption_key(&mut self, key: Option<[u8; 16]>, key_uri: &str) {
+        self.encryption_key = key;
+        self.encryption_key_uri = key_uri.to_string();
+    }
+
+    fn codec_brand(&self) -> [u8; 4] {
+        if self.last_init_vps.is_some() {
+            *b"hvc1"
+        } else {
+            *b"avc1"
+        }
+    }
+
+    /// Hard limits that split a segment mid-GOP, for cameras whose keyframe
+    /// interval is far longer than the target duration. Off when `None`.
+    pub fn set_segment_limits(&mut self, max_bytes: Option<u64>, max_secs: Option<f64>) {
+        self.max_segment_bytes = max_bytes.filter(|bytes| *bytes > 0);
+        self.max_segment_secs = max_secs.filter(|secs| secs.is_finite() && *secs > 0.0);
+    }
+
+    /// `pattern` takes `{seq}` and `{session}`; with `segments_per_dir`,
+    /// segments are sharded into numbered subdirectories of that many files.
+    pub fn set_segment_naming(&mut self, pattern: &str, segments_per_dir: Option<u64>) {
+        self.segment_pattern = pattern.to_string();
+        self.segments_per_dir = segments_per_dir.filter(|count| *count > 0);
+    }
+
+    /// Path of segment `seq` relative to the output directory, as it appears
+    /// in the playlist.
+    fn segment_path(&self, seq: u64) -> String {
+        let name = self
+            .segment_pattern
+            .replace("{session}", &self.session)
+            .replace("{seq}", &format!("{seq:06}"));
+        match self.segments_per_dir {
+            Some(count) => format!("{:06}/{name}", seq / count),
+            None => name,
+        }
+    }
+
+    /// Queues an `EXT-X-DATERANGE` for `event_type` (e.g. a job state) on the
+    /// next segment, so players can mark where it happened.
+    pub fn add_event(&mut self, event_type: &str) {
+        self.event_sequence += 1;
+        self.pending_events.push(PlaylistEvent {
+            id: format!("{}-{}", self.session, self.event_sequence),
+            event_type: event_type.to_string(),
+        });
+    }
+
+    /// Signalled frame rate of the current H.264 stream, if any.
+    pub fn frame_rate(&self) -> Option<f64> {
+        self.frame_rate
+    }
+
+    /// Number of segments closed so far; the open one is not counted.
+    pub fn segments_produced(&self) -> u64 {
+        self.sequence
+            .saturating_sub(u64::from(self.current.is_some()))
+    }
+
+    pub fn parts_produced(&self) -> u64 {
+        u64::from(self.fragment_sequence.saturating_sub(1))
+    }
+
+    /// Total size of the fragments produced so far, before any encryption.
+    pub fn bytes_produced(&self) -> u64 {
+        self.bytes_produced
+    }
+
+    pub fn current_segment_duration(&self) -> Option<f64> {
+        self.current
+            .as_ref()
+            .map(|current| current.last_pts.saturating_sub(current.start_pts) as f64 / 90_000.0)
+    }
+
+    /// False until the first IDR has started a segment.
+    pub fn has_open_segment(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn parameter_sets_known(&self) -> bool {
+        self.sps.is_some() && self.pps.is_some()
+    }
+
     pub async fn ensure_init(&mut self) -> anyhow::Result<()> {
         self.write_init_if_needed().await
     }
@@ -132,10 +361,14 @@ impl CmafSegmenter {
         self.write_init_if_needed().await?;
 
         if self.current.is_none() {
+            // Nothing is decodable before the first IDR. There is no known way to
+            // ask a Bambu camera for one: its RTSP server documents no
+            // SET_PARAMETER keys, and the MQTT `ipcam` commands only toggle
+            // recording/timelapse. Startup latency is bounded by the camera's GOP.
             if !access_unit.is_idr {
                 return Ok(());
             }
-            self.start_segment(pts90k).await?;
+            self.start_segment(pts90k, false).await?;
         }
 
         let mut current = match self.current.take() {
@@ -161,10 +394,19 @@ impl CmafSegmenter {
         }
 
         let elapsed = (pts90k.saturating_sub(current.start_pts)) as f64 / 90_000.0;
-        if elapsed >= self.target_duration && access_unit.is_idr {
+        let hard_split = !access_unit.is_idr && self.exceeds_segment_limits(&current, elapsed);
+        if (elapsed >= self.target_duration && access_unit.is_idr) || hard_split {
+            if hard_split {
+                debug!(
+                    segment = %current.filename,
+                    elapsed,
+                    bytes = current.bytes_written,
+                    "splitting segment without a keyframe"
+                );
+            }
             self.flush_part(&mut current).await?;
             self.finalize_segment_buffer(current).await?;
-            self.start_segment(pts90k).await?;
+            self.start_segment(pts90k, hard_split).await?;
             current = match self.current.take() {
                 Some(current) => current,
                 None => return Ok(()),
@@ -192,8 +434,12 @@ impl CmafSegmenter {
             self.write_playlist(Some(&current)).await?;
         }
 
-        let part_elapsed = (pts90k.saturating_sub(current.part_start_pts)) as f64 / 90_000.0;
-        if !current.part_samples.is_empty() && part_elapsed >= self.part_duration {
+        // Close the part before this frame would carry it past PART-TARGET,
+        // assuming the frame lasts as long as the one before it.
+        let part_end =
+            pts90k.saturating_sub(current.part_start_pts) + pts90k.saturating_sub(current.last_pts);
+        let part_target_90k = (self.part_duration * 90_000.0).round() as u64;
+        if !current.part_samples.is_empty() && part_end > part_target_90k {
             self.flush_part(&mut current).await?;
             current.part_start_pts = pts90k;
             current.part_start_byte = current.bytes_written;
@@ -223,15 +469,33 @@ impl CmafSegmenter {
         self.finalize_segment_buffer(current).await
     }
 
-    async fn start_segment(&mut self, pts90k: u64) -> anyhow::Result<()> {
+    fn exceeds_segment_limits(&self, current: &SegmentBuffer, elapsed: f64) -> bool {
+        let bytes = current
+            .bytes_written
+            .saturating_add(current.part_bytes_estimate as u64);
+        self.max_segment_secs.is_some_and(|max| elapsed >= max)
+            || self.max_segment_bytes.is_some_and(|max| bytes >= max)
+    }
+
+    /// `discontinuity` marks a segment that does not start on a keyframe.
+    async fn start_segment(&mut self, pts90k: u64, discontinuity: bool) -> anyhow::Result<()> {
         let seq = self.sequence;
         self.sequence = self.sequence.wrapping_add(1);
-        let filename = format!("seg{:06}.m4s", seq);
-        let file = if self.write_files {
-            let path = self.output_dir.join(&filename);
-            Some(fs::File::create(&path).await?)
-        } else {
-            None
+        let filename = self.segment_path(seq);
+        let plaintext = self
+            .writer
+            .dir()
+            .and(self.encryption_key)
+            .map(|_| Vec::new());
+        let file = match self.writer.dir().filter(|_| plaintext.is_none()) {
+            Some(dir) => {
+                let path = dir.join(&filename);
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent).await?;
+                }
+                Some(fs::File::create(path).await?)
+            }
+            None => None,
         };
         self.current = Some(SegmentBuffer {
             seq,
@@ -240,6 +504,7 @@ impl CmafSegmenter {
             frames: 0,
             filename,
             file,
+            plaintext,
             bytes_written: 0,
             parts: Vec::new(),
             part_index: 0,
@@ -248,6 +513,9 @@ impl CmafSegmenter {
             part_samples: Vec::new(),
             part_bytes_estimate: 0,
             part_independent: true,
+            discontinuity,
+            started_at: Utc::now(),
+            events: std::mem::take(&mut self.pending_events),
         });
         Ok(())
     }
@@ -257,48 +525,63 @@ impl CmafSegmenter {
             return Ok(());
         }
 
+        let started = Instant::now();
         let samples = std::mem::take(&mut current.part_samples);
         let part_start_pts = current.part_start_pts;
         let (durations, total_duration_90k) = self.compute_sample_durations(&samples);
 
-        let mut sample_datas = Vec::with_capacity(samples.len());
         let mut sample_sizes = Vec::with_capacity(samples.len());
         let mut sample_flags = Vec::with_capacity(samples.len());
-        for (idx, sample) in samples.iter().enumerate() {
-            let data = build_avc_sample(&sample.nals);
-            sample_sizes.push(data.len() as u32);
-            sample_datas.push(data);
+        for sample in &samples {
+            sample_sizes.push(avc_sample_size(&sample.nals) as u32);
             sample_flags.push(if sample.is_idr {
                 SAMPLE_FLAG_SYNC
             } else {
                 SAMPLE_FLAG_NON_SYNC
             });
-            if idx == samples.len() - 1 {
-                self.last_sample_duration = durations.last().copied();
-            }
         }
+        self.last_sample_duration = durations.last().copied();
 
         let sequence = self.fragment_sequence;
         self.fragment_sequence = self.fragment_sequence.wrapping_add(1);
-        let moof = build_moof(
+        // Box sizes are known up front, so the whole part is written into one
+        // buffer and frozen without further copies.
+        let mdat_size = 8 + sample_sizes
+            .iter()
+            .map(|size| *size as usize)
+            .sum::<usize>();
+        let moof_size = moof_size(samples.len());
+        let sidx_size = if self.emit_sidx { SIDX_SIZE } else { 0 };
+        let mut part = BytesMut::with_capacity(STYP_SIZE + sidx_size + moof_size + mdat_size);
+        write_styp(&mut part, self.codec_brand());
+        if self.emit_sidx {
+            write_sidx(
+                &mut part,
+                part_start_pts,
+                total_duration_90k,
+                (moof_size + mdat_size) as u64,
+            );
+        }
+        write_moof(
+            &mut part,
             sequence,
             part_start_pts,
             &durations,
             &sample_sizes,
             &sample_flags,
         );
-        let styp = build_styp();
-        let mdat = build_mdat(&sample_datas);
-        let mut part_bytes = Vec::with_capacity(styp.len() + moof.len() + mdat.len());
-        part_bytes.extend_from_slice(&styp);
-        part_bytes.extend_from_slice(&moof);
-        part_bytes.extend_from_slice(&mdat);
-        let part_bytes = Bytes::from(part_bytes);
+        write_mdat(&mut part, &samples);
+        debug_assert_eq!(part.len(), STYP_SIZE + sidx_size + moof_size + mdat_size);
+        let part_bytes = part.freeze();
 
         if let Some(stream) = &self.stream {
             stream.send_fragment(part_bytes.clone());
         }
 
+        if let Some(plaintext) = current.plaintext.as_mut() {
+            plaintext.extend_from_slice(&part_bytes);
+        }
+
         if let Some(file) = current.file.as_mut() {
             file.write_all(part_bytes.as_ref()).await?;
             file.flush().await?;
@@ -307,6 +590,7 @@ impl CmafSegmenter {
         let byte_start = current.part_start_byte;
         let byte_length = part_bytes.len() as u64;
         current.bytes_written = current.bytes_written.saturating_add(byte_length);
+        self.bytes_produced = self.bytes_produced.saturating_add(byte_length);
 
         let duration = (total_duration_90k as f64) / 90_000.0;
         let part_index = current.part_index;
@@ -327,6 +611,8 @@ impl CmafSegmenter {
             duration = duration,
             "cmaf part written"
         );
+        metrics::histogram!("cmaf_segment_write_duration_seconds")
+            .record(started.elapsed().as_secs_f64());
 
         Ok(())
     }
@@ -363,28 +649,47 @@ impl CmafSegmenter {
         if let Some(file) = current.file.as_mut() {
             let _ = file.flush().await;
         }
+        if let (Some(dir), Some(key), Some(plaintext)) = (
+            self.writer.dir(),
+            self.encryption_key.as_ref(),
+            current.plaintext.take(),
+        ) {
+            let path = dir.join(&current.filename);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::write(path, encrypt_segment(key, current.seq, &plaintext)).await?;
+        }
 
-        let duration = if current.last_pts > current.start_pts {
-            (current.last_pts - current.start_pts) as f64 / 90_000.0
-        } else {
-            0.1
-        };
+        // Sum what was actually muxed: the PTS span misses the final sample.
+        let duration: f64 = current.parts.iter().map(|part| part.duration).sum();
+        let duration = if duration > 0.0 { duration } else { 0.1 };
 
         let filename = current.filename.clone();
         debug!(segment = %filename, duration = %duration, "cmaf segment written");
 
-        if self.write_files {
+        if let Some(dir) = self.writer.dir() {
             self.segments.push_back(SegmentInfo {
                 seq: current.seq,
                 duration,
                 filename,
                 parts: current.parts,
+                discontinuity: current.discontinuity,
+                started_at: current.started_at,
+                events: current.events,
             });
 
             while self.segments.len() > self.window {
                 if let Some(old) = self.segments.pop_front() {
-                    let old_path = self.output_dir.join(&old.filename);
-                    let _ = fs::remove_file(old_path).await;
+                    if old.discontinuity {
+                        self.discontinuity_sequence += 1;
+                    }
+                    let old_path = dir.join(&old.filename);
+                    let _ = fs::remove_file(&old_path).await;
+                    // Only succeeds once the shard directory is empty.
+                    if let Some(parent) = old_path.parent().filter(|parent| *parent != dir) {
+                        let _ = fs::remove_dir(parent).await;
+                    }
                 }
             }
 
@@ -394,74 +699,194 @@ impl CmafSegmenter {
     }
 
     async fn write_playlist(&self, current: Option<&SegmentBuffer>) -> anyhow::Result<()> {
-        if !self.write_files {
+        let Some(dir) = self.writer.dir() else {
             return Ok(());
-        }
+        };
         let playlist = self.render_playlist(current);
-        let tmp_path = self.output_dir.join("stream.m3u8.tmp");
-        let final_path = self.output_dir.join("stream.m3u8");
+        let tmp_path = dir.join("stream.m3u8.tmp");
+        let final_path = dir.join("stream.m3u8");
         fs::write(&tmp_path, playlist).await?;
         fs::rename(tmp_path, final_path).await?;
+        let json = serde_json::to_vec(&self.playlist_json(current))?;
+        let tmp_path = dir.join("stream.json.tmp");
+        fs::write(&tmp_path, json).await?;
+        fs::rename(tmp_path, dir.join("stream.json")).await?;
         Ok(())
     }
 
-    fn render_playlist(&self, current: Option<&SegmentBuffer>) -> String {
-        let max_segment = self
+    /// Same segments and parts as `render_playlist`.
+    fn playlist_json(&self, current: Option<&SegmentBuffer>) -> PlaylistJson {
+        let encrypted = self.encryption_key.is_some();
+        // Parts of an encrypted segment cannot be decrypted on their own.
+        let parts = |parts: &[PartInfo]| {
+            if encrypted {
+                Vec::new()
+            } else {
+                PartJson::list(parts)
+            }
+        };
+        let mut segments: Vec<SegmentJson> = self
             .segments
             .iter()
-            .map(|seg| seg.duration)
-            .fold(0.0_f64, f64::max);
-        let target_duration = self.target_duration.max(max_segment).ceil() as u64;
-        let mut max_part = self.part_duration;
-        for seg in &self.segments {
-            for part in &seg.parts {
-                if part.duration > max_part {
-                    max_part = part.duration;
-                }
-            }
+            .map(|seg| SegmentJson {
+                seq: seg.seq,
+                duration: Some(seg.duration),
+                uri: seg.filename.clone(),
+                discontinuity: seg.discontinuity,
+                parts: parts(&seg.parts),
+            })
+            .collect();
+        if let Some(current) = current.filter(|_| !encrypted) {
+            segments.push(SegmentJson {
+                seq: current.seq,
+                duration: None,
+                uri: current.filename.clone(),
+                discontinuity: current.discontinuity,
+                parts: PartJson::list(&current.parts),
+            });
         }
-        if let Some(current) = current {
-            for part in &current.parts {
-                if part.duration > max_part {
-                    max_part = part.duration;
-                }
-            }
+        PlaylistJson {
+            target_duration: self.playlist_target_duration(),
+            part_target: self.part_target(),
+            media_sequence: self.media_sequence(current),
+            discontinuity_sequence: self.discontinuity_sequence,
+            init_uri: "init.mp4",
+            encryption: encrypted.then(|| EncryptionJson {
+                method: "AES-128",
+                key_uri: self.encryption_key_uri.clone(),
+            }),
+            segments,
         }
-        let media_sequence = self
+    }
+
+    /// EXT-X-TARGETDURATION: whole seconds, at least the longest segment.
+    fn playlist_target_duration(&self) -> u64 {
+        let max_segment = self
             .segments
+            .iter()
+            .map(|seg| seg.duration)
+            .fold(0.0_f64, f64::max);
+        self.target_duration.max(max_segment).ceil() as u64
+    }
+
+    fn media_sequence(&self, current: Option<&SegmentBuffer>) -> u64 {
+        self.segments
             .front()
             .map(|seg| seg.seq)
             .or_else(|| current.map(|seg| seg.seq))
-            .unwrap_or(0);
-        let part_hold_back = (max_part * 3.0).max(max_part + 0.1);
-        let hold_back = (target_duration as f64 * 3.0).max(part_hold_back * 2.0);
+            .unwrap_or(0)
+    }
+
+    /// Always the full playlist: whatever serves the files cannot answer
+    /// `_HLS_skip` delta requests, so `CAN-SKIP-UNTIL` is not advertised.
+    fn render_playlist(&self, current: Option<&SegmentBuffer>) -> String {
+        let target_duration = self.playlist_target_duration();
+        let part_target = self.part_target();
+        let media_sequence = self.media_sequence(current);
+        let part_hold_back = part_target * 3.0;
+        let encrypted = self.encryption_key.is_some();
+        let hold_back = if encrypted {
+            target_duration as f64 * 3.0
+        } else {
+            (target_duration as f64 * 3.0).max(part_hold_back * 2.0)
+        };
+
+        // Hard splits start segments mid-GOP, so only claim independent
+        // segments while none can be, or are still in the window.
+        let independent = self.max_segment_bytes.is_none()
+            && self.max_segment_secs.is_none()
+            && !self.segments.iter().any(|seg| seg.discontinuity)
+            && !current.is_some_and(|seg| seg.discontinuity);
 
         let mut lines = Vec::new();
         lines.push("#EXTM3U".to_string());
         lines.push("#EXT-X-VERSION:9".to_string());
-        lines.push("#EXT-X-INDEPENDENT-SEGMENTS".to_string());
+        if independent {
+            lines.push("#EXT-X-INDEPENDENT-SEGMENTS".to_string());
+        }
         lines.push(format!("#EXT-X-TARGETDURATION:{}", target_duration));
-        lines.push(format!("#EXT-X-PART-INF:PART-TARGET={:.3}", max_part));
-        lines.push(format!(
-            "#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK={:.3},HOLD-BACK={:.3}",
-            part_hold_back, hold_back
-        ));
+        if encrypted {
+            lines.push(format!(
+                "#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,HOLD-BACK={:.3}",
+                hold_back
+            ));
+        } else {
+            lines.push(format!("#EXT-X-PART-INF:PART-TARGET={:.3}", part_target));
+            lines.push(format!(
+                "#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK={:.3},HOLD-BACK={:.3}",
+                part_hold_back, hold_back
+            ));
+        }
         lines.push("#EXT-X-MAP:URI=\"init.mp4\"".to_string());
         lines.push(format!("#EXT-X-MEDIA-SEQUENCE:{}", media_sequence));
+        if self.discontinuity_sequence > 0 {
+            lines.push(format!(
+                "#EXT-X-DISCONTINUITY-SEQUENCE:{}",
+                self.discontinuity_sequence
+            ));
+        }
+
+        if encrypted {
+            // No IV attribute: each segment uses its media sequence number.
+            lines.push(format!(
+                "#EXT-X-KEY:METHOD=AES-128,URI=\"{}\"",
+                self.encryption_key_uri
+            ));
+        }
 
         for seg in &self.segments {
-            Self::append_parts(&mut lines, &seg.filename, &seg.parts);
+            if seg.discontinuity {
+                lines.push("#EXT-X-DISCONTINUITY".to_string());
+            }
+            Self::append_events(&mut lines, seg.started_at, &seg.events);
+            if !encrypted {
+                Self::append_parts(&mut lines, &seg.filename, &seg.parts);
+            }
             lines.push(format!("#EXTINF:{:.3},", seg.duration));
             lines.push(seg.filename.clone());
         }
 
-        if let Some(current) = current {
+        // An encrypted segment is only written once it is closed.
+        if let Some(current) = current.filter(|_| !encrypted) {
+            if current.discontinuity {
+                lines.push("#EXT-X-DISCONTINUITY".to_string());
+            }
+            Self::append_events(&mut lines, current.started_at, &current.events);
             Self::append_parts(&mut lines, &current.filename, &current.parts);
+            // The next part is appended to the same file at the current end, so
+            // players can issue the blocking range request ahead of time.
+            lines.push(format!(
+                "#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"{}\",BYTERANGE-START={}",
+                current.filename, current.bytes_written
+            ));
         }
 
         lines.join("\n") + "\n"
     }
 
+    /// Advertised PART-TARGET: the configured part duration rather than the
+    /// observed maximum, so it and PART-HOLD-BACK (3x) stay stable across
+    /// reloads.
+    fn part_target(&self) -> f64 {
+        self.part_duration.min(self.target_duration)
+    }
+
+    /// DATERANGE needs a PROGRAM-DATE-TIME to anchor to, so the segment
+    /// carrying events gets one and the events start with it.
+    fn append_events(lines: &mut Vec<String>, started_at: DateTime<Utc>, events: &[PlaylistEvent]) {
+        if events.is_empty() {
+            return;
+        }
+        let start = started_at.to_rfc3339_opts(SecondsFormat::Millis, true);
+        lines.push(format!("#EXT-X-PROGRAM-DATE-TIME:{start}"));
+        for event in events {
+            lines.push(format!(
+                "#EXT-X-DATERANGE:ID=\"{}\",CLASS=\"com.bambu.event\",START-DATE=\"{start}\",X-EVENT-TYPE=\"{}\"",
+                event.id, event.event_type
+            ));
+        }
+    }
+
     fn append_parts(lines: &mut Vec<String>, filename: &str, parts: &[PartInfo]) {
         for part in parts {
             let mut line = format!(
@@ -481,17 +906,38 @@ impl CmafSegmenter {
             _ => return Ok(()),
         };
 
-        if self.last_init_sps.as_ref() == Some(&sps) && self.last_init_pps.as_ref() == Some(&pps) {
+        let vps = self.vps.clone();
+        if self.last_init_sps.as_ref() == Some(&sps)
+            && self.last_init_pps.as_ref() == Some(&pps)
+            && self.last_init_vps == vps
+        {
             return Ok(());
         }
 
-        let (width, height) = parse_sps_dimensions(&sps).unwrap_or((1280, 720));
-        let init = build_init_mp4(&sps, &pps, width, height);
-        let codec = codec_string_from_sps(&sps);
+        let started = Instant::now();
+        let (init, codec) = match vps.as_deref() {
+            Some(vps) => {
+                let (width, height) = parse_hevc_sps(&sps)
+                    .map(|info| (info.width, info.height))
+                    .unwrap_or((1280, 720));
+                let sample_entry = build_hvc1(vps, &sps, &pps, width, height);
+                (
+                    build_init_mp4(&sample_entry, width, height),
+                    hevc_codec_string(&sps),
+                )
+            }
+            None => {
+                let (width, height) = parse_sps_dimensions(&sps).unwrap_or((1280, 720));
+                let sample_entry = build_avc1(&sps, &pps, width, height);
+                (
+                    build_init_mp4(&sample_entry, width, height),
+                    codec_string_from_sps(&sps),
+                )
+            }
+        };
         let init_bytes = Bytes::from(init);
-        if self.write_files {
-            let path = self.output_dir.join("init.mp4");
-            fs::write(&path, init_bytes.as_ref()).await?;
+        if let Some(dir) = self.writer.dir() {
+            fs::write(dir.join("init.mp4"), init_bytes.as_ref()).await?;
         }
         if let Some(stream) = &self.stream {
             stream.update_init(CmafInit {
@@ -499,130 +945,157 @@ impl CmafSegmenter {
                 codec,
             });
         }
+        self.last_init_vps = vps;
         self.last_init_sps = Some(sps);
         self.last_init_pps = Some(pps);
+        metrics::histogram!("cmaf_init_write_duration_seconds")
+            .record(started.elapsed().as_secs_f64());
         Ok(())
     }
 }
 
 fn estimate_sample_bytes(access_unit: &AccessUnit) -> usize {
-    access_unit
-        .nals
-        .iter()
+    avc_sample_size(&access_unit.nals)
+}
+
+/// Size of a sample once each NAL gets its 4-byte length prefix.
+fn avc_sample_size(nals: &[Vec<u8>]) -> usize {
+    nals.iter()
         .map(|nal| nal.len().saturating_add(4))
         .sum::<usize>()
 }
 
+fn encrypt_segment(key: &[u8; 16], media_sequence: u64, data: &[u8]) -> Vec<u8> {
+    let iv = u128::from(media_sequence).to_be_bytes();
+    Aes128CbcEnc::new(key.into(), &iv.into()).encrypt_padded_vec_mut::<Pkcs7>(data)
+}
+
+type Aes128CbcEnc = cbc::Encryptor<aes::Aes128>;
+
 const MAX_PART_SAMPLES: usize = 240;
 const MAX_PART_BYTES: usize = 8 * 1024 * 1024;
 
 const SAMPLE_FLAG_SYNC: u32 = 0x02000000;
 const SAMPLE_FLAG_NON_SYNC: u32 = 0x01010000;
 
-fn build_avc_sample(nals: &[Vec<u8>]) -> Vec<u8> {
-    let mut out = Vec::new();
-    for nal in nals {
-        let len = nal.len() as u32;
-        out.extend_from_slice(&len.to_be_bytes());
-        out.extend_from_slice(nal);
-    }
-    out
+fn moof_size(sample_count: usize) -> usize {
+    let trun_size = 8 + 12 + sample_count * 12;
+    let traf_size = 8 + 16 + 20 + trun_size;
+    8 + 16 + traf_size
 }
 
-fn build_moof(
+fn write_moof(
+    out: &mut BytesMut,
     sequence: u32,
     base_decode_time: u64,
     sample_durations: &[u32],
     sample_sizes: &[u32],
     sample_flags: &[u32],
-) -> Vec<u8> {
-    let sample_count = sample_durations.len() as u32;
-    let trun_size = 20 + (sample_count as usize * 12);
-    let traf_size = 8 + 16 + 20 + trun_size;
-    let moof_size = 8 + 16 + traf_size;
-    let data_offset = (moof_size + 8) as i32;
-
-    let mut trun = Vec::with_capacity(trun_size);
-    write_u32(&mut trun, 0x000001 | 0x000100 | 0x000200 | 0x000400);
-    write_u32(&mut trun, sample_count);
-    write_i32(&mut trun, data_offset);
-    for i in 0..sample_count as usize {
-        write_u32(&mut trun, sample_durations[i]);
-        write_u32(&mut trun, sample_sizes[i]);
-        write_u32(&mut trun, sample_flags[i]);
-    }
-    let trun_box = make_box(*b"trun", trun);
-
-    let mut tfhd = Vec::with_capacity(8);
-    write_u32(&mut tfhd, 0x020000);
-    write_u32(&mut tfhd, 1);
-    let tfhd_box = make_box(*b"tfhd", tfhd);
-
-    let mut tfdt = Vec::with_capacity(12);
-    write_u32(&mut tfdt, 0x01000000);
-    write_u64(&mut tfdt, base_decode_time);
-    let tfdt_box = make_box(*b"tfdt", tfdt);
-
-    let mut traf_payload = Vec::new();
-    traf_payload.extend_from_slice(&tfhd_box);
-    traf_payload.extend_from_slice(&tfdt_box);
-    traf_payload.extend_from_slice(&trun_box);
-    let traf_box = make_box(*b"traf", traf_payload);
-
-    let mut mfhd = Vec::with_capacity(8);
-    write_u32(&mut mfhd, 0);
-    write_u32(&mut mfhd, sequence);
-    let mfhd_box = make_box(*b"mfhd", mfhd);
-
-    let mut moof_payload = Vec::new();
-    moof_payload.extend_from_slice(&mfhd_box);
-    moof_payload.extend_from_slice(&traf_box);
-    make_box(*b"moof", moof_payload)
+) {
+    let sample_count = sample_durations.len();
+    let data_offset = (moof_size(sample_count) + 8) as i32;
+
+    let moof = begin_box(out, *b"moof");
+    let mfhd = begin_box(out, *b"mfhd");
+    out.put_u32(0);
+    out.put_u32(sequence);
+    end_box(out, mfhd);
+
+    let traf = begin_box(out, *b"traf");
+    let tfhd = begin_box(out, *b"tfhd");
+    out.put_u32(0x020000);
+    out.put_u32(1);
+    end_box(out, tfhd);
+
+    let tfdt = begin_box(out, *b"tfdt");
+    out.put_u32(0x01000000);
+    out.put_u64(base_decode_time);
+    end_box(out, tfdt);
+
+    let trun = begin_box(out, *b"trun");
+    out.put_u32(0x000001 | 0x000100 | 0x000200 | 0x000400);
+    out.put_u32(sample_count as u32);
+    out.put_i32(data_offset);
+    for i in 0..sample_count {
+        out.put_u32(sample_durations[i]);
+        out.put_u32(sample_sizes[i]);
+        out.put_u32(sample_flags[i]);
+    }
+    end_box(out, trun);
+    end_box(out, traf);
+    end_box(out, moof);
 }
 
-fn build_mdat(samples: &[Vec<u8>]) -> Vec<u8> {
-    let mut payload = Vec::new();
+const SIDX_SIZE: usize = 52;
+
+fn write_sidx(out: &mut BytesMut, earliest_pts: u64, duration_90k: u64, segment_size: u64) {
+    let sidx = begin_box(out, *b"sidx");
+    out.put_u32(0x01000000);
+    out.put_u32(1);
+    out.put_u32(90_000);
+    out.put_u64(earliest_pts);
+    out.put_u64(0);
+    out.put_u16(0);
+    out.put_u16(1);
+    // reference_type 0 (media) with a 31-bit referenced_size.
+    out.put_u32(segment_size.min(0x7FFF_FFFF) as u32);
+    out.put_u32(duration_90k.min(u64::from(u32::MAX)) as u32);
+    // SAP info left unspecified; parts do not always start on a keyframe.
+    out.put_u32(0);
+    end_box(out, sidx);
+}
+
+/// Writes each sample as length-prefixed NALs (AVCC/HVCC framing).
+fn write_mdat(out: &mut BytesMut, samples: &[Sample]) {
+    let mdat = begin_box(out, *b"mdat");
     for sample in samples {
-        payload.extend_from_slice(sample);
+        for nal in &sample.nals {
+            out.put_u32(nal.len() as u32);
+            out.put_slice(nal);
+        }
     }
-    make_box(*b"mdat", payload)
+    end_box(out, mdat);
 }
 
-fn build_init_mp4(sps: &[u8], pps: &[u8], width: u32, height: u32) -> Vec<u8> {
-    let ftyp = build_ftyp();
-    let moov = build_moov(sps, pps, width, height);
+fn build_init_mp4(sample_entry: &[u8], width: u32, height: u32) -> Vec<u8> {
+    // The sample entry tag (`avc1`/`hvc1`) doubles as the codec brand.
+    let brand = sample_entry[4..8].try_into().unwrap_or(*b"avc1");
+    let ftyp = build_ftyp(brand);
+    let moov = build_moov(sample_entry, width, height);
     let mut out = Vec::with_capacity(ftyp.len() + moov.len());
     out.extend_from_slice(&ftyp);
     out.extend_from_slice(&moov);
     out
 }
 
-fn build_ftyp() -> Vec<u8> {
+fn build_ftyp(codec_brand: [u8; 4]) -> Vec<u8> {
     let mut payload = Vec::new();
     payload.extend_from_slice(b"isom");
     write_u32(&mut payload, 0x200);
     payload.extend_from_slice(b"isom");
     payload.extend_from_slice(b"iso6");
-    payload.extend_from_slice(b"avc1");
+    payload.extend_from_slice(&codec_brand);
     payload.extend_from_slice(b"cmfc");
     make_box(*b"ftyp", payload)
 }
 
-fn build_styp() -> Vec<u8> {
-    let mut payload = Vec::new();
-    payload.extend_from_slice(b"msdh");
-    write_u32(&mut payload, 0);
-    payload.extend_from_slice(b"msdh");
-    payload.extend_from_slice(b"msix");
-    payload.extend_from_slice(b"iso6");
-    payload.extend_from_slice(b"avc1");
-    payload.extend_from_slice(b"cmfc");
-    make_box(*b"styp", payload)
+const STYP_SIZE: usize = 36;
+
+fn write_styp(out: &mut BytesMut, codec_brand: [u8; 4]) {
+    let styp = begin_box(out, *b"styp");
+    out.put_slice(b"msdh");
+    out.put_u32(0);
+    out.put_slice(b"msdh");
+    out.put_slice(b"msix");
+    out.put_slice(b"iso6");
+    out.put_slice(&codec_brand);
+    out.put_slice(b"cmfc");
+    end_box(out, styp);
 }
 
-fn build_moov(sps: &[u8], pps: &[u8], width: u32, height: u32) -> Vec<u8> {
+fn build_moov(sample_entry: &[u8], width: u32, height: u32) -> Vec<u8> {
     let mvhd = build_mvhd();
-    let trak = build_trak(sps, pps, width, height);
+    let trak = build_trak(sample_entry, width, height);
     let mvex = build_mvex();
     let mut payload = Vec::new();
     payload.extend_from_slice(&mvhd);
@@ -651,9 +1124,9 @@ fn build_mvhd() -> Vec<u8> {
     make_box(*b"mvhd", payload)
 }
 
-fn build_trak(sps: &[u8], pps: &[u8], width: u32, height: u32) -> Vec<u8> {
+fn build_trak(sample_entry: &[u8], width: u32, height: u32) -> Vec<u8> {
     let tkhd = build_tkhd(width, height);
-    let mdia = build_mdia(sps, pps, width, height);
+    let mdia = build_mdia(sample_entry);
     let mut payload = Vec::new();
     payload.extend_from_slice(&tkhd);
     payload.extend_from_slice(&mdia);
@@ -680,10 +1153,10 @@ fn build_tkhd(width: u32, height: u32) -> Vec<u8> {
     make_box(*b"tkhd", payload)
 }
 
-fn build_mdia(sps: &[u8], pps: &[u8], width: u32, height: u32) -> Vec<u8> {
+fn build_mdia(sample_entry: &[u8]) -> Vec<u8> {
     let mdhd = build_mdhd();
     let hdlr = build_hdlr();
-    let minf = build_minf(sps, pps, width, height);
+    let minf = build_minf(sample_entry);
     let mut payload = Vec::new();
     payload.extend_from_slice(&mdhd);
     payload.extend_from_slice(&hdlr);
@@ -716,10 +1189,10 @@ fn build_hdlr() -> Vec<u8> {
     make_box(*b"hdlr", payload)
 }
 
-fn build_minf(sps: &[u8], pps: &[u8], width: u32, height: u32) -> Vec<u8> {
+fn build_minf(sample_entry: &[u8]) -> Vec<u8> {
     let vmhd = build_vmhd();
     let dinf = build_dinf();
-    let stbl = build_stbl(sps, pps, width, height);
+    let stbl = build_stbl(sample_entry);
     let mut payload = Vec::new();
     payload.extend_from_slice(&vmhd);
     payload.extend_from_slice(&dinf);
@@ -753,8 +1226,8 @@ fn build_dinf() -> Vec<u8> {
     make_box(*b"dinf", payload)
 }
 
-fn build_stbl(sps: &[u8], pps: &[u8], width: u32, height: u32) -> Vec<u8> {
-    let stsd = build_stsd(sps, pps, width, height);
+fn build_stbl(sample_entry: &[u8]) -> Vec<u8> {
+    let stsd = build_stsd(sample_entry);
     let stts = make_box(*b"stts", vec![0, 0, 0, 0, 0, 0, 0, 0]);
     let stsc = make_box(*b"stsc", vec![0, 0, 0, 0, 0, 0, 0, 0]);
     let stsz = make_box(*b"stsz", vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
@@ -769,17 +1242,23 @@ fn build_stbl(sps: &[u8], pps: &[u8], width: u32, height: u32) -> Vec<u8> {
     make_box(*b"stbl", payload)
 }
 
-fn build_stsd(sps: &[u8], pps: &[u8], width: u32, height: u32) -> Vec<u8> {
-    let avc1 = build_avc1(sps, pps, width, height);
+fn build_stsd(sample_entry: &[u8]) -> Vec<u8> {
     let mut payload = Vec::new();
     write_u32(&mut payload, 0);
     write_u32(&mut payload, 1);
-    payload.extend_from_slice(&avc1);
+    payload.extend_from_slice(sample_entry);
     make_box(*b"stsd", payload)
 }
 
 fn build_avc1(sps: &[u8], pps: &[u8], width: u32, height: u32) -> Vec<u8> {
-    let avcc = build_avcc(sps, pps);
+    build_visual_sample_entry(*b"avc1", &build_avcc(sps, pps), width, height)
+}
+
+fn build_hvc1(vps: &[u8], sps: &[u8], pps: &[u8], width: u32, height: u32) -> Vec<u8> {
+    build_visual_sample_entry(*b"hvc1", &build_hvcc(vps, sps, pps), width, height)
+}
+
+fn build_visual_sample_entry(tag: [u8; 4], config: &[u8], width: u32, height: u32) -> Vec<u8> {
     let mut payload = Vec::new();
     payload.extend_from_slice(&[0; 6]);
     write_u16(&mut payload, 1);
@@ -797,8 +1276,8 @@ fn build_avc1(sps: &[u8], pps: &[u8], width: u32, height: u32) -> Vec<u8> {
     payload.extend_from_slice(&[0; 32]);
     write_u16(&mut payload, 0x0018);
     write_u16(&mut payload, 0xffff);
-    payload.extend_from_slice(&avcc);
-    make_box(*b"avc1", payload)
+    payload.extend_from_slice(config);
+    make_box(tag, payload)
 }
 
 fn build_avcc(sps: &[u8], pps: &[u8]) -> Vec<u8> {
@@ -814,6 +1293,57 @@ fn build_avcc(sps: &[u8], pps: &[u8]) -> Vec<u8> {
     make_box(*b"avcC", payload)
 }
 
+fn build_hvcc(vps: &[u8], sps: &[u8], pps: &[u8]) -> Vec<u8> {
+    let info = parse_hevc_sps(sps).unwrap_or_default();
+    let mut payload = vec![1];
+    payload.extend_from_slice(&info.profile_tier_level);
+    write_u16(&mut payload, 0xF000);
+    payload.push(0xFC);
+    payload.push(0xFC | (info.chroma_format_idc as u8 & 0x03));
+    payload.push(0xF8 | (info.bit_depth_luma_minus8 as u8 & 0x07));
+    payload.push(0xF8 | (info.bit_depth_chroma_minus8 as u8 & 0x07));
+    write_u16(&mut payload, 0);
+    // constantFrameRate 0, numTemporalLayers, temporalIdNested, 4-byte lengths.
+    payload.push(
+        ((info.max_sub_layers_minus1 + 1) << 3) | (u8::from(info.temporal_id_nesting) << 2) | 0x03,
+    );
+    payload.push(3);
+    for (nal_type, nal) in [(32u8, vps), (33, sps), (34, pps)] {
+        payload.push(0x80 | nal_type);
+        write_u16(&mut payload, 1);
+        write_u16(&mut payload, nal.len() as u16);
+        payload.extend_from_slice(nal);
+    }
+    make_box(*b"hvcC", payload)
+}
+
+/// RFC 6381 / ISO 14496-15 Annex E codec string, e.g. `hvc1.1.6.L93.B0`.
+fn hevc_codec_string(sps: &[u8]) -> String {
+    let ptl = parse_hevc_sps(sps).unwrap_or_default().profile_tier_level;
+    let profile_space = match ptl[0] >> 6 {
+        1 => "A",
+        2 => "B",
+        3 => "C",
+        _ => "",
+    };
+    let tier = if ptl[0] & 0x20 != 0 { 'H' } else { 'L' };
+    let profile_idc = ptl[0] & 0x1F;
+    let compatibility = u32::from_be_bytes([ptl[1], ptl[2], ptl[3], ptl[4]]).reverse_bits();
+    let mut codec = format!(
+        "hvc1.{profile_space}{profile_idc}.{compatibility:X}.{tier}{}",
+        ptl[11]
+    );
+    let constraints = &ptl[5..11];
+    let used = constraints
+        .iter()
+        .rposition(|byte| *byte != 0)
+        .map_or(0, |index| index + 1);
+    for byte in &constraints[..used] {
+        codec.push_str(&format!(".{byte:X}"));
+    }
+    codec
+}
+
 fn codec_string_from_sps(sps: &[u8]) -> String {
     let profile_idc = sps.get(1).copied().unwrap_or(0);
     let profile_compat = sps.get(2).copied().unwrap_or(0);
@@ -836,6 +1366,19 @@ fn build_mvex() -> Vec<u8> {
     make_box(*b"mvex", trex_box)
 }
 
+/// Starts a box in place; `end_box` patches in the size once the payload is written.
+fn begin_box(out: &mut BytesMut, tag: [u8; 4]) -> usize {
+    let start = out.len();
+    out.put_u32(0);
+    out.put_slice(&tag);
+    start
+}
+
+fn end_box(out: &mut BytesMut, start: usize) {
+    let size = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
 fn make_box(tag: [u8; 4], payload: Vec<u8>) -> Vec<u8> {
     let size = (payload.len() + 8) as u32;
     let mut out = Vec::with_capacity(payload.len() + 8);
@@ -865,20 +1408,87 @@ fn write_u32(out: &mut Vec<u8>, value: u32) {
     out.extend_from_slice(&value.to_be_bytes());
 }
 
-fn write_u64(out: &mut Vec<u8>, value: u64) {
-    out.extend_from_slice(&value.to_be_bytes());
+fn parse_sps_dimensions(sps: &[u8]) -> Option<(u32, u32)> {
+    if sps.len() < 2 {
+        return None;
+    }
+    let rbsp = nal_to_rbsp(&sps[1..]);
+    read_sps_dimensions(&mut BitReader::new(&rbsp))
 }
 
-fn write_i32(out: &mut Vec<u8>, value: i32) {
-    out.extend_from_slice(&value.to_be_bytes());
+/// Picks the highest-resolution SPS and the PPS that refers to it, for
+/// encoders that advertise several layers (e.g. a thumbnail stream first).
+pub fn select_parameter_sets(sps: &[Vec<u8>], pps: &[Vec<u8>]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let best = sps.iter().max_by_key(|sps| {
+        parse_sps_dimensions(sps).map_or(0, |(width, height)| u64::from(width) * u64::from(height))
+    })?;
+    let sps_id = parameter_set_ids(best.get(4..)?).map(|(id, _)| id);
+    let pps = pps
+        .iter()
+        .find(|pps| {
+            pps.get(1..)
+                .and_then(parameter_set_ids)
+                .map(|(_, referenced)| Some(referenced) == sps_id)
+                .unwrap_or(false)
+        })
+        .or_else(|| pps.first())?;
+    Some((best.clone(), pps.clone()))
 }
 
-fn parse_sps_dimensions(sps: &[u8]) -> Option<(u32, u32)> {
+/// The two leading ue(v) fields: (sps id, -) for an SPS body after
+/// profile/level, (pps id, sps id) for a PPS.
+fn parameter_set_ids(data: &[u8]) -> Option<(u32, u32)> {
+    let rbsp = nal_to_rbsp(data);
+    let mut br = BitReader::new(&rbsp);
+    let first = br.read_ue()?;
+    Some((first, br.read_ue().unwrap_or(0)))
+}
+
+/// Frame rate from the H.264 SPS VUI `timing_info`; `None` when the encoder
+/// does not signal one.
+pub fn parse_sps_frame_rate(sps: &[u8]) -> Option<f64> {
     if sps.len() < 2 {
         return None;
     }
     let rbsp = nal_to_rbsp(&sps[1..]);
     let mut br = BitReader::new(&rbsp);
+    read_sps_dimensions(&mut br)?;
+    if !br.read_bit()? {
+        return None;
+    }
+    // aspect_ratio_info
+    if br.read_bit()? && br.read_bits(8)? == 255 {
+        br.read_u32(32)?;
+    }
+    // overscan_info
+    if br.read_bit()? {
+        br.read_bit()?;
+    }
+    // video_signal_type
+    if br.read_bit()? {
+        br.read_bits(4)?;
+        if br.read_bit()? {
+            br.read_u32(24)?;
+        }
+    }
+    // chroma_loc_info
+    if br.read_bit()? {
+        br.read_ue()?;
+        br.read_ue()?;
+    }
+    if !br.read_bit()? {
+        return None;
+    }
+    let num_units_in_tick = br.read_u32(32)?;
+    let time_scale = br.read_u32(32)?;
+    if num_units_in_tick == 0 || time_scale == 0 {
+        return None;
+    }
+    Some(time_scale as f64 / (2.0 * num_units_in_tick as f64))
+}
+
+/// Reads the SPS up to and including the frame cropping fields.
+fn read_sps_dimensions(br: &mut BitReader<'_>) -> Option<(u32, u32)> {
     let profile_idc = br.read_bits(8)?;
     br.read_bits(8)?;
     br.read_bits(8)?;
@@ -890,7 +1500,7 @@ fn parse_sps_dimensions(sps: &[u8]) -> Option<(u32, u32)> {
         profile_idc,
         100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135 | 144
     ) {
-        chroma_format_idc = br.read_ue()? as u32;
+        chroma_format_idc = br.read_ue()?;
         if chroma_format_idc == 3 {
             _separate_colour_plane_flag = br.read_bit()?;
         }
@@ -901,7 +1511,7 @@ fn parse_sps_dimensions(sps: &[u8]) -> Option<(u32, u32)> {
             let count = if chroma_format_idc == 3 { 12 } else { 8 };
             for i in 0..count {
                 if br.read_bit()? {
-                    skip_scaling_list(&mut br, if i < 6 { 16 } else { 64 })?;
+                    skip_scaling_list(br, if i < 6 { 16 } else { 64 })?;
                 }
             }
         }
@@ -922,8 +1532,8 @@ fn parse_sps_dimensions(sps: &[u8]) -> Option<(u32, u32)> {
     }
     br.read_ue()?;
     br.read_bit()?;
-    let pic_width_in_mbs_minus1 = br.read_ue()? as u32;
-    let pic_height_in_map_units_minus1 = br.read_ue()? as u32;
+    let pic_width_in_mbs_minus1 = br.read_ue()?;
+    let pic_height_in_map_units_minus1 = br.read_ue()?;
     let frame_mbs_only_flag = br.read_bit()?;
     if !frame_mbs_only_flag {
         br.read_bit()?;
@@ -931,12 +1541,7 @@ fn parse_sps_dimensions(sps: &[u8]) -> Option<(u32, u32)> {
     br.read_bit()?;
     let frame_cropping_flag = br.read_bit()?;
     let (crop_left, crop_right, crop_top, crop_bottom) = if frame_cropping_flag {
-        (
-            br.read_ue()? as u32,
-            br.read_ue()? as u32,
-            br.read_ue()? as u32,
-            br.read_ue()? as u32,
-        )
+        (br.read_ue()?, br.read_ue()?, br.read_ue()?, br.read_ue()?)
     } else {
         (0, 0, 0, 0)
     };
@@ -961,6 +1566,88 @@ fn parse_sps_dimensions(sps: &[u8]) -> Option<(u32, u32)> {
     Some((width, height))
 }
 
+#[derive(Debug, Default)]
+struct HevcSpsInfo {
+    /// general_profile_space .. general_level_idc, copied verbatim into hvcC.
+    profile_tier_level: [u8; 12],
+    max_sub_layers_minus1: u8,
+    temporal_id_nesting: bool,
+    chroma_format_idc: u32,
+    width: u32,
+    height: u32,
+    bit_depth_luma_minus8: u32,
+    bit_depth_chroma_minus8: u32,
+}
+
+fn parse_hevc_sps(sps: &[u8]) -> Option<HevcSpsInfo> {
+    if sps.len() < 3 {
+        return None;
+    }
+    let rbsp = nal_to_rbsp(&sps[2..]);
+    let mut br = BitReader::new(&rbsp);
+    br.read_bits(4)?;
+    let max_sub_layers_minus1 = br.read_bits(3)?;
+    let temporal_id_nesting = br.read_bit()?;
+    let mut profile_tier_level = [0u8; 12];
+    for byte in profile_tier_level.iter_mut() {
+        *byte = br.read_bits(8)?;
+    }
+
+    let mut sub_layer_flags = Vec::with_capacity(usize::from(max_sub_layers_minus1));
+    for _ in 0..max_sub_layers_minus1 {
+        sub_layer_flags.push((br.read_bit()?, br.read_bit()?));
+    }
+    if max_sub_layers_minus1 > 0 {
+        for _ in max_sub_layers_minus1..8 {
+            br.read_bits(2)?;
+        }
+    }
+    for (profile_present, level_present) in sub_layer_flags {
+        if profile_present {
+            for _ in 0..11 {
+                br.read_bits(8)?;
+            }
+        }
+        if level_present {
+            br.read_bits(8)?;
+        }
+    }
+
+    br.read_ue()?;
+    let chroma_format_idc = br.read_ue()?;
+    let mut separate_colour_plane = false;
+    if chroma_format_idc == 3 {
+        separate_colour_plane = br.read_bit()?;
+    }
+    let mut width = br.read_ue()?;
+    let mut height = br.read_ue()?;
+    if br.read_bit()? {
+        let (left, right, top, bottom) =
+            (br.read_ue()?, br.read_ue()?, br.read_ue()?, br.read_ue()?);
+        let (sub_width, sub_height) = match chroma_format_idc {
+            1 if !separate_colour_plane => (2, 2),
+            2 if !separate_colour_plane => (2, 1),
+            _ => (1, 1),
+        };
+        // Crop offsets are unbounded ue(v) values; reject rather than wrap.
+        width = width.saturating_sub(left.checked_add(right)?.checked_mul(sub_width)?);
+        height = height.saturating_sub(top.checked_add(bottom)?.checked_mul(sub_height)?);
+    }
+    let bit_depth_luma_minus8 = br.read_ue()?;
+    let bit_depth_chroma_minus8 = br.read_ue()?;
+
+    Some(HevcSpsInfo {
+        profile_tier_level,
+        max_sub_layers_minus1,
+        temporal_id_nesting,
+        chroma_format_idc,
+        width,
+        height,
+        bit_depth_luma_minus8,
+        bit_depth_chroma_minus8,
+    })
+}
+
 fn nal_to_rbsp(nal: &[u8]) -> Vec<u8> {
     let mut out = Vec::with_capacity(nal.len());
     let mut zeros = 0u8;
@@ -1016,13 +1703,21 @@ impl<'a> BitReader<'a> {
         Some(value)
     }
 
+    fn read_u32(&mut self, count: u8) -> Option<u32> {
+        let mut value = 0u32;
+        for _ in 0..count {
+            value = (value << 1) | self.read_bit()? as u32;
+        }
+        Some(value)
+    }
+
     fn read_ue(&mut self) -> Option<u32> {
         let mut zeros = 0u32;
-        while let Some(bit) = self.read_bit() {
-            if bit {
-                break;
-            }
+        while !self.read_bit()? {
             zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
         }
         let mut value = 1u32;
         for _ in 0..zeros {
@@ -1064,19 +1759,362 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn playlist_does_not_offer_delta_updates() {
+        let mut segmenter = CmafSegmenter::new(WriterMode::Memory, 1.0, 20, 0.333, None, 15.0)
+            .await
+            .expect("segmenter");
+        for seq in 0..10 {
+            segmenter.segments.push_back(SegmentInfo {
+                seq,
+                duration: 1.0,
+                filename: format!("seg{:06}.m4s", seq),
+                parts: Vec::new(),
+                discontinuity: false,
+                started_at: Utc::now(),
+                events: Vec::new(),
+            });
+        }
+
+        let playlist = segmenter.render_playlist(None);
+
+        assert!(!playlist.contains("CAN-SKIP-UNTIL"));
+        assert!(!playlist.contains("#EXT-X-SKIP"));
+        assert!(playlist.contains("#EXT-X-MEDIA-SEQUENCE:0"));
+        assert_eq!(playlist.matches("#EXTINF:").count(), 10);
+    }
+
+    #[tokio::test]
+    async fn playlist_json_matches_rendered_playlist() {
+        let mut segmenter = CmafSegmenter::new(WriterMode::Memory, 2.0, 6, 0.5, None, 15.0)
+            .await
+            .expect("segmenter");
+        for seq in 10..12 {
+            segmenter.segments.push_back(SegmentInfo {
+                seq,
+                duration: 2.001,
+                filename: format!("seg{:06}.m4s", seq),
+                parts: vec![PartInfo {
+                    duration: 0.5,
+                    byte_start: 0,
+                    byte_length: 100,
+                    independent: true,
+                }],
+                discontinuity: seq == 11,
+                started_at: Utc::now(),
+                events: Vec::new(),
+            });
+        }
+
+        let playlist = segmenter.render_playlist(None);
+        let json = serde_json::to_value(segmenter.playlist_json(None)).expect("json");
+
+        assert!(playlist.contains("#EXT-X-TARGETDURATION:3\n"));
+        assert!(playlist.contains("#EXT-X-MEDIA-SEQUENCE:10\n"));
+        assert_eq!(json["targetDuration"], 3);
+        assert_eq!(json["mediaSequence"], 10);
+        assert_eq!(json["segments"][0]["seq"], 10);
+        assert_eq!(json["segments"][0]["duration"], 2.001);
+        assert_eq!(json["segments"][0]["uri"], "seg000010.m4s");
+        assert_eq!(json["segments"][0]["parts"][0]["byteLength"], 100);
+        assert_eq!(json["segments"][1]["discontinuity"], true);
+    }
+
+    #[tokio::test]
+    async fn part_hold_back_follows_configured_part_target() {
+        let mut segmenter = CmafSegmenter::new(WriterMode::Memory, 2.0, 6, 0.5, None, 15.0)
+            .await
+            .expect("segmenter");
+        segmenter.segments.push_back(SegmentInfo {
+            seq: 0,
+            duration: 2.0,
+            filename: "seg000000.m4s".to_string(),
+            parts: (0..4)
+                .map(|index| PartInfo {
+                    duration: if index == 0 { 0.5 } else { 0.45 },
+                    byte_start: index * 100,
+                    byte_length: 100,
+                    independent: index == 0,
+                })
+                .collect(),
+            discontinuity: false,
+            started_at: Utc::now(),
+            events: Vec::new(),
+        });
+
+        let playlist = segmenter.render_playlist(None);
+
+        // Parts shorter than the target must not shrink the advertised values.
+        assert!(playlist.contains("#EXT-X-PART-INF:PART-TARGET=0.500\n"));
+        assert!(playlist.contains("PART-HOLD-BACK=1.500,"));
+    }
+
+    #[tokio::test]
+    async fn parts_stay_within_target_when_frames_do_not_divide_it() {
+        // 15 fps frames last 1/15 s; 0.3 s holds 4.5 of them.
+        let mut segmenter = CmafSegmenter::new(WriterMode::Memory, 2.0, 6, 0.3, None, 15.0)
+            .await
+            .expect("segmenter");
+        segmenter
+            .push_access_unit(access_unit(true), 0)
+            .await
+            .expect("push idr");
+        for frame in 1..30 {
+            segmenter
+                .push_access_unit(access_unit(false), frame * 6_000)
+                .await
+                .expect("push p-frame");
+        }
+
+        let parts = &segmenter.current.as_ref().expect("current").parts;
+        assert!(parts.len() >= 6);
+        for part in parts {
+            assert!(part.duration <= 0.3 + 1e-9, "part of {}s", part.duration);
+            assert!((part.duration - 4.0 / 15.0).abs() < 1e-9);
+        }
+        let playlist = segmenter.render_playlist(segmenter.current.as_ref());
+        assert!(playlist.contains("#EXT-X-PART-INF:PART-TARGET=0.300\n"));
+    }
+
+    #[tokio::test]
+    async fn preload_hint_points_at_next_part() {
+        let mut segmenter = CmafSegmenter::new(WriterMode::Memory, 2.0, 6, 0.2, None, 15.0)
+            .await
+            .expect("segmenter");
+        segmenter
+            .push_access_unit(access_unit(true), 0)
+            .await
+            .expect("push idr");
+        let mut pts = 0;
+        while segmenter
+            .current
+            .as_ref()
+            .expect("current")
+            .parts
+            .is_empty()
+        {
+            pts += 6_000;
+            segmenter
+                .push_access_unit(access_unit(false), pts)
+                .await
+                .expect("push p-frame");
+        }
+
+        let playlist = segmenter.render_playlist(segmenter.current.as_ref());
+        let hint_start = segmenter.current.as_ref().expect("current").bytes_written;
+        assert!(playlist.ends_with(&format!(
+            "#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"{}\",BYTERANGE-START={}\n",
+            segmenter.segment_path(0),
+            hint_start
+        )));
+
+        while segmenter.current.as_ref().expect("current").parts.len() < 2 {
+            pts += 6_000;
+            segmenter
+                .push_access_unit(access_unit(false), pts)
+                .await
+                .expect("push p-frame");
+        }
+        let current = segmenter.current.as_ref().expect("current");
+        assert_eq!(current.parts[1].byte_start, hint_start);
+    }
+
+    /// A fresh directory under the system temp dir; tests remove it when done.
+    fn scratch_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("cmaf-test-{}", rand::random::<u64>()))
+    }
+
+    /// Writes 1s segments of 0.2s parts into `dir`, keeping six.
+    async fn file_segmenter(dir: &Path) -> CmafSegmenter {
+        CmafSegmenter::new(WriterMode::File(dir.to_path_buf()), 1.0, 6, 0.2, None, 15.0)
+            .await
+            .expect("segmenter")
+    }
+
+    #[tokio::test]
+    async fn segment_duration_includes_final_sample() {
+        let dir = scratch_dir();
+        let mut segmenter = file_segmenter(&dir).await;
+        for frame in 0..15u64 {
+            segmenter
+                .push_access_unit(access_unit(frame == 0), frame * 6_000)
+                .await
+                .expect("push frame");
+        }
+        segmenter
+            .push_access_unit(access_unit(true), 90_000)
+            .await
+            .expect("push next idr");
+
+        let segment = segmenter.segments.front().expect("finished segment");
+        let muxed: f64 = segment.parts.iter().map(|part| part.duration).sum();
+        assert!((segment.duration - 1.0).abs() < 1e-9);
+        assert!((segment.duration - muxed).abs() < 1e-9);
+        let playlist = segmenter.render_playlist(None);
+        assert!(playlist.contains("#EXTINF:1.000,"));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn long_gop_is_split_at_hard_limit() {
+        let dir = scratch_dir();
+        let mut segmenter = file_segmenter(&dir).await;
+        segmenter.set_segment_limits(None, Some(2.0));
+        // One keyframe followed by five seconds of P-frames.
+        for frame in 0..75u64 {
+            segmenter
+                .push_access_unit(access_unit(frame == 0), frame * 6_000)
+                .await
+                .expect("push frame");
+        }
+
+        assert_eq!(segmenter.segments.len(), 2);
+        assert!(!segmenter.segments[0].discontinuity);
+        assert!(segmenter.segments[1].discontinuity);
+        assert!(segmenter
+            .segments
+            .iter()
+            .all(|seg| seg.duration <= 2.0 + 1e-9));
+        let playlist = segmenter.render_playlist(None);
+        assert_eq!(playlist.matches("#EXT-X-DISCONTINUITY\n").count(), 1);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn independent_segments_are_not_claimed_with_hard_splits() {
+        let dir = scratch_dir();
+        let mut segmenter = file_segmenter(&dir).await;
+        write_segments(&mut segmenter, 2).await;
+        assert!(segmenter
+            .render_playlist(None)
+            .contains("#EXT-X-INDEPENDENT-SEGMENTS\n"));
+
+        segmenter.set_segment_limits(None, Some(2.0));
+        assert!(!segmenter
+            .render_playlist(None)
+            .contains("#EXT-X-INDEPENDENT-SEGMENTS"));
+        // A long GOP is split mid-GOP.
+        for frame in 31..120u64 {
+            segmenter
+                .push_access_unit(access_unit(false), frame * 6_000)
+                .await
+                .expect("push frame");
+        }
+        assert!(segmenter.segments.iter().any(|seg| seg.discontinuity));
+
+        // The split segment stays in the window after the limits are lifted.
+        segmenter.set_segment_limits(None, None);
+        assert!(!segmenter
+            .render_playlist(None)
+            .contains("#EXT-X-INDEPENDENT-SEGMENTS"));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    async fn write_segments(segmenter: &mut CmafSegmenter, count: u64) {
+        // 1s GOPs against a 1s target: one segment per keyframe.
+        for frame in 0..count * 15 + 1 {
+            segmenter
+                .push_access_unit(access_unit(frame % 15 == 0), frame * 6_000)
+                .await
+                .expect("push frame");
+        }
+    }
+
+    #[tokio::test]
+    async fn segment_names_do_not_collide_after_restart() {
+        let dir = scratch_dir();
+        let mut first = file_segmenter(&dir).await;
+        write_segments(&mut first, 3).await;
+        let first_names: Vec<String> = first
+            .segments
+            .iter()
+            .map(|seg| seg.filename.clone())
+            .collect();
+        drop(first);
+
+        // The restarted segmenter starts again at sequence 0 in the same directory.
+        let mut second = file_segmenter(&dir).await;
+        write_segments(&mut second, 3).await;
+
+        assert_eq!(second.segments[0].seq, 0);
+        for seg in &second.segments {
+            assert!(
+                !first_names.contains(&seg.filename),
+                "{} reused",
+                seg.filename
+            );
+        }
+        for name in &first_names {
+            assert!(dir.join(name).is_file(), "{name} was overwritten");
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn events_are_marked_on_the_next_segment() {
+        let dir = scratch_dir();
+        let mut segmenter = file_segmenter(&dir).await;
+        for frame in 0..31u64 {
+            if frame == 10 {
+                segmenter.add_event("FINISH");
+            }
+            segmenter
+                .push_access_unit(access_unit(frame % 15 == 0), frame * 6_000)
+                .await
+                .expect("push frame");
+        }
+
+        assert!(segmenter.segments[0].events.is_empty());
+        let marked = &segmenter.segments[1];
+        assert_eq!(marked.events.len(), 1);
+        let playlist = segmenter.render_playlist(segmenter.current.as_ref());
+        let start = marked
+            .started_at
+            .to_rfc3339_opts(SecondsFormat::Millis, true);
+        let expected = format!(
+            "{}\n#EXT-X-PROGRAM-DATE-TIME:{start}\n#EXT-X-DATERANGE:ID=\"{}-1\",CLASS=\"com.bambu.event\",START-DATE=\"{start}\",X-EVENT-TYPE=\"FINISH\"\n#EXT-X-PART:",
+            segmenter.segments[0].filename, segmenter.session
+        );
+        assert!(playlist.contains(&expected), "{playlist}");
+        assert_eq!(playlist.matches("#EXT-X-DATERANGE").count(), 1);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn segments_are_sharded_into_subdirectories() {
+        let dir = scratch_dir();
+        let mut segmenter =
+            CmafSegmenter::new(WriterMode::File(dir.clone()), 1.0, 2, 0.2, None, 15.0)
+                .await
+                .expect("segmenter");
+        segmenter.set_segment_naming("part{seq}.m4s", Some(2));
+        write_segments(&mut segmenter, 5).await;
+
+        let names: Vec<&str> = segmenter
+            .segments
+            .iter()
+            .map(|seg| seg.filename.as_str())
+            .collect();
+        assert_eq!(names, ["000001/part000003.m4s", "000002/part000004.m4s"]);
+        assert!(dir.join("000002/part000004.m4s").is_file());
+        // Shard 0 emptied out of the window and was removed.
+        assert!(!dir.join("000000").exists());
+        let playlist = std::fs::read_to_string(dir.join("stream.m3u8")).expect("playlist");
+        assert!(playlist.contains("\n000001/part000003.m4s\n"));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
     #[tokio::test]
     async fn first_sample_keeps_its_original_pts() {
-        let mut segmenter = CmafSegmenter::new(
-            PathBuf::from("unused"),
-            2.0,
-            6,
-            0.333,
-            None,
-            false,
-            15.0,
-        )
-        .await
-        .expect("segmenter");
+        let mut segmenter = CmafSegmenter::new(WriterMode::Memory, 2.0, 6, 0.333, None, 15.0)
+            .await
+            .expect("segmenter");
 
         segmenter
             .push_access_unit(access_unit(true), 0)
@@ -1091,17 +2129,9 @@ mod tests {
 
     #[tokio::test]
     async fn monotonic_pts_follow_real_frame_rate_after_segment_start() {
-        let mut segmenter = CmafSegmenter::new(
-            PathBuf::from("unused"),
-            2.0,
-            6,
-            0.333,
-            None,
-            false,
-            15.0,
-        )
-        .await
-        .expect("segmenter");
+        let mut segmenter = CmafSegmenter::new(WriterMode::Memory, 2.0, 6, 0.333, None, 15.0)
+            .await
+            .expect("segmenter");
 
         segmenter
             .push_access_unit(access_unit(true), 0)
@@ -1116,4 +2146,378 @@ mod tests {
         assert_eq!(current.last_pts, 3_000);
         assert_eq!(current.frames, 2);
     }
+
+    fn find_box(data: &[u8], tag: &[u8; 4]) -> Option<(usize, usize)> {
+        let mut offset = 0;
+        while offset + 8 <= data.len() {
+            let size = u32::from_be_bytes(data[offset..offset + 4].try_into().ok()?) as usize;
+            if size < 8 || offset + size > data.len() {
+                return None;
+            }
+            if &data[offset + 4..offset + 8] == tag {
+                return Some((offset, size));
+            }
+            offset += size;
+        }
+        None
+    }
+
+    #[test]
+    fn moof_data_offset_points_at_first_sample_byte() {
+        let samples = vec![
+            Sample {
+                pts90k: 0,
+                is_idr: true,
+                nals: vec![vec![0xAA; 33]],
+            },
+            Sample {
+                pts90k: 3_000,
+                is_idr: false,
+                nals: vec![vec![0xBB; 1]],
+            },
+        ];
+        let sizes: Vec<u32> = samples
+            .iter()
+            .map(|sample| avc_sample_size(&sample.nals) as u32)
+            .collect();
+        let mut fragment = BytesMut::new();
+        write_styp(&mut fragment, *b"avc1");
+        assert_eq!(fragment.len(), STYP_SIZE);
+        write_moof(
+            &mut fragment,
+            1,
+            0,
+            &[3_000, 3_000],
+            &sizes,
+            &[SAMPLE_FLAG_SYNC, SAMPLE_FLAG_NON_SYNC],
+        );
+        assert_eq!(fragment.len(), STYP_SIZE + moof_size(samples.len()));
+        write_mdat(&mut fragment, &samples);
+
+        let (moof_start, moof_size) = find_box(&fragment, b"moof").expect("moof box");
+        let moof_box = &fragment[moof_start..moof_start + moof_size];
+        let (traf_start, traf_size) = find_box(&moof_box[8..], b"traf").expect("traf box");
+        let traf_box = &moof_box[8 + traf_start..8 + traf_start + traf_size];
+        let (trun_start, _) = find_box(&traf_box[8..], b"trun").expect("trun box");
+        let trun_payload = &traf_box[8 + trun_start + 8..];
+        let data_offset = i32::from_be_bytes(trun_payload[8..12].try_into().unwrap());
+
+        let (mdat_start, _) = find_box(&fragment, b"mdat").expect("mdat box");
+        let first_sample = moof_start + data_offset as usize;
+        assert_eq!(first_sample, mdat_start + 8);
+        assert_eq!(
+            &fragment[first_sample..first_sample + 4],
+            &33u32.to_be_bytes()
+        );
+        assert_eq!(
+            &fragment[first_sample + 4..first_sample + 37],
+            &[0xAA; 33][..]
+        );
+        assert_eq!(fragment[first_sample + 37 + 4], 0xBB);
+    }
+
+    #[tokio::test]
+    async fn encryption_covers_segment_files_but_not_the_stream() {
+        use aes::cipher::BlockDecryptMut;
+
+        let dir = scratch_dir();
+        let stream = CmafStream::new(64);
+        let mut segmenter = CmafSegmenter::new(
+            WriterMode::File(dir.clone()),
+            1.0,
+            6,
+            0.2,
+            Some(stream.clone()),
+            15.0,
+        )
+        .await
+        .expect("segmenter");
+        let key = [7u8; 16];
+        segmenter.set_encryption_key(Some(key), "/api/printers/1/video/key");
+        write_segments(&mut segmenter, 1).await;
+
+        let fragments: Vec<u8> = stream
+            .backlog_snapshot()
+            .iter()
+            .take(segmenter.segments[0].parts.len())
+            .flat_map(|fragment| fragment.bytes.to_vec())
+            .collect();
+        assert_eq!(&fragments[4..8], b"styp");
+        let ciphertext = std::fs::read(dir.join(&segmenter.segments[0].filename)).expect("segment");
+        let iv = u128::from(segmenter.segments[0].seq).to_be_bytes();
+        let decrypted = cbc::Decryptor::<aes::Aes128>::new(&key.into(), &iv.into())
+            .decrypt_padded_vec_mut::<Pkcs7>(&ciphertext)
+            .expect("decrypt");
+        assert_eq!(decrypted, fragments);
+
+        let playlist = std::fs::read_to_string(dir.join("stream.m3u8")).expect("playlist");
+        assert!(playlist.contains("#EXT-X-KEY:METHOD=AES-128,URI=\"/api/printers/1/video/key\"\n"));
+        assert!(!playlist.contains("#EXT-X-PART"));
+        assert!(!playlist.contains("#EXT-X-PRELOAD-HINT"));
+        assert!(!dir.join("enc.key").exists());
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.join("stream.json")).expect("json"))
+                .expect("parse");
+        assert_eq!(json["encryption"]["method"], "AES-128");
+        assert_eq!(json["encryption"]["keyUri"], "/api/printers/1/video/key");
+        assert!(json["segments"]
+            .as_array()
+            .expect("segments")
+            .iter()
+            .all(|segment| segment["parts"].as_array().is_some_and(Vec::is_empty)));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    const HEVC_SPS_720P: [u8; 41] = [
+        0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00, 0x00,
+        0x03, 0x00, 0x5D, 0xA0, 0x02, 0x80, 0x80, 0x2D, 0x16, 0x59, 0x59, 0xA4, 0x93, 0x2B, 0xC0,
+        0x5A, 0x70, 0x80, 0x00, 0x01, 0xF4, 0x80, 0x00, 0x3A, 0x98, 0x04,
+    ];
+
+    #[test]
+    fn hevc_sps_yields_dimensions_and_codec_string() {
+        let info = parse_hevc_sps(&HEVC_SPS_720P).expect("sps");
+
+        assert_eq!((info.width, info.height), (1280, 720));
+        assert_eq!(info.chroma_format_idc, 1);
+        assert_eq!(hevc_codec_string(&HEVC_SPS_720P), "hvc1.1.6.L93.90");
+    }
+
+    #[test]
+    fn hevc_sps_with_overflowing_crop_is_rejected() {
+        // 4:2:0 1280x720 with a conformance window of `left`/`right` luma pairs.
+        let sps = |left: u32, right: u32| {
+            let mut bits = vec![false; 8 + 96];
+            for value in [0, 1, 1280, 720] {
+                push_ue(&mut bits, value);
+            }
+            bits.push(true);
+            for value in [left, right, 0, 0, 0, 0] {
+                push_ue(&mut bits, value);
+            }
+            let mut sps = vec![0x42, 0x01];
+            sps.extend(bits.chunks(8).map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |byte, (i, bit)| byte | (u8::from(*bit) << (7 - i)))
+            }));
+            sps
+        };
+        let info = parse_hevc_sps(&sps(4, 4)).expect("sps");
+        assert_eq!((info.width, info.height), (1264, 720));
+        assert!(parse_hevc_sps(&sps(u32::MAX >> 1, u32::MAX >> 1)).is_none());
+    }
+
+    fn push_ue(bits: &mut Vec<bool>, value: u32) {
+        let code = u64::from(value) + 1;
+        let len = 64 - code.leading_zeros();
+        bits.extend(std::iter::repeat_n(false, len as usize - 1));
+        bits.extend((0..len).rev().map(|shift| code >> shift & 1 == 1));
+    }
+
+    #[test]
+    fn hevc_init_uses_hvc1_sample_entry() {
+        let vps = [0x40, 0x01, 0x0C];
+        let pps = [0x44, 0x01, 0xC0];
+        let init = build_init_mp4(
+            &build_hvc1(&vps, &HEVC_SPS_720P, &pps, 1280, 720),
+            1280,
+            720,
+        );
+
+        let contains = |tag: &[u8]| init.windows(4).position(|window| window == tag);
+        assert!(contains(b"hvc1").is_some());
+        assert!(contains(b"avc1").is_none());
+        // configurationVersion, then the profile/tier/level copied from the SPS.
+        let hvcc_payload = contains(b"hvcC").expect("hvcC box") + 4;
+        assert_eq!(init[hvcc_payload], 1);
+        assert_eq!(init[hvcc_payload + 1], 0x01);
+        assert_eq!(init[hvcc_payload + 12], 0x5D);
+    }
+
+    #[test]
+    fn sidx_references_single_subsegment() {
+        let mut sidx = BytesMut::new();
+        write_sidx(&mut sidx, 180_000, 30_000, 1_234);
+
+        assert_eq!(sidx.len(), SIDX_SIZE);
+        assert_eq!(&sidx[4..8], b"sidx");
+        assert_eq!(sidx[8], 1);
+        assert_eq!(u32::from_be_bytes(sidx[16..20].try_into().unwrap()), 90_000);
+        assert_eq!(
+            u64::from_be_bytes(sidx[20..28].try_into().unwrap()),
+            180_000
+        );
+        assert_eq!(u64::from_be_bytes(sidx[28..36].try_into().unwrap()), 0);
+        assert_eq!(u16::from_be_bytes(sidx[38..40].try_into().unwrap()), 1);
+        let reference = u32::from_be_bytes(sidx[40..44].try_into().unwrap());
+        assert_eq!(reference >> 31, 0);
+        assert_eq!(reference & 0x7FFF_FFFF, 1_234);
+        assert_eq!(u32::from_be_bytes(sidx[44..48].try_into().unwrap()), 30_000);
+    }
+
+    #[test]
+    fn bit_reader_reads_single_bits_msb_first() {
+        let data = [0b1010_0001];
+        let mut br = BitReader::new(&data);
+        let bits: Vec<bool> = (0..8).map(|_| br.read_bit().unwrap()).collect();
+        assert_eq!(bits, [true, false, true, false, false, false, false, true]);
+        assert_eq!(br.read_bit(), None);
+    }
+
+    #[test]
+    fn bit_reader_reads_values_across_byte_boundaries() {
+        let data = [0xAB, 0xCD];
+        let mut br = BitReader::new(&data);
+        assert_eq!(br.read_bits(4), Some(0xA));
+        assert_eq!(br.read_bits(8), Some(0xBC));
+        assert_eq!(br.read_bits(3), Some(0b110));
+        assert_eq!(br.read_bits(2), None);
+    }
+
+    #[test]
+    fn bit_reader_decodes_exp_golomb_values() {
+        // ue: 1 -> 0, 010 -> 1, 011 -> 2, 00100 -> 3, 00111 -> 6
+        let data = [0b1010_0110, 0b0100_0011, 0b1000_0000];
+        let mut br = BitReader::new(&data);
+        assert_eq!(br.read_ue(), Some(0));
+        assert_eq!(br.read_ue(), Some(1));
+        assert_eq!(br.read_ue(), Some(2));
+        assert_eq!(br.read_ue(), Some(3));
+        assert_eq!(br.read_ue(), Some(6));
+
+        // se maps ue 1, 2, 3, 4 to 1, -1, 2, -2.
+        let data = [0b0100_1100, 0b1000_0101];
+        let mut br = BitReader::new(&data);
+        assert_eq!(br.read_se(), Some(1));
+        assert_eq!(br.read_se(), Some(-1));
+        assert_eq!(br.read_se(), Some(2));
+        assert_eq!(br.read_se(), Some(-2));
+    }
+
+    #[test]
+    fn bit_reader_returns_none_at_end_of_input() {
+        assert_eq!(BitReader::new(&[]).read_bit(), None);
+        assert_eq!(BitReader::new(&[]).read_ue(), None);
+        assert_eq!(BitReader::new(&[]).read_se(), None);
+        // Prefix of two zeros, then the input ends before the suffix bits.
+        let mut br = BitReader::new(&[0b1111_1001]);
+        br.read_bits(5).unwrap();
+        assert_eq!(br.read_ue(), None);
+        // All-zero input has no terminating one bit.
+        assert_eq!(BitReader::new(&[0, 0, 0, 0, 0]).read_ue(), None);
+    }
+
+    #[test]
+    fn nal_to_rbsp_strips_emulation_prevention_bytes() {
+        assert_eq!(
+            nal_to_rbsp(&[0x67, 0x00, 0x00, 0x03, 0x01, 0x00, 0x00, 0x03, 0x00]),
+            vec![0x67, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]
+        );
+        // A lone 0x03 after a single zero is payload, not escaping.
+        assert_eq!(nal_to_rbsp(&[0x00, 0x03, 0x00]), vec![0x00, 0x03, 0x00]);
+        // The counter resets after an escape, so 00 00 03 00 03 keeps the second 03.
+        assert_eq!(
+            nal_to_rbsp(&[0x00, 0x00, 0x03, 0x00, 0x03]),
+            vec![0x00, 0x00, 0x00, 0x03]
+        );
+    }
+
+    /// Baseline 1280x720 SPS, optionally with VUI timing_info.
+    fn baseline_sps(timing: Option<(u32, u32)>) -> Vec<u8> {
+        sized_baseline_sps(0, 80, 45, timing)
+    }
+
+    fn sized_baseline_sps(
+        sps_id: u32,
+        width_mbs: u32,
+        height_mbs: u32,
+        timing: Option<(u32, u32)>,
+    ) -> Vec<u8> {
+        let mut bits = Vec::new();
+        let mut put = |value: u64, count: u32| {
+            for shift in (0..count).rev() {
+                bits.push((value >> shift) & 1 == 1);
+            }
+        };
+        let ue = |value: u32| {
+            let code = u64::from(value) + 1;
+            let len = 64 - code.leading_zeros();
+            (code, 2 * len - 1)
+        };
+        put(66, 8); // profile_idc
+        put(0, 8);
+        put(31, 8); // level_idc
+        for value in [sps_id, 0, 2, 1] {
+            // sps id, log2_max_frame_num, poc type, max_num_ref_frames
+            let (code, len) = ue(value);
+            put(code, len);
+        }
+        put(0, 1);
+        let (code, len) = ue(width_mbs - 1);
+        put(code, len);
+        let (code, len) = ue(height_mbs - 1);
+        put(code, len);
+        put(0b110, 3); // frame_mbs_only, direct_8x8, no cropping
+        match timing {
+            Some((num_units_in_tick, time_scale)) => {
+                put(1, 1);
+                put(0, 4); // no aspect, overscan, signal type, chroma loc
+                put(1, 1);
+                put(u64::from(num_units_in_tick), 32);
+                put(u64::from(time_scale), 32);
+                put(1, 1);
+            }
+            None => put(0, 1),
+        }
+        put(1, 1); // rbsp stop bit
+        let mut sps = vec![0x67];
+        sps.extend(bits.chunks(8).map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (i, bit)| byte | (u8::from(*bit) << (7 - i)))
+        }));
+        sps
+    }
+
+    #[test]
+    fn sps_frame_rate_comes_from_vui_timing() {
+        let sps = baseline_sps(Some((1001, 60_000)));
+        assert_eq!(parse_sps_dimensions(&sps), Some((1280, 720)));
+        let fps = parse_sps_frame_rate(&sps).expect("frame rate");
+        assert!((fps - 29.97).abs() < 0.01, "{fps}");
+        assert_eq!(
+            parse_sps_frame_rate(&baseline_sps(Some((1, 30)))),
+            Some(15.0)
+        );
+
+        let without_vui = baseline_sps(None);
+        assert_eq!(parse_sps_dimensions(&without_vui), Some((1280, 720)));
+        assert_eq!(parse_sps_frame_rate(&without_vui), None);
+        assert_eq!(parse_sps_frame_rate(&baseline_sps(Some((0, 30)))), None);
+        assert_eq!(parse_sps_frame_rate(&[0x67]), None);
+    }
+
+    #[test]
+    fn highest_resolution_sps_is_selected_with_its_pps() {
+        let thumbnail = sized_baseline_sps(0, 20, 12, None);
+        let main = sized_baseline_sps(1, 120, 68, None);
+        // pps_id 0 -> sps 0, pps_id 1 -> sps 1.
+        let pps = [vec![0x68, 0b1100_0000], vec![0x68, 0b0100_1000]];
+
+        let (sps, selected_pps) =
+            select_parameter_sets(&[thumbnail.clone(), main.clone()], &pps).expect("pair");
+        assert_eq!(parse_sps_dimensions(&sps), Some((1920, 1088)));
+        assert_eq!(selected_pps, pps[1]);
+
+        // Falls back to the first PPS when none refers to the chosen SPS.
+        let (sps, selected_pps) =
+            select_parameter_sets(&[main, thumbnail], &pps[..1]).expect("pair");
+        assert_eq!(parse_sps_dimensions(&sps), Some((1920, 1088)));
+        assert_eq!(selected_pps, pps[0]);
+        assert_eq!(select_parameter_sets(&[], &pps), None);
+    }
 }
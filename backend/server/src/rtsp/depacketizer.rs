@@ -13,6 +13,11 @@ pub struct H264RtpDepacketizer {
     current_access_unit_bytes: usize,
     fu_buffer: Option<Vec<u8>>,
     fu_sequence: Option<u16>,
+    // Sizes of the last access unit and FU-A NAL. Buffers move on to the
+    // segmenter with each access unit, so they cannot be pooled; presizing from
+    // the previous frame avoids regrowing them packet by packet instead.
+    last_access_unit_nals: usize,
+    last_fu_bytes: usize,
     sps: Option<Vec<u8>>,
     pps: Option<Vec<u8>>,
     parameter_sets_dirty: bool,
@@ -26,6 +31,8 @@ impl H264RtpDepacketizer {
             current_access_unit_bytes: 0,
             fu_buffer: None,
             fu_sequence: None,
+            last_access_unit_nals: 0,
+            last_fu_bytes: 0,
             sps: None,
             pps: None,
             parameter_sets_dirty: false,
@@ -52,8 +59,9 @@ impl H264RtpDepacketizer {
         }
 
         let nals = self.extract_nals(packet);
+        let mut parameter_sets_seen = false;
         for nal in nals {
-            self.append_nal(nal, packet.timestamp);
+            parameter_sets_seen |= self.append_nal(nal, packet.timestamp);
             if self.current_access_unit_bytes >= MAX_ACCESS_UNIT_BYTES {
                 if let Some(ts) = self.current_timestamp {
                     tracing::warn!(
@@ -64,6 +72,11 @@ impl H264RtpDepacketizer {
                 }
             }
         }
+        // Flagged once per packet, so a STAP-A carrying SPS and PPS together is
+        // reported as a single complete update.
+        if parameter_sets_seen && self.sps.is_some() && self.pps.is_some() {
+            self.parameter_sets_dirty = true;
+        }
 
         if packet.marker && self.current_timestamp.is_some() && !self.current_access_unit.is_empty()
         {
@@ -75,7 +88,11 @@ impl H264RtpDepacketizer {
     }
 
     fn build_access_unit(&mut self, timestamp: u32) -> AccessUnit {
-        let nals = std::mem::take(&mut self.current_access_unit);
+        self.last_access_unit_nals = self.current_access_unit.len();
+        let nals = std::mem::replace(
+            &mut self.current_access_unit,
+            Vec::with_capacity(self.last_access_unit_nals),
+        );
         self.current_timestamp = None;
         self.current_access_unit_bytes = 0;
         let is_idr = nals
@@ -88,25 +105,27 @@ impl H264RtpDepacketizer {
         }
     }
 
-    fn append_nal(&mut self, nal: Vec<u8>, timestamp: u32) {
+    /// Returns whether `nal` was an SPS or PPS.
+    fn append_nal(&mut self, nal: Vec<u8>, timestamp: u32) -> bool {
         if self.current_timestamp.is_none() {
             self.current_timestamp = Some(timestamp);
         }
 
-        if let Some(nal_type) = nal.first().map(|b| b & 0x1F) {
-            if nal_type == 7 {
+        let parameter_set = match nal.first().map(|b| b & 0x1F) {
+            Some(7) => {
                 self.sps = Some(nal.clone());
-                self.parameter_sets_dirty = self.pps.is_some();
-            } else if nal_type == 8 {
+                true
+            }
+            Some(8) => {
                 self.pps = Some(nal.clone());
-                self.parameter_sets_dirty = self.sps.is_some();
+                true
             }
-        }
+            _ => false,
+        };
 
-        self.current_access_unit_bytes = self
-            .current_access_unit_bytes
-            .saturating_add(nal.len());
+        self.current_access_unit_bytes = self.current_access_unit_bytes.saturating_add(nal.len());
         self.current_access_unit.push(nal);
+        parameter_set
     }
 
     fn extract_nals(&mut self, packet: &RtpPacket) -> Vec<Vec<u8>> {
@@ -153,7 +172,7 @@ impl H264RtpDepacketizer {
         let nal_header = (fu_indicator & 0xE0) | nal_type;
 
         if start {
-            let mut buffer = Vec::with_capacity(payload.len());
+            let mut buffer = Vec::with_capacity(self.last_fu_bytes.max(payload.len()));
             buffer.push(nal_header);
             buffer.extend_from_slice(&payload[2..]);
             self.fu_buffer = Some(buffer);
@@ -191,7 +210,10 @@ impl H264RtpDepacketizer {
             return self
                 .fu_buffer
                 .take()
-                .map(|data| vec![data])
+                .map(|data| {
+                    self.last_fu_bytes = data.len();
+                    vec![data]
+                })
                 .unwrap_or_default();
         }
 
@@ -201,3 +223,61 @@ impl H264RtpDepacketizer {
 
 const MAX_ACCESS_UNIT_BYTES: usize = 8 * 1024 * 1024;
 const MAX_FU_BUFFER_BYTES: usize = 4 * 1024 * 1024;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stap_a_with_sps_and_pps_reports_one_update() {
+        let mut depacketizer = H264RtpDepacketizer::new();
+        let sps = vec![0x67, 0x64, 0x00, 0x1F];
+        let pps = vec![0x68, 0xEE, 0x3C, 0x80];
+        let mut stap_a = vec![24];
+        for nal in [&sps, &pps] {
+            stap_a.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+            stap_a.extend_from_slice(nal);
+        }
+        let packet = RtpPacket {
+            payload_type: 96,
+            marker: false,
+            sequence_number: 1,
+            timestamp: 3_000,
+            ssrc: 1,
+            payload: stap_a,
+        };
+
+        assert!(depacketizer.take_parameter_sets().is_none());
+        depacketizer.handle(&packet);
+        assert_eq!(depacketizer.take_parameter_sets(), Some((sps, pps)));
+        assert!(depacketizer.take_parameter_sets().is_none());
+    }
+
+    #[test]
+    fn fu_a_fragments_reassemble_across_access_units() {
+        let mut depacketizer = H264RtpDepacketizer::new();
+        let fragment = |sequence_number: u16, timestamp: u32, header: u8, data: &[u8]| {
+            let mut payload = vec![0x7C, header];
+            payload.extend_from_slice(data);
+            RtpPacket {
+                payload_type: 96,
+                marker: header & 0x40 != 0,
+                sequence_number,
+                timestamp,
+                ssrc: 1,
+                payload,
+            }
+        };
+
+        for (frame, timestamp) in [(0u16, 0u32), (1, 3_000)] {
+            let sequence = frame * 2;
+            assert!(depacketizer
+                .handle(&fragment(sequence, timestamp, 0x85, &[1, 2, 3]))
+                .is_empty());
+            let units = depacketizer.handle(&fragment(sequence + 1, timestamp, 0x45, &[4, 5]));
+            assert_eq!(units.len(), 1);
+            assert!(units[0].is_idr);
+            assert_eq!(units[0].nals, vec![vec![0x65, 1, 2, 3, 4, 5]]);
+        }
+    }
+}
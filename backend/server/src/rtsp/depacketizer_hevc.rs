@@ -0,0 +1,304 @@
+use crate::rtsp::depacketizer::AccessUnit;
+use crate::rtsp::rtp::RtpPacket;
+
+const NAL_TYPE_AP: u8 = 48;
+const NAL_TYPE_FU: u8 = 49;
+const NAL_TYPE_VPS: u8 = 32;
+const NAL_TYPE_SPS: u8 = 33;
+const NAL_TYPE_PPS: u8 = 34;
+
+/// RFC 7798 depacketizer. Assumes `sprop-max-don-diff` is 0, so aggregation
+/// and fragmentation units carry no DONL/DOND fields.
+pub struct H265RtpDepacketizer {
+    current_access_unit: Vec<Vec<u8>>,
+    current_timestamp: Option<u32>,
+    current_access_unit_bytes: usize,
+    fu_buffer: Option<Vec<u8>>,
+    fu_sequence: Option<u16>,
+    vps: Option<Vec<u8>>,
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+    parameter_sets_dirty: bool,
+}
+
+impl H265RtpDepacketizer {
+    pub fn new() -> Self {
+        Self {
+            current_access_unit: Vec::new(),
+            current_timestamp: None,
+            current_access_unit_bytes: 0,
+            fu_buffer: None,
+            fu_sequence: None,
+            vps: None,
+            sps: None,
+            pps: None,
+            parameter_sets_dirty: false,
+        }
+    }
+
+    pub fn take_parameter_sets(&mut self) -> Option<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+        if self.parameter_sets_dirty {
+            self.parameter_sets_dirty = false;
+            if let (Some(vps), Some(sps), Some(pps)) =
+                (self.vps.clone(), self.sps.clone(), self.pps.clone())
+            {
+                return Some((vps, sps, pps));
+            }
+        }
+        None
+    }
+
+    pub fn handle(&mut self, packet: &RtpPacket) -> Vec<AccessUnit> {
+        let mut output = Vec::new();
+
+        if let Some(current_ts) = self.current_timestamp {
+            if current_ts != packet.timestamp && !self.current_access_unit.is_empty() {
+                output.push(self.build_access_unit(current_ts));
+            }
+        }
+
+        let nals = self.extract_nals(packet);
+        let mut parameter_sets_seen = false;
+        for nal in nals {
+            parameter_sets_seen |= self.append_nal(nal, packet.timestamp);
+            if self.current_access_unit_bytes >= MAX_ACCESS_UNIT_BYTES {
+                if let Some(ts) = self.current_timestamp {
+                    tracing::warn!(
+                        bytes = self.current_access_unit_bytes,
+                        "rtp access unit exceeded size limit; forcing flush"
+                    );
+                    output.push(self.build_access_unit(ts));
+                }
+            }
+        }
+        // Flagged once per packet, after a whole aggregation unit is unpacked.
+        if parameter_sets_seen && self.vps.is_some() && self.sps.is_some() && self.pps.is_some() {
+            self.parameter_sets_dirty = true;
+        }
+
+        if packet.marker && self.current_timestamp.is_some() && !self.current_access_unit.is_empty()
+        {
+            let ts = self.current_timestamp.unwrap_or(packet.timestamp);
+            output.push(self.build_access_unit(ts));
+        }
+
+        output
+    }
+
+    fn build_access_unit(&mut self, timestamp: u32) -> AccessUnit {
+        let nals = std::mem::take(&mut self.current_access_unit);
+        self.current_timestamp = None;
+        self.current_access_unit_bytes = 0;
+        let is_idr = nals
+            .iter()
+            .any(|nal| nal_type(nal).is_some_and(is_random_access));
+        AccessUnit {
+            nals,
+            rtp_timestamp: timestamp,
+            is_idr,
+        }
+    }
+
+    /// Returns whether `nal` was a VPS, SPS or PPS.
+    fn append_nal(&mut self, nal: Vec<u8>, timestamp: u32) -> bool {
+        if self.current_timestamp.is_none() {
+            self.current_timestamp = Some(timestamp);
+        }
+
+        let slot = match nal_type(&nal) {
+            Some(NAL_TYPE_VPS) => Some(&mut self.vps),
+            Some(NAL_TYPE_SPS) => Some(&mut self.sps),
+            Some(NAL_TYPE_PPS) => Some(&mut self.pps),
+            _ => None,
+        };
+        let parameter_set = slot.map(|slot| *slot = Some(nal.clone())).is_some();
+
+        self.current_access_unit_bytes = self.current_access_unit_bytes.saturating_add(nal.len());
+        self.current_access_unit.push(nal);
+        parameter_set
+    }
+
+    fn extract_nals(&mut self, packet: &RtpPacket) -> Vec<Vec<u8>> {
+        let payload = &packet.payload;
+        match nal_type(payload) {
+            Some(0..=47) => vec![payload.clone()],
+            Some(NAL_TYPE_AP) => self.extract_ap(payload),
+            Some(NAL_TYPE_FU) => self.extract_fu(payload, packet.sequence_number),
+            // PACI (50) and reserved types are not used by cameras we support.
+            _ => Vec::new(),
+        }
+    }
+
+    fn extract_ap(&self, payload: &[u8]) -> Vec<Vec<u8>> {
+        let mut index = 2;
+        let mut nals = Vec::new();
+        while index + 2 <= payload.len() {
+            let size = u16::from_be_bytes([payload[index], payload[index + 1]]) as usize;
+            index += 2;
+            if size == 0 || index + size > payload.len() {
+                break;
+            }
+            nals.push(payload[index..index + size].to_vec());
+            index += size;
+        }
+        nals
+    }
+
+    fn extract_fu(&mut self, payload: &[u8], sequence: u16) -> Vec<Vec<u8>> {
+        if payload.len() <= 3 {
+            return Vec::new();
+        }
+        let fu_header = payload[2];
+        let start = (fu_header & 0x80) != 0;
+        let end = (fu_header & 0x40) != 0;
+        let fu_type = fu_header & 0x3F;
+
+        if start {
+            let mut buffer = Vec::with_capacity(payload.len());
+            // Rebuild the NAL header: keep F and LayerId MSB, swap in FuType.
+            buffer.push((payload[0] & 0x81) | (fu_type << 1));
+            buffer.push(payload[1]);
+            buffer.extend_from_slice(&payload[3..]);
+            self.fu_buffer = Some(buffer);
+            self.fu_sequence = Some(sequence);
+            return Vec::new();
+        }
+
+        let expected_sequence = self.fu_sequence.map(|seq| seq.wrapping_add(1));
+        if let Some(expected) = expected_sequence {
+            if sequence != expected {
+                self.fu_buffer = None;
+                self.fu_sequence = None;
+                return Vec::new();
+            }
+        }
+
+        if let Some(buffer) = self.fu_buffer.as_mut() {
+            buffer.extend_from_slice(&payload[3..]);
+            if buffer.len() > MAX_FU_BUFFER_BYTES {
+                tracing::warn!(
+                    bytes = buffer.len(),
+                    "rtp hevc fu buffer exceeded size limit; dropping"
+                );
+                self.fu_buffer = None;
+                self.fu_sequence = None;
+                return Vec::new();
+            }
+        } else {
+            return Vec::new();
+        }
+        self.fu_sequence = Some(sequence);
+
+        if end {
+            self.fu_sequence = None;
+            return self
+                .fu_buffer
+                .take()
+                .map(|data| vec![data])
+                .unwrap_or_default();
+        }
+
+        Vec::new()
+    }
+}
+
+fn nal_type(nal: &[u8]) -> Option<u8> {
+    if nal.len() < 2 {
+        return None;
+    }
+    Some((nal[0] >> 1) & 0x3F)
+}
+
+/// IRAP pictures: BLA (16-18), IDR (19-20) and CRA (21). Any of them can
+/// start a segment.
+fn is_random_access(nal_type: u8) -> bool {
+    (16..=21).contains(&nal_type)
+}
+
+const MAX_ACCESS_UNIT_BYTES: usize = 8 * 1024 * 1024;
+const MAX_FU_BUFFER_BYTES: usize = 4 * 1024 * 1024;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(sequence_number: u16, timestamp: u32, marker: bool, payload: Vec<u8>) -> RtpPacket {
+        RtpPacket {
+            payload_type: 96,
+            sequence_number,
+            timestamp,
+            ssrc: 1,
+            marker,
+            payload,
+        }
+    }
+
+    #[test]
+    fn aggregation_unit_yields_parameter_sets() {
+        let mut depacketizer = H265RtpDepacketizer::new();
+        let vps = vec![0x40, 0x01, 0x0C];
+        let sps = vec![0x42, 0x01, 0x01];
+        let pps = vec![0x44, 0x01, 0xC0];
+        let mut ap = vec![NAL_TYPE_AP << 1, 0x01];
+        for nal in [&vps, &sps, &pps] {
+            ap.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+            ap.extend_from_slice(nal);
+        }
+
+        let units = depacketizer.handle(&packet(1, 1000, false, ap));
+
+        assert!(units.is_empty());
+        assert_eq!(depacketizer.take_parameter_sets(), Some((vps, sps, pps)));
+        assert_eq!(depacketizer.take_parameter_sets(), None);
+    }
+
+    #[test]
+    fn fragmentation_units_reassemble_idr_slice() {
+        let mut depacketizer = H265RtpDepacketizer::new();
+        let idr_w_radl = 19;
+        let fu_indicator = [NAL_TYPE_FU << 1, 0x01];
+
+        let mut first = fu_indicator.to_vec();
+        first.push(0x80 | idr_w_radl);
+        first.extend_from_slice(&[0xAA, 0xBB]);
+        let mut last = fu_indicator.to_vec();
+        last.push(0x40 | idr_w_radl);
+        last.extend_from_slice(&[0xCC]);
+
+        assert!(depacketizer
+            .handle(&packet(10, 3000, false, first))
+            .is_empty());
+        let units = depacketizer.handle(&packet(11, 3000, true, last));
+
+        assert_eq!(units.len(), 1);
+        assert!(units[0].is_idr);
+        assert_eq!(
+            units[0].nals,
+            vec![vec![idr_w_radl << 1, 0x01, 0xAA, 0xBB, 0xCC]]
+        );
+    }
+
+    #[test]
+    fn fragmentation_unit_with_sequence_gap_is_dropped() {
+        let mut depacketizer = H265RtpDepacketizer::new();
+        let first = vec![NAL_TYPE_FU << 1, 0x01, 0x80 | 1, 0xAA];
+        let last = vec![NAL_TYPE_FU << 1, 0x01, 0x40 | 1, 0xBB];
+
+        assert!(depacketizer.handle(&packet(1, 0, false, first)).is_empty());
+        let units = depacketizer.handle(&packet(3, 0, true, last));
+
+        assert!(units.is_empty());
+    }
+
+    #[test]
+    fn single_trailing_slice_is_not_random_access() {
+        let mut depacketizer = H265RtpDepacketizer::new();
+        let trail_r = vec![1 << 1, 0x01, 0x12, 0x34];
+
+        let units = depacketizer.handle(&packet(1, 9000, true, trail_r.clone()));
+
+        assert_eq!(units.len(), 1);
+        assert!(!units[0].is_idr);
+        assert_eq!(units[0].nals, vec![trail_r]);
+    }
+}
@@ -0,0 +1,154 @@
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::time::{timeout_at, Instant};
+use tracing::debug;
+
+const SERVICE_TYPE: &str = "_rtsp._tcp.local.";
+/// Port Bambu printers serve RTSPS on.
+const RTSPS_PORT: u16 = 322;
+const DEFAULT_PATH: &str = "/streaming/live/1";
+
+/// Browses `_rtsp._tcp.local` for a service whose TXT records carry `serial`,
+/// so the stream can start before the first MQTT report names its URL.
+///
+/// The session sends the printer's access code, so only services advertised
+/// from one of `host`'s addresses count, and the URL always points at `host`:
+/// any LAN device can send an advertisement.
+pub async fn discover_rtsp_url(serial: &str, host: &str, wait: Duration) -> Option<String> {
+    let addresses: HashSet<IpAddr> = match tokio::net::lookup_host((host, 0)).await {
+        Ok(addresses) => addresses.map(|address| address.ip()).collect(),
+        Err(error) => {
+            debug!(?error, host, "cannot resolve printer host for mdns");
+            return None;
+        }
+    };
+    let daemon = match ServiceDaemon::new() {
+        Ok(daemon) => daemon,
+        Err(error) => {
+            debug!(?error, "mdns daemon unavailable");
+            return None;
+        }
+    };
+    let receiver = daemon.browse(SERVICE_TYPE).ok()?;
+    let deadline = Instant::now() + wait;
+    let mut found = None;
+    while let Ok(Ok(event)) = timeout_at(deadline, receiver.recv_async()).await {
+        if let ServiceEvent::ServiceResolved(info) = event {
+            if let Some(url) = rtsp_url_for(&info, serial, host, &addresses) {
+                found = Some(url);
+                break;
+            }
+        }
+    }
+    let _ = daemon.shutdown();
+    found
+}
+
+/// The URL on `host` for the service when one of its TXT values is `serial`
+/// and it was advertised from one of `addresses`. Only the port, and the
+/// scheme and path from `scheme`/`path` TXT keys, are taken from it.
+fn rtsp_url_for(
+    info: &ServiceInfo,
+    serial: &str,
+    host: &str,
+    addresses: &HashSet<IpAddr>,
+) -> Option<String> {
+    let properties = info.get_properties();
+    if !properties
+        .iter()
+        .any(|property| property.val_str().eq_ignore_ascii_case(serial))
+    {
+        return None;
+    }
+    if info.get_addresses().is_disjoint(addresses) {
+        return None;
+    }
+    let host = if host.contains(':') {
+        format!("[{host}]")
+    } else {
+        host.to_string()
+    };
+    let port = info.get_port();
+    let scheme = match info.get_property_val_str("scheme") {
+        Some(scheme) => scheme.to_string(),
+        None if port == RTSPS_PORT => "rtsps".to_string(),
+        None => "rtsp".to_string(),
+    };
+    let path = info
+        .get_property_val_str("path")
+        .filter(|path| !path.is_empty())
+        .unwrap_or(DEFAULT_PATH);
+    let path = path.trim_start_matches('/');
+    Some(format!("{scheme}://{host}:{port}/{path}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn service(port: u16, txt: &[(&str, &str)]) -> ServiceInfo {
+        let properties: HashMap<String, String> = txt
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        ServiceInfo::new(
+            SERVICE_TYPE,
+            "printer",
+            "bambu-x1c.local.",
+            "192.168.1.40",
+            port,
+            properties,
+        )
+        .expect("service info")
+    }
+
+    fn addresses(address: &str) -> HashSet<IpAddr> {
+        HashSet::from([address.parse().expect("address")])
+    }
+
+    #[test]
+    fn only_services_advertising_the_serial_are_used() {
+        let printer = addresses("192.168.1.40");
+        let info = service(322, &[("sn", "01S00C123456789")]);
+        assert_eq!(
+            rtsp_url_for(&info, "01s00c123456789", "192.168.1.40", &printer).as_deref(),
+            Some("rtsps://192.168.1.40:322/streaming/live/1")
+        );
+        assert_eq!(rtsp_url_for(&info, "OTHER", "192.168.1.40", &printer), None);
+
+        let info = service(
+            8554,
+            &[("serial", "SERIAL"), ("path", "/cam"), ("scheme", "rtsp")],
+        );
+        assert_eq!(
+            rtsp_url_for(&info, "SERIAL", "printer.lan", &printer).as_deref(),
+            Some("rtsp://printer.lan:8554/cam")
+        );
+    }
+
+    #[test]
+    fn services_advertised_from_another_host_are_ignored() {
+        let info = service(322, &[("sn", "SERIAL")]);
+        assert_eq!(
+            rtsp_url_for(&info, "SERIAL", "192.168.1.41", &addresses("192.168.1.41")),
+            None
+        );
+        let printer = addresses("fd00::5");
+        let info = ServiceInfo::new(
+            SERVICE_TYPE,
+            "printer",
+            "bambu-x1c.local.",
+            "fd00::5",
+            322,
+            HashMap::from([("sn".to_string(), "SERIAL".to_string())]),
+        )
+        .expect("service info");
+        assert_eq!(
+            rtsp_url_for(&info, "SERIAL", "fd00::5", &printer).as_deref(),
+            Some("rtsps://[fd00::5]:322/streaming/live/1")
+        );
+    }
+}
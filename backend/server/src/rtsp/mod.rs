@@ -2,12 +2,18 @@ pub mod auth;
 pub mod client;
 pub mod cmaf;
 pub mod depacketizer;
+pub mod depacketizer_hevc;
+pub mod discovery;
 pub mod parser;
 pub mod pipeline;
+pub mod reorder;
 pub mod rtp;
 pub mod sdp;
+pub mod server;
+pub mod stats;
 pub mod stream;
 pub mod time;
 
 pub use pipeline::run_rtsp_hls;
+pub use stats::StreamStats;
 pub use stream::CmafStream;
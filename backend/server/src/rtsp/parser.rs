@@ -3,6 +3,7 @@ use std::collections::HashMap;
 #[derive(Debug)]
 pub enum RtspEvent {
     Response(RtspResponse),
+    Request(RtspRequest),
     Interleaved { channel: u8, payload: Vec<u8> },
 }
 
@@ -26,6 +27,26 @@ impl RtspResponse {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct RtspRequest {
+    pub method: String,
+    pub uri: String,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl RtspRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(|value| value.as_str())
+    }
+
+    pub fn cseq(&self) -> Option<u32> {
+        self.header("cseq").and_then(|value| value.parse().ok())
+    }
+}
+
 pub struct RtspStreamParser {
     buffer: Vec<u8>,
 }
@@ -52,8 +73,8 @@ impl RtspStreamParser {
                 break;
             }
 
-            if let Some(event) = self.extract_response() {
-                events.push(RtspEvent::Response(event));
+            if let Some(event) = self.extract_message() {
+                events.push(event);
                 continue;
             }
 
@@ -80,16 +101,13 @@ impl RtspStreamParser {
         Some(RtspEvent::Interleaved { channel, payload })
     }
 
-    fn extract_response(&mut self) -> Option<RtspResponse> {
+    fn extract_message(&mut self) -> Option<RtspEvent> {
         let header_end = find_double_crlf(&self.buffer)?;
         let header_bytes = &self.buffer[..header_end];
         let header_text = String::from_utf8_lossy(header_bytes);
         let mut lines = header_text.split("\r\n").filter(|line| !line.is_empty());
-        let status_line = lines.next()?;
-        let mut status_parts = status_line.splitn(3, ' ');
-        let _proto = status_parts.next()?;
-        let status_code = status_parts.next()?.parse().ok()?;
-        let reason_phrase = status_parts.next().unwrap_or("").to_string();
+        let start_line = lines.next()?;
+        let start = StartLine::parse(start_line)?;
 
         let mut headers = HashMap::new();
         for line in lines {
@@ -113,11 +131,56 @@ impl RtspStreamParser {
         let body = self.buffer[body_start..total_length].to_vec();
         self.buffer.drain(0..total_length);
 
-        Some(RtspResponse {
-            status_code,
-            reason_phrase,
-            headers,
-            body,
+        Some(match start {
+            StartLine::Status {
+                status_code,
+                reason_phrase,
+            } => RtspEvent::Response(RtspResponse {
+                status_code,
+                reason_phrase,
+                headers,
+                body,
+            }),
+            StartLine::Request { method, uri } => RtspEvent::Request(RtspRequest {
+                method,
+                uri,
+                headers,
+                body,
+            }),
+        })
+    }
+}
+
+enum StartLine {
+    Status {
+        status_code: u16,
+        reason_phrase: String,
+    },
+    Request {
+        method: String,
+        uri: String,
+    },
+}
+
+impl StartLine {
+    fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.splitn(3, ' ');
+        let first = parts.next()?;
+        if first.starts_with("RTSP/") {
+            let status_code = parts.next()?.parse().ok()?;
+            let reason_phrase = parts.next().unwrap_or("").to_string();
+            return Some(Self::Status {
+                status_code,
+                reason_phrase,
+            });
+        }
+        let uri = parts.next()?.to_string();
+        if !parts.next()?.starts_with("RTSP/") {
+            return None;
+        }
+        Some(Self::Request {
+            method: first.to_ascii_uppercase(),
+            uri,
         })
     }
 }
@@ -125,3 +188,38 @@ impl RtspStreamParser {
 fn find_double_crlf(buffer: &[u8]) -> Option<usize> {
     buffer.windows(4).position(|window| window == b"\r\n\r\n")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_requests_responses_and_interleaved_frames() {
+        let mut parser = RtspStreamParser::new();
+        let mut data =
+            b"ANNOUNCE rtsp://host/1 RTSP/1.0\r\nCSeq: 2\r\nContent-Length: 3\r\n\r\nv=0".to_vec();
+        data.extend_from_slice(&[0x24, 0x00, 0x00, 0x02, 0xAA, 0xBB]);
+        data.extend_from_slice(b"RTSP/1.0 200 OK\r\nCSeq: 3\r\n\r\n");
+
+        let events = parser.append(&data);
+
+        assert_eq!(events.len(), 3);
+        match &events[0] {
+            RtspEvent::Request(request) => {
+                assert_eq!(request.method, "ANNOUNCE");
+                assert_eq!(request.uri, "rtsp://host/1");
+                assert_eq!(request.cseq(), Some(2));
+                assert_eq!(request.body, b"v=0");
+            }
+            other => panic!("expected request, got {other:?}"),
+        }
+        assert!(matches!(
+            &events[1],
+            RtspEvent::Interleaved { channel: 0, payload } if payload == &[0xAA, 0xBB]
+        ));
+        assert!(matches!(
+            &events[2],
+            RtspEvent::Response(response) if response.status_code == 200 && response.cseq() == Some(3)
+        ));
+    }
+}
@@ -1,31 +1,62 @@
+use crate::backoff::RetryBackoff;
 use crate::config::{AppConfig, PrinterConfig};
 use crate::rtsp::auth::RtspCredentials;
-use crate::rtsp::client::RtspClient;
-use crate::rtsp::cmaf::CmafSegmenter;
-use crate::rtsp::depacketizer::H264RtpDepacketizer;
+use crate::rtsp::client::{InterleavedPacket, RtspClient};
+use crate::rtsp::cmaf::{select_parameter_sets, CmafSegmenter, WriterMode};
+use crate::rtsp::depacketizer::{AccessUnit, H264RtpDepacketizer};
+use crate::rtsp::depacketizer_hevc::H265RtpDepacketizer;
+use crate::rtsp::discovery::discover_rtsp_url;
+use crate::rtsp::reorder::{RtpReorderBuffer, DEFAULT_REORDER_WINDOW};
 use crate::rtsp::rtp::RtpPacket;
+use crate::rtsp::sdp::{SdpCodec, SdpInfo};
+use crate::rtsp::stats::{RtspSessionState, StreamStats};
 use crate::rtsp::stream::CmafStream;
 use crate::rtsp::time::RtpTimeMapper;
-use crate::state::PrinterState;
+use crate::state::{PrinterState, VideoStatus};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::RwLock;
-use tokio::time::{sleep, timeout};
+use tokio::sync::{mpsc, watch};
+use tokio::time::{sleep, timeout_at, Instant};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
 use url::Url;
 
+const URL_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const MDNS_BROWSE_WAIT: Duration = Duration::from_secs(2);
+const MDNS_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
 pub async fn run_rtsp_hls(
     settings: AppConfig,
     printer: PrinterConfig,
-    state: Arc<RwLock<PrinterState>>,
+    status_rx: watch::Receiver<PrinterState>,
     output_dir: PathBuf,
     stream: CmafStream,
+    stats: StreamStats,
+    shutdown: CancellationToken,
 ) {
     let mut warned_missing = false;
+    let mut discovered: Option<Url> = None;
+    let mut next_discovery = Instant::now();
+    let mut backoff = RetryBackoff::from_secs_f64(
+        settings.rtsp_reconnect_initial_secs,
+        settings.rtsp_reconnect_max_secs,
+    );
 
     loop {
-        let url = match resolve_rtsp_url(&printer, &state).await {
+        stats.set_status(VideoStatus::Connecting);
+        let mut url = resolve_rtsp_url(&printer, &status_rx).or_else(|| discovered.clone());
+        if url.is_none() && settings.rtsp_mdns_discovery && Instant::now() >= next_discovery {
+            next_discovery = Instant::now() + MDNS_RETRY_INTERVAL;
+            discovered =
+                discover_rtsp_url(&printer.serial, printer.connect_host(), MDNS_BROWSE_WAIT)
+                    .await
+                    .and_then(|url| Url::parse(&url).ok());
+            if let Some(found) = discovered.as_ref() {
+                info!(url = %found, "rtsp url discovered over mdns");
+            }
+            url = discovered.clone();
+        }
+        let url = match url {
             Some(url) => {
                 warned_missing = false;
                 url
@@ -35,77 +66,231 @@ pub async fn run_rtsp_hls(
                     warn!("waiting for rtsp url from mqtt report");
                     warned_missing = true;
                 }
-                sleep(Duration::from_secs(2)).await;
+                if !retry_after_delay(&shutdown, URL_POLL_INTERVAL).await {
+                    return;
+                }
                 continue;
             }
         };
 
-        if settings.cmaf_write_files {
-            if let Err(error) = clean_output_dir(&output_dir).await {
-                warn!(?error, "failed to clean cmaf output directory");
-            }
-        }
-        let mut cmaf_segmenter = match CmafSegmenter::new(
-            output_dir.clone(),
-            settings.cmaf_target_duration_secs,
-            settings.cmaf_window_segments,
-            settings.cmaf_part_duration_secs,
-            Some(stream.clone()),
-            settings.cmaf_write_files,
-            settings.cmaf_fallback_fps,
+        // A pushed recording keeps the stream until it ends.
+        let writer = tokio::select! {
+            _ = shutdown.cancelled() => return,
+            writer = stream.claim_writer() => writer,
+        };
+        let mut cmaf_segmenter =
+            match open_segmenter(&settings, printer.id, &output_dir, &stream).await {
+                Ok(segmenter) => segmenter,
+                Err(error) => {
+                    warn!(?error, "failed to initialize cmaf segmenter");
+                    drop(writer);
+                    if !retry_after_delay(&shutdown, backoff.next_delay()).await {
+                        return;
+                    }
+                    continue;
+                }
+            };
+        let credentials = Some(RtspCredentials {
+            username: printer.resolved_rtsp_username().to_string(),
+            password: printer.access_code.clone(),
+        });
+        info!(%url, "starting rtsp session");
+        stats.rtsp().set_url(&url);
+        stats.rtsp().set_state(RtspSessionState::Connecting);
+        let client = RtspClient::new(
+            url,
+            credentials,
+            settings.rtsp_tls_insecure,
+            settings.rtsp_user_agent.clone(),
+        );
+        match run_session(
+            &settings,
+            client,
+            &mut cmaf_segmenter,
+            status_rx.clone(),
+            &mut backoff,
+            &stats,
+            &shutdown,
         )
         .await
         {
-            Ok(segmenter) => segmenter,
+            Ok(()) => stats.set_status(VideoStatus::Connecting),
             Err(error) => {
-                warn!(?error, "failed to initialize cmaf segmenter");
-                sleep(Duration::from_secs(2)).await;
-                continue;
+                warn!(?error, "rtsp session ended");
+                if stats.status() != VideoStatus::Stalled {
+                    stats.set_status(VideoStatus::Error);
+                }
             }
-        };
-        if let Err(error) = run_session(&settings, &printer, &mut cmaf_segmenter, url).await {
-            warn!(?error, "rtsp session ended");
         }
-        sleep(Duration::from_secs(2)).await;
+        drop(writer);
+        // The service may have moved ports; browse again before reconnecting.
+        if discovered.take().is_some() {
+            next_discovery = Instant::now();
+        }
+        let delay = backoff.next_delay();
+        debug!(
+            delay_ms = delay.as_millis() as u64,
+            "waiting before rtsp reconnect"
+        );
+        if !retry_after_delay(&shutdown, delay).await {
+            return;
+        }
+    }
+}
+
+pub(crate) async fn open_segmenter(
+    settings: &AppConfig,
+    printer_id: i64,
+    output_dir: &Path,
+    stream: &CmafStream,
+) -> anyhow::Result<CmafSegmenter> {
+    let writer = if settings.cmaf_write_files {
+        if let Err(error) = clean_output_dir(output_dir).await {
+            warn!(?error, "failed to clean cmaf output directory");
+        }
+        WriterMode::File(output_dir.to_path_buf())
+    } else {
+        WriterMode::Memory
+    };
+    let mut cmaf_segmenter = CmafSegmenter::new(
+        writer,
+        settings.cmaf_target_duration_secs,
+        settings.cmaf_window_segments,
+        settings.cmaf_part_duration_secs,
+        Some(stream.clone()),
+        settings.cmaf_fallback_fps,
+    )
+    .await?;
+    cmaf_segmenter.set_emit_sidx(settings.cmaf_emit_sidx);
+    cmaf_segmenter.set_encryption_key(
+        settings.cmaf_encryption_key,
+        &format!("/api/printers/{printer_id}/video/key"),
+    );
+    cmaf_segmenter.set_segment_limits(
+        settings.cmaf_max_segment_bytes,
+        settings.cmaf_max_segment_secs,
+    );
+    cmaf_segmenter.set_segment_naming(
+        &settings.cmaf_segment_pattern,
+        settings.cmaf_segments_per_dir,
+    );
+    Ok(cmaf_segmenter)
+}
+
+/// Waits before the next attempt; returns `false` once shutdown was requested.
+async fn retry_after_delay(shutdown: &CancellationToken, delay: Duration) -> bool {
+    tokio::select! {
+        _ = shutdown.cancelled() => false,
+        _ = sleep(delay) => true,
     }
 }
 
 async fn run_session(
     settings: &AppConfig,
-    printer: &PrinterConfig,
+    client: RtspClient,
     cmaf_segmenter: &mut CmafSegmenter,
-    url: Url,
+    status_rx: watch::Receiver<PrinterState>,
+    backoff: &mut RetryBackoff,
+    stats: &StreamStats,
+    shutdown: &CancellationToken,
 ) -> anyhow::Result<()> {
-    let credentials = Some(RtspCredentials {
-        username: "bblp".to_string(),
-        password: printer.access_code.clone(),
-    });
-    info!(%url, "starting rtsp session");
-    let client = RtspClient::new(url.clone(), credentials, settings.rtsp_tls_insecure);
     let mut session = client.start().await?;
 
-    if let (Some(sps), Some(pps)) = (session.sdp.sps.clone(), session.sdp.pps.clone()) {
-        cmaf_segmenter.set_parameter_sets(sps, pps);
-        cmaf_segmenter.ensure_init().await?;
+    let result = segment_rtp(
+        settings,
+        cmaf_segmenter,
+        RtpInput {
+            sdp: &session.sdp,
+            rtp_channel: session.rtp_channel,
+            interleaved_rx: &mut session.interleaved_rx,
+            status_rx,
+        },
+        Some(backoff),
+        stats,
+        shutdown,
+    )
+    .await;
+    session.teardown().await;
+    result
+}
+
+/// Interleaved RTP from an established session, pulled or pushed.
+pub(crate) struct RtpInput<'a> {
+    pub sdp: &'a SdpInfo,
+    pub rtp_channel: u8,
+    pub interleaved_rx: &'a mut mpsc::Receiver<InterleavedPacket>,
+    /// Job state changes are marked in the playlist.
+    pub status_rx: watch::Receiver<PrinterState>,
+}
+
+/// Depacketizes `input` into the segmenter until the channel closes or
+/// shutdown fires.
+pub(crate) async fn segment_rtp(
+    settings: &AppConfig,
+    cmaf_segmenter: &mut CmafSegmenter,
+    input: RtpInput<'_>,
+    mut backoff: Option<&mut RetryBackoff>,
+    stats: &StreamStats,
+    shutdown: &CancellationToken,
+) -> anyhow::Result<()> {
+    let RtpInput {
+        sdp,
+        rtp_channel,
+        interleaved_rx,
+        mut status_rx,
+    } = input;
+    let mut job_state = status_rx.borrow_and_update().job_state.clone();
+    let _connected = stats.mark_connected();
+    stats.set_status(VideoStatus::WaitingForKeyframe);
+    match (
+        sdp.codec,
+        sdp.vps.clone(),
+        sdp.sps.first().cloned(),
+        sdp.pps.first().cloned(),
+    ) {
+        (SdpCodec::H264, ..) => {
+            if let Some((sps, pps)) = select_parameter_sets(&sdp.sps, &sdp.pps) {
+                cmaf_segmenter.set_parameter_sets(sps, pps);
+                cmaf_segmenter.ensure_init().await?;
+            }
+        }
+        (SdpCodec::H265, Some(vps), Some(sps), Some(pps)) => {
+            cmaf_segmenter.set_hevc_parameter_sets(vps, sps, pps);
+            cmaf_segmenter.ensure_init().await?;
+        }
+        _ => {}
     }
 
-    let expected_payload = session.sdp.payload_type;
-    let mut depacketizer = H264RtpDepacketizer::new();
+    let expected_payload = sdp.payload_type;
+    let mut depacketizer = VideoDepacketizer::for_codec(sdp.codec);
     let mut time_mapper = RtpTimeMapper::new();
-    let interleaved_timeout = Duration::from_secs(settings.rtsp_packet_timeout_secs.max(1));
+    let mut reorder = RtpReorderBuffer::new(DEFAULT_REORDER_WINDOW);
+    // Only RTP on the video channel extends the deadline, so a printer that
+    // keeps sending RTCP after the encoder stalls still gets restarted.
+    let packet_timeout = Duration::from_secs(settings.rtsp_packet_timeout_secs.max(1));
+    let mut rtp_deadline = Instant::now() + packet_timeout;
 
     let mut saw_interleaved = false;
     let mut saw_rtp = false;
     let mut saw_access_unit = false;
+    let mut previous_pts: Option<u64> = None;
 
     loop {
-        let packet = match timeout(interleaved_timeout, session.interleaved_rx.recv()).await {
+        let received = tokio::select! {
+            _ = shutdown.cancelled() => {
+                info!("shutdown requested; finalizing open segment");
+                break;
+            }
+            received = timeout_at(rtp_deadline, interleaved_rx.recv()) => received,
+        };
+        let packet = match received {
             Ok(Some(packet)) => packet,
             Ok(None) => break,
             Err(_) => {
+                stats.set_status(VideoStatus::Stalled);
                 anyhow::bail!(
-                    "rtsp interleaved timeout ({}s without packets)",
-                    interleaved_timeout.as_secs()
+                    "rtsp packet timeout ({}s without rtp packets)",
+                    packet_timeout.as_secs()
                 );
             }
         };
@@ -117,7 +302,7 @@ async fn run_session(
                 "rtsp interleaved packet received"
             );
         }
-        if packet.channel != session.rtp_channel {
+        if packet.channel != rtp_channel {
             continue;
         }
         let rtp = match RtpPacket::parse(&packet.payload) {
@@ -139,42 +324,126 @@ async fn run_session(
                 continue;
             }
         }
+        stats.rtsp().record_rtp_packet();
 
-        let access_units = depacketizer.handle(&rtp);
+        let released = reorder.push(rtp);
+        // Packets the reorder buffer discards do not prove the stream is alive.
+        if !released.is_empty() {
+            rtp_deadline = Instant::now() + packet_timeout;
+        }
+        let mut access_units = Vec::new();
+        for rtp in released {
+            access_units.extend(depacketizer.handle(&rtp));
+        }
+        let lost = reorder.take_lost();
+        let discarded = reorder.take_dropped();
         if !access_units.is_empty() && !saw_access_unit {
             saw_access_unit = true;
+            if let Some(backoff) = backoff.as_mut() {
+                backoff.reset();
+            }
             let first = &access_units[0];
             debug!(
                 nals = first.nals.len(),
                 is_idr = first.is_idr,
                 rtp_timestamp = first.rtp_timestamp,
-                "h264 access unit assembled"
+                codec = ?sdp.codec,
+                "access unit assembled"
             );
         }
-        if let Some((sps, pps)) = depacketizer.take_parameter_sets() {
-            cmaf_segmenter.set_parameter_sets(sps, pps);
+        if depacketizer.update_parameter_sets(cmaf_segmenter) {
             cmaf_segmenter.ensure_init().await?;
         }
 
+        let mut last_pts = None;
         for access_unit in access_units {
             let pts = time_mapper.pts90k(access_unit.rtp_timestamp);
+            stats.rtsp().record_access_unit(
+                access_unit.is_idr,
+                previous_pts.and_then(|previous| pts.checked_sub(previous)),
+            );
+            previous_pts = Some(pts);
             cmaf_segmenter.push_access_unit(access_unit, pts).await?;
+            last_pts = Some(pts);
+        }
+        if cmaf_segmenter.has_open_segment() {
+            stats.set_status(VideoStatus::Live);
+        }
+        if status_rx.has_changed().unwrap_or(false) {
+            let current = status_rx.borrow_and_update().job_state.clone();
+            if current != job_state {
+                if let Some(state) = current.as_deref() {
+                    cmaf_segmenter.add_event(state);
+                }
+                job_state = current;
+            }
         }
+
+        stats.update(|stats| {
+            stats.packets_received += 1;
+            stats.rtp_packets_lost += lost;
+            stats.rtp_packets_discarded += discarded;
+            stats.last_packet_at = Some(chrono::Utc::now());
+            if last_pts.is_some() {
+                stats.last_pts_90k = last_pts;
+            }
+            stats.segments_produced = cmaf_segmenter.segments_produced();
+            stats.parts_produced = cmaf_segmenter.parts_produced();
+            stats.bytes_produced = cmaf_segmenter.bytes_produced();
+            stats.current_segment_duration_secs = cmaf_segmenter.current_segment_duration();
+            stats.parameter_sets_known = cmaf_segmenter.parameter_sets_known();
+            stats.fps = cmaf_segmenter.frame_rate();
+        });
     }
 
     cmaf_segmenter.finalize_segment().await?;
     Ok(())
 }
 
-async fn resolve_rtsp_url(
+enum VideoDepacketizer {
+    H264(H264RtpDepacketizer),
+    H265(H265RtpDepacketizer),
+}
+
+impl VideoDepacketizer {
+    fn for_codec(codec: SdpCodec) -> Self {
+        match codec {
+            SdpCodec::H264 => Self::H264(H264RtpDepacketizer::new()),
+            SdpCodec::H265 => Self::H265(H265RtpDepacketizer::new()),
+        }
+    }
+
+    fn handle(&mut self, packet: &RtpPacket) -> Vec<AccessUnit> {
+        match self {
+            Self::H264(depacketizer) => depacketizer.handle(packet),
+            Self::H265(depacketizer) => depacketizer.handle(packet),
+        }
+    }
+
+    /// Hands in-band parameter sets to the segmenter; returns whether they changed.
+    fn update_parameter_sets(&mut self, cmaf_segmenter: &mut CmafSegmenter) -> bool {
+        match self {
+            Self::H264(depacketizer) => depacketizer
+                .take_parameter_sets()
+                .map(|(sps, pps)| cmaf_segmenter.set_parameter_sets(sps, pps))
+                .is_some(),
+            Self::H265(depacketizer) => depacketizer
+                .take_parameter_sets()
+                .map(|(vps, sps, pps)| cmaf_segmenter.set_hevc_parameter_sets(vps, sps, pps))
+                .is_some(),
+        }
+    }
+}
+
+fn resolve_rtsp_url(
     printer: &PrinterConfig,
-    state: &Arc<RwLock<PrinterState>>,
+    status_rx: &watch::Receiver<PrinterState>,
 ) -> Option<Url> {
     if let Some(url) = printer.rtsp_url.as_ref() {
         return Url::parse(url).ok();
     }
 
-    let rtsp_url = state.read().await.rtsp_url.clone()?;
+    let rtsp_url = status_rx.borrow().rtsp_url.clone()?;
     Url::parse(&rtsp_url).ok()
 }
 
@@ -191,6 +460,9 @@ async fn clean_output_dir(dir: &Path) -> anyhow::Result<()> {
         let path = entry.path();
         if path.is_file() {
             let _ = tokio::fs::remove_file(&path).await;
+        } else if path.is_dir() {
+            // Segment shards from `CMAF_SEGMENTS_PER_DIR`.
+            let _ = tokio::fs::remove_dir_all(&path).await;
         }
     }
     Ok(())
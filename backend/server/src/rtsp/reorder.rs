@@ -0,0 +1,174 @@
+use crate::rtsp::rtp::RtpPacket;
+use std::collections::VecDeque;
+
+/// Packets held while waiting for a missing sequence number. Over TCP
+/// interleaved transport nothing is ever held.
+pub const DEFAULT_REORDER_WINDOW: usize = 32;
+/// This many late packets in a row means the sender restarted its sequence
+/// numbers (e.g. a camera reboot), not that packets are arriving late.
+const RESYNC_AFTER_LATE: u32 = 8;
+
+/// Small jitter buffer in front of the depacketizer: releases packets in
+/// sequence order, drops duplicates and late arrivals, and gives up on a gap
+/// once `window` newer packets are waiting behind it. A run of late packets
+/// resynchronizes it to the new sequence.
+#[derive(Debug)]
+pub struct RtpReorderBuffer {
+    window: usize,
+    expected: Option<u16>,
+    // Slot `i` holds the packet with sequence `expected + i`.
+    slots: VecDeque<Option<RtpPacket>>,
+    lost: u64,
+    dropped: u64,
+    late_streak: u32,
+}
+
+impl RtpReorderBuffer {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            expected: None,
+            slots: VecDeque::new(),
+            lost: 0,
+            dropped: 0,
+            late_streak: 0,
+        }
+    }
+
+    /// Accepts one packet and returns every packet that is now in order.
+    pub fn push(&mut self, packet: RtpPacket) -> Vec<RtpPacket> {
+        let expected = *self.expected.get_or_insert(packet.sequence_number);
+        let offset = packet.sequence_number.wrapping_sub(expected);
+        if offset >= 0x8000 {
+            self.late_streak += 1;
+            if self.late_streak < RESYNC_AFTER_LATE {
+                // Behind the release point: a retransmit or a packet we
+                // already gave up on.
+                self.dropped += 1;
+                return Vec::new();
+            }
+            let mut output: Vec<RtpPacket> = self.slots.drain(..).flatten().collect();
+            self.expected = Some(packet.sequence_number);
+            output.extend(self.push(packet));
+            return output;
+        }
+        self.late_streak = 0;
+
+        let mut output = Vec::new();
+        let mut offset = offset as usize;
+        while offset >= self.window {
+            self.advance(&mut output);
+            offset -= 1;
+        }
+
+        if self.slots.len() <= offset {
+            self.slots.resize(offset + 1, None);
+        }
+        if self.slots[offset].is_some() {
+            self.dropped += 1;
+        } else {
+            self.slots[offset] = Some(packet);
+        }
+
+        while matches!(self.slots.front(), Some(Some(_))) {
+            self.advance(&mut output);
+        }
+        output
+    }
+
+    /// Sequence numbers skipped since the last call.
+    pub fn take_lost(&mut self) -> u64 {
+        std::mem::take(&mut self.lost)
+    }
+
+    /// Duplicate or late packets discarded since the last call.
+    pub fn take_dropped(&mut self) -> u64 {
+        std::mem::take(&mut self.dropped)
+    }
+
+    /// Releases the head slot, counting it as lost if it is still empty.
+    fn advance(&mut self, output: &mut Vec<RtpPacket>) {
+        match self.slots.pop_front().flatten() {
+            Some(packet) => output.push(packet),
+            None => self.lost += 1,
+        }
+        self.expected = self.expected.map(|seq| seq.wrapping_add(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(sequence_number: u16) -> RtpPacket {
+        RtpPacket {
+            payload_type: 96,
+            marker: false,
+            sequence_number,
+            timestamp: 0,
+            ssrc: 1,
+            payload: vec![sequence_number as u8],
+        }
+    }
+
+    fn sequences(packets: Vec<RtpPacket>) -> Vec<u16> {
+        packets
+            .iter()
+            .map(|packet| packet.sequence_number)
+            .collect()
+    }
+
+    #[test]
+    fn sequence_reset_resynchronizes_after_a_run_of_late_packets() {
+        let mut buffer = RtpReorderBuffer::new(4);
+        assert_eq!(sequences(buffer.push(packet(5000))), vec![5000]);
+
+        for seq in 0..RESYNC_AFTER_LATE as u16 - 1 {
+            assert!(buffer.push(packet(seq)).is_empty());
+        }
+        assert_eq!(sequences(buffer.push(packet(7))), vec![7]);
+        assert_eq!(sequences(buffer.push(packet(8))), vec![8]);
+        assert_eq!(buffer.take_dropped(), u64::from(RESYNC_AFTER_LATE) - 1);
+    }
+
+    #[test]
+    fn duplicates_are_dropped() {
+        let mut buffer = RtpReorderBuffer::new(4);
+
+        assert_eq!(sequences(buffer.push(packet(10))), vec![10]);
+        assert!(buffer.push(packet(10)).is_empty());
+        assert_eq!(sequences(buffer.push(packet(11))), vec![11]);
+        assert!(buffer.push(packet(13)).is_empty());
+        assert!(buffer.push(packet(13)).is_empty());
+        assert_eq!(sequences(buffer.push(packet(12))), vec![12, 13]);
+
+        assert_eq!(buffer.take_dropped(), 2);
+        assert_eq!(buffer.take_lost(), 0);
+    }
+
+    #[test]
+    fn gap_is_skipped_once_window_fills() {
+        let mut buffer = RtpReorderBuffer::new(3);
+
+        assert_eq!(sequences(buffer.push(packet(1))), vec![1]);
+        assert!(buffer.push(packet(3)).is_empty());
+        assert!(buffer.push(packet(4)).is_empty());
+        assert_eq!(sequences(buffer.push(packet(5))), vec![3, 4, 5]);
+        assert_eq!(buffer.take_lost(), 1);
+
+        // The missing packet showing up afterwards is too late to use.
+        assert!(buffer.push(packet(2)).is_empty());
+        assert_eq!(buffer.take_dropped(), 1);
+    }
+
+    #[test]
+    fn sequence_wraps_around() {
+        let mut buffer = RtpReorderBuffer::new(4);
+
+        assert_eq!(sequences(buffer.push(packet(65_534))), vec![65_534]);
+        assert!(buffer.push(packet(0)).is_empty());
+        assert_eq!(sequences(buffer.push(packet(65_535))), vec![65_535, 0]);
+        assert_eq!(sequences(buffer.push(packet(1))), vec![1]);
+        assert_eq!(buffer.take_lost(), 0);
+    }
+}
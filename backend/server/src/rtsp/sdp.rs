@@ -1,13 +1,24 @@
 use base64::{engine::general_purpose, Engine as _};
 use url::Url;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SdpCodec {
+    #[default]
+    H264,
+    H265,
+}
+
 #[derive(Debug, Clone)]
 pub struct SdpInfo {
     pub video_control: Option<String>,
     pub session_control: Option<String>,
     pub payload_type: Option<u8>,
-    pub sps: Option<Vec<u8>>,
-    pub pps: Option<Vec<u8>>,
+    pub codec: SdpCodec,
+    /// H.265 only.
+    pub vps: Option<Vec<u8>>,
+    /// Every SPS/PPS advertised; some encoders send one per resolution layer.
+    pub sps: Vec<Vec<u8>>,
+    pub pps: Vec<Vec<u8>>,
 }
 
 impl SdpInfo {
@@ -33,8 +44,10 @@ pub fn parse_sdp(body: &[u8]) -> Option<SdpInfo> {
     let mut session_control = None;
     let mut video_control = None;
     let mut payload_type = None;
-    let mut sps = None;
-    let mut pps = None;
+    let mut codec = SdpCodec::default();
+    let mut vps = None;
+    let mut sps = Vec::new();
+    let mut pps = Vec::new();
     let mut in_video = false;
 
     for raw_line in text.lines() {
@@ -66,9 +79,14 @@ pub fn parse_sdp(body: &[u8]) -> Option<SdpInfo> {
         if in_video && line.starts_with("a=rtpmap:") {
             let value = line.trim_start_matches("a=rtpmap:");
             let mut parts = value.split_whitespace();
-            if let (Some(pt), Some(codec)) = (parts.next(), parts.next()) {
-                if codec.to_ascii_uppercase().starts_with("H264") {
+            if let (Some(pt), Some(encoding)) = (parts.next(), parts.next()) {
+                let encoding = encoding.to_ascii_uppercase();
+                if encoding.starts_with("H264") {
+                    payload_type = pt.parse::<u8>().ok();
+                    codec = SdpCodec::H264;
+                } else if encoding.starts_with("H265") || encoding.starts_with("HEVC") {
                     payload_type = pt.parse::<u8>().ok();
+                    codec = SdpCodec::H265;
                 }
             }
             continue;
@@ -86,14 +104,21 @@ pub fn parse_sdp(body: &[u8]) -> Option<SdpInfo> {
                 let mut kv = param.splitn(2, '=');
                 let key = kv.next().unwrap_or("").trim();
                 let val = kv.next().unwrap_or("").trim();
-                if key == "sprop-parameter-sets" {
-                    let mut sets = val.split(',');
-                    if let Some(sps_b64) = sets.next() {
-                        sps = general_purpose::STANDARD.decode(sps_b64).ok();
-                    }
-                    if let Some(pps_b64) = sets.next() {
-                        pps = general_purpose::STANDARD.decode(pps_b64).ok();
+                match key {
+                    "sprop-parameter-sets" => {
+                        for set in decode_sets(val) {
+                            match set.first().map(|header| header & 0x1F) {
+                                Some(7) => sps.push(set),
+                                Some(8) => pps.push(set),
+                                _ => {}
+                            }
+                        }
                     }
+                    // RFC 7798 carries each H.265 parameter set separately.
+                    "sprop-vps" => vps = decode_sets(val).into_iter().next(),
+                    "sprop-sps" => sps = decode_sets(val),
+                    "sprop-pps" => pps = decode_sets(val),
+                    _ => {}
                 }
             }
         }
@@ -103,11 +128,22 @@ pub fn parse_sdp(body: &[u8]) -> Option<SdpInfo> {
         video_control,
         session_control,
         payload_type,
+        codec,
+        vps,
         sps,
         pps,
     })
 }
 
+/// Decodes a comma-separated list of base64 parameter sets, skipping bad ones.
+fn decode_sets(value: &str) -> Vec<Vec<u8>> {
+    value
+        .split(',')
+        .filter_map(|set| general_purpose::STANDARD.decode(set.trim()).ok())
+        .filter(|set| !set.is_empty())
+        .collect()
+}
+
 fn resolve_control(control: &str, base_url: &Url) -> String {
     let lower = control.to_ascii_lowercase();
     if lower.starts_with("rtsp://") || lower.starts_with("rtsps://") {
@@ -121,3 +157,55 @@ fn resolve_control(control: &str, base_url: &Url) -> String {
         .map(|url| url.to_string())
         .unwrap_or_else(|_| base_url.to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_h265_and_separate_parameter_sets() {
+        let sdp = b"v=0\r\n\
+m=video 0 RTP/AVP 96\r\n\
+a=rtpmap:96 H265/90000\r\n\
+a=fmtp:96 sprop-vps=QAEMAf//; sprop-sps=QgEBAWA=; sprop-pps=RAHA8vA=\r\n\
+a=control:track1\r\n";
+
+        let info = parse_sdp(sdp).expect("sdp");
+
+        assert_eq!(info.codec, SdpCodec::H265);
+        assert_eq!(info.payload_type, Some(96));
+        assert_eq!(
+            info.vps.as_deref(),
+            Some(&[0x40, 0x01, 0x0C, 0x01, 0xFF, 0xFF][..])
+        );
+        assert_eq!(info.sps, [vec![0x42, 0x01, 0x01, 0x01, 0x60]]);
+        assert_eq!(info.pps, [vec![0x44, 0x01, 0xC0, 0xF2, 0xF0]]);
+        assert_eq!(info.video_control.as_deref(), Some("track1"));
+    }
+
+    #[test]
+    fn h264_remains_the_default_codec() {
+        let sdp = b"m=video 0 RTP/AVP 96\r\na=rtpmap:96 H264/90000\r\n";
+
+        let info = parse_sdp(sdp).expect("sdp");
+
+        assert_eq!(info.codec, SdpCodec::H264);
+        assert!(info.vps.is_none());
+    }
+
+    #[test]
+    fn keeps_every_h264_parameter_set() {
+        // SPS, a second SPS, then a PPS for each.
+        let sdp = b"m=video 0 RTP/AVP 96\r\n\
+a=rtpmap:96 H264/90000\r\n\
+a=fmtp:96 packetization-mode=1; sprop-parameter-sets=Z0IAHw==,Z00AKg==,aM4=,aM8=,!!\r\n";
+
+        let info = parse_sdp(sdp).expect("sdp");
+
+        assert_eq!(
+            info.sps,
+            [vec![0x67, 0x42, 0x00, 0x1F], vec![0x67, 0x4D, 0x00, 0x2A]]
+        );
+        assert_eq!(info.pps, [vec![0x68, 0xCE], vec![0x68, 0xCF]]);
+    }
+}
@@ -0,0 +1,469 @@
+use crate::config::AppConfig;
+use crate::printers::PrinterRuntime;
+use crate::rtsp::auth::RtspCredentials;
+use crate::rtsp::client::{parse_interleaved, InterleavedPacket};
+use crate::rtsp::parser::{RtspEvent, RtspRequest, RtspStreamParser};
+use crate::rtsp::pipeline::{open_segmenter, segment_rtp, RtpInput};
+use crate::rtsp::sdp::{parse_sdp, SdpInfo};
+use crate::rtsp::stats::RtspSessionState;
+use crate::state::VideoStatus;
+use anyhow::Context;
+use rand::Rng;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, RwLock};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use url::Url;
+
+const SESSION_TIMEOUT_SECS: u64 = 60;
+const ALLOWED_METHODS: &str =
+    "OPTIONS, ANNOUNCE, SETUP, RECORD, TEARDOWN, GET_PARAMETER, SET_PARAMETER";
+
+type PrinterRegistry = Arc<RwLock<HashMap<i64, Arc<PrinterRuntime>>>>;
+
+/// Accepts cameras that push their stream (ANNOUNCE + RECORD) instead of
+/// being pulled. The last path segment of the announced URL selects the
+/// printer, e.g. `rtsp://server:8554/printers/3`, and the stream is segmented
+/// into that printer's CMAF output. Cameras authenticate with HTTP Basic
+/// using the printer's RTSP username and access code, and a push is refused
+/// while the printer's own camera stream is being pulled.
+pub struct RtspServer {
+    listen: SocketAddr,
+    settings: AppConfig,
+    printers: PrinterRegistry,
+}
+
+struct Announced {
+    printer_id: i64,
+    url: Url,
+    sdp: SdpInfo,
+}
+
+struct Recorder {
+    packet_tx: mpsc::Sender<InterleavedPacket>,
+    task: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct PushSession {
+    id: String,
+    announced: Option<Announced>,
+    video_channel: Option<u8>,
+    recorder: Option<Recorder>,
+}
+
+impl RtspServer {
+    pub fn new(listen: SocketAddr, settings: AppConfig, printers: PrinterRegistry) -> Self {
+        Self {
+            listen,
+            settings,
+            printers,
+        }
+    }
+
+    pub async fn run(self, shutdown: CancellationToken) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(self.listen)
+            .await
+            .context("bind rtsp push listener")?;
+        info!(addr = %self.listen, "rtsp push listener ready");
+
+        let server = Arc::new(self);
+        let mut connections = JoinSet::new();
+        loop {
+            let (socket, peer) = tokio::select! {
+                _ = shutdown.cancelled() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(error) => {
+                        warn!(?error, "failed to accept rtsp push connection");
+                        continue;
+                    }
+                },
+            };
+            let server = Arc::clone(&server);
+            let shutdown = shutdown.child_token();
+            connections.spawn(async move {
+                if let Err(error) = server.handle_connection(socket, shutdown).await {
+                    warn!(?error, %peer, "rtsp push connection ended");
+                }
+            });
+        }
+
+        // Let open recordings finalize their segments.
+        while connections.join_next().await.is_some() {}
+        Ok(())
+    }
+
+    async fn handle_connection(
+        &self,
+        socket: TcpStream,
+        shutdown: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let (mut reader, mut writer) = socket.into_split();
+        let mut parser = RtspStreamParser::new();
+        let mut buffer = [0u8; 16 * 1024];
+        let mut session = PushSession {
+            id: format!("{:016X}", rand::thread_rng().gen::<u64>()),
+            ..PushSession::default()
+        };
+
+        'connection: loop {
+            let read = tokio::select! {
+                _ = shutdown.cancelled() => break,
+                read = reader.read(&mut buffer) => read?,
+            };
+            if read == 0 {
+                break;
+            }
+            for event in parser.append(&buffer[..read]) {
+                match event {
+                    RtspEvent::Interleaved { channel, payload } => {
+                        let Some(recorder) = session.recorder.as_ref() else {
+                            continue;
+                        };
+                        if recorder
+                            .packet_tx
+                            .send(InterleavedPacket { channel, payload })
+                            .await
+                            .is_err()
+                        {
+                            break 'connection;
+                        }
+                    }
+                    RtspEvent::Request(request) => {
+                        let teardown = request.method == "TEARDOWN";
+                        let response = self.respond(&mut session, &request, &shutdown).await;
+                        writer.write_all(response.as_bytes()).await?;
+                        writer.flush().await?;
+                        if teardown {
+                            break 'connection;
+                        }
+                    }
+                    RtspEvent::Response(_) => {}
+                }
+            }
+        }
+
+        if let Some(recorder) = session.recorder.take() {
+            drop(recorder.packet_tx);
+            let _ = recorder.task.await;
+        }
+        Ok(())
+    }
+
+    async fn respond(
+        &self,
+        session: &mut PushSession,
+        request: &RtspRequest,
+        shutdown: &CancellationToken,
+    ) -> String {
+        let cseq = request.cseq();
+        match request.method.as_str() {
+            "OPTIONS" => {
+                build_response(200, "OK", cseq, &[("Public", ALLOWED_METHODS.to_string())])
+            }
+            "ANNOUNCE" => self.announce(session, request).await,
+            "SETUP" => setup(session, request),
+            "RECORD" => self.record(session, request, shutdown).await,
+            "GET_PARAMETER" | "SET_PARAMETER" | "TEARDOWN" => {
+                build_response(200, "OK", cseq, &[("Session", session.id.clone())])
+            }
+            _ => build_response(
+                405,
+                "Method Not Allowed",
+                cseq,
+                &[("Allow", ALLOWED_METHODS.to_string())],
+            ),
+        }
+    }
+
+    async fn announce(&self, session: &mut PushSession, request: &RtspRequest) -> String {
+        let cseq = request.cseq();
+        if session.recorder.is_some() {
+            return build_response(455, "Method Not Valid in This State", cseq, &[]);
+        }
+        let Ok(url) = Url::parse(&request.uri) else {
+            return build_response(400, "Bad Request", cseq, &[]);
+        };
+        let Some(printer_id) = printer_id_from_url(&url) else {
+            return build_response(404, "Not Found", cseq, &[]);
+        };
+        let Some(runtime) = self.printers.read().await.get(&printer_id).cloned() else {
+            return build_response(404, "Not Found", cseq, &[]);
+        };
+        if !authorized(&runtime, request) {
+            return unauthorized(cseq);
+        }
+        let Some(sdp) = parse_sdp(&request.body).filter(|sdp| sdp.payload_type.is_some()) else {
+            return build_response(415, "Unsupported Media Type", cseq, &[]);
+        };
+
+        info!(printer_id, %url, "rtsp push stream announced");
+        runtime.stream_stats.rtsp().set_url(&url);
+        runtime
+            .stream_stats
+            .rtsp()
+            .set_state(RtspSessionState::Connecting);
+        session.announced = Some(Announced {
+            printer_id,
+            url,
+            sdp,
+        });
+        session.video_channel = None;
+        build_response(200, "OK", cseq, &[])
+    }
+
+    async fn record(
+        &self,
+        session: &mut PushSession,
+        request: &RtspRequest,
+        shutdown: &CancellationToken,
+    ) -> String {
+        let cseq = request.cseq();
+        let (Some(announced), Some(channel)) = (session.announced.as_ref(), session.video_channel)
+        else {
+            return build_response(455, "Method Not Valid in This State", cseq, &[]);
+        };
+        if session.recorder.is_some() {
+            return build_response(455, "Method Not Valid in This State", cseq, &[]);
+        }
+        let Some(runtime) = self
+            .printers
+            .read()
+            .await
+            .get(&announced.printer_id)
+            .cloned()
+        else {
+            return build_response(404, "Not Found", cseq, &[]);
+        };
+        if !authorized(&runtime, request) {
+            return unauthorized(cseq);
+        }
+        let Some(writer) = runtime.cmaf_stream.try_claim_writer() else {
+            warn!(
+                printer_id = announced.printer_id,
+                "rtsp push refused while the camera stream is pulled"
+            );
+            return build_response(503, "Service Unavailable", cseq, &[]);
+        };
+
+        let (packet_tx, mut packet_rx) = mpsc::channel(64);
+        let settings = self.settings.clone();
+        let sdp = announced.sdp.clone();
+        let printer_id = announced.printer_id;
+        let shutdown = shutdown.clone();
+        let task = tokio::spawn(async move {
+            let result = async {
+                let mut segmenter = open_segmenter(
+                    &settings,
+                    printer_id,
+                    &runtime.cmaf_dir,
+                    &runtime.cmaf_stream,
+                )
+                .await?;
+                segment_rtp(
+                    &settings,
+                    &mut segmenter,
+                    RtpInput {
+                        sdp: &sdp,
+                        rtp_channel: channel,
+                        interleaved_rx: &mut packet_rx,
+                        status_rx: runtime.status_tx.subscribe(),
+                    },
+                    None,
+                    &runtime.stream_stats,
+                    &shutdown,
+                )
+                .await
+            }
+            .await;
+            if let Err(error) = result {
+                warn!(?error, printer_id, "rtsp push recording ended");
+                runtime.stream_stats.set_status(VideoStatus::Error);
+            }
+            drop(writer);
+        });
+        session.recorder = Some(Recorder { packet_tx, task });
+
+        info!(printer_id, channel, "rtsp push recording started");
+        build_response(200, "OK", cseq, &[("Session", session.id.clone())])
+    }
+}
+
+fn setup(session: &mut PushSession, request: &RtspRequest) -> String {
+    let cseq = request.cseq();
+    let Some(announced) = session.announced.as_ref() else {
+        return build_response(455, "Method Not Valid in This State", cseq, &[]);
+    };
+    // Only RTP interleaved on the RTSP connection is supported.
+    let Some((rtp_channel, rtcp_channel)) = request.header("transport").and_then(parse_interleaved)
+    else {
+        return build_response(461, "Unsupported Transport", cseq, &[]);
+    };
+
+    let video_uri = announced.sdp.resolved_video_control_url(&announced.url);
+    let is_video = announced.sdp.video_control.is_none()
+        || request.uri.trim_end_matches('/') == video_uri.trim_end_matches('/');
+    if is_video {
+        session.video_channel = Some(rtp_channel);
+    }
+
+    build_response(
+        200,
+        "OK",
+        cseq,
+        &[
+            (
+                "Transport",
+                format!("RTP/AVP/TCP;unicast;interleaved={rtp_channel}-{rtcp_channel};mode=record"),
+            ),
+            (
+                "Session",
+                format!("{};timeout={SESSION_TIMEOUT_SECS}", session.id),
+            ),
+        ],
+    )
+}
+
+fn authorized(runtime: &PrinterRuntime, request: &RtspRequest) -> bool {
+    let config = runtime.config();
+    RtspCredentials {
+        username: config.resolved_rtsp_username().to_string(),
+        password: config.access_code,
+    }
+    .matches_basic(request.header("authorization"))
+}
+
+fn unauthorized(cseq: Option<u32>) -> String {
+    build_response(
+        401,
+        "Unauthorized",
+        cseq,
+        &[(
+            "WWW-Authenticate",
+            "Basic realm=\"BambuLANViewer\"".to_string(),
+        )],
+    )
+}
+
+fn printer_id_from_url(url: &Url) -> Option<i64> {
+    url.path_segments()?
+        .rfind(|segment| !segment.is_empty())?
+        .parse()
+        .ok()
+}
+
+fn build_response(
+    status_code: u16,
+    reason: &str,
+    cseq: Option<u32>,
+    headers: &[(&str, String)],
+) -> String {
+    let mut lines = vec![format!("RTSP/1.0 {status_code} {reason}")];
+    if let Some(cseq) = cseq {
+        lines.push(format!("CSeq: {cseq}"));
+    }
+    lines.push("Server: BambuLANViewer/1.0".to_string());
+    for (key, value) in headers {
+        lines.push(format!("{key}: {value}"));
+    }
+    lines.push(String::new());
+    lines.push(String::new());
+    lines.join("\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn request(method: &str, uri: &str, headers: &[(&str, &str)], body: &[u8]) -> RtspRequest {
+        RtspRequest {
+            method: method.to_string(),
+            uri: uri.to_string(),
+            headers: headers
+                .iter()
+                .map(|(key, value)| (key.to_ascii_lowercase(), value.to_string()))
+                .chain([("cseq".to_string(), "1".to_string())])
+                .collect::<HashMap<_, _>>(),
+            body: body.to_vec(),
+        }
+    }
+
+    #[test]
+    fn printer_id_comes_from_last_path_segment() {
+        let url = Url::parse("rtsp://server:8554/printers/7/").unwrap();
+        assert_eq!(printer_id_from_url(&url), Some(7));
+        let url = Url::parse("rtsp://server:8554/camera").unwrap();
+        assert_eq!(printer_id_from_url(&url), None);
+    }
+
+    #[test]
+    fn push_credentials_must_match_the_printer() {
+        let credentials = RtspCredentials {
+            username: "bblp".to_string(),
+            password: "12345678".to_string(),
+        };
+        // "bblp:12345678"
+        assert!(credentials.matches_basic(Some("Basic YmJscDoxMjM0NTY3OA==")));
+        // "bblp:wrong"
+        assert!(!credentials.matches_basic(Some("Basic YmJscDp3cm9uZw==")));
+        assert!(!credentials.matches_basic(Some("Digest username=\"bblp\"")));
+        assert!(!credentials.matches_basic(None));
+        assert!(unauthorized(Some(2)).starts_with("RTSP/1.0 401"));
+    }
+
+    #[test]
+    fn setup_selects_the_video_track_channel() {
+        let sdp = b"v=0\r\nm=audio 0 RTP/AVP 97\r\na=control:trackID=0\r\nm=video 0 RTP/AVP 96\r\na=rtpmap:96 H264/90000\r\na=control:trackID=1\r\n";
+        let url = Url::parse("rtsp://server:8554/printers/1/").unwrap();
+        let mut session = PushSession {
+            id: "ABC".to_string(),
+            announced: Some(Announced {
+                printer_id: 1,
+                url,
+                sdp: parse_sdp(sdp).unwrap(),
+            }),
+            ..PushSession::default()
+        };
+
+        let audio = request(
+            "SETUP",
+            "rtsp://server:8554/printers/1/trackID=0",
+            &[(
+                "Transport",
+                "RTP/AVP/TCP;unicast;interleaved=0-1;mode=record",
+            )],
+            b"",
+        );
+        assert!(setup(&mut session, &audio).starts_with("RTSP/1.0 200"));
+        assert_eq!(session.video_channel, None);
+
+        let video = request(
+            "SETUP",
+            "rtsp://server:8554/printers/1/trackID=1",
+            &[(
+                "Transport",
+                "RTP/AVP/TCP;unicast;interleaved=2-3;mode=record",
+            )],
+            b"",
+        );
+        let response = setup(&mut session, &video);
+        assert!(response.contains("interleaved=2-3;mode=record"));
+        assert!(response.contains("Session: ABC;timeout=60"));
+        assert_eq!(session.video_channel, Some(2));
+
+        let udp = request(
+            "SETUP",
+            "rtsp://server:8554/printers/1/trackID=1",
+            &[("Transport", "RTP/AVP;unicast;client_port=5000-5001")],
+            b"",
+        );
+        assert!(setup(&mut session, &udp).starts_with("RTSP/1.0 461"));
+    }
+}
@@ -2,7 +2,7 @@ use bytes::Bytes;
 use std::collections::VecDeque;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use tokio::sync::{broadcast, watch};
+use tokio::sync::{broadcast, watch, Mutex as AsyncMutex, OwnedMutexGuard};
 
 #[derive(Clone, Debug)]
 pub struct CmafInit {
@@ -23,6 +23,7 @@ pub struct CmafStream {
     backlog: Arc<Mutex<VecDeque<CmafFragment>>>,
     next_seq: Arc<AtomicU64>,
     backlog_capacity: usize,
+    writer: Arc<AsyncMutex<()>>,
 }
 
 pub struct CmafStreamSubscription {
@@ -41,9 +42,20 @@ impl CmafStream {
             backlog: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
             next_seq: Arc::new(AtomicU64::new(1)),
             backlog_capacity: capacity,
+            writer: Arc::new(AsyncMutex::new(())),
         }
     }
 
+    /// Waits until no other segmenter feeds this stream. Pulled and pushed
+    /// video share the output directory, so only the holder may write.
+    pub async fn claim_writer(&self) -> OwnedMutexGuard<()> {
+        Arc::clone(&self.writer).lock_owned().await
+    }
+
+    pub fn try_claim_writer(&self) -> Option<OwnedMutexGuard<()>> {
+        Arc::clone(&self.writer).try_lock_owned().ok()
+    }
+
     pub fn subscribe(&self) -> CmafStreamSubscription {
         CmafStreamSubscription {
             init_rx: self.init_tx.subscribe(),
@@ -51,6 +63,11 @@ impl CmafStream {
         }
     }
 
+    /// Latest init segment, if the segmenter has produced one yet.
+    pub fn latest_init(&self) -> Option<CmafInit> {
+        self.init_tx.borrow().clone()
+    }
+
     pub fn update_init(&self, init: CmafInit) {
         self.init_tx.send_replace(Some(init));
     }
@@ -70,6 +87,13 @@ impl CmafStream {
         let _ = self.fragment_tx.send(entry);
     }
 
+    pub fn backlog_len(&self) -> usize {
+        self.backlog
+            .lock()
+            .map(|backlog| backlog.len())
+            .unwrap_or(0)
+    }
+
     pub fn backlog_snapshot(&self) -> Vec<CmafFragment> {
         self.backlog
             .lock()
@@ -0,0 +1,119 @@
+use anyhow::Context;
+use base64::{engine::general_purpose, Engine as _};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 12;
+
+#[derive(Clone)]
+pub struct SecretCipher {
+    key: Option<Key>,
+}
+
+impl SecretCipher {
+    pub fn new(secret_key: Option<&str>) -> Self {
+        let key = secret_key
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(|value| {
+                let digest = Sha256::digest(value.as_bytes());
+                *Key::from_slice(&digest)
+            });
+        Self { key }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.key.is_some()
+    }
+
+    pub fn is_encrypted(value: &str) -> bool {
+        value.starts_with(ENCRYPTED_PREFIX)
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> anyhow::Result<String> {
+        let Some(key) = self.key.as_ref() else {
+            return Ok(plaintext.to_string());
+        };
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = ChaCha20Poly1305::new(key)
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+            .map_err(|_| anyhow::anyhow!("secret encryption failed"))?;
+
+        let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(format!(
+            "{ENCRYPTED_PREFIX}{}",
+            general_purpose::STANDARD.encode(sealed)
+        ))
+    }
+
+    pub fn decrypt(&self, stored: &str) -> anyhow::Result<String> {
+        let Some(encoded) = stored.strip_prefix(ENCRYPTED_PREFIX) else {
+            // Rows written before a key was configured are still plaintext.
+            return Ok(stored.to_string());
+        };
+        let Some(key) = self.key.as_ref() else {
+            anyhow::bail!("encrypted secret found but SECRET_KEY is not set");
+        };
+        let sealed = general_purpose::STANDARD
+            .decode(encoded)
+            .context("decode encrypted secret")?;
+        if sealed.len() <= NONCE_LEN {
+            anyhow::bail!("encrypted secret is truncated");
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let plaintext = ChaCha20Poly1305::new(key)
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("secret decryption failed; check SECRET_KEY"))?;
+        String::from_utf8(plaintext).context("decrypted secret is not utf-8")
+    }
+}
+
+impl std::fmt::Debug for SecretCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecretCipher")
+            .field("enabled", &self.is_enabled())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypt_round_trips_with_same_key() {
+        let cipher = SecretCipher::new(Some("correct horse"));
+        let sealed = cipher.encrypt("12345678").expect("encrypt");
+
+        assert!(SecretCipher::is_encrypted(&sealed));
+        assert!(!sealed.contains("12345678"));
+        assert_eq!(cipher.decrypt(&sealed).expect("decrypt"), "12345678");
+    }
+
+    #[test]
+    fn decrypt_fails_with_wrong_key() {
+        let sealed = SecretCipher::new(Some("correct horse"))
+            .encrypt("12345678")
+            .expect("encrypt");
+
+        assert!(SecretCipher::new(Some("battery staple"))
+            .decrypt(&sealed)
+            .is_err());
+        assert!(SecretCipher::new(None).decrypt(&sealed).is_err());
+    }
+
+    #[test]
+    fn plaintext_passes_through_without_key_and_for_legacy_rows() {
+        let disabled = SecretCipher::new(None);
+        assert_eq!(disabled.encrypt("12345678").expect("encrypt"), "12345678");
+
+        let enabled = SecretCipher::new(Some("correct horse"));
+        assert_eq!(enabled.decrypt("12345678").expect("decrypt"), "12345678");
+    }
+}
@@ -1,8 +1,49 @@
-use chrono::{DateTime, Utc};
-use serde::Serialize;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::sync::Arc;
 
-#[derive(Clone, Debug, Serialize, Default)]
+const AMS_TRAYS_PER_UNIT: u8 = 4;
+const AMS_TRAY_UNLOADED: u8 = 255;
+/// Unit and `tray_now` id Bambu uses for the external spool holder.
+const EXTERNAL_SPOOL_ID: u8 = 254;
+
+/// Where the video pipeline is, so the UI can explain an empty player.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum VideoStatus {
+    #[default]
+    Connecting,
+    WaitingForKeyframe,
+    Live,
+    Stalled,
+    Error,
+}
+
+/// Bambu HMS alert levels, most severe first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HmsSeverity {
+    Fatal,
+    Serious,
+    Common,
+    Info,
+}
+
+impl HmsSeverity {
+    /// The level lives in the upper half of an HMS `code`.
+    fn from_code(code: u64) -> Option<Self> {
+        match code >> 16 {
+            1 => Some(Self::Fatal),
+            2 => Some(Self::Serious),
+            3 => Some(Self::Common),
+            4 => Some(Self::Info),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct AmsUnitState {
     pub id: Option<u8>,
@@ -11,41 +52,125 @@ pub struct AmsUnitState {
     pub trays: Vec<AmsTrayState>,
 }
 
-#[derive(Clone, Debug, Serialize, Default)]
+impl AmsUnitState {
+    pub fn is_external(&self) -> bool {
+        self.id == Some(EXTERNAL_SPOOL_ID)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct AmsTrayState {
     pub id: Option<u8>,
     pub filament_type: Option<String>,
     pub color: Option<String>,
+    #[serde(default)]
+    pub active: bool,
+    pub remaining_percent: Option<u8>,
+    pub remaining_grams: Option<u32>,
 }
 
-#[derive(Clone, Debug, Serialize, Default)]
-#[serde(rename_all = "camelCase")]
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[serde(default, rename_all = "camelCase")]
 pub struct PrinterState {
     pub connected: bool,
     pub job_state: Option<String>,
+    /// Current `print_error`, shown by Bambu as `XXXX-XXXX` hex; cleared when
+    /// the printer reports 0.
+    pub print_error_code: Option<u32>,
+    pub print_error_text: Option<String>,
+    /// Most severe alert in the printer's active HMS list.
+    pub hms_severity: Option<HmsSeverity>,
     pub percent: Option<u8>,
+    /// Job name shown in Bambu Studio; also the 3MF file name on storage.
+    pub subtask_name: Option<String>,
+    /// File being printed, e.g. `Metadata/plate_2.gcode` inside a 3MF.
+    pub gcode_file: Option<String>,
     pub layer_num: Option<u32>,
     pub total_layer_num: Option<u32>,
+    /// `layer_num / total_layer_num`; unlike `percent` it ignores time estimates.
+    pub layer_percent: Option<u8>,
+    /// Only reported by some firmware.
+    pub current_z_mm: Option<f64>,
     pub remaining_minutes: Option<u32>,
+    pub print_started_at: Option<DateTime<Utc>>,
+    pub elapsed_minutes: Option<u32>,
+    pub estimated_finish: Option<DateTime<Utc>>,
     pub nozzle_c: Option<f64>,
     pub nozzle_target_c: Option<f64>,
+    pub nozzle_diameter_mm: Option<f64>,
+    /// As reported, e.g. `hardened_steel` or `stainless_steel`.
+    pub nozzle_type: Option<String>,
     pub bed_c: Option<f64>,
     pub bed_target_c: Option<f64>,
     pub chamber_c: Option<f64>,
+    /// Only reported by printers with an active chamber heater.
+    pub chamber_target_c: Option<f64>,
     pub light: Option<String>,
     pub rtsp_url: Option<String>,
     #[serde(default)]
     pub ams: Vec<AmsUnitState>,
+    /// Global AMS slot currently feeding the toolhead (`unit * 4 + tray`).
+    pub active_tray: Option<u8>,
     pub last_update: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub command_queue_depth: usize,
+    /// Filled from the video pipeline when served; not part of MQTT reports.
+    #[serde(default)]
+    pub video_status: VideoStatus,
+    /// Camera frame rate from the video pipeline, like `video_status`.
+    #[serde(default)]
+    pub fps: Option<f64>,
+    /// Every value the printer has reported, merged across partial reports.
+    /// Served only through `?extra=` pointers on the status endpoint.
+    #[serde(skip)]
+    pub raw_report: Option<Arc<Value>>,
 }
 
 impl PrinterState {
     pub fn apply_report(&mut self, report: &Value) {
+        self.apply_report_at(report, Utc::now());
+    }
+
+    fn apply_report_at(&mut self, report: &Value, now: DateTime<Utc>) {
+        // Copies only while a snapshot still shares the Arc, which then keeps
+        // the previous report.
+        let raw = Arc::make_mut(
+            self.raw_report
+                .get_or_insert_with(|| Arc::new(Value::Object(Default::default()))),
+        );
+        merge_report(raw, report);
+
         if let Some(state) = read_str(report.pointer("/print/gcode_state")) {
+            // A new job starting clears the previous job's failure reason.
+            if is_active_job(Some(state)) && !is_active_job(self.job_state.as_deref()) {
+                self.print_error_code = None;
+                self.print_error_text = None;
+            }
             self.job_state = Some(state.to_string());
         }
 
+        if let Some(code) = read_u32(
+            report
+                .pointer("/print/print_error")
+                .or_else(|| report.pointer("/print/mc_print_error_code")),
+        ) {
+            self.print_error_code = (code != 0).then_some(code);
+            self.print_error_text = self
+                .print_error_code
+                .and_then(print_error_text)
+                .map(str::to_string);
+        }
+
+        // Full reports carry the whole active list; an empty one clears it.
+        if let Some(hms) = report.pointer("/print/hms").and_then(Value::as_array) {
+            self.hms_severity = hms
+                .iter()
+                .filter_map(|entry| read_u64(entry.get("code")))
+                .filter_map(HmsSeverity::from_code)
+                .min();
+        }
+
         if let Some(percent) = read_u8(
             report
                 .pointer("/print/mc_percent")
@@ -54,6 +179,14 @@ impl PrinterState {
             self.percent = Some(percent);
         }
 
+        if let Some(name) = read_str(report.pointer("/print/subtask_name")) {
+            self.subtask_name = (!name.is_empty()).then(|| name.to_string());
+        }
+
+        if let Some(file) = read_str(report.pointer("/print/gcode_file")) {
+            self.gcode_file = (!file.is_empty()).then(|| file.to_string());
+        }
+
         if let Some(layer_num) = read_u32(report.pointer("/print/layer_num")) {
             self.layer_num = Some(layer_num);
         }
@@ -61,6 +194,15 @@ impl PrinterState {
         if let Some(total_layer_num) = read_u32(report.pointer("/print/total_layer_num")) {
             self.total_layer_num = Some(total_layer_num);
         }
+        self.layer_percent = layer_percent(self.layer_num, self.total_layer_num);
+
+        if let Some(z) = read_f64(
+            report
+                .pointer("/print/z_height")
+                .or_else(|| report.pointer("/print/current_z")),
+        ) {
+            self.current_z_mm = Some(z);
+        }
 
         if let Some(remaining) = read_u32(
             report
@@ -88,6 +230,14 @@ impl PrinterState {
             self.nozzle_target_c = Some(nozzle_target);
         }
 
+        if let Some(diameter) = read_f64(report.pointer("/print/nozzle_diameter")) {
+            self.nozzle_diameter_mm = Some(diameter).filter(|diameter| *diameter > 0.0);
+        }
+
+        if let Some(nozzle_type) = read_str(report.pointer("/print/nozzle_type")) {
+            self.nozzle_type = Some(nozzle_type.to_string()).filter(|value| !value.is_empty());
+        }
+
         if let Some(bed) = read_f64(
             report
                 .pointer("/print/bed_temper")
@@ -114,6 +264,15 @@ impl PrinterState {
             self.chamber_c = Some(chamber);
         }
 
+        if let Some(chamber_target) = read_f64(
+            report
+                .pointer("/print/chamber_target_temper")
+                .or_else(|| report.pointer("/temp/chamber_target_temper"))
+                .or_else(|| report.pointer("/print/device/ctc/info/htar")),
+        ) {
+            self.chamber_target_c = Some(chamber_target);
+        }
+
         if let Some(light) = extract_light(report) {
             self.light = Some(light);
         }
@@ -128,14 +287,137 @@ impl PrinterState {
             }
         }
 
-        if let Some(ams) = extract_ams(report) {
+        if let Some(mut ams) = extract_ams(report) {
+            // The external spool arrives separately under `vt_tray`; keep it.
+            ams.extend(self.ams.drain(..).filter(AmsUnitState::is_external));
             self.ams = ams;
         }
 
-        self.last_update = Some(Utc::now());
+        if let Some(external) = extract_external_spool(report) {
+            self.ams.retain(|unit| !unit.is_external());
+            self.ams.push(external);
+        }
+
+        if let Some(tray_now) = read_u8(
+            report
+                .pointer("/print/ams/tray_now")
+                .or_else(|| report.pointer("/ams/tray_now")),
+        ) {
+            // 255 means nothing is loaded.
+            self.active_tray = (tray_now != AMS_TRAY_UNLOADED).then_some(tray_now);
+        }
+        self.mark_active_tray();
+        self.update_print_timing(report, now);
+
+        self.last_update = Some(now);
+    }
+
+    /// Tracks when the current job started. Firmware that reports
+    /// `gcode_start_time` wins; otherwise the first running report is used.
+    fn update_print_timing(&mut self, report: &Value, now: DateTime<Utc>) {
+        let job_state = self.job_state.as_deref().map(str::to_ascii_uppercase);
+        match job_state.as_deref() {
+            Some("RUNNING" | "PREPARE" | "PRINTING") => {
+                let reported = read_u64(report.pointer("/print/gcode_start_time"))
+                    .filter(|secs| *secs > 0)
+                    .and_then(|secs| Utc.timestamp_opt(i64::try_from(secs).ok()?, 0).single());
+                if let Some(started_at) = reported {
+                    self.print_started_at = Some(started_at);
+                } else if self.print_started_at.is_none() {
+                    self.print_started_at = Some(now);
+                }
+            }
+            // Paused jobs keep their start time.
+            Some("PAUSE" | "PAUSED") | None => {}
+            Some(_) => {
+                self.print_started_at = None;
+            }
+        }
+
+        self.elapsed_minutes = self.print_started_at.map(|started_at| {
+            u32::try_from((now - started_at).num_minutes().max(0)).unwrap_or(u32::MAX)
+        });
+        self.estimated_finish = match (self.print_started_at, self.remaining_minutes) {
+            (Some(_), Some(remaining)) => Some(now + Duration::minutes(i64::from(remaining))),
+            _ => None,
+        };
+    }
+
+    /// Flags the tray matching `active_tray`. AMS units are matched by their
+    /// position in the report, which follows the AMS bus order.
+    fn mark_active_tray(&mut self) {
+        let external_active = self.active_tray == Some(EXTERNAL_SPOOL_ID);
+        let active = self.active_tray.map(|index| {
+            (
+                usize::from(index / AMS_TRAYS_PER_UNIT),
+                index % AMS_TRAYS_PER_UNIT,
+            )
+        });
+        let mut unit_index = 0;
+        for unit in self.ams.iter_mut() {
+            if unit.is_external() {
+                for tray in unit.trays.iter_mut() {
+                    tray.active = external_active;
+                }
+                continue;
+            }
+            for (tray_index, tray) in unit.trays.iter_mut().enumerate() {
+                let tray_id = tray.id.or_else(|| u8::try_from(tray_index).ok());
+                tray.active = !external_active
+                    && active
+                        .is_some_and(|(unit, slot)| unit == unit_index && tray_id == Some(slot));
+            }
+            unit_index += 1;
+        }
     }
 }
 
+/// Objects merge key by key; anything else, arrays included, is replaced.
+fn merge_report(target: &mut Value, report: &Value) {
+    match (target, report) {
+        (Value::Object(target), Value::Object(report)) => {
+            for (key, value) in report {
+                match target.get_mut(key) {
+                    Some(existing) => merge_report(existing, value),
+                    None => {
+                        target.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (target, report) => *target = report.clone(),
+    }
+}
+
+/// Running, preparing or paused, i.e. a job that has not ended.
+fn is_active_job(state: Option<&str>) -> bool {
+    state.is_some_and(|state| {
+        matches!(
+            state.to_ascii_uppercase().as_str(),
+            "RUNNING" | "PREPARE" | "PRINTING" | "PAUSE" | "PAUSED"
+        )
+    })
+}
+
+/// Messages for the common `print_error` codes; others are left to the UI.
+fn print_error_text(code: u32) -> Option<&'static str> {
+    Some(match code {
+        0x0300_400C => "The print was cancelled.",
+        0x0300_8001 => "The print was paused by the user.",
+        0x0300_8003 => "Spaghetti defects were detected by AI print monitoring.",
+        0x0300_800A => "A filament pile-up was detected by AI print monitoring.",
+        _ => return None,
+    })
+}
+
+fn layer_percent(layer: Option<u32>, total: Option<u32>) -> Option<u8> {
+    let (layer, total) = (layer?, total?);
+    if total == 0 {
+        return None;
+    }
+    Some((u64::from(layer.min(total)) * 100 / u64::from(total)) as u8)
+}
+
 fn read_str(value: Option<&Value>) -> Option<&str> {
     value.and_then(|value| value.as_str())
 }
@@ -156,6 +438,14 @@ fn read_u32(value: Option<&Value>) -> Option<u32> {
     }
 }
 
+fn read_u64(value: Option<&Value>) -> Option<u64> {
+    match value? {
+        Value::Number(number) => number.as_u64(),
+        Value::String(text) => text.parse().ok(),
+        _ => None,
+    }
+}
+
 fn read_f64(value: Option<&Value>) -> Option<f64> {
     match value? {
         Value::Number(number) => number.as_f64(),
@@ -201,22 +491,52 @@ fn extract_ams_trays(value: Option<&Value>) -> Vec<AmsTrayState> {
         .filter_map(|(index, tray)| {
             let tray = tray.as_object()?;
             let id = read_u8(tray.get("id")).or_else(|| u8::try_from(index).ok());
-            let filament_type = read_str(tray.get("tray_type")).and_then(non_empty_text);
-            let color = extract_tray_color(tray);
+            let tray = extract_tray(id, tray);
 
-            if id.is_none() && filament_type.is_none() && color.is_none() {
+            if tray.id.is_none() && tray.filament_type.is_none() && tray.color.is_none() {
                 return None;
             }
-
-            Some(AmsTrayState {
-                id,
-                filament_type,
-                color,
-            })
+            Some(tray)
         })
         .collect()
 }
 
+/// Maps `vt_tray` to a synthetic single-tray unit so printers without an AMS
+/// still show what is loaded.
+fn extract_external_spool(report: &Value) -> Option<AmsUnitState> {
+    let tray = report
+        .pointer("/print/vt_tray")
+        .or_else(|| report.pointer("/vt_tray"))?
+        .as_object()?;
+
+    Some(AmsUnitState {
+        id: Some(EXTERNAL_SPOOL_ID),
+        humidity_raw: None,
+        trays: vec![extract_tray(Some(0), tray)],
+    })
+}
+
+fn extract_tray(id: Option<u8>, tray: &serde_json::Map<String, Value>) -> AmsTrayState {
+    let filament_type = read_str(tray.get("tray_type")).and_then(non_empty_text);
+    let color = extract_tray_color(tray);
+    // Firmware reports -1 when the spool was not read from an RFID tag.
+    let remaining_percent = read_u8(tray.get("remain")).filter(|percent| *percent <= 100);
+    let remaining_grams = remaining_percent
+        .zip(read_u32(tray.get("tray_weight")))
+        .and_then(|(percent, weight)| {
+            u32::try_from(u64::from(weight) * u64::from(percent) / 100).ok()
+        });
+
+    AmsTrayState {
+        id,
+        filament_type,
+        color,
+        active: false,
+        remaining_percent,
+        remaining_grams,
+    }
+}
+
 fn extract_tray_color(tray: &serde_json::Map<String, Value>) -> Option<String> {
     if let Some(color) = read_str(tray.get("tray_color")) {
         return non_empty_text(color);
@@ -274,11 +594,7 @@ fn extract_light(report: &Value) -> Option<String> {
 }
 
 fn normalize_light_mode(mode: &str) -> String {
-    match mode {
-        "on" | "off" => mode.to_string(),
-        "flashing" => "on".to_string(),
-        other => other.to_string(),
-    }
+    mode.trim().to_ascii_lowercase()
 }
 
 #[cfg(test)]
@@ -332,6 +648,159 @@ mod tests {
         assert_eq!(state.ams[0].trays[2].color, None);
     }
 
+    #[test]
+    fn apply_report_marks_active_tray_and_remaining_filament() {
+        let report = json!({
+            "print": {
+                "ams": {
+                    "tray_now": "5",
+                    "ams": [
+                        {
+                            "id": "0",
+                            "tray": [
+                                { "id": "0", "tray_type": "PLA", "remain": -1 },
+                                { "id": "1", "tray_type": "PLA", "remain": 80 }
+                            ]
+                        },
+                        {
+                            "id": "1",
+                            "tray": [
+                                { "id": "0", "tray_type": "PETG", "remain": 100 },
+                                {
+                                    "id": "1",
+                                    "tray_type": "ABS",
+                                    "remain": 25,
+                                    "tray_weight": "1000"
+                                }
+                            ]
+                        }
+                    ]
+                }
+            }
+        });
+
+        let mut state = PrinterState::default();
+        state.apply_report(&report);
+
+        assert_eq!(state.active_tray, Some(5));
+        let active: Vec<_> = state
+            .ams
+            .iter()
+            .flat_map(|unit| unit.trays.iter())
+            .filter(|tray| tray.active)
+            .collect();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].filament_type.as_deref(), Some("ABS"));
+        assert_eq!(active[0].remaining_percent, Some(25));
+        assert_eq!(active[0].remaining_grams, Some(250));
+        assert_eq!(state.ams[0].trays[0].remaining_percent, None);
+        assert_eq!(state.ams[0].trays[1].remaining_grams, None);
+
+        // A later partial report without AMS data keeps the flag in sync.
+        state.apply_report(&json!({ "print": { "ams": { "tray_now": "255" } } }));
+        assert_eq!(state.active_tray, None);
+        assert!(state.ams[1].trays.iter().all(|tray| !tray.active));
+    }
+
+    #[test]
+    fn remaining_grams_do_not_overflow_on_huge_spool_weights() {
+        let tray = json!({ "remain": 50, "tray_weight": "4294967295" });
+        let tray = extract_tray(Some(0), tray.as_object().expect("object"));
+        assert_eq!(tray.remaining_grams, Some(2_147_483_647));
+    }
+
+    #[test]
+    fn apply_report_maps_external_spool_to_virtual_unit() {
+        let report = json!({
+            "print": {
+                "vt_tray": {
+                    "id": "254",
+                    "tray_type": "PETG",
+                    "tray_color": "FF8800FF"
+                },
+                "ams": { "tray_now": "254" }
+            }
+        });
+
+        let mut state = PrinterState::default();
+        state.apply_report(&report);
+
+        assert_eq!(state.ams.len(), 1);
+        assert!(state.ams[0].is_external());
+        assert_eq!(state.ams[0].trays.len(), 1);
+        assert_eq!(state.ams[0].trays[0].filament_type.as_deref(), Some("PETG"));
+        assert_eq!(state.ams[0].trays[0].color.as_deref(), Some("FF8800FF"));
+        assert!(state.ams[0].trays[0].active);
+
+        // A later AMS-only report keeps the external spool next to the AMS.
+        state.apply_report(&json!({
+            "print": {
+                "ams": {
+                    "tray_now": "0",
+                    "ams": [{ "id": "0", "tray": [{ "id": "0", "tray_type": "PLA" }] }]
+                }
+            }
+        }));
+
+        assert_eq!(state.ams.len(), 2);
+        assert_eq!(state.ams[0].id, Some(0));
+        assert!(state.ams[0].trays[0].active);
+        assert!(state.ams[1].is_external());
+        assert!(!state.ams[1].trays[0].active);
+    }
+
+    #[test]
+    fn print_start_is_captured_on_running_and_cleared_when_finished() {
+        let start = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        let mut state = PrinterState::default();
+
+        state.apply_report_at(&json!({ "print": { "gcode_state": "IDLE" } }), start);
+        assert_eq!(state.print_started_at, None);
+
+        state.apply_report_at(
+            &json!({ "print": { "gcode_state": "RUNNING", "mc_remaining_time": 90 } }),
+            start,
+        );
+        assert_eq!(state.print_started_at, Some(start));
+
+        let later = start + Duration::minutes(30);
+        state.apply_report_at(
+            &json!({ "print": { "gcode_state": "RUNNING", "mc_remaining_time": 60 } }),
+            later,
+        );
+        assert_eq!(state.print_started_at, Some(start));
+        assert_eq!(state.elapsed_minutes, Some(30));
+        assert_eq!(state.estimated_finish, Some(later + Duration::minutes(60)));
+
+        state.apply_report_at(&json!({ "print": { "gcode_state": "PAUSE" } }), later);
+        assert_eq!(state.print_started_at, Some(start));
+
+        state.apply_report_at(&json!({ "print": { "gcode_state": "FINISH" } }), later);
+        assert_eq!(state.print_started_at, None);
+        assert_eq!(state.elapsed_minutes, None);
+        assert_eq!(state.estimated_finish, None);
+    }
+
+    #[test]
+    fn reported_gcode_start_time_overrides_first_seen_time() {
+        let now = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        let started = now - Duration::hours(2);
+        let mut state = PrinterState::default();
+
+        state.apply_report_at(
+            &json!({
+                "print": {
+                    "gcode_state": "RUNNING",
+                    "gcode_start_time": started.timestamp().to_string()
+                }
+            }),
+            now,
+        );
+
+        assert_eq!(state.print_started_at, Some(started));
+        assert_eq!(state.elapsed_minutes, Some(120));
+    }
+
     #[test]
     fn apply_report_parses_root_ams_payload() {
         let report = json!({
@@ -362,6 +831,22 @@ mod tests {
         assert_eq!(state.ams[0].trays[0].color.as_deref(), Some("ABCDEF12"));
     }
 
+    #[test]
+    fn apply_report_keeps_flashing_light_mode() {
+        let report = json!({
+            "print": {
+                "lights_report": [
+                    { "node": "chamber_light", "mode": "flashing" }
+                ]
+            }
+        });
+
+        let mut state = PrinterState::default();
+        state.apply_report(&report);
+
+        assert_eq!(state.light.as_deref(), Some("flashing"));
+    }
+
     #[test]
     fn apply_report_parses_target_temperatures() {
         let report = json!({
@@ -377,4 +862,115 @@ mod tests {
         assert_eq!(state.nozzle_target_c, Some(220.0));
         assert_eq!(state.bed_target_c, Some(65.0));
     }
+
+    #[test]
+    fn apply_report_parses_chamber_heater_target() {
+        let mut state = PrinterState::default();
+        state.apply_report(&json!({ "print": { "chamber_temper": 31.0 } }));
+        assert_eq!(state.chamber_target_c, None);
+
+        state.apply_report(&json!({
+            "print": { "device": { "ctc": { "info": { "temp": 38.5, "htar": 45 } } } }
+        }));
+        assert_eq!(state.chamber_c, Some(38.5));
+        assert_eq!(state.chamber_target_c, Some(45.0));
+
+        state.apply_report(&json!({ "print": { "chamber_target_temper": "50" } }));
+        assert_eq!(state.chamber_target_c, Some(50.0));
+    }
+
+    #[test]
+    fn layer_percent_is_derived_from_layer_counts() {
+        let mut state = PrinterState::default();
+        state.apply_report(&json!({
+            "print": { "layer_num": 30, "total_layer_num": 120, "z_height": "6.2" }
+        }));
+        assert_eq!(state.layer_percent, Some(25));
+        assert_eq!(state.current_z_mm, Some(6.2));
+
+        state.apply_report(&json!({ "print": { "layer_num": 0, "total_layer_num": 0 } }));
+        assert_eq!(state.layer_percent, None);
+        assert_eq!(layer_percent(Some(130), Some(120)), Some(100));
+        assert_eq!(layer_percent(Some(3), None), None);
+
+        let json = serde_json::to_value(&state).expect("serialize");
+        assert!(json["layerPercent"].is_null());
+        assert_eq!(json["currentZMm"], 6.2);
+    }
+
+    #[test]
+    fn apply_report_parses_nozzle_info() {
+        let report = json!({
+            "print": {
+                "nozzle_diameter": "0.4",
+                "nozzle_type": "hardened_steel"
+            }
+        });
+
+        let mut state = PrinterState::default();
+        state.apply_report(&report);
+
+        assert_eq!(state.nozzle_diameter_mm, Some(0.4));
+        assert_eq!(state.nozzle_type.as_deref(), Some("hardened_steel"));
+
+        state.apply_report(&json!({ "print": { "nozzle_diameter": 0.6 } }));
+        assert_eq!(state.nozzle_diameter_mm, Some(0.6));
+        assert_eq!(state.nozzle_type.as_deref(), Some("hardened_steel"));
+
+        let json = serde_json::to_value(&state).expect("serialize");
+        assert_eq!(json["nozzleDiameterMm"], 0.6);
+        assert_eq!(json["nozzleType"], "hardened_steel");
+    }
+
+    #[test]
+    fn print_error_accepts_numeric_and_string_codes() {
+        let mut state = PrinterState::default();
+        state.apply_report(&json!({
+            "print": { "gcode_state": "FAILED", "print_error": 50348044 }
+        }));
+        assert_eq!(state.print_error_code, Some(0x0300_400C));
+        assert_eq!(
+            state.print_error_text.as_deref(),
+            Some("The print was cancelled.")
+        );
+
+        let mut state = PrinterState::default();
+        state.apply_report(&json!({ "print": { "mc_print_error_code": "50364419" } }));
+        assert_eq!(state.print_error_code, Some(0x0300_8003));
+        assert!(state.print_error_text.is_some());
+
+        state.apply_report(&json!({ "print": { "print_error": 12345 } }));
+        assert_eq!(state.print_error_code, Some(12345));
+        assert_eq!(state.print_error_text, None);
+    }
+
+    #[test]
+    fn print_error_clears_when_a_new_print_starts() {
+        let mut state = PrinterState::default();
+        state.apply_report(&json!({
+            "print": { "gcode_state": "FAILED", "print_error": 50348044 }
+        }));
+        state.apply_report(&json!({ "print": { "gcode_state": "PREPARE" } }));
+
+        assert_eq!(state.print_error_code, None);
+        assert_eq!(state.print_error_text, None);
+    }
+
+    #[test]
+    fn hms_severity_is_the_most_severe_active_alert() {
+        let mut state = PrinterState::default();
+        state.apply_report(&json!({
+            "print": { "hms": [
+                { "attr": 50397440, "code": 196616 },
+                { "attr": 117571840, "code": 131073 }
+            ] }
+        }));
+        assert_eq!(state.hms_severity, Some(HmsSeverity::Serious));
+
+        state.apply_report(&json!({ "print": { "nozzle_temper": 200.0 } }));
+        assert_eq!(state.hms_severity, Some(HmsSeverity::Serious));
+
+        state.apply_report(&json!({ "print": { "hms": [] } }));
+        assert_eq!(state.hms_severity, None);
+    }
 }
@@ -0,0 +1,74 @@
+//! Serves the frontend build so the backend can run as a single binary.
+
+use axum::http::StatusCode;
+use axum::routing::any;
+use axum::Router;
+use std::path::Path;
+use tower_http::services::{ServeDir, ServeFile};
+
+/// Falls back to files under `dir`, and to `index.html` for client-side
+/// routes. Unknown `/api` paths still 404 instead of returning the SPA.
+pub fn mount<S>(router: Router<S>, dir: &Path) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let spa = ServeDir::new(dir).fallback(ServeFile::new(dir.join("index.html")));
+    router
+        .route("/api/*path", any(|| async { StatusCode::NOT_FOUND }))
+        .fallback_service(spa)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    async fn fetch(router: &Router, path: &str) -> (StatusCode, String) {
+        let response = router
+            .clone()
+            .oneshot(Request::get(path).body(Body::empty()).expect("request"))
+            .await
+            .expect("response");
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .expect("body");
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    #[tokio::test]
+    async fn spa_fallback_does_not_shadow_api_routes() {
+        let dir =
+            std::env::temp_dir().join(format!("bambu-lan-viewer-static-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(dir.join("assets")).expect("create dir");
+        std::fs::write(dir.join("index.html"), "<html>app</html>").expect("write index");
+        std::fs::write(dir.join("assets/app.js"), "console.log(1)").expect("write asset");
+
+        let router = mount(
+            Router::new()
+                .route("/api/printers", get(|| async { "printers" }))
+                .route("/healthz", get(|| async { "ok" })),
+            &dir,
+        );
+
+        assert_eq!(
+            fetch(&router, "/api/printers").await,
+            (StatusCode::OK, "printers".to_string())
+        );
+        assert_eq!(
+            fetch(&router, "/api/unknown").await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(fetch(&router, "/healthz").await.1, "ok");
+        assert_eq!(fetch(&router, "/assets/app.js").await.1, "console.log(1)");
+        assert_eq!(
+            fetch(&router, "/printers/3").await,
+            (StatusCode::OK, "<html>app</html>".to_string())
+        );
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
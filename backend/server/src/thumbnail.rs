@@ -0,0 +1,227 @@
+use crate::config::PrinterConfig;
+use crate::ftps::{valid_segment, FtpsClient, FtpsReplyError};
+use crate::state::PrinterState;
+use bytes::{Bytes, BytesMut};
+use futures_core::Stream;
+use std::io::{Cursor, Read};
+use std::pin::pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+/// Larger project files are skipped rather than buffered in memory.
+const MAX_PROJECT_BYTES: u64 = 128 * 1024 * 1024;
+/// A failed lookup is retried after this long, e.g. once the upload finishes.
+const MISS_RETRY: Duration = Duration::from_secs(60);
+
+/// The plate preview for the current job, fetched from the job's 3MF over
+/// FTPS once per job. Misses are cached for a while too, so a job without a
+/// reachable project file does not hit the printer on every request.
+#[derive(Debug, Default)]
+pub struct ThumbnailCache {
+    entry: Arc<Mutex<Option<CachedThumbnail>>>,
+}
+
+/// The job looked up, its PNG if one was found, and when.
+type CachedThumbnail = (JobKey, Option<Bytes>, Instant);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct JobKey {
+    subtask_name: String,
+    gcode_file: Option<String>,
+}
+
+impl ThumbnailCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn get(
+        &self,
+        printer: &PrinterConfig,
+        state: &PrinterState,
+        tls_insecure: bool,
+    ) -> Option<Bytes> {
+        let key = JobKey {
+            subtask_name: state.subtask_name.clone()?,
+            gcode_file: state.gcode_file.clone(),
+        };
+        let entry = Arc::clone(&self.entry);
+        let printer = printer.clone();
+        // The lookup runs in its own task so a download outliving the request
+        // timeout still fills the cache. It holds the lock across the download
+        // so concurrent requests share one fetch.
+        let lookup = tokio::spawn(async move {
+            let mut entry = entry.lock_owned().await;
+            if let Some((cached_key, png, fetched_at)) = entry.as_ref() {
+                if *cached_key == key && (png.is_some() || fetched_at.elapsed() < MISS_RETRY) {
+                    return png.clone();
+                }
+            }
+            let png = match fetch_project(&printer, &key, tls_insecure).await {
+                Ok(project) => {
+                    let plate = key
+                        .gcode_file
+                        .as_deref()
+                        .and_then(plate_number)
+                        .unwrap_or(1);
+                    extract_plate_png(&project, plate).map(Bytes::from)
+                }
+                Err(error) => {
+                    warn!(?error, printer_id = printer.id, "failed to fetch job 3mf");
+                    None
+                }
+            };
+            *entry = Some((key, png.clone(), Instant::now()));
+            png
+        });
+        lookup.await.ok().flatten()
+    }
+}
+
+/// Where the printer keeps the project for a job: the file being printed when
+/// it is a 3MF, otherwise a 3MF named after the subtask (sent from Studio).
+/// Both come from MQTT reports, so names that could smuggle extra FTP
+/// commands are dropped.
+fn candidate_paths(key: &JobKey) -> Vec<String> {
+    let mut paths = Vec::new();
+    if let Some(file) = key.gcode_file.as_deref() {
+        let file = file.strip_prefix("/sdcard").unwrap_or(file);
+        let file = file.trim_start_matches('/');
+        if file.to_ascii_lowercase().ends_with(".3mf") && file.split('/').all(valid_segment) {
+            paths.push(format!("/{file}"));
+        }
+    }
+    let name = &key.subtask_name;
+    if valid_segment(name) {
+        for dir in ["/cache", "", "/model"] {
+            paths.push(format!("{dir}/{name}.gcode.3mf"));
+            paths.push(format!("{dir}/{name}.3mf"));
+        }
+    }
+    paths
+}
+
+async fn fetch_project(
+    printer: &PrinterConfig,
+    key: &JobKey,
+    tls_insecure: bool,
+) -> anyhow::Result<Vec<u8>> {
+    let mut client =
+        FtpsClient::connect(printer.connect_host(), &printer.access_code, tls_insecure).await?;
+    for path in candidate_paths(key) {
+        let size = match client.size(&path).await {
+            Ok(size) => size,
+            Err(error)
+                if error
+                    .downcast_ref::<FtpsReplyError>()
+                    .is_some_and(|reply| reply.code == 550) =>
+            {
+                continue
+            }
+            Err(error) => return Err(error),
+        };
+        if size > MAX_PROJECT_BYTES {
+            anyhow::bail!("{path} is too large to scan for a thumbnail ({size} bytes)");
+        }
+        let stream = client.retrieve(&path, 0, size).await?;
+        return collect(stream, size).await;
+    }
+    client.quit().await;
+    anyhow::bail!("no 3mf found for job {:?}", key.subtask_name)
+}
+
+async fn collect(
+    stream: impl Stream<Item = std::io::Result<Bytes>>,
+    size: u64,
+) -> anyhow::Result<Vec<u8>> {
+    let mut stream = pin!(stream);
+    let mut out = BytesMut::with_capacity(size as usize);
+    while let Some(chunk) = std::future::poll_fn(|cx| stream.as_mut().poll_next(cx)).await {
+        out.extend_from_slice(&chunk?);
+    }
+    Ok(out.to_vec())
+}
+
+/// `Metadata/plate_2.gcode` -> 2.
+fn plate_number(gcode_file: &str) -> Option<u32> {
+    let name = gcode_file.rsplit('/').next()?;
+    name.strip_prefix("plate_")?
+        .strip_suffix(".gcode")?
+        .parse()
+        .ok()
+}
+
+/// The PNG Bambu Studio embeds for `plate`, falling back to the first plate.
+fn extract_plate_png(project: &[u8], plate: u32) -> Option<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(project)).ok()?;
+    for name in [
+        format!("Metadata/plate_{plate}.png"),
+        "Metadata/plate_1.png".to_string(),
+    ] {
+        if let Ok(mut file) = archive.by_name(&name) {
+            let mut png = Vec::with_capacity(file.size() as usize);
+            file.read_to_end(&mut png).ok()?;
+            return Some(png);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn project(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer
+                .start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn plate_png_is_extracted_for_the_printing_plate() {
+        let archive = project(&[
+            ("3D/3dmodel.model", b"<model/>"),
+            ("Metadata/plate_1.png", b"plate one"),
+            ("Metadata/plate_2.png", b"plate two"),
+        ]);
+        assert_eq!(plate_number("Metadata/plate_2.gcode"), Some(2));
+        assert_eq!(plate_number("/data/Metadata/plate_11.gcode"), Some(11));
+        assert_eq!(plate_number("benchy.gcode"), None);
+
+        assert_eq!(
+            extract_plate_png(&archive, 2).as_deref(),
+            Some(&b"plate two"[..])
+        );
+        assert_eq!(
+            extract_plate_png(&archive, 5).as_deref(),
+            Some(&b"plate one"[..])
+        );
+        assert_eq!(extract_plate_png(b"not a zip", 1), None);
+    }
+
+    #[test]
+    fn candidate_paths_prefer_the_file_being_printed() {
+        let key = JobKey {
+            subtask_name: "Benchy".to_string(),
+            gcode_file: Some("/sdcard/model/Benchy v2.gcode.3mf".to_string()),
+        };
+        let paths = candidate_paths(&key);
+        assert_eq!(paths[0], "/model/Benchy v2.gcode.3mf");
+        assert!(paths.contains(&"/cache/Benchy.gcode.3mf".to_string()));
+        assert!(paths.contains(&"/Benchy.3mf".to_string()));
+
+        let key = JobKey {
+            subtask_name: "x.3mf\r\nDELE /model/a".to_string(),
+            gcode_file: Some("/sdcard/../model/a.3mf".to_string()),
+        };
+        assert!(candidate_paths(&key).is_empty());
+    }
+}
@@ -5,8 +5,18 @@ use rustls::{
     Certificate, ClientConfig, DigitallySignedStruct, Error as RustlsError, ServerName,
     SignatureScheme,
 };
+use std::net::IpAddr;
 use std::sync::Arc;
 
+/// SNI name for `host`. IP literals need `ServerName::IpAddress`; rustls
+/// rejects them as DNS names.
+pub fn server_name(host: &str) -> anyhow::Result<ServerName> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(ServerName::IpAddress(ip));
+    }
+    ServerName::try_from(host).map_err(|_| anyhow::anyhow!("invalid server name: {host}"))
+}
+
 pub fn insecure_client_config() -> ClientConfig {
     // Bambu printers present arbitrary self-signed certificates on LAN endpoints.
     // This verifier is only for direct printer transports (MQTT/RTSP), not for
@@ -0,0 +1,286 @@
+use crate::backoff::RetryBackoff;
+use crate::config::PrinterConfig;
+use crate::state::{HmsSeverity, PrinterState};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+const WEBHOOK_MAX_ATTEMPTS: u32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WebhookEventKind {
+    PrintStarted,
+    PrintPaused,
+    PrintFinished,
+    PrintFailed,
+    JobStateChanged,
+    PrintError,
+    /// An HMS alert appeared, or the most severe active one got worse.
+    HmsAlert,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookEvent {
+    pub printer_id: i64,
+    pub printer_name: String,
+    pub event: WebhookEventKind,
+    pub job_state: Option<String>,
+    pub previous_job_state: Option<String>,
+    pub percent: Option<u8>,
+    pub print_error_code: Option<u32>,
+    pub print_error_text: Option<String>,
+    pub hms_severity: Option<HmsSeverity>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// The fields transitions are detected on, captured before a report is applied.
+#[derive(Clone, Debug, Default)]
+pub struct TrackedState {
+    job_state: Option<String>,
+    print_error_code: Option<u32>,
+    hms_severity: Option<HmsSeverity>,
+}
+
+impl TrackedState {
+    pub fn of(state: &PrinterState) -> Self {
+        Self {
+            job_state: state.job_state.clone(),
+            print_error_code: state.print_error_code,
+            hms_severity: state.hms_severity,
+        }
+    }
+
+    /// Events between this state and `after`. The first report after startup
+    /// (no previous job state) never fires, so restarts stay quiet.
+    pub fn events(&self, after: &PrinterState) -> Vec<WebhookEventKind> {
+        let mut events = Vec::new();
+        if let (Some(before), Some(now)) = (self.job_state.as_deref(), after.job_state.as_deref()) {
+            if !before.eq_ignore_ascii_case(now) {
+                events.push(match now.to_ascii_uppercase().as_str() {
+                    "RUNNING" | "PREPARE" | "PRINTING"
+                        if !matches!(
+                            before.to_ascii_uppercase().as_str(),
+                            "PAUSE" | "PAUSED" | "RUNNING" | "PREPARE" | "PRINTING"
+                        ) =>
+                    {
+                        WebhookEventKind::PrintStarted
+                    }
+                    "PAUSE" | "PAUSED" => WebhookEventKind::PrintPaused,
+                    "FINISH" | "FINISHED" => WebhookEventKind::PrintFinished,
+                    "FAILED" => WebhookEventKind::PrintFailed,
+                    _ => WebhookEventKind::JobStateChanged,
+                });
+            }
+        }
+        if after.print_error_code.is_some() && after.print_error_code != self.print_error_code {
+            events.push(WebhookEventKind::PrintError);
+        }
+        // `HmsSeverity` orders the most severe first.
+        if let Some(severity) = after.hms_severity.filter(|_| self.job_state.is_some()) {
+            if self.hms_severity.is_none_or(|before| severity < before) {
+                events.push(WebhookEventKind::HmsAlert);
+            }
+        }
+        events
+    }
+}
+
+/// Posts printer events to `WEBHOOK_URL`, retrying with backoff in the
+/// background so the MQTT loop never waits on the receiver.
+#[derive(Clone, Debug)]
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+    url: String,
+    retry_initial: Duration,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &str) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()?;
+        Ok(Self {
+            client,
+            url: url.to_string(),
+            retry_initial: Duration::from_secs(1),
+        })
+    }
+
+    pub fn notify(
+        &self,
+        printer: &PrinterConfig,
+        previous: &TrackedState,
+        state: &PrinterState,
+        event: WebhookEventKind,
+    ) {
+        let payload = WebhookEvent {
+            printer_id: printer.id,
+            printer_name: printer.name.clone(),
+            event,
+            job_state: state.job_state.clone(),
+            previous_job_state: previous.job_state.clone(),
+            percent: state.percent,
+            print_error_code: state.print_error_code,
+            print_error_text: state.print_error_text.clone(),
+            hms_severity: state.hms_severity,
+            timestamp: Utc::now(),
+        };
+        let notifier = self.clone();
+        tokio::spawn(async move { notifier.deliver(&payload).await });
+    }
+
+    async fn deliver(&self, payload: &WebhookEvent) -> bool {
+        let mut backoff = RetryBackoff::new(self.retry_initial, Duration::from_secs(30));
+        for attempt in 1..=WEBHOOK_MAX_ATTEMPTS {
+            let retryable = match self.client.post(&self.url).json(payload).send().await {
+                Ok(response) if response.status().is_success() => {
+                    debug!(event = ?payload.event, printer_id = payload.printer_id, "webhook delivered");
+                    return true;
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    warn!(%status, attempt, "webhook rejected");
+                    status.is_server_error() || status.as_u16() == 429
+                }
+                Err(error) => {
+                    warn!(?error, attempt, "webhook request failed");
+                    true
+                }
+            };
+            if !retryable || attempt == WEBHOOK_MAX_ATTEMPTS {
+                break;
+            }
+            tokio::time::sleep(backoff.next_delay()).await;
+        }
+        warn!(event = ?payload.event, printer_id = payload.printer_id, "giving up on webhook");
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use serde_json::Value;
+    use std::sync::{Arc, Mutex};
+
+    fn state(job_state: &str) -> PrinterState {
+        PrinterState {
+            job_state: Some(job_state.to_string()),
+            ..PrinterState::default()
+        }
+    }
+
+    #[test]
+    fn transitions_map_to_events() {
+        let running = TrackedState::of(&state("RUNNING"));
+        assert_eq!(
+            running.events(&state("FINISH")),
+            vec![WebhookEventKind::PrintFinished]
+        );
+        assert_eq!(
+            running.events(&state("FAILED")),
+            vec![WebhookEventKind::PrintFailed]
+        );
+        assert!(running.events(&state("RUNNING")).is_empty());
+
+        let paused = TrackedState::of(&state("PAUSE"));
+        assert!(paused
+            .events(&state("RUNNING"))
+            .contains(&WebhookEventKind::JobStateChanged));
+        assert_eq!(
+            TrackedState::of(&state("IDLE")).events(&state("PREPARE")),
+            vec![WebhookEventKind::PrintStarted]
+        );
+        assert!(TrackedState::default().events(&state("FINISH")).is_empty());
+
+        let mut failed = state("RUNNING");
+        failed.print_error_code = Some(0x0300_400C);
+        assert_eq!(running.events(&failed), vec![WebhookEventKind::PrintError]);
+    }
+
+    #[test]
+    fn hms_alerts_fire_when_they_appear_or_get_worse() {
+        let with_hms = |severity| PrinterState {
+            hms_severity: severity,
+            ..state("RUNNING")
+        };
+        let clear = TrackedState::of(&with_hms(None));
+        assert_eq!(
+            clear.events(&with_hms(Some(HmsSeverity::Common))),
+            vec![WebhookEventKind::HmsAlert]
+        );
+        assert!(TrackedState::default()
+            .events(&with_hms(Some(HmsSeverity::Fatal)))
+            .is_empty());
+
+        let common = TrackedState::of(&with_hms(Some(HmsSeverity::Common)));
+        assert!(common
+            .events(&with_hms(Some(HmsSeverity::Common)))
+            .is_empty());
+        assert!(common.events(&with_hms(Some(HmsSeverity::Info))).is_empty());
+        assert!(common.events(&with_hms(None)).is_empty());
+        assert_eq!(
+            common.events(&with_hms(Some(HmsSeverity::Fatal))),
+            vec![WebhookEventKind::HmsAlert]
+        );
+    }
+
+    #[tokio::test]
+    async fn delivery_retries_server_errors() {
+        let received = Arc::new(Mutex::new(Vec::<Value>::new()));
+        let app =
+            Router::new()
+                .route(
+                    "/hook",
+                    post(
+                        |State(received): State<Arc<Mutex<Vec<Value>>>>,
+                         Json(body): Json<Value>| async move {
+                            let mut received = received.lock().unwrap();
+                            received.push(body);
+                            if received.len() == 1 {
+                                StatusCode::INTERNAL_SERVER_ERROR
+                            } else {
+                                StatusCode::NO_CONTENT
+                            }
+                        },
+                    ),
+                )
+                .with_state(received.clone());
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let server = axum::Server::from_tcp(listener)
+            .expect("server")
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+
+        let mut notifier = WebhookNotifier::new(&format!("http://{addr}/hook")).expect("notifier");
+        notifier.retry_initial = Duration::from_millis(100);
+        let payload = WebhookEvent {
+            printer_id: 3,
+            printer_name: "X1C".to_string(),
+            event: WebhookEventKind::PrintFinished,
+            job_state: Some("FINISH".to_string()),
+            previous_job_state: Some("RUNNING".to_string()),
+            percent: Some(100),
+            print_error_code: None,
+            print_error_text: None,
+            hms_severity: None,
+            timestamp: Utc::now(),
+        };
+
+        assert!(notifier.deliver(&payload).await);
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[1]["printerId"], 3);
+        assert_eq!(received[1]["event"], "printFinished");
+        assert_eq!(received[1]["previousJobState"], "RUNNING");
+    }
+}
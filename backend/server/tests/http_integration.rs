@@ -0,0 +1,150 @@
+//! The HTTP API end to end, against an in-memory database.
+//!
+//! The server is a binary crate, so the router and every module it reaches are
+//! compiled into the test directly. Their own unit tests come along with them.
+
+// Only the HTTP path through these modules is used here.
+#![allow(dead_code)]
+
+#[path = "../src/auth.rs"]
+mod auth;
+#[path = "../src/backoff.rs"]
+mod backoff;
+#[path = "../src/command_queue.rs"]
+mod command_queue;
+#[path = "../src/commands.rs"]
+mod commands;
+#[path = "../src/config.rs"]
+mod config;
+#[path = "../src/connectivity.rs"]
+mod connectivity;
+#[path = "../src/db.rs"]
+mod db;
+#[path = "../src/disk_limit.rs"]
+mod disk_limit;
+#[path = "../src/ftps.rs"]
+mod ftps;
+#[path = "../src/http.rs"]
+mod http;
+#[path = "../src/metrics_history.rs"]
+mod metrics_history;
+#[path = "../src/mqtt.rs"]
+mod mqtt;
+#[path = "../src/printers.rs"]
+mod printers;
+#[path = "../src/ratelimit.rs"]
+mod ratelimit;
+#[path = "../src/rtsp/mod.rs"]
+mod rtsp;
+#[path = "../src/secrets.rs"]
+mod secrets;
+#[path = "../src/state.rs"]
+mod state;
+#[cfg(feature = "static-files")]
+#[path = "../src/static_files.rs"]
+mod static_files;
+#[path = "../src/thumbnail.rs"]
+mod thumbnail;
+#[path = "../src/tls.rs"]
+mod tls;
+#[path = "../src/webhooks.rs"]
+mod webhooks;
+
+use auth::AuthManager;
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use axum::Router;
+use config::AppConfig;
+use http::AppState;
+use ratelimit::CommandRateLimiter;
+use secrets::SecretCipher;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+use tower::ServiceExt;
+
+async fn test_app() -> (Router, Arc<AppState>) {
+    let config = AppConfig::for_tests();
+    let db = db::init("sqlite::memory:").await.expect("init db");
+    let state = Arc::new(AppState {
+        command_limiter: Arc::new(CommandRateLimiter::new(
+            config.command_rate_per_sec,
+            config.command_burst,
+        )),
+        config,
+        db,
+        cipher: SecretCipher::new(None),
+        printers: Arc::new(RwLock::new(HashMap::new())),
+        printer_locks: Arc::default(),
+        auth: AuthManager::new(None, None),
+        metrics: None,
+        shutdown: CancellationToken::new(),
+    });
+    (http::router(Arc::clone(&state)).expect("router"), state)
+}
+
+async fn send(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    body: Option<serde_json::Value>,
+) -> (StatusCode, Vec<u8>) {
+    let mut request = Request::builder().method(method).uri(uri);
+    let body = match body {
+        Some(body) => {
+            request = request.header(header::CONTENT_TYPE, "application/json");
+            Body::from(body.to_string())
+        }
+        None => Body::empty(),
+    };
+    let response = app
+        .clone()
+        .oneshot(request.body(body).expect("request"))
+        .await
+        .expect("response");
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .expect("body");
+    (status, body.to_vec())
+}
+
+#[tokio::test]
+async fn printer_api_round_trip() {
+    let (app, state) = test_app().await;
+    let printer = serde_json::json!({
+        "name": "X1C",
+        "host": "127.0.0.1",
+        "serial": "01S00A000000001",
+        "accessCode": "12345678"
+    });
+
+    let (status, body) = send(&app, Method::POST, "/api/printers", Some(printer.clone())).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let created: serde_json::Value = serde_json::from_slice(&body).expect("json");
+    assert_eq!(created["name"], "X1C");
+    assert_eq!(created["serial"], "01S00A000000001");
+    assert!(created["id"].is_i64());
+
+    let (status, body) = send(&app, Method::POST, "/api/printers", Some(printer)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let error: serde_json::Value = serde_json::from_slice(&body).expect("json");
+    assert_eq!(error["code"], "DUPLICATE_SERIAL");
+
+    let (status, _) = send(&app, Method::DELETE, "/api/printers/999", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    assert_eq!(
+        send(&app, Method::GET, "/healthz", None).await.0,
+        StatusCode::OK
+    );
+    assert_eq!(
+        send(&app, Method::GET, "/readyz", None).await.0,
+        StatusCode::OK
+    );
+
+    for runtime in state.printers.read().await.values() {
+        runtime.shutdown();
+    }
+}
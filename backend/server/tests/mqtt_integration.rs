@@ -0,0 +1,173 @@
+//! The MQTT loop against a real broker (rumqttd) standing in for a printer.
+//!
+//! The server is a binary crate, so the MQTT loop and the modules it uses are
+//! compiled into the test directly. Their own unit tests come along with them.
+
+// Only the MQTT path through these modules is used here.
+#![allow(dead_code)]
+
+#[path = "../src/backoff.rs"]
+mod backoff;
+#[path = "../src/commands.rs"]
+mod commands;
+#[path = "../src/config.rs"]
+mod config;
+#[path = "../src/connectivity.rs"]
+mod connectivity;
+#[path = "../src/mqtt.rs"]
+mod mqtt;
+#[path = "../src/state.rs"]
+mod state;
+#[path = "../src/tls.rs"]
+mod tls;
+#[path = "../src/webhooks.rs"]
+mod webhooks;
+
+#[path = "../src/rtsp"]
+mod rtsp {
+    pub mod cmaf;
+    pub mod depacketizer;
+    pub mod rtp;
+    pub mod stream;
+}
+
+use commands::CommandRequest;
+use config::{AppConfig, PrinterConfig};
+use connectivity::LinkTimestamp;
+use rumqttd::local::LinkRx;
+use rumqttd::{Broker, Config, ConnectionSettings, Notification, RouterConfig, ServerSettings};
+use state::PrinterState;
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch, RwLock};
+
+const SERIAL: &str = "01S00TEST";
+const ACCESS_CODE: &str = "12345678";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A plain-TCP broker that only accepts the printer's `bblp` login.
+fn start_broker() -> (SocketAddr, Broker) {
+    // rumqttd does not report the port it bound, so reserve one first.
+    let listen = TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("free port");
+    let server = ServerSettings {
+        name: "v4".to_string(),
+        listen,
+        tls: None,
+        next_connection_delay_ms: 1,
+        connections: ConnectionSettings {
+            connection_timeout_ms: 5_000,
+            max_payload_size: 256 * 1024,
+            max_inflight_count: 100,
+            auth: Some(HashMap::from([(
+                "bblp".to_string(),
+                ACCESS_CODE.to_string(),
+            )])),
+            external_auth: None,
+            dynamic_filters: true,
+        },
+    };
+    let config = Config {
+        id: 0,
+        router: RouterConfig {
+            max_connections: 10,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 1024 * 1024,
+            max_segment_count: 10,
+            ..RouterConfig::default()
+        },
+        v4: Some(HashMap::from([("1".to_string(), server)])),
+        ..Config::default()
+    };
+    (listen, Broker::new(config))
+}
+
+/// Next publish the broker forwards to `link`, as (topic, JSON payload).
+fn next_publish(link: &mut LinkRx, deadline: Instant) -> Option<(String, serde_json::Value)> {
+    while Instant::now() < deadline {
+        if let Ok(Some(Notification::Forward(forward))) = link.recv_deadline(deadline) {
+            let topic = String::from_utf8_lossy(&forward.publish.topic).to_string();
+            let payload = serde_json::from_slice(&forward.publish.payload).ok()?;
+            return Some((topic, payload));
+        }
+    }
+    None
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_update_state_and_commands_reach_request_topic() {
+    let (listen, mut broker) = start_broker();
+    let (mut printer_tx, mut printer_rx) = broker.link("printer").expect("printer link");
+    printer_tx
+        .subscribe(format!("device/{SERIAL}/request"))
+        .expect("subscribe");
+    std::thread::spawn(move || broker.start().expect("broker stopped"));
+
+    let mut settings = AppConfig::for_tests();
+    settings.mqtt_tls = false;
+    settings.mqtt_port = listen.port();
+    let printer = PrinterConfig {
+        id: 1,
+        name: "Test".to_string(),
+        host: listen.ip().to_string(),
+        serial: SERIAL.to_string(),
+        access_code: ACCESS_CODE.to_string(),
+        rtsp_url: None,
+        overrides: None,
+        tags: Vec::new(),
+        mqtt_user_id: Some("4242".to_string()),
+        rtsp_username: None,
+    };
+    let state = Arc::new(RwLock::new(PrinterState::default()));
+    let (command_tx, command_rx) = mpsc::channel(4);
+    let (status_tx, mut status_rx) = watch::channel(PrinterState::default());
+    let (_config_tx, config_rx) = watch::channel(printer);
+    let task = tokio::spawn(mqtt::run(
+        settings,
+        config_rx,
+        Arc::clone(&state),
+        command_rx,
+        status_tx,
+        LinkTimestamp::new(),
+        None,
+    ));
+
+    // Reports are not retained, so keep sending until the client has
+    // subscribed and one arrives.
+    let report = serde_json::json!({ "print": { "nozzle_temper": 215.5 } });
+    tokio::time::timeout(TIMEOUT, async {
+        while status_rx.borrow_and_update().nozzle_c.is_none() {
+            printer_tx
+                .publish(format!("device/{SERIAL}/report"), report.to_string())
+                .expect("publish report");
+            let _ = tokio::time::timeout(Duration::from_millis(100), status_rx.changed()).await;
+        }
+    })
+    .await
+    .expect("nozzle temperature never arrived");
+    assert_eq!(state.read().await.nozzle_c, Some(215.5));
+
+    command_tx
+        .send(CommandRequest::Pause)
+        .await
+        .expect("command channel");
+    let (topic, payload) = tokio::task::spawn_blocking(move || {
+        next_publish(&mut printer_rx, Instant::now() + TIMEOUT)
+    })
+    .await
+    .expect("link reader")
+    .expect("pause never published");
+    assert_eq!(topic, format!("device/{SERIAL}/request"));
+    assert_eq!(payload["print"]["command"], "pause");
+    // The printer's own user id wins over MQTT_USER_ID.
+    assert_eq!(payload["user_id"], "4242");
+
+    drop(command_tx);
+    tokio::time::timeout(TIMEOUT, task)
+        .await
+        .expect("mqtt task did not stop")
+        .expect("mqtt task panicked");
+}
@@ -79,6 +79,13 @@ export default function App() {
     onError: setError,
   });
 
+  const thumbnailUrl =
+    selectedPrinterId && status?.subtaskName
+      ? `${API_BASE}/api/printers/${selectedPrinterId}/thumbnail.png?job=${encodeURIComponent(
+          `${status.subtaskName}:${status.gcodeFile ?? ""}`,
+        )}`
+      : null;
+
   return (
     <div className="app">
       <Header
@@ -98,9 +105,11 @@ export default function App() {
           apiBase={API_BASE}
           selectedPrinterId={selectedPrinterId}
           selectedPrinter={selectedPrinter}
+          videoStatus={status?.videoStatus}
         />
         <StatusControls
           jobStateDisplay={jobStateDisplay}
+          thumbnailUrl={thumbnailUrl}
           lightIsOn={lightIsOn}
           canToggleLight={canToggleLight}
           handleLightToggle={handleLightToggle}
@@ -1,3 +1,6 @@
+const LOW_FILAMENT_PERCENT = 10;
+const EXTERNAL_SPOOL_ID = 254;
+
 function formatHumidity(value) {
   if (value == null || Number.isNaN(Number(value))) {
     return "--";
@@ -32,19 +35,23 @@ export default function AmsFilamentCard({ status }) {
     <>
       {amsUnits.map((unit, unitIndex) => {
         const trays = Array.isArray(unit?.trays) ? unit.trays : [];
-        const amsLabel = `AMS ${unit?.id != null ? Number(unit.id) : unitIndex + 1}`;
+        const isExternal = Number(unit?.id) === EXTERNAL_SPOOL_ID;
+        const amsLabel = isExternal
+          ? "External spool"
+          : `AMS ${unit?.id != null ? Number(unit.id) : unitIndex + 1}`;
+        const slotCount = isExternal ? 1 : 4;
         const traysBySlot = new Map();
 
         trays.forEach((tray, trayIndex) => {
           const rawId = Number(tray?.id);
           const slotId = Number.isInteger(rawId) ? rawId : trayIndex;
-          if (slotId < 0 || slotId > 3 || traysBySlot.has(slotId)) {
+          if (slotId < 0 || slotId >= slotCount || traysBySlot.has(slotId)) {
             return;
           }
           traysBySlot.set(slotId, tray);
         });
 
-        const orderedSlots = Array.from({ length: 4 }, (_, slotId) => {
+        const orderedSlots = Array.from({ length: slotCount }, (_, slotId) => {
           const tray = traysBySlot.get(slotId);
           const filamentType =
             typeof tray?.filamentType === "string" &&
@@ -53,12 +60,15 @@ export default function AmsFilamentCard({ status }) {
               : "Empty";
           const rawColor = typeof tray?.color === "string" ? tray.color.trim() : "";
           const colorHex = normalizeColor(rawColor);
+          const remaining = Number(tray?.remainingPercent);
 
           return {
             slotId,
             filamentType,
             colorHex,
             colorLabel: (colorHex ?? rawColor) || "--",
+            active: tray?.active === true,
+            remainingPercent: Number.isFinite(remaining) ? remaining : null,
           };
         });
 
@@ -66,9 +76,11 @@ export default function AmsFilamentCard({ status }) {
           <div key={`${amsLabel}-${unitIndex}`} className="card ams-card">
             <div className="ams-header">
               <span className="ams-title">{amsLabel}</span>
-              <span className="ams-humidity mono">
-                Humidity {formatHumidity(unit?.humidityRaw)}
-              </span>
+              {isExternal ? null : (
+                <span className="ams-humidity mono">
+                  Humidity {formatHumidity(unit?.humidityRaw)}
+                </span>
+              )}
             </div>
 
             <div className="ams-slots">
@@ -77,9 +89,20 @@ export default function AmsFilamentCard({ status }) {
                 return (
                   <div
                     key={`${amsLabel}-slot-${slot.slotId}`}
-                    className={`ams-tray ${isEmpty ? "empty" : ""}`}
+                    className={`ams-tray ${isEmpty ? "empty" : ""} ${
+                      slot.active ? "active" : ""
+                    }`}
                   >
                     <span className="ams-type">{slot.filamentType}</span>
+                    {slot.remainingPercent != null ? (
+                      <span
+                        className={`ams-remaining mono ${
+                          slot.remainingPercent <= LOW_FILAMENT_PERCENT ? "low" : ""
+                        }`}
+                      >
+                        {slot.remainingPercent}%
+                      </span>
+                    ) : null}
                     <span
                       className="ams-color"
                       title={slot.colorLabel === "--" ? "Color unknown" : slot.colorLabel}
@@ -77,7 +77,7 @@ export default function PrinterManagerDrawer({
       const endpoint = formState.id
         ? `${apiBase}/api/printers/${formState.id}`
         : `${apiBase}/api/printers`;
-      const method = formState.id ? "PUT" : "POST";
+      const method = formState.id ? "PATCH" : "POST";
       const response = await fetch(endpoint, {
         method,
         headers: { "Content-Type": "application/json" },
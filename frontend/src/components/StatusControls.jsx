@@ -3,6 +3,7 @@ import { LightBulbIcon } from "./Icons";
 
 export default function StatusControls({
   jobStateDisplay,
+  thumbnailUrl,
   lightIsOn,
   canToggleLight,
   handleLightToggle,
@@ -42,6 +43,17 @@ export default function StatusControls({
   return (
     <div className="card status-controls">
       <div className="status-header">
+        {thumbnailUrl ? (
+          <img
+            key={thumbnailUrl}
+            className="job-thumbnail"
+            src={thumbnailUrl}
+            alt="Plate preview"
+            onError={(event) => {
+              event.currentTarget.hidden = true;
+            }}
+          />
+        ) : null}
         <div className="status-title">{jobStateDisplay}</div>
         <button
           type="button"
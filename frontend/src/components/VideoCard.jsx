@@ -2,7 +2,19 @@ import { useVideoStream } from "../hooks/useVideoStream";
 
 const PLAYLIST_LABEL = "Chunked CMAF (MSE)";
 
-export default function VideoCard({ apiBase, selectedPrinterId, selectedPrinter }) {
+const VIDEO_STATUS_TEXT = {
+  connecting: "Connecting to camera…",
+  waitingForKeyframe: "Acquiring video…",
+  stalled: "Video stalled; reconnecting…",
+  error: "Camera connection failed; retrying…",
+};
+
+export default function VideoCard({
+  apiBase,
+  selectedPrinterId,
+  selectedPrinter,
+  videoStatus,
+}) {
   const {
     videoRef,
     videoError,
@@ -45,6 +57,9 @@ export default function VideoCard({ apiBase, selectedPrinterId, selectedPrinter
             </div>
           </details>
           {videoError && <div className="video-error">{videoError}</div>}
+          {!videoError && selectedPrinterId && VIDEO_STATUS_TEXT[videoStatus] && (
+            <div className="video-status">{VIDEO_STATUS_TEXT[videoStatus]}</div>
+          )}
         </div>
       </div>
     </div>
@@ -64,7 +64,7 @@ export function normalizeLight(value) {
     return null;
   }
   const text = String(value).toLowerCase();
-  if (text === "on") {
+  if (text === "on" || text === "flashing") {
     return true;
   }
   if (text === "off") {